@@ -1,16 +1,10 @@
 use std::path::PathBuf;
 
 fn main() {
-  println!("cargo:rustc-link-lib=flutter_engine");
-
-  let engine_dir = PathBuf::from("./engine")
-    .canonicalize()
-    .expect("unable to get the absolute path of engine");
-
-  println!("cargo:rustc-link-search={}", engine_dir.display());
-
+  // the engine is loaded at runtime, through the proc table of FlutterEngineGetProcAddresses
   let bindings = bindgen::builder()
     .header("engine/embedder.h")
+    .blocklist_function(".*")
     .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
     .generate()
     .expect("unable to generate bindings");
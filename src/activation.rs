@@ -0,0 +1,163 @@
+//! One instance per app id, like GApplication: a later launch hands its route to the running
+//! instance over `org.freedesktop.Application` on the session bus and exits, instead of
+//! showing a second bar.
+
+#[cfg(feature = "dbus")]
+use std::collections::HashMap;
+
+#[cfg(feature = "dbus")]
+use anyhow::Context;
+use anyhow::Result;
+use serde_json::json;
+use smol::channel::Receiver;
+#[cfg(feature = "dbus")]
+use smol::channel::Sender;
+#[cfg(feature = "dbus")]
+use zbus::blocking::Connection;
+#[cfg(feature = "dbus")]
+use zbus::fdo::RequestNameFlags;
+#[cfg(feature = "dbus")]
+use zbus::fdo::RequestNameReply;
+#[cfg(feature = "dbus")]
+use zbus::interface;
+#[cfg(feature = "dbus")]
+use zbus::zvariant::OwnedValue;
+
+use crate::FlutterEngine;
+use crate::channel;
+
+/// The channel of `SystemNavigator` and the `Router`
+const NAVIGATION_CHANNEL: &str = "flutter/navigation";
+
+/// Routes handed over by later launches. Outlives restarts of the engine.
+pub struct Activations {
+  routes: Option<Receiver<String>>,
+  /// owns the name of the app while it runs
+  #[cfg(feature = "dbus")]
+  _conn: Option<Connection>,
+}
+
+impl Activations {
+  /// Become the instance of `app_id`, or hand `route` to the running one and return `None`.
+  /// Any number of instances run without an app id.
+  pub fn claim(app_id: Option<&str>, route: Option<&str>) -> Result<Option<Self>> {
+    let Some(app_id) = app_id else {
+      return Ok(Some(Self::none()));
+    };
+    #[cfg(feature = "dbus")]
+    return claim(app_id, route);
+    #[cfg(not(feature = "dbus"))]
+    {
+      let _ = route;
+      log::warn!(
+        "built without D-Bus, so another instance of {} may run already",
+        app_id
+      );
+      Ok(Some(Self::none()))
+    }
+  }
+
+  fn none() -> Self {
+    Self {
+      routes: None,
+      #[cfg(feature = "dbus")]
+      _conn: None,
+    }
+  }
+
+  /// Push the routes onto the navigator of the app. Never resolves.
+  pub async fn serve(&self, engine: &FlutterEngine) -> Result<()> {
+    let Some(routes) = &self.routes else {
+      return std::future::pending().await;
+    };
+    while let Ok(route) = routes.recv().await {
+      log::info!("opening {:?} for another launch", route);
+      channel::invoke_method(
+        engine,
+        NAVIGATION_CHANNEL,
+        "pushRouteInformation",
+        json!({ "location": route, "state": null }),
+      )?;
+    }
+    std::future::pending().await
+  }
+}
+
+#[cfg(feature = "dbus")]
+fn claim(app_id: &str, route: Option<&str>) -> Result<Option<Activations>> {
+  let path = object_path(app_id);
+  let conn = Connection::session().context("failed to connect to the session bus")?;
+  let (tx, rx) = smol::channel::unbounded();
+  // before the name, so that no call finds it missing
+  conn
+    .object_server()
+    .at(path.as_str(), Application { routes: tx })?;
+  let reply = conn
+    .request_name_with_flags(app_id, RequestNameFlags::DoNotQueue.into())
+    .with_context(|| format!("failed to own the name {}", app_id))?;
+  if reply != RequestNameReply::Exists {
+    return Ok(Some(Activations {
+      routes: Some(rx),
+      _conn: Some(conn),
+    }));
+  }
+
+  let platform_data = HashMap::<&str, OwnedValue>::new();
+  let interface = Some("org.freedesktop.Application");
+  match route {
+    Some(route) => conn.call_method(
+      Some(app_id),
+      path.as_str(),
+      interface,
+      "Open",
+      &(vec![route], platform_data),
+    ),
+    None => conn.call_method(
+      Some(app_id),
+      path.as_str(),
+      interface,
+      "Activate",
+      &(platform_data,),
+    ),
+  }
+  .with_context(|| format!("failed to activate the running instance of {}", app_id))?;
+  log::info!("{} runs already, activated it instead", app_id);
+  Ok(None)
+}
+
+/// `/org/example/Bar` for `org.example.Bar`, like GApplication
+#[cfg(feature = "dbus")]
+fn object_path(app_id: &str) -> String {
+  format!("/{}", app_id.replace('.', "/").replace('-', "_"))
+}
+
+#[cfg(feature = "dbus")]
+struct Application {
+  routes: Sender<String>,
+}
+
+#[cfg(feature = "dbus")]
+#[interface(name = "org.freedesktop.Application")]
+impl Application {
+  /// A launch without a route, where the app is on screen already
+  fn activate(&self, _platform_data: HashMap<String, OwnedValue>) {}
+
+  /// `uris` are routes of the app
+  fn open(&self, uris: Vec<String>, _platform_data: HashMap<String, OwnedValue>) {
+    for uri in uris {
+      let _ = self.routes.try_send(uri);
+    }
+  }
+
+  fn activate_action(
+    &self,
+    action_name: String,
+    _parameter: Vec<OwnedValue>,
+    _platform_data: HashMap<String, OwnedValue>,
+  ) -> zbus::fdo::Result<()> {
+    Err(zbus::fdo::Error::NotSupported(format!(
+      "no action {:?}",
+      action_name
+    )))
+  }
+}
@@ -0,0 +1,140 @@
+//! Benchmarks of the presentation path that run without a compositor, behind the `bench`
+//! feature: `cargo bench --features bench`.
+//!
+//! The backing store pool renders on the first EGL device, without a surface. It's skipped if
+//! there's none.
+
+use std::ffi::CString;
+use std::hint::black_box;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use glutin::api::egl::context::PossiblyCurrentContext;
+use glutin::api::egl::device::Device;
+use glutin::api::egl::display::Display;
+use glutin::config::ConfigSurfaceTypes;
+use glutin::config::ConfigTemplateBuilder;
+use glutin::context::ContextAttributesBuilder;
+use glutin::prelude::GlDisplay;
+use serde_json::json;
+
+use crate::channel;
+use crate::channel::MethodCall;
+use crate::channel::standard;
+use crate::compositor::backing_store::BackingStorePool;
+use crate::compositor::backing_store::GLBackingStore;
+
+/// how long each benchmark runs for, after warming up
+const MEASUREMENT_TIME: Duration = Duration::from_secs(1);
+
+/// Run every benchmark, printing the mean time per iteration.
+pub fn run() -> Result<()> {
+  codecs()?;
+  match surfaceless_context() {
+    // SAFETY: the context stays current until it's dropped at the end of the arm
+    Ok(_context) => unsafe { pool() },
+    Err(e) => println!("skipped the backing store pool: {:#}", e),
+  }
+  Ok(())
+}
+
+fn measure(name: &str, mut f: impl FnMut()) {
+  for _ in 0..10 {
+    f();
+  }
+  let mut iterations = 0;
+  let started = Instant::now();
+  while started.elapsed() < MEASUREMENT_TIME {
+    f();
+    iterations += 1;
+  }
+  println!(
+    "{:<44} {:>12.3?} ({} iterations)",
+    name,
+    started.elapsed() / iterations,
+    iterations
+  );
+}
+
+fn codecs() -> Result<()> {
+  // what the embedder's own channels get
+  let small = json!({
+    "method": "setLayerProps",
+    "args": {
+      "viewId": 1,
+      "anchor": ["top", "left", "right"],
+      "height": 32,
+      "margin": [0, 8, 0, 8],
+      "exclusiveZone": "auto",
+      "opacity": 0.9,
+    },
+  });
+  // bulky arguments, like of a plugin passing pixels or samples
+  let large = json!({
+    "method": "setSamples",
+    "args": (0..4096).map(|i| i as f64 / 7.0).collect::<Vec<_>>(),
+  });
+
+  for (name, call) in [("small", small), ("large", large)] {
+    let json = serde_json::to_vec(&call)?;
+    measure(&format!("json: decode {} method call", name), || {
+      black_box(serde_json::from_slice::<MethodCall>(black_box(&json)).unwrap());
+    });
+    let args = &call["args"];
+    measure(&format!("json: encode {} result", name), || {
+      black_box(channel::encode_result(Ok(black_box(args).clone())));
+    });
+
+    let mut standard = Vec::new();
+    standard::write_value(&mut standard, &call["method"]);
+    standard::write_value(&mut standard, args);
+    measure(&format!("standard: decode {} method call", name), || {
+      black_box(standard::decode_method_call(black_box(&standard)).unwrap());
+    });
+    measure(&format!("standard: encode {} result", name), || {
+      black_box(standard::encode_result(Ok(black_box(args).clone())));
+    });
+  }
+  Ok(())
+}
+
+/// Make a context current on the first EGL device, and load GL with it.
+fn surfaceless_context() -> Result<PossiblyCurrentContext> {
+  let device = Device::query_devices()?.next().context("no EGL device")?;
+  let display = unsafe { Display::with_device(&device, None)? };
+  let template = ConfigTemplateBuilder::new()
+    .with_surface_type(ConfigSurfaceTypes::empty())
+    .build();
+  let config = unsafe { display.find_configs(template)? }
+    .next()
+    .context("no EGL config")?;
+  let context =
+    unsafe { display.create_context(&config, &ContextAttributesBuilder::new().build(None))? };
+  let context = context.make_current_surfaceless()?;
+  gl::load_with(|symbol| {
+    let symbol = CString::new(symbol).expect("GL symbols have no nul");
+    display.get_proc_address(&symbol)
+  });
+  Ok(context)
+}
+
+/// Must be called with a GL context current.
+unsafe fn pool() {
+  const WIDTH: i32 = 1920;
+  const HEIGHT: i32 = 1080;
+
+  measure("pool: allocate a 1920x1080 backing store", || unsafe {
+    let store = GLBackingStore::new(WIDTH, HEIGHT, None, false, None);
+    gl::Finish();
+    store.destroy();
+  });
+
+  let pool = BackingStorePool::default();
+  unsafe { pool.put(GLBackingStore::new(WIDTH, HEIGHT, None, false, None)) };
+  measure("pool: reuse a 1920x1080 backing store", || {
+    let store = pool.take(WIDTH, HEIGHT).expect("put back");
+    unsafe { pool.put(black_box(store)) };
+  });
+}
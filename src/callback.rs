@@ -1,43 +1,107 @@
 use std::ffi::c_void;
-use std::time::Duration;
+use std::panic::AssertUnwindSafe;
 
 use anyhow::Context;
 use glutin::prelude::GlDisplay;
 
 use crate::error::FFIFlutterEngineResultExt;
 use crate::error_in_callback;
+use crate::exception;
+use crate::exception::DartException;
 use crate::ffi;
+use crate::isolate;
+use crate::locale;
+use crate::semantics;
+use crate::semantics::SemanticsNode;
 
-// `let state = unsafe { ... }` SAFETY: none of these callbacks borrows a mutable reference to the state
+// `unsafe { engine_state(user_data) }` SAFETY: the user data is `FlutterEngine::user_data`,
+// given only to this engine, which is deinitialized before the shared state is dropped
+
+/// What the [`FlutterEngine`](super::FlutterEngine) behind `user_data` shares with its callbacks
+///
+/// # Safety
+///
+/// `user_data` must come from `FlutterEngine::user_data` of an engine that's still alive.
+pub unsafe fn engine_shared<'a>(user_data: *mut c_void) -> &'a super::EngineShared {
+  unsafe { &*(user_data as *const super::EngineShared) }
+}
+
+/// `None` until the embedder has set up the state, right before the engine runs
+///
+/// # Safety
+///
+/// See [`engine_shared`].
+pub unsafe fn engine_state<'a>(user_data: *mut c_void) -> Option<&'a super::FlutterEngineState> {
+  unsafe { engine_shared(user_data) }.state.get()
+}
+
+/// Runs the body of an engine callback, which must not unwind into the engine.
+///
+/// A panic is sent to the main event loop like an error in `error_in_callback!`, and `on_panic`
+/// is returned instead. Aborts if there's no `state` to send it with.
+pub fn catch_panic<T>(
+  state: Option<&super::FlutterEngineState>,
+  on_panic: T,
+  body: impl FnOnce() -> T,
+) -> T {
+  let payload = match std::panic::catch_unwind(AssertUnwindSafe(body)) {
+    Ok(v) => return v,
+    Err(payload) => payload,
+  };
+  let message = payload
+    .downcast_ref::<&str>()
+    .copied()
+    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    .unwrap_or("<unknown>");
+  let error = anyhow::anyhow!("panicked in an engine callback: {}", message);
+  if let Some(state) = state
+    && state.terminate.unbounded_send(Err(error)).is_ok()
+  {
+    return on_panic;
+  }
+  log::error!("panicked in an engine callback: {}", message);
+  std::process::abort();
+}
 
 pub extern "C" fn make_current(user_data: *mut c_void) -> bool {
-  let state = unsafe { &*(user_data as *const super::FlutterEngineState) };
-  error_in_callback!(state, state.opengl_state.make_current_no_surface());
-  true
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return false;
+  };
+  catch_panic(Some(state), false, || {
+    error_in_callback!(state, state.opengl_state.make_current_no_surface());
+    true
+  })
 }
 
-pub extern "C" fn clear_current(user_data: *mut c_void) -> bool {
-  let state = unsafe { &*(user_data as *const super::FlutterEngineState) };
-  error_in_callback!(state, state.opengl_state.make_not_current());
+pub extern "C" fn clear_current(_user_data: *mut c_void) -> bool {
+  // the render context stays current on the raster thread, see `OpenGLState::make_current`
   true
 }
 
 pub extern "C" fn make_resource_current(user_data: *mut c_void) -> bool {
-  let state = unsafe { &*(user_data as *const super::FlutterEngineState) };
-  let context = &state.opengl_state.resource_context;
-  error_in_callback!(
-    state,
-    context
-      .make_current_surfaceless()
-      .context("Failed to make resource context current.")
-  );
-  true
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return false;
+  };
+  catch_panic(Some(state), false, || {
+    let context = &state.opengl_state.resource_context;
+    error_in_callback!(
+      state,
+      context
+        .make_current_surfaceless()
+        .context("Failed to make resource context current.")
+    );
+    true
+  })
 }
 
 pub extern "C" fn gl_proc_resolver(user_data: *mut c_void, name: *const i8) -> *mut c_void {
-  let state = unsafe { &*(user_data as *const super::FlutterEngineState) };
-  let name = unsafe { std::ffi::CStr::from_ptr(name) };
-  state.opengl_state.egl_display.get_proc_address(name) as *mut c_void
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return std::ptr::null_mut();
+  };
+  catch_panic(Some(state), std::ptr::null_mut(), || {
+    let name = unsafe { std::ffi::CStr::from_ptr(name) };
+    state.opengl_state.egl_display.get_proc_address(name) as *mut c_void
+  })
 }
 
 pub extern "C" fn present_with_info(
@@ -55,23 +119,94 @@ pub extern "C" fn fbo_with_frame_info_callback(
   0
 }
 
-pub extern "C" fn log_message_callback(
-  tag: *const i8,
-  message: *const i8,
-  _user_data: *mut c_void,
+/// What the app `print`s, and the engine's messages for it like uncaught exceptions. Logged with
+/// the target `dart`, so that `RUST_LOG` can tell them apart from the embedder's own.
+pub extern "C" fn log_message_callback(tag: *const i8, message: *const i8, user_data: *mut c_void) {
+  let state = unsafe { engine_state(user_data) };
+  catch_panic(state, (), || {
+    let tag = unsafe { std::ffi::CStr::from_ptr(tag) };
+    let message = unsafe { std::ffi::CStr::from_ptr(message) };
+    let message = message.to_str().unwrap_or("<invalid utf8>");
+    let tag = tag.to_str().unwrap_or("<invalid utf8>");
+    let level = crate::logging::engine_level(message);
+    let quiet = state.is_some_and(|state| state.quiet_dart);
+    if !quiet || level <= log::Level::Warn {
+      log::log!(target: "dart", level, tag; "[{}] {}", tag, message);
+    }
+    crate::vm_service::on_log_message(message);
+    if let Some(state) = state
+      && let Some(exception) = DartException::from_log_message(message)
+    {
+      let ret = state
+        .task_runner_handle
+        .post_task(move |engine| exception::report(engine, exception));
+      error_in_callback!(state, ret, return ());
+    }
+  })
+}
+
+/// On the platform thread
+pub extern "C" fn update_semantics_callback(
+  update: *const ffi::FlutterSemanticsUpdate2,
+  user_data: *mut c_void,
 ) {
-  let tag = unsafe { std::ffi::CStr::from_ptr(tag) };
-  let message = unsafe { std::ffi::CStr::from_ptr(message) };
-  log::info!(
-    "[{}] {}",
-    tag.to_str().unwrap_or("<invalid utf8>"),
-    message.to_str().unwrap_or("<invalid utf8>")
-  );
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return;
+  };
+  catch_panic(Some(state), (), || {
+    let update = unsafe { &*update };
+    let nodes = (0..update.node_count)
+      .map(|i| unsafe { SemanticsNode::from_raw(&**update.nodes.add(i)) })
+      .collect();
+    semantics::update(state, nodes);
+  })
+}
+
+/// A hot restart from `flutter attach` or DevTools, which keeps the views but starts Dart over
+pub extern "C" fn on_pre_engine_restart_callback(user_data: *mut c_void) {
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return;
+  };
+  catch_panic(Some(state), (), || {
+    log::info!("hot restart");
+    // the new isolate subscribes again if it wants the events
+    state.compositor.cancel_event_listeners();
+    state.frame_stats.set_listening(false);
+    semantics::reset(state);
+    let ret = state.task_runner_handle.post_task(isolate::shut_down);
+    error_in_callback!(state, ret, return ());
+  })
+}
+
+/// Called on the UI thread before `main` runs, also after a hot restart
+pub extern "C" fn root_isolate_create_callback(user_data: *mut c_void) {
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return;
+  };
+  catch_panic(Some(state), (), || {
+    let ret = state.task_runner_handle.post_task(isolate::created);
+    error_in_callback!(state, ret, return ());
+  })
+}
+
+/// Which of the app's locales `Localizations` should use. There's no user data, so the
+/// engine state isn't at hand.
+pub extern "C" fn compute_platform_resolved_locale_callback(
+  supported_locales: *mut *const ffi::FlutterLocale,
+  number_of_locales: usize,
+) -> *const ffi::FlutterLocale {
+  catch_panic(None, std::ptr::null(), || {
+    let supported = (0..number_of_locales)
+      .map(|i| unsafe { &**supported_locales.add(i) })
+      .collect::<Vec<_>>();
+    locale::resolve(&supported).map_or(std::ptr::null(), |locale| locale as *const _)
+  })
 }
 
 pub extern "C" fn runs_task_on_current_thread_callback(user_data: *mut c_void) -> bool {
-  let state = unsafe { &*(user_data as *const super::FlutterEngineState) };
-  state.platform_thread_id == std::thread::current().id()
+  // also asked while the engine is being set up
+  let shared = unsafe { engine_shared(user_data) };
+  shared.platform_thread_id == std::thread::current().id()
 }
 
 pub extern "C" fn post_task_callback(
@@ -82,22 +217,95 @@ pub extern "C" fn post_task_callback(
   struct TaskWrapper(ffi::FlutterTask);
   unsafe impl Send for TaskWrapper {}
 
-  let state = unsafe { &*(user_data as *const super::FlutterEngineState) };
-  let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
-  let delay = target_time_nanos.saturating_sub(now);
-  let delay = Duration::from_nanos(delay);
-  let task_wrapped = TaskWrapper(task);
-  let ret = state.task_runner_handle.post_task_after(
-    move |engine| {
-      let task = task_wrapped;
-      unsafe {
-        let ret = ffi::FlutterEngineRunTask(engine.engine, &task.0).into_flutter_engine_result();
-        if let Err(e) = ret {
-          log::error!("failed to run the task posted by the engine: {}", e);
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    log::error!("the engine posted a task before it runs, dropped");
+    return;
+  };
+  catch_panic(Some(state), (), || {
+    let task_wrapped = TaskWrapper(task);
+    let ret = state.task_runner_handle.post_task_at(
+      move |engine| {
+        let task = task_wrapped;
+        unsafe {
+          let ret = ffi::FlutterEngineRunTask(engine.engine, &task.0).into_flutter_engine_result();
+          if let Err(e) = ret {
+            log::error!("failed to run the task posted by the engine: {}", e);
+          }
+        }
+      },
+      target_time_nanos,
+    );
+    error_in_callback!(state, ret, return ());
+  })
+}
+
+pub extern "C" fn vsync_callback(user_data: *mut c_void, baton: isize) {
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return;
+  };
+  catch_panic(Some(state), (), || {
+    let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
+    state.heartbeat.vsync_requested(now);
+    {
+      // resumed in `CompositorHandler::frame`. Checked under the lock so that a frame callback
+      // arriving right now can't miss the parked baton.
+      let mut parked_baton = state.parked_vsync_baton.lock();
+      if state.compositor.all_views_occluded() {
+        log::debug!("all views are occluded, suspend rendering");
+        *parked_baton = Some(baton);
+        state.heartbeat.vsync_settled();
+        return;
+      }
+    }
+    // held back while every view is limited to fewer frames than the refresh rate
+    let due = state
+      .compositor
+      .frame_interval()
+      .map_or(now, |interval| state.frame_clock.limit(now, interval));
+    let ret = state.task_runner_handle.post_task_at(
+      move |engine| {
+        if let Err(e) = engine.answer_vsync(baton) {
+          log::error!("failed to answer the vsync baton: {}", e);
         }
+      },
+      due,
+    );
+    error_in_callback!(state, ret, return ());
+  })
+}
+
+pub extern "C" fn platform_message_callback(
+  message: *const ffi::FlutterPlatformMessage,
+  user_data: *mut c_void,
+) {
+  struct ResponseHandle(*const ffi::FlutterPlatformMessageResponseHandle);
+  unsafe impl Send for ResponseHandle {}
+
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return;
+  };
+  catch_panic(Some(state), (), || {
+    let message = unsafe { &*message };
+    let channel = unsafe { std::ffi::CStr::from_ptr(message.channel) }
+      .to_string_lossy()
+      .into_owned();
+    let data = match message.message_size {
+      0 => Vec::new(),
+      size => unsafe { std::slice::from_raw_parts(message.message, size) }.to_vec(),
+    };
+    let response_handle = ResponseHandle(message.response_handle);
+    let ret = state.task_runner_handle.post_task(move |engine| {
+      let response_handle = response_handle;
+      let state = engine.state();
+      let response = state.channels.handle_message(engine, &channel, &data);
+      if response_handle.0.is_null() {
+        return;
+      }
+      if let Err(e) = engine.send_platform_message_response(response_handle.0, response.as_deref())
+      {
+        log::error!("failed to respond to a message on {}: {}", channel, e);
       }
-    },
-    delay,
-  );
-  error_in_callback!(state, ret, return ());
+    });
+    error_in_callback!(state, ret, return ());
+  })
 }
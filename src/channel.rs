@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::FlutterEngine;
+
+pub mod standard;
+
+/// A method call, with the arguments as JSON whatever the codec
+#[derive(Debug, Deserialize)]
+pub struct MethodCall {
+  pub method: String,
+  #[serde(default)]
+  pub args: Value,
+}
+
+impl MethodCall {
+  pub fn args<T: DeserializeOwned>(&self) -> Result<T, MethodError> {
+    T::deserialize(&self.args).map_err(|e| {
+      MethodError::new(
+        "invalid_args",
+        format!("invalid arguments for {}: {}", self.method, e),
+      )
+    })
+  }
+}
+
+/// Becomes a `PlatformException` on the Dart side.
+#[derive(Debug)]
+pub struct MethodError {
+  pub code: String,
+  pub message: String,
+}
+
+impl MethodError {
+  pub fn new(code: impl Into<String>, message: impl Into<String>) -> Self {
+    Self {
+      code: code.into(),
+      message: message.into(),
+    }
+  }
+}
+
+impl From<anyhow::Error> for MethodError {
+  fn from(e: anyhow::Error) -> Self {
+    Self::new("error", format!("{:#}", e))
+  }
+}
+
+pub type MethodResult = Result<Value, MethodError>;
+
+/// Returns `None` for methods the channel doesn't implement.
+pub type MethodHandler = fn(&FlutterEngine, &MethodCall) -> Option<MethodResult>;
+
+/// How a channel encodes method calls and results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+  /// `JSONMethodCodec`, which the embedder's own channels use
+  Json,
+  /// `StandardMethodCodec`, for plugins written for other embedders
+  Standard,
+}
+
+/// Method channels implemented by the embedder, by channel name
+#[derive(Default)]
+pub struct Channels {
+  handlers: HashMap<&'static str, (Codec, MethodHandler)>,
+}
+
+impl Channels {
+  pub fn register(&mut self, channel: &'static str, handler: MethodHandler) {
+    self.register_with_codec(channel, Codec::Json, handler);
+  }
+
+  pub fn register_with_codec(
+    &mut self,
+    channel: &'static str,
+    codec: Codec,
+    handler: MethodHandler,
+  ) {
+    self.handlers.insert(channel, (codec, handler));
+  }
+
+  /// Returns the encoded response, or `None` if the message isn't handled, which the engine
+  /// reports as `MissingPluginException` / not implemented.
+  pub fn handle_message(
+    &self,
+    engine: &FlutterEngine,
+    channel: &str,
+    message: &[u8],
+  ) -> Option<Vec<u8>> {
+    let (codec, handler) = self.handlers.get(channel)?;
+    let call = match codec {
+      Codec::Json => serde_json::from_slice(message).map_err(anyhow::Error::from),
+      Codec::Standard => standard::decode_method_call(message),
+    };
+    let call = match call {
+      Ok(call) => call,
+      Err(e) => {
+        log::warn!("malformed method call on {}: {:#}", channel, e);
+        return None;
+      }
+    };
+    let result = handler(engine, &call)?;
+    if let Err(e) = &result {
+      log::debug!("{}.{} failed: {}", channel, call.method, e.message);
+    }
+    Some(match codec {
+      Codec::Json => encode_result(result),
+      Codec::Standard => standard::encode_result(result),
+    })
+  }
+}
+
+/// Send an event to the Dart listener of an event channel.
+///
+/// Must be called on the platform thread.
+pub fn send_event(engine: &FlutterEngine, channel: &str, event: Value) -> Result<()> {
+  engine.send_platform_message(channel, &encode_result(Ok(event)))
+}
+
+/// Call a method of a Dart `MethodChannel` with `JSONMethodCodec`, without waiting for the
+/// result.
+///
+/// Must be called on the platform thread.
+pub fn invoke_method(
+  engine: &FlutterEngine,
+  channel: &str,
+  method: &str,
+  args: Value,
+) -> Result<()> {
+  let call = serde_json::json!({ "method": method, "args": args });
+  engine.send_platform_message(channel, &serde_json::to_vec(&call)?)
+}
+
+/// Success is `[result]`, failure is `[code, message, details]`.
+pub fn encode_result(result: MethodResult) -> Vec<u8> {
+  let envelope = match result {
+    Ok(value) => Value::Array(vec![value]),
+    Err(e) => Value::Array(vec![
+      Value::String(e.code),
+      Value::String(e.message),
+      Value::Null,
+    ]),
+  };
+  serde_json::to_vec(&envelope).expect("serializing a json value never fails")
+}
@@ -0,0 +1,229 @@
+//! `StandardMethodCodec`, the default codec of `MethodChannel`, which plugins written for other
+//! embedders use. Values map to JSON ones, typed lists to arrays.
+
+use anyhow::Context;
+use anyhow::Result;
+use serde_json::Map;
+use serde_json::Number;
+use serde_json::Value;
+
+use super::MethodCall;
+use super::MethodResult;
+
+const NULL: u8 = 0;
+const TRUE: u8 = 1;
+const FALSE: u8 = 2;
+const INT32: u8 = 3;
+const INT64: u8 = 4;
+const LARGE_INT: u8 = 5;
+const FLOAT64: u8 = 6;
+const STRING: u8 = 7;
+const UINT8_LIST: u8 = 8;
+const INT32_LIST: u8 = 9;
+const INT64_LIST: u8 = 10;
+const FLOAT64_LIST: u8 = 11;
+const LIST: u8 = 12;
+const MAP: u8 = 13;
+const FLOAT32_LIST: u8 = 14;
+
+pub fn decode_method_call(data: &[u8]) -> Result<MethodCall> {
+  let mut reader = Reader { data, pos: 0 };
+  let Value::String(method) = reader.value()? else {
+    anyhow::bail!("the method name is not a string");
+  };
+  let args = reader.value()?;
+  Ok(MethodCall { method, args })
+}
+
+/// Success is `0, result`, failure is `1, code, message, details`.
+pub fn encode_result(result: MethodResult) -> Vec<u8> {
+  let mut data = Vec::new();
+  match result {
+    Ok(value) => {
+      data.push(0);
+      write_value(&mut data, &value);
+    }
+    Err(e) => {
+      data.push(1);
+      write_value(&mut data, &Value::String(e.code));
+      write_value(&mut data, &Value::String(e.message));
+      write_value(&mut data, &Value::Null);
+    }
+  }
+  data
+}
+
+struct Reader<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl Reader<'_> {
+  fn bytes(&mut self, len: usize) -> Result<&[u8]> {
+    let bytes = self
+      .data
+      .get(self.pos..self.pos + len)
+      .context("truncated message")?;
+    self.pos += len;
+    Ok(bytes)
+  }
+
+  fn byte(&mut self) -> Result<u8> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+    Ok(self.bytes(N)?.try_into().expect("N bytes"))
+  }
+
+  /// Below 254 in a byte, else in the 2 or 4 bytes after
+  fn size(&mut self) -> Result<usize> {
+    Ok(match self.byte()? {
+      254 => u16::from_le_bytes(self.array()?) as usize,
+      255 => u32::from_le_bytes(self.array()?) as usize,
+      size => size as usize,
+    })
+  }
+
+  /// Typed data starts at a multiple of its element size.
+  fn align(&mut self, alignment: usize) {
+    self.pos = self.pos.next_multiple_of(alignment);
+  }
+
+  fn value(&mut self) -> Result<Value> {
+    Ok(match self.byte()? {
+      NULL => Value::Null,
+      TRUE => Value::Bool(true),
+      FALSE => Value::Bool(false),
+      INT32 => i32::from_le_bytes(self.array()?).into(),
+      INT64 => i64::from_le_bytes(self.array()?).into(),
+      FLOAT64 => {
+        self.align(8);
+        float(f64::from_le_bytes(self.array()?))
+      }
+      LARGE_INT | STRING => {
+        let len = self.size()?;
+        Value::String(String::from_utf8(self.bytes(len)?.to_vec())?)
+      }
+      UINT8_LIST => {
+        let len = self.size()?;
+        self
+          .bytes(len)?
+          .iter()
+          .map(|&byte| Value::from(byte))
+          .collect()
+      }
+      INT32_LIST => {
+        let len = self.size()?;
+        self.align(4);
+        (0..len)
+          .map(|_| Ok(Value::from(i32::from_le_bytes(self.array()?))))
+          .collect::<Result<Value>>()?
+      }
+      INT64_LIST => {
+        let len = self.size()?;
+        self.align(8);
+        (0..len)
+          .map(|_| Ok(Value::from(i64::from_le_bytes(self.array()?))))
+          .collect::<Result<Value>>()?
+      }
+      FLOAT32_LIST => {
+        let len = self.size()?;
+        self.align(4);
+        (0..len)
+          .map(|_| Ok(float(f32::from_le_bytes(self.array()?) as f64)))
+          .collect::<Result<Value>>()?
+      }
+      FLOAT64_LIST => {
+        let len = self.size()?;
+        self.align(8);
+        (0..len)
+          .map(|_| Ok(float(f64::from_le_bytes(self.array()?))))
+          .collect::<Result<Value>>()?
+      }
+      LIST => {
+        let len = self.size()?;
+        (0..len).map(|_| self.value()).collect::<Result<Value>>()?
+      }
+      MAP => {
+        let len = self.size()?;
+        let mut map = Map::new();
+        for _ in 0..len {
+          let key = match self.value()? {
+            Value::String(key) => key,
+            // JSON objects have string keys only
+            key => key.to_string(),
+          };
+          map.insert(key, self.value()?);
+        }
+        Value::Object(map)
+      }
+      tag => anyhow::bail!("unknown type {}", tag),
+    })
+  }
+}
+
+/// `null` for NaN and the infinities, like `serde_json`
+fn float(value: f64) -> Value {
+  Number::from_f64(value).map_or(Value::Null, Value::Number)
+}
+
+fn write_size(data: &mut Vec<u8>, size: usize) {
+  if size < 254 {
+    data.push(size as u8);
+  } else if let Ok(size) = u16::try_from(size) {
+    data.push(254);
+    data.extend_from_slice(&size.to_le_bytes());
+  } else {
+    data.push(255);
+    data.extend_from_slice(&(size as u32).to_le_bytes());
+  }
+}
+
+pub fn write_value(data: &mut Vec<u8>, value: &Value) {
+  match value {
+    Value::Null => data.push(NULL),
+    Value::Bool(true) => data.push(TRUE),
+    Value::Bool(false) => data.push(FALSE),
+    Value::Number(number) => {
+      if let Some(int) = number.as_i64() {
+        match i32::try_from(int) {
+          Ok(int) => {
+            data.push(INT32);
+            data.extend_from_slice(&int.to_le_bytes());
+          }
+          Err(_) => {
+            data.push(INT64);
+            data.extend_from_slice(&int.to_le_bytes());
+          }
+        }
+      } else {
+        data.push(FLOAT64);
+        let len = data.len().next_multiple_of(8);
+        data.resize(len, 0);
+        let float = number.as_f64().unwrap_or(f64::NAN);
+        data.extend_from_slice(&float.to_le_bytes());
+      }
+    }
+    Value::String(string) => {
+      data.push(STRING);
+      write_size(data, string.len());
+      data.extend_from_slice(string.as_bytes());
+    }
+    Value::Array(values) => {
+      data.push(LIST);
+      write_size(data, values.len());
+      for value in values {
+        write_value(data, value);
+      }
+    }
+    Value::Object(map) => {
+      data.push(MAP);
+      write_size(data, map.len());
+      for (key, value) in map {
+        write_value(data, &Value::String(key.clone()));
+        write_value(data, value);
+      }
+    }
+  }
+}
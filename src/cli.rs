@@ -0,0 +1,624 @@
+use std::num::NonZero;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use clap::FromArgMatches;
+use clap::Parser;
+use clap::Subcommand;
+use clap::ValueEnum;
+
+use crate::compositor::auto_hide::AutoHide;
+use crate::compositor::layer::ContentType;
+use crate::compositor::layer::Edge;
+use crate::compositor::layer::ExclusiveZone;
+use crate::compositor::layer::KeyboardInteractivity;
+use crate::compositor::layer::Layer;
+use crate::compositor::layer::LayerProps;
+use crate::config::Config;
+use crate::config::discover;
+use crate::dart_vm::DartVmOptions;
+use crate::embedder::CrashRestartOptions;
+use crate::error::ErrorPolicies;
+use crate::exception::ExceptionOptions;
+#[cfg(feature = "golden")]
+use crate::golden::GoldenOptions;
+use crate::ipc::PointerButton;
+use crate::ipc::Switch;
+use crate::ipc::ViewRef;
+use crate::opengl::RenderOptions;
+use crate::vm_service::VmServiceOptions;
+use crate::watchdog::WatchdogOptions;
+
+#[derive(Debug, Parser)]
+#[command(
+  version,
+  about,
+  args_conflicts_with_subcommands = true,
+  subcommand_negates_reqs = true
+)]
+pub struct Args {
+  #[command(subcommand)]
+  pub command: Option<Command>,
+
+  /// Read the setup from a TOML file instead of the other options.
+  ///
+  /// Without any options, `$XDG_CONFIG_HOME/wayflutter/config.toml` is read.
+  #[arg(long, value_name = "PATH", conflicts_with = "RunArgs")]
+  pub config: Option<PathBuf>,
+
+  /// Log to the systemd journal with structured fields instead of stderr, e.g. when run as a
+  /// user service. Levels are filtered by `RUST_LOG` either way.
+  #[arg(long, global = true)]
+  pub journal: bool,
+
+  /// Ask an instance running with the same config to exit and take over its surfaces
+  #[arg(long)]
+  pub replace: bool,
+
+  /// Send the command to the instance running with this config file, assets directory or app
+  /// id. Needed only when several instances run.
+  #[arg(long, global = true, value_name = "CONFIG|ASSETS|APP_ID")]
+  pub instance: Option<String>,
+
+  #[cfg(feature = "golden")]
+  #[command(flatten)]
+  pub golden: GoldenArgs,
+
+  #[command(flatten)]
+  pub run: Option<RunArgs>,
+}
+
+/// Checks of the rendering against golden PNGs
+#[cfg(feature = "golden")]
+#[derive(Debug, clap::Args)]
+pub struct GoldenArgs {
+  /// Compare a frame of the implicit view with this PNG, then exit, failing if they differ
+  #[arg(long, value_name = "PATH")]
+  pub golden: Option<PathBuf>,
+
+  /// Present this many frames before the compared one
+  #[arg(long, value_name = "N", requires = "golden")]
+  pub golden_frames: Option<u32>,
+
+  /// How far a channel of a pixel may be off
+  #[arg(long, value_name = "DELTA", requires = "golden")]
+  pub golden_tolerance: Option<u8>,
+
+  /// How many pixels may be off by more than the tolerance
+  #[arg(long, value_name = "N", requires = "golden")]
+  pub golden_max_diff_pixels: Option<usize>,
+
+  /// Write the frame to the golden PNG instead of comparing
+  #[arg(long, requires = "golden")]
+  pub update_golden: bool,
+}
+
+#[cfg(feature = "golden")]
+impl GoldenArgs {
+  pub fn options(&self) -> Option<GoldenOptions> {
+    let mut options = GoldenOptions::new(self.golden.clone()?);
+    options.frames = self.golden_frames.unwrap_or(options.frames);
+    options.tolerance = self.golden_tolerance.unwrap_or(options.tolerance);
+    options.max_diff_pixels = self
+      .golden_max_diff_pixels
+      .unwrap_or(options.max_diff_pixels);
+    options.update = self.update_golden;
+    Some(options)
+  }
+}
+
+/// Commands sent to a running instance over its control socket. Views are given by id or by
+/// the name of their surface.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+  /// Save the next frame presented on a view as PNG
+  Screenshot {
+    /// 0 for the implicit view
+    view: ViewRef,
+    /// Where to write the PNG file
+    path: PathBuf,
+  },
+  /// Save the next frame presented on a view as numbered PNGs: each backing store it was
+  /// composited from, bottom to top, then the surface. Prints where each layer was placed.
+  Dump {
+    view: ViewRef,
+    /// Directory to write the PNG files into, created if missing
+    dir: PathBuf,
+  },
+  /// Print frame timing statistics over the last frames, in milliseconds
+  Stats,
+  /// Print the views with their surfaces
+  Views,
+  /// Print the semantics tree of the app as JSON, i.e. its widgets as assistive technologies
+  /// see them, with ids, roles, labels and bounds in surface coordinates
+  Semantics,
+  /// Unmap the surface of a view, keeping its state in the app
+  Hide { view: ViewRef },
+  /// Map the surface of a hidden view again
+  Show { view: ViewRef },
+  /// Hide a view if it's shown, show it otherwise
+  Toggle { view: ViewRef },
+  /// Change the layer surface of a view.
+  ///
+  /// Takes a JSON object with the keys of `[[surface]]` in the config file, like
+  /// '{"layer":"top","height":40}'. Keys left out keep their values.
+  Configure {
+    view: ViewRef,
+    /// JSON object
+    props: String,
+  },
+  /// Read the config file again and apply its surfaces. Other settings need a restart.
+  Reload,
+  /// Move a pointer of wayflutter's own to a point of a view, in logical pixels
+  Move { view: ViewRef, x: f64, y: f64 },
+  /// Click at a point of a view with that pointer
+  Click {
+    view: ViewRef,
+    x: f64,
+    y: f64,
+    #[arg(long, value_enum, default_value_t = PointerButton::Left)]
+    button: PointerButton,
+  },
+  /// Scroll at a point of a view with that pointer, by logical pixels
+  Scroll {
+    view: ViewRef,
+    x: f64,
+    y: f64,
+    #[arg(allow_negative_numbers = true)]
+    dx: f64,
+    #[arg(allow_negative_numbers = true)]
+    dy: f64,
+  },
+  /// Focus a view and press keys on it in order, given by their keysym names with modifiers,
+  /// like `ctrl+a`, `shift+Tab` or `Return`
+  Key {
+    view: ViewRef,
+    #[arg(required = true)]
+    keys: Vec<String>,
+  },
+  /// Focus a view and type text on it key by key
+  Type { view: ViewRef, text: String },
+  /// Show or hide an overlay with the frame rate, frame times, tasks waiting for the platform
+  /// thread and memory of pooled backing stores
+  Hud,
+  /// Render every view at a fraction of its surface size, upscaled by the compositor, to save
+  /// power e.g. on battery
+  LowPower {
+    #[arg(value_enum, default_value_t = Switch::Toggle)]
+    switch: Switch,
+  },
+}
+
+/// Run an app
+#[derive(Debug, clap::Args)]
+pub struct RunArgs {
+  /// Path to the flutter_assets directory.
+  ///
+  /// Defaults to data/flutter_assets next to the executable, as in the bundle of `flutter build
+  /// linux`.
+  pub asset_path: Option<PathBuf>,
+
+  /// Path to icudtl.dat.
+  ///
+  /// Defaults to the one in the bundle, in $FLUTTER_ROOT or in /usr/share/flutter.
+  pub icu_data_path: Option<PathBuf>,
+
+  /// Load the engine from this libflutter_engine.so.
+  ///
+  /// Defaults to $WAYFLUTTER_ENGINE_LIBRARY, or else lib/libflutter_engine.so next to the
+  /// executable, or else the one the dynamic linker finds.
+  #[arg(long, value_name = "PATH")]
+  pub engine_library: Option<PathBuf>,
+
+  /// Run the AOT compiled app in this libapp.so, for release and profile builds. The engine
+  /// must be built in the same mode.
+  ///
+  /// Defaults to the lib/libapp.so of the bundle with such an engine.
+  #[arg(long, value_name = "PATH")]
+  pub aot_library: Option<PathBuf>,
+
+  /// Render into multisampled framebuffers with N samples per pixel
+  #[arg(long, value_name = "N")]
+  pub msaa: Option<NonZero<u32>>,
+
+  /// Use an sRGB window surface and blend in linear space
+  #[arg(long)]
+  pub srgb: bool,
+
+  /// Use a window surface with 10 bits per color channel if the EGL implementation supports it
+  #[arg(long)]
+  pub deep_color: bool,
+
+  /// Synchronize buffers with the compositor explicitly (linux-drm-syncobj-v1).
+  ///
+  /// Only for EGL implementations that don't use the protocol on their own.
+  #[arg(long)]
+  pub explicit_sync: bool,
+
+  /// Render into dmabufs and attach them to surfaces directly when possible, skipping a
+  /// full-frame copy.
+  #[arg(long)]
+  pub zero_copy: bool,
+
+  /// Render on the GPU with this DRM device node, e.g. /dev/dri/renderD129.
+  ///
+  /// Defaults to the GPU the compositor runs on.
+  #[arg(long, value_name = "PATH")]
+  pub gpu: Option<PathBuf>,
+
+  /// Render with Impeller instead of Skia. Requires OpenGL ES 3.
+  #[arg(long)]
+  pub impeller: bool,
+
+  /// Tint the regions repainted in each frame, to check that the app repaints only what
+  /// changed.
+  #[arg(long)]
+  pub debug_damage: bool,
+
+  /// Rasterize on the CPU into shared memory instead of on the GPU, for debugging. Slow, and
+  /// not supported with Impeller.
+  #[arg(long)]
+  pub software: bool,
+
+  /// Give the engine textures to render into instead of framebuffers, for engine builds that
+  /// need them. Turns off MSAA.
+  #[arg(long)]
+  pub texture_targets: bool,
+
+  /// Log the errors and warnings the GL driver reports, and check the framebuffers of backing
+  /// stores, instead of failures only showing as black surfaces
+  #[arg(long)]
+  pub gl_debug: bool,
+
+  /// Pass a switch to the engine, e.g. --engine-arg=--trace-skia or
+  /// --engine-arg=--dart-flags=--verbose-gc. Can be repeated.
+  #[arg(long = "engine-arg", value_name = "SWITCH", allow_hyphen_values = true)]
+  pub engine_args: Vec<String>,
+
+  /// Run a single instance, owning this D-Bus name. Launching it again opens --route in the
+  /// running instance.
+  #[arg(long, value_name = "ID")]
+  pub app_id: Option<String>,
+
+  /// Start the app on this route
+  #[arg(long)]
+  pub route: Option<String>,
+
+  /// Start the engine over whenever the app is rebuilt, recreating its surfaces
+  #[arg(long)]
+  pub hot_restart: bool,
+
+  /// Run integration tests: exit with their result, and print the VM service URI once the
+  /// flutter_driver extension is registered
+  #[arg(long, conflicts_with = "disable_vm_service")]
+  pub test: bool,
+
+  /// Start the engine over once the compositor is back after losing the connection to it,
+  /// instead of exiting with status 75
+  #[arg(long)]
+  pub reconnect: bool,
+
+  /// Let the app set the color temperature and gamma of outputs, for a night light
+  #[arg(long)]
+  pub gamma_control: bool,
+
+  /// Run Dart on the platform thread instead of a separate UI thread
+  #[arg(long)]
+  pub merged_ui_thread: bool,
+
+  /// Leave out what the app prints from the log, but for uncaught exceptions
+  #[arg(long)]
+  pub quiet_dart: bool,
+
+  /// Start the engine over after a fatal error, up to N times in a row
+  #[arg(long, value_name = "N", default_value_t = 0)]
+  pub crash_restarts: u32,
+
+  /// Record embedder spans and frame timings into a Chrome/Perfetto JSON trace, written on exit
+  #[arg(long, value_name = "PATH")]
+  pub trace_output: Option<PathBuf>,
+
+  /// Append the exceptions the app doesn't handle to this file
+  #[arg(long, value_name = "PATH")]
+  pub exception_file: Option<PathBuf>,
+
+  /// Report the engine as stuck once it hasn't answered a vsync, or its tasks piled up, for
+  /// this long. 0 disables the watchdog.
+  #[arg(long, value_name = "SECONDS", default_value_t = WatchdogOptions::default().timeout)]
+  pub watchdog_timeout: u64,
+
+  /// Exit with an error when the watchdog finds the engine stuck
+  #[arg(long)]
+  pub watchdog_terminate: bool,
+
+  /// Don't start the Dart VM service
+  #[arg(long, conflicts_with_all = ["vm_service_host", "vm_service_port"])]
+  pub disable_vm_service: bool,
+
+  /// Bind the Dart VM service to this address instead of localhost
+  #[arg(long, value_name = "HOST")]
+  pub vm_service_host: Option<String>,
+
+  /// Bind the Dart VM service to this port instead of a free one
+  #[arg(long, value_name = "PORT")]
+  pub vm_service_port: Option<u16>,
+
+  /// Write the URI of the Dart VM service to this file once it listens, for DevTools and
+  /// `flutter attach --debug-url`
+  #[arg(long, value_name = "PATH")]
+  pub vm_service_uri_file: Option<PathBuf>,
+
+  /// Cap the old generation of the Dart heap to this many MiB
+  #[arg(long, value_name = "MIB")]
+  pub old_gen_heap_size: Option<u32>,
+
+  /// Collect Dart garbage on the thread running Dart instead of helper threads
+  #[arg(long)]
+  pub serial_gc: bool,
+
+  /// The app never draws translucent pixels. Lets the compositor skip blending behind it.
+  #[arg(long)]
+  pub opaque: bool,
+
+  /// Present without waiting for vsync if the compositor allows it. May tear.
+  #[arg(long)]
+  pub allow_tearing: bool,
+
+  /// Show the app on every output, with a view per output.
+  ///
+  /// Dart can tell the views apart by the output name from the `getViewOutput` method of the
+  /// `wayflutter/view` channel.
+  #[arg(long, conflicts_with = "output")]
+  pub every_output: bool,
+
+  /// Device pixel ratio to lay out with instead of the scale of the output
+  #[arg(long, value_name = "RATIO")]
+  pub pixel_ratio: Option<f64>,
+
+  /// Start in low-power mode, rendering at a fraction of the surface size that the compositor
+  /// upscales. Toggled at runtime with `wayflutter low-power`.
+  #[arg(long)]
+  pub low_power: bool,
+
+  /// Fraction of the surface size to render at in low-power mode [default: 0.5]
+  #[arg(long, value_name = "FRACTION")]
+  pub low_power_resolution: Option<f64>,
+
+  /// Start from a preset placement of the surface instead of the other placement options
+  #[arg(
+    long,
+    value_enum,
+    conflicts_with_all = ["layer", "anchor", "size", "exclusive_zone", "keyboard_interactivity"],
+  )]
+  pub mode: Option<Mode>,
+
+  /// Opacity of the black drawn behind the app, from 0 to 1
+  #[arg(long, value_name = "OPACITY", default_value_t = 0.0)]
+  pub dim: f32,
+
+  /// Layer of the surface
+  #[arg(long, value_enum, default_value_t = Layer::Background)]
+  pub layer: Layer,
+
+  /// Edges to anchor the surface to, comma-separated
+  #[arg(
+    long,
+    value_enum,
+    value_delimiter = ',',
+    default_values_t = [Edge::Top, Edge::Bottom, Edge::Left, Edge::Right],
+  )]
+  pub anchor: Vec<Edge>,
+
+  /// Size in logical pixels. 0 stretches the surface between opposite anchors.
+  #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_size, default_value = "0x0")]
+  pub size: (u32, u32),
+
+  /// Margins in logical pixels, either one for all edges or TOP,RIGHT,BOTTOM,LEFT
+  #[arg(long, value_parser = parse_margin, default_value = "0", allow_negative_numbers = true)]
+  pub margin: [i32; 4],
+
+  /// Space reserved along the anchored edge in logical pixels. -1 overlaps the zones of other
+  /// surfaces, auto reserves the size of the surface.
+  #[arg(
+    long,
+    value_name = "PIXELS",
+    default_value = "0",
+    allow_negative_numbers = true
+  )]
+  pub exclusive_zone: ExclusiveZone,
+
+  /// When the surface gets keyboard focus
+  #[arg(long, value_enum, default_value_t = KeyboardInteractivity::OnDemand)]
+  pub keyboard_interactivity: KeyboardInteractivity,
+
+  /// Output to show the surface on, by connector name like DP-1 or part of its description
+  /// like "Dell". Defaults to the compositor's choice.
+  #[arg(long, value_name = "NAME")]
+  pub output: Option<String>,
+
+  /// Layer-shell namespace, which compositors match window rules against
+  #[arg(long, default_value = "wayflutter")]
+  pub namespace: String,
+
+  /// Name of the surface for the app and for commands like `wayflutter hide NAME`
+  #[arg(long)]
+  pub name: Option<String>,
+
+  /// Let pointer and touch input through to the surfaces below
+  #[arg(long)]
+  pub click_through: bool,
+
+  /// Slide the surface out along its anchored edge while the pointer is away, leaving a strip
+  /// at the edge of the screen to point at
+  #[arg(long, conflicts_with = "click_through")]
+  pub auto_hide: bool,
+
+  /// Hide the surface like a menu on Escape and when it loses the keyboard focus, e.g. to a
+  /// click elsewhere
+  #[arg(long)]
+  pub popup: bool,
+
+  /// Draw at most this many frames per second
+  #[arg(long, value_name = "FPS")]
+  pub max_fps: Option<NonZero<u32>>,
+
+  /// What the surface shows, for the compositor to pick scaling and latency policies
+  #[arg(long, value_enum, default_value_t = ContentType::None)]
+  pub content_type: ContentType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+  /// Cover the whole output above everything else and take the keyboard, for launchers,
+  /// session menus and dialogs. The app dismisses itself with the `dismissView` method of the
+  /// `wayflutter/view` channel.
+  Overlay,
+  /// Cover the whole background of every output, or of --output, without taking any input.
+  /// Rendering stops while the wallpaper is covered and whenever nothing animates.
+  Wallpaper,
+}
+
+impl RunArgs {
+  /// The defaults of every option, as if run without any
+  pub fn defaults() -> Self {
+    let command = <Self as clap::Args>::augment_args(clap::Command::new("wayflutter"));
+    Self::from_arg_matches(&command.get_matches_from(["wayflutter"]))
+      .expect("the defaults are valid")
+  }
+
+  pub fn config(&self) -> Result<Config> {
+    let asset_path = self
+      .asset_path
+      .clone()
+      .or_else(discover::asset_path)
+      .context("no ASSET_PATH given and no data/flutter_assets next to the executable")?;
+    let icu_data_path = self
+      .icu_data_path
+      .clone()
+      .or_else(|| discover::icu_data_path(&asset_path))
+      .context("no ICU_DATA_PATH given and no icudtl.dat found")?;
+    Ok(Config {
+      asset_path,
+      icu_data_path,
+      engine_library: self.engine_library.clone(),
+      aot_library: self.aot_library.clone(),
+      render: self.render_options(),
+      app_id: self.app_id.clone(),
+      route: self.route.clone(),
+      engine_args: self.engine_args.clone(),
+      hot_restart: self.hot_restart,
+      test: self.test,
+      reconnect: self.reconnect,
+      gamma_control: self.gamma_control,
+      merged_ui_thread: self.merged_ui_thread,
+      quiet_dart: self.quiet_dart,
+      crash_restart: CrashRestartOptions {
+        max_attempts: self.crash_restarts,
+        ..Default::default()
+      },
+      trace_output: self.trace_output.clone(),
+      watchdog: WatchdogOptions {
+        timeout: self.watchdog_timeout,
+        terminate: self.watchdog_terminate,
+        ..Default::default()
+      },
+      errors: ErrorPolicies::default(),
+      exceptions: ExceptionOptions {
+        file: self.exception_file.clone(),
+      },
+      vm_service: VmServiceOptions {
+        disable: self.disable_vm_service,
+        host: self.vm_service_host.clone(),
+        port: self.vm_service_port,
+        uri_file: self.vm_service_uri_file.clone(),
+      },
+      dart_vm: DartVmOptions {
+        old_gen_heap_size: self.old_gen_heap_size,
+        serial_gc: self.serial_gc,
+        ..Default::default()
+      },
+      opaque: self.opaque,
+      allow_tearing: self.allow_tearing,
+      every_output: self.every_output
+        || (self.mode == Some(Mode::Wallpaper) && self.output.is_none()),
+      pixel_ratio: self.pixel_ratio,
+      low_power: self.low_power,
+      low_power_resolution: self.low_power_resolution,
+      surfaces: vec![self.layer_props()],
+      path: None,
+    })
+  }
+
+  fn layer_props(&self) -> LayerProps {
+    let placement = match self.mode {
+      Some(Mode::Overlay) => LayerProps::overlay(),
+      Some(Mode::Wallpaper) => LayerProps::wallpaper(),
+      None => {
+        let (width, height) = self.size;
+        LayerProps {
+          layer: self.layer,
+          anchor: self.anchor.clone(),
+          width,
+          height,
+          exclusive_zone: self.exclusive_zone,
+          keyboard_interactivity: self.keyboard_interactivity,
+          ..Default::default()
+        }
+      }
+    };
+    LayerProps {
+      margin: self.margin,
+      namespace: self.namespace.clone(),
+      name: self.name.clone(),
+      output: self.output.clone(),
+      dim: self.dim,
+      click_through: self.click_through || placement.click_through,
+      auto_hide: self.auto_hide.then(AutoHide::default),
+      popup: self.popup,
+      max_fps: self.max_fps,
+      content_type: self.content_type,
+      ..placement
+    }
+  }
+
+  fn render_options(&self) -> RenderOptions {
+    RenderOptions {
+      msaa_samples: self.msaa,
+      srgb: self.srgb,
+      deep_color: self.deep_color,
+      explicit_sync: self.explicit_sync,
+      zero_copy: self.zero_copy,
+      gpu: self.gpu.clone(),
+      impeller: self.impeller,
+      debug_damage: self.debug_damage,
+      software: self.software,
+      texture_targets: self.texture_targets,
+      gl_debug: self.gl_debug,
+    }
+  }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+  let (width, height) = s
+    .split_once('x')
+    .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {:?}", s))?;
+  let parse = |n: &str| n.parse::<u32>().map_err(|e| format!("{:?}: {}", n, e));
+  Ok((parse(width)?, parse(height)?))
+}
+
+fn parse_margin(s: &str) -> Result<[i32; 4], String> {
+  let margins = s
+    .split(',')
+    .map(|n| {
+      n.trim()
+        .parse::<i32>()
+        .map_err(|e| format!("{:?}: {}", n, e))
+    })
+    .collect::<Result<Vec<_>, _>>()?;
+  match margins[..] {
+    [all] => Ok([all; 4]),
+    [top, right, bottom, left] => Ok([top, right, bottom, left]),
+    _ => Err(format!("expected 1 or 4 margins, got {}", margins.len())),
+  }
+}
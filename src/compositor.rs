@@ -1,33 +1,94 @@
 use std::collections::HashMap;
 use std::num::NonZero;
 use std::ptr::NonNull;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
 
 use anyhow::Context;
 use anyhow::Result;
+use egl::surface::Surface;
+use futures::channel::oneshot;
 use glutin::api::egl;
 use glutin::prelude::GlDisplay;
+use glutin::prelude::GlSurface;
 use glutin::surface::SurfaceAttributesBuilder;
 use glutin::surface::WindowSurface;
 use parking_lot::Mutex;
+use parking_lot::RwLock;
 use raw_window_handle::RawWindowHandle;
 use raw_window_handle::WaylandWindowHandle;
-use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::Layer;
+use serde::Deserialize;
+use smithay_client_toolkit::compositor::CompositorState;
+use smithay_client_toolkit::compositor::Region;
 use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1;
-use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::Anchor;
-use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::KeyboardInteractivity;
+use smithay_client_toolkit::registry::SimpleGlobal;
 use wayland_client::Proxy;
+use wayland_client::protocol::wl_compositor::WlCompositor;
+use wayland_client::protocol::wl_output::Transform;
+use wayland_client::protocol::wl_output::WlOutput;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::protocol::wl_surface::WlSurface;
 
+use crate::EngineShared;
+use crate::FlutterEngine;
+use crate::callback::catch_panic;
+use crate::compositor::animation::Animation;
+use crate::compositor::animation::Curve;
+use crate::compositor::animation::Placement;
+use crate::compositor::auto_hide::AutoHideState;
+use crate::compositor::backing_store::BackingStorePool;
+use crate::compositor::capture::CaptureReceiver;
+use crate::compositor::capture::DumpReceiver;
+use crate::compositor::capture::FrameDump;
+use crate::compositor::capture::Image;
+use crate::compositor::layer::ContentType;
+use crate::compositor::layer::ExclusiveZone;
+use crate::compositor::layer::LayerProps;
 use crate::error::FFIFlutterEngineResultExt;
+use crate::error_in_callback;
+use crate::ffi;
 use crate::opengl::OpenGLState;
+use crate::trace_span;
+use crate::wayland::Output;
 use crate::wayland::WaylandClient;
-use crate::wayland::layer_shell::CreateLayerSurfaceProp;
+use crate::wayland::content_type::ContentTypeManager;
+use crate::wayland::content_type::SurfaceContentType;
+use crate::wayland::dmabuf::LinuxDmabuf;
+use crate::wayland::explicit_sync::ExplicitSync;
+use crate::wayland::explicit_sync::SurfaceSync;
+use crate::wayland::input_method::InputMethod;
+use crate::wayland::input_method::InputMethodManager;
+use crate::wayland::input_method::InputPopupSurface;
+use crate::wayland::layer_shell::LayerShell;
 use crate::wayland::layer_shell::LayerSurface;
-use crate::wayland::layer_shell::WaylandClientLayerSurfaceExt;
-use crate::error_in_callback;
-use crate::ffi;
-use egl::surface::Surface;
+use crate::wayland::shm::Shm;
+use crate::wayland::shortcuts_inhibit::ShortcutsInhibitManager;
+use crate::wayland::shortcuts_inhibit::ShortcutsInhibitor;
+use crate::wayland::tearing_control::TearingControl;
+use crate::wayland::tearing_control::TearingControlManager;
+use crate::wayland::viewporter::Viewport;
+use crate::wayland::viewporter::Viewporter;
 
+pub mod animation;
+pub mod auto_hide;
+pub mod backing_store;
 pub mod callback;
+pub mod capture;
+pub mod channel;
+pub mod hud;
+pub mod input_method;
+pub mod layer;
+pub mod popup;
+
+/// A view whose frame callback hasn't been answered for this long is considered invisible.
+const OCCLUSION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// see [`SurfaceOptions::low_power_resolution`]
+const DEFAULT_LOW_POWER_RESOLUTION: f64 = 0.5;
 
 #[derive(Debug, Clone, Copy)]
 pub struct ViewId {
@@ -64,144 +125,1957 @@ impl std::fmt::Display for ViewId {
   }
 }
 
+/// Options for the surfaces of all views
+#[derive(Debug, Clone, Default)]
+pub struct SurfaceOptions {
+  /// The app never draws translucent pixels, so whole surfaces are marked opaque.
+  pub opaque: bool,
+  /// Present asynchronously (possibly tearing) for lower latency, if the compositor allows it.
+  pub allow_tearing: bool,
+  /// Replicate the implicit view on every output, so each gets a surface of its own.
+  pub every_output: bool,
+  /// device pixel ratio reported to the engine instead of the buffer scale
+  pub pixel_ratio: Option<f64>,
+  /// Start in low-power mode, see [`Compositor::set_low_power`].
+  pub low_power: bool,
+  /// fraction of the surface size views are rendered at in low-power mode, 0.5 if `None`
+  pub low_power_resolution: Option<f64>,
+}
+
 pub struct Compositor {
-  views: HashMap<ViewId, FlutterView>,
+  views: RwLock<HashMap<ViewId, Arc<FlutterView>>>,
+  /// removed views whose surfaces are destroyed on the raster thread with the next frame
+  retired_views: Mutex<Vec<Arc<FlutterView>>>,
+  wl_compositor: SimpleGlobal<WlCompositor, { CompositorState::API_VERSION_MAX }>,
+  layer_shell: LayerShell,
+  explicit_sync: Option<ExplicitSync>,
+  tearing_control_manager: Option<TearingControlManager>,
+  content_type_manager: Option<ContentTypeManager>,
+  /// `None` if the compositor doesn't support viewporter, which low-power mode needs
+  viewporter: Option<Viewporter>,
+  input_method_manager: Option<InputMethodManager>,
+  shortcuts_inhibit_manager: Option<ShortcutsInhibitManager>,
+  /// the seat input popups are shown for and shortcuts are inhibited on
+  seat: Option<WlSeat>,
+  /// taken on the seat when the first input popup is created or Dart first listens to
+  /// [`input_method::CHANNEL`]
+  input_method: Mutex<Option<InputMethod>>,
+  /// connected outputs
+  outputs: Mutex<Vec<Output>>,
+  options: SurfaceOptions,
+  /// the view whose surface has the keyboard focus of the seat
+  keyboard_focus: Mutex<Option<ViewId>>,
+  /// whether Dart listens to the events of [`auto_hide::CHANNEL`]
+  auto_hide_listening: AtomicBool,
+  /// whether Dart listens to the events of [`popup::CHANNEL`]
+  popup_listening: AtomicBool,
+  /// whether Dart listens to the events of [`input_method::CHANNEL`]
+  input_method_listening: AtomicBool,
+  /// placement of the implicit view and its replicas on other outputs
+  view_props: Mutex<LayerProps>,
+  /// ids of views added at runtime. 0 is the implicit view.
+  next_view_id: AtomicI64,
+  /// see [`Compositor::set_low_power`]
+  low_power: AtomicBool,
+  pub backing_stores: BackingStorePool,
+  /// `Some` if backing stores are shared with the compositor as dmabufs
+  pub linux_dmabuf: Option<LinuxDmabuf>,
+  /// `Some` if backing stores are rasterized into shared memory, see
+  /// [`crate::opengl::RenderOptions::software`]
+  pub shm: Option<Shm>,
 }
 
 impl Compositor {
-  pub fn init(wayland_client: &WaylandClient<'_>, opengl_state: &OpenGLState) -> Result<Self> {
-    let mut map = HashMap::with_capacity(1);
-
-    // create implicit view
-    let layer_prop = CreateLayerSurfaceProp::builder()
-      .layer(Layer::Background)
-      .namespace("aaaaa")
-      .anchor(Anchor::Left | Anchor::Right | Anchor::Top | Anchor::Bottom)
-      .keyboard_interactivity(KeyboardInteractivity::OnDemand)
-      .user_data(ViewId::new(0))
-      .event_listener(|engine, event, id| {
-        let state = unsafe { engine.get_state() };
-        let result = || {
-          let this = state.compositor.get_view(*id).with_context(|| {
-            format!(
-              "Inconsistent: event from {}, which is not registered in the compositor",
-              id
-            )
-          })?;
-          let FlutterViewKind::LayerSurface(layer_surface) = &this.kind;
-
-          match event {
-            zwlr_layer_surface_v1::Event::Configure {
-              serial,
-              width,
-              height,
-            } => match (NonZero::new(width), NonZero::new(height)) {
-              (Some(width), Some(height)) => {
-                let event = ffi::FlutterWindowMetricsEvent {
-                  struct_size: size_of::<ffi::FlutterWindowMetricsEvent>(),
-                  width: width.get() as usize,
-                  height: height.get() as usize,
-                  pixel_ratio: 1.0,
-                  left: 0,
-                  top: 0,
-                  physical_view_inset_top: 0.0,
-                  physical_view_inset_right: 0.0,
-                  physical_view_inset_bottom: 0.0,
-                  physical_view_inset_left: 0.0,
-                  display_id: 0,
-                  view_id: id.raw(),
-                };
-                unsafe {
-                  ffi::FlutterEngineSendWindowMetricsEvent(engine.engine, &event)
-                    .into_flutter_engine_result()?;
-                }
-                layer_surface
-                  .layer_surface
-                  .wlr_layer_surface()
-                  .ack_configure(serial);
-                {
-                  let mut guard = this.size.lock();
-
-                  guard.0.width = width;
-                  guard.0.height = height;
-                  guard.1 = true;
-                }
-              }
-              _ => {}
-            },
-            _ => {}
-          }
+  pub fn init(
+    wayland_client: &WaylandClient<'_>,
+    opengl_state: &OpenGLState,
+    options: SurfaceOptions,
+    props: LayerProps,
+    extra_props: &[LayerProps],
+  ) -> Result<Self> {
+    let linux_dmabuf = match (
+      &opengl_state.dmabuf_allocator,
+      wayland_client.linux_dmabuf(),
+    ) {
+      (Some(_), None) => {
+        log::warn!("Zero-copy presenting disabled: linux-dmabuf-v1 is not supported");
+        None
+      }
+      (Some(_), linux_dmabuf) => linux_dmabuf,
+      (None, _) => None,
+    };
+    let shm = if opengl_state.options.software {
+      Some(wayland_client.shm().context("wl_shm is not supported")?)
+    } else {
+      None
+    };
 
-          anyhow::Ok(())
-        };
-        error_in_callback!(state, result(), return ());
-      })
-      .build();
-    let layer_surface = wayland_client.create_layer_surface(layer_prop)?;
-    let implicit_view = FlutterView {
-      view_id: ViewId::new(0),
-      kind: FlutterViewKind::LayerSurface(LayerSurfaceView::new(layer_surface, opengl_state)?),
-      size: Mutex::new((
-        NonZeroSize {
-          width: NonZero::new(1600).unwrap(),
-          height: NonZero::new(900).unwrap(),
+    let viewporter = wayland_client.viewporter();
+    let low_power = options.low_power && viewporter.is_some();
+    if options.low_power && !low_power {
+      log::warn!("Low-power mode disabled: viewporter is not supported");
+    }
+
+    let every_output = options.every_output;
+    let outputs = wayland_client.outputs();
+    let this = Self {
+      views: RwLock::new(HashMap::with_capacity(1)),
+      retired_views: Mutex::new(Vec::new()),
+      wl_compositor: SimpleGlobal::from_bound(wayland_client.wl_compositor()),
+      layer_shell: wayland_client.layer_shell(),
+      explicit_sync: wayland_client.explicit_sync(),
+      tearing_control_manager: wayland_client.tearing_control_manager(),
+      content_type_manager: wayland_client.content_type_manager(),
+      viewporter,
+      input_method_manager: wayland_client.input_method_manager(),
+      shortcuts_inhibit_manager: wayland_client.shortcuts_inhibit_manager(),
+      seat: wayland_client.seat(),
+      input_method: Mutex::new(None),
+      outputs: Mutex::new(outputs.clone()),
+      options,
+      keyboard_focus: Mutex::new(None),
+      auto_hide_listening: AtomicBool::new(false),
+      popup_listening: AtomicBool::new(false),
+      input_method_listening: AtomicBool::new(false),
+      view_props: Mutex::new(props.clone()),
+      next_view_id: AtomicI64::new(1),
+      low_power: AtomicBool::new(low_power),
+      backing_stores: BackingStorePool::default(),
+      linux_dmabuf,
+      shm,
+    };
+
+    let replicated_on = if every_output { &outputs[..] } else { &[] };
+    // the implicit view exists in the engine from the start. It takes the first output.
+    this.create_layer_view(
+      opengl_state,
+      ViewId::new(0),
+      &props,
+      replicated_on.first(),
+      Some(0),
+      true,
+    )?;
+    for output in replicated_on.iter().skip(1) {
+      let view_id = this.next_view_id();
+      this.create_layer_view(opengl_state, view_id, &props, Some(output), Some(0), false)?;
+      log::info!("Created {} for output {:?}", view_id, output.name);
+    }
+    for (i, props) in extra_props.iter().enumerate() {
+      let view_id = this.next_view_id();
+      this.create_layer_view(opengl_state, view_id, props, None, Some(i + 1), false)?;
+      log::info!("Created {}", view_id);
+    }
+
+    Ok(this)
+  }
+
+  /// Create a layer surface for a new view. The view is added to the engine once the
+  /// compositor configures its size.
+  ///
+  /// Must be called on the platform thread.
+  pub fn add_view(&self, engine: &FlutterEngine, props: &LayerProps) -> Result<ViewId> {
+    let state = engine.state();
+    let view_id = self.next_view_id();
+    self.create_layer_view(&state.opengl_state, view_id, props, None, None, false)?;
+    log::info!("Created {}", view_id);
+    Ok(view_id)
+  }
+
+  /// Create a view on an input method popup surface of `size` and add it to the engine. The
+  /// compositor shows it next to the focused text input while the input method is active,
+  /// which makes wayflutter the input method of the seat.
+  ///
+  /// Must be called on the platform thread.
+  pub fn add_input_popup_view(&self, engine: &FlutterEngine, size: NonZeroSize) -> Result<ViewId> {
+    let state = engine.state();
+    let popup_surface = self
+      .with_input_method(|input_method| input_method.create_popup_surface(&self.wl_compositor))?;
+
+    let view_id = self.next_view_id();
+    let view = self.create_view(
+      &state.opengl_state,
+      view_id,
+      SurfaceRole::InputPopup(popup_surface),
+      None,
+      false,
+    )?;
+    {
+      // popups aren't configured, their size is up to the client
+      let mut geometry = view.geometry.lock();
+      geometry.pending = Some(PendingGeometry {
+        geometry: SurfaceGeometry {
+          logical_size: size,
+          scale: geometry.scale,
+          resolution: geometry.resolution,
         },
-        false,
-      )),
+        configure_serial: None,
+      });
+    }
+    if let Err(e) = view.add_to_engine(engine) {
+      self.unregister_view(view_id);
+      return Err(e);
+    }
+    log::info!("Created {} as an input popup", view_id);
+    Ok(view_id)
+  }
+
+  /// Run `f` with the input method of the seat, which becomes ours if it isn't yet.
+  pub fn with_input_method<T>(&self, f: impl FnOnce(&InputMethod) -> Result<T>) -> Result<T> {
+    let mut input_method = self.input_method.lock();
+    let input_method = match &mut *input_method {
+      Some(input_method) => input_method,
+      None => {
+        let manager = self
+          .input_method_manager
+          .as_ref()
+          .context("input-method-unstable-v2 is not supported by the compositor")?;
+        let seat = self.seat.as_ref().context("no seat to take input for")?;
+        input_method.insert(manager.get_input_method(seat))
+      }
+    };
+    f(input_method)
+  }
+
+  /// Replicate the implicit view on an output connected at runtime if `--every-output` is set.
+  ///
+  /// Must be called on the platform thread.
+  pub fn output_added(&self, engine: &FlutterEngine, output: &Output) -> Result<()> {
+    self.outputs.lock().push(output.clone());
+    self.notify_displays(engine)?;
+    if !self.options.every_output {
+      return Ok(());
+    }
+    let state = engine.state();
+    let view_id = self.next_view_id();
+    let props = self.view_props.lock().clone();
+    self.create_layer_view(
+      &state.opengl_state,
+      view_id,
+      &props,
+      Some(output),
+      Some(0),
+      false,
+    )?;
+    log::info!("Created {} for output {:?}", view_id, output.name);
+    Ok(())
+  }
+
+  /// Remove the views created for a disconnected output.
+  ///
+  /// Must be called on the platform thread.
+  pub fn output_removed(&self, engine: &FlutterEngine, wl_output: &WlOutput) -> Result<()> {
+    if let Some(display_id) = self.display_id(wl_output) {
+      // the compositor may not send leave events for an output that is gone
+      for view in self.views() {
+        view.leave_display(engine, display_id)?;
+      }
+    }
+    self
+      .outputs
+      .lock()
+      .retain(|output| output.wl_output != *wl_output);
+    self.notify_displays(engine)?;
+    let view_ids = self
+      .views
+      .read()
+      .values()
+      .filter(|view| {
+        view
+          .output
+          .as_ref()
+          .is_some_and(|output| output.wl_output == *wl_output)
+      })
+      .map(|view| view.view_id)
+      .collect::<Vec<_>>();
+    for view_id in view_ids {
+      if view_id == ViewId::new(0) {
+        log::warn!("The output of the implicit view was disconnected");
+        continue;
+      }
+      log::info!("Removing {}, its output was disconnected", view_id);
+      self.remove_view(engine, view_id)?;
+    }
+    Ok(())
+  }
+
+  /// Pick up a changed mode or scale of a connected output.
+  pub fn output_updated(&self, engine: &FlutterEngine, output: &Output) -> Result<()> {
+    {
+      let mut outputs = self.outputs.lock();
+      let Some(known) = outputs
+        .iter_mut()
+        .find(|known| known.wl_output == output.wl_output)
+      else {
+        return Ok(());
+      };
+      *known = output.clone();
+    }
+    self.notify_displays(engine)
+  }
+
+  /// The connected outputs
+  pub fn outputs(&self) -> Vec<Output> {
+    self.outputs.lock().clone()
+  }
+
+  /// The id the engine knows `wl_output` by. `None` if it's not connected anymore.
+  pub fn display_id(&self, wl_output: &WlOutput) -> Option<u64> {
+    self
+      .outputs
+      .lock()
+      .iter()
+      .find(|output| output.wl_output == *wl_output)
+      .map(|output| output.display_id)
+  }
+
+  /// Tell the engine about the connected outputs, for the `Display`s of `dart:ui`.
+  pub fn notify_displays(&self, engine: &FlutterEngine) -> Result<()> {
+    if !ffi::features().display_updates {
+      return Ok(());
+    }
+    let outputs = self.outputs.lock();
+    let displays = outputs
+      .iter()
+      .map(|output| ffi::FlutterEngineDisplay {
+        struct_size: size_of::<ffi::FlutterEngineDisplay>(),
+        display_id: output.display_id,
+        single_display: outputs.len() == 1,
+        refresh_rate: output.refresh_rate,
+        width: output.size.0 as usize,
+        height: output.size.1 as usize,
+        device_pixel_ratio: output.scale as f64,
+      })
+      .collect::<Vec<_>>();
+    unsafe {
+      // the only update type, which replaces the displays whenever they change
+      ffi::FlutterEngineNotifyDisplayUpdate(
+        engine.engine,
+        ffi::FlutterEngineDisplaysUpdateType_kFlutterEngineDisplaysUpdateTypeStartup,
+        displays.as_ptr(),
+        displays.len(),
+      )
+      .into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  /// Remove a view from the engine, then destroy its surfaces. The implicit view can't be
+  /// removed.
+  ///
+  /// Must be called on the platform thread.
+  pub fn remove_view(&self, engine: &FlutterEngine, view_id: ViewId) -> Result<()> {
+    if view_id == ViewId::new(0) {
+      anyhow::bail!("the implicit view can't be removed");
+    }
+    let view = self
+      .get_view(view_id)
+      .with_context(|| format!("{} not found", view_id))?;
+    if view.removing.swap(true, Ordering::Relaxed) {
+      // e.g. both its layer surface and its output are gone
+      return Ok(());
+    }
+    if !view.added_to_engine.load(Ordering::Relaxed) {
+      // not configured yet, so the engine doesn't know it
+      self.unregister_view(view_id);
+      return Ok(());
+    }
+
+    let user_data = Box::new(ViewChangeUserData {
+      shared: engine.shared.clone(),
+      view_id,
+    });
+    let info = ffi::FlutterRemoveViewInfo {
+      struct_size: size_of::<ffi::FlutterRemoveViewInfo>(),
+      view_id: view_id.raw(),
+      user_data: Box::into_raw(user_data) as _,
+      remove_view_callback: Some(remove_view_callback),
+    };
+    unsafe {
+      if let Err(e) =
+        ffi::FlutterEngineRemoveView(engine.engine, &info).into_flutter_engine_result()
+      {
+        drop(Box::from_raw(info.user_data as *mut ViewChangeUserData));
+        return Err(e.into());
+      }
+    }
+    Ok(())
+  }
+
+  /// Forget a view the engine doesn't render anymore. Its surfaces are destroyed by
+  /// [`Compositor::collect_retired_views`].
+  fn unregister_view(&self, view_id: ViewId) {
+    let mut keyboard_focus = self.keyboard_focus.lock();
+    if *keyboard_focus == Some(view_id) {
+      *keyboard_focus = None;
+    }
+    if let Some(view) = self.views.write().remove(&view_id) {
+      self.retired_views.lock().push(view);
+    }
+  }
+
+  /// Destroy the surfaces of removed views.
+  ///
+  /// Must be called on the raster thread, where the render context may still be bound to them.
+  pub fn collect_retired_views(&self, opengl_state: &OpenGLState) -> Result<()> {
+    let retired = std::mem::take(&mut *self.retired_views.lock());
+    for view in retired {
+      let FlutterViewKind::Surface(surface_view) = &view.kind;
+      if let Some(egl_surface) = &*surface_view.egl_surface.lock() {
+        opengl_state.release_surface(egl_surface)?;
+      }
+      log::info!("Destroyed the surfaces of {}", view.view_id);
+    }
+    Ok(())
+  }
+
+  /// The output with the connector name `name`, or else the only one whose description
+  /// contains it
+  pub fn find_output(&self, name: &str) -> Result<Output> {
+    let outputs = self.outputs.lock();
+    if let Some(output) = outputs
+      .iter()
+      .find(|output| output.name.as_deref() == Some(name))
+    {
+      return Ok(output.clone());
+    }
+    let matching = outputs
+      .iter()
+      .filter(|output| output.description_contains(name))
+      .collect::<Vec<_>>();
+    match matching.as_slice() {
+      [output] => Ok((*output).clone()),
+      _ => {
+        let connected = outputs
+          .iter()
+          .map(|output| match (&output.name, &output.description) {
+            (Some(name), Some(description)) => format!("{} ({})", name, description),
+            (Some(name), None) => name.clone(),
+            (None, Some(description)) => description.clone(),
+            (None, None) => "unnamed".to_owned(),
+          })
+          .collect::<Vec<_>>();
+        if matching.is_empty() {
+          anyhow::bail!(
+            "no output named {:?}. Connected: {}",
+            name,
+            connected.join(", ")
+          )
+        } else {
+          anyhow::bail!(
+            "{:?} matches the description of more than one output. Connected: {}",
+            name,
+            connected.join(", ")
+          )
+        }
+      }
+    }
+  }
+
+  fn next_view_id(&self) -> ViewId {
+    ViewId::new(self.next_view_id.fetch_add(1, Ordering::Relaxed))
+  }
+
+  /// `output` overrides [`LayerProps::output`]. `config_index` is the `[[surface]]` of the
+  /// config the view is created for.
+  fn create_layer_view(
+    &self,
+    opengl_state: &OpenGLState,
+    view_id: ViewId,
+    props: &LayerProps,
+    output: Option<&Output>,
+    config_index: Option<usize>,
+    added_to_engine: bool,
+  ) -> Result<Arc<FlutterView>> {
+    props.validate()?;
+    let named_output;
+    let output = match (output, &props.output) {
+      (Some(output), _) => Some(output),
+      (None, Some(name)) => {
+        named_output = self.find_output(name)?;
+        Some(&named_output)
+      }
+      (None, None) => None,
+    };
+    let layer_surface = self.layer_shell.create_layer_surface(
+      &self.wl_compositor,
+      props.to_prop(
+        view_id,
+        output.map(|output| output.wl_output.clone()),
+        handle_layer_surface_event,
+      ),
+    )?;
+    let view = self.create_view(
+      opengl_state,
+      view_id,
+      SurfaceRole::Layer {
+        surface: layer_surface,
+        props: Mutex::new(props.clone()),
+        animation: Mutex::new(None),
+        config_index,
+      },
+      output.cloned(),
+      added_to_engine,
+    )?;
+    self.apply_input_region(view.wl_surface(), props.click_through)?;
+    if props.content_type != ContentType::None
+      && let Err(e) = view.set_content_type(props.content_type)
+    {
+      log::warn!("Content type hint dropped: {:#}", e);
+    }
+    Ok(view)
+  }
+
+  fn create_view(
+    &self,
+    opengl_state: &OpenGLState,
+    view_id: ViewId,
+    role: SurfaceRole,
+    output: Option<Output>,
+    added_to_engine: bool,
+  ) -> Result<Arc<FlutterView>> {
+    let tearing_control = self
+      .tearing_control_manager
+      .as_ref()
+      .map(|manager| manager.get_tearing_control(role.wl_surface()));
+    let content_type = self
+      .content_type_manager
+      .as_ref()
+      .map(|manager| manager.get_surface_content_type(role.wl_surface()));
+    let viewport = self
+      .viewporter
+      .as_ref()
+      .map(|viewporter| viewporter.get_viewport(role.wl_surface()));
+    let view = Arc::new(FlutterView {
+      view_id,
+      kind: FlutterViewKind::Surface(SurfaceView::new(
+        role,
+        opengl_state,
+        self.explicit_sync.as_ref(),
+      )?),
+      geometry: Mutex::new(ViewGeometry {
+        current: None,
+        pending: None,
+        scale: NonZero::new(1).unwrap(),
+        resolution: self.resolution(),
+      }),
+      output,
+      added_to_engine: AtomicBool::new(added_to_engine),
+      closed: AtomicBool::new(false),
+      removing: AtomicBool::new(false),
+      visibility: Mutex::new(Visibility::Shown),
+      frame_callback_requested_at: Mutex::new(None),
+      opaque: self.options.opaque,
+      pixel_ratio: self.options.pixel_ratio,
+      opaque_region: Mutex::new(OpaqueRegion::default_for(self.options.opaque)),
+      tearing_control,
+      allow_tearing: AtomicBool::new(false),
+      content_type,
+      shortcuts_inhibitor: Mutex::new(None),
+      viewport,
+      capture_requests: Mutex::new(Vec::new()),
+      dump_requests: Mutex::new(Vec::new()),
+      auto_hide: Mutex::new(AutoHideState::default()),
+      displays: Mutex::new(Vec::new()),
+    });
+    if self.options.allow_tearing
+      && let Err(e) = view.set_allow_tearing(true)
+    {
+      log::warn!("Tearing disabled: {:#}", e);
+    }
+    self.views.write().insert(view_id, view.clone());
+    Ok(view)
+  }
+
+  pub fn get_view(&self, view_id: ViewId) -> Option<Arc<FlutterView>> {
+    self.views.read().get(&view_id).cloned()
+  }
+
+  /// The view with the lowest id whose surface is named `name`
+  pub fn find_view_by_name(&self, name: &str) -> Option<ViewId> {
+    self
+      .views()
+      .into_iter()
+      .find(|view| {
+        view
+          .layer_props()
+          .is_some_and(|props| props.name.as_deref() == Some(name))
+      })
+      .map(|view| view.view_id)
+  }
+
+  /// All views, ordered by id
+  pub fn views(&self) -> Vec<Arc<FlutterView>> {
+    let mut views = self.views.read().values().cloned().collect::<Vec<_>>();
+    views.sort_by_key(|view| view.view_id.raw());
+    views
+  }
+
+  /// Change the placement of a layer view. Takes effect with the next commit, after which the
+  /// compositor configures the new size.
+  ///
+  /// The namespace and output are fixed once the surface exists.
+  ///
+  /// Must be called on the platform thread.
+  pub fn set_layer_props(
+    &self,
+    engine: &FlutterEngine,
+    view_id: ViewId,
+    props: &LayerProps,
+  ) -> Result<()> {
+    props.validate()?;
+    let view = self
+      .get_view(view_id)
+      .with_context(|| format!("{} not found", view_id))?;
+    let FlutterViewKind::Surface(surface_view) = &view.kind;
+    let SurfaceRole::Layer {
+      surface,
+      props: current,
+      animation,
+      ..
+    } = &surface_view.role
+    else {
+      anyhow::bail!("{} is not a layer surface", view_id);
+    };
+    *animation.lock() = None;
+    {
+      let mut current = current.lock();
+      if props.namespace != current.namespace || props.output != current.output {
+        anyhow::bail!(
+          "the namespace and output of {} can't change while it exists",
+          view_id
+        );
+      }
+      let layer_surface = surface.wlr_layer_surface();
+      if props.layer != current.layer && layer_surface.version() < 2 {
+        anyhow::bail!("the compositor can't move surfaces between layers");
+      }
+      if props.content_type != current.content_type {
+        view.set_content_type(props.content_type)?;
+      }
+      let configured_size = view
+        .geometry
+        .lock()
+        .target()
+        .map(|target| target.logical_size);
+      props.apply(layer_surface, configured_size);
+      if props.click_through != current.click_through {
+        self.apply_input_region(surface.wl_surface(), props.click_through)?;
+      }
+      *current = props.clone();
+    }
+    auto_hide::reset(engine, &view)?;
+    // double-buffered, commit them with the next frame
+    engine.schedule_frame()?;
+    Ok(())
+  }
+
+  /// Tween the margin and exclusive zone of a layer view to `margin` and `exclusive_zone`
+  /// over `duration`, from where a running animation is. The frames are driven by the
+  /// embedder rather than by Dart.
+  pub fn animate_view(
+    &self,
+    engine: &FlutterEngine,
+    view_id: ViewId,
+    margin: [i32; 4],
+    exclusive_zone: ExclusiveZone,
+    duration: Duration,
+    curve: Curve,
+  ) -> Result<()> {
+    let view = self
+      .get_view(view_id)
+      .with_context(|| format!("{} not found", view_id))?;
+    self.animate(engine, &view, duration, curve, |props| {
+      props.margin = margin;
+      props.exclusive_zone = exclusive_zone;
+    })
+  }
+
+  /// Slide an auto-hiding view in or out, as [`FlutterView::auto_hide`] says. Returns how long
+  /// it takes.
+  pub fn slide_view(&self, engine: &FlutterEngine, view: &FlutterView) -> Result<Duration> {
+    let auto_hide = view
+      .layer_props()
+      .and_then(|props| props.auto_hide)
+      .with_context(|| format!("{} doesn't auto-hide", view.view_id))?;
+    let duration = Duration::from_millis(auto_hide.duration);
+    self.animate(engine, view, duration, Curve::EaseInOut, |_| {})?;
+    Ok(duration)
+  }
+
+  /// Tween a layer view from where it is to where it rests after `update` changed its props,
+  /// hidden or not.
+  fn animate(
+    &self,
+    engine: &FlutterEngine,
+    view: &FlutterView,
+    duration: Duration,
+    curve: Curve,
+    update: impl FnOnce(&mut LayerProps),
+  ) -> Result<()> {
+    let FlutterViewKind::Surface(surface_view) = &view.kind;
+    let SurfaceRole::Layer {
+      props, animation, ..
+    } = &surface_view.role
+    else {
+      anyhow::bail!("{} is not a layer surface", view.view_id);
+    };
+    let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
+    let configured_size = view
+      .geometry
+      .lock()
+      .target()
+      .map(|target| target.logical_size);
+    {
+      let mut props = props.lock();
+      let mut animation = animation.lock();
+      let from = match &*animation {
+        Some(running) => running.sample(now).0,
+        None => view.resting_placement(&props, configured_size),
+      };
+      update(&mut props);
+      let to = view.resting_placement(&props, configured_size);
+      *animation = Some(Animation::new(from, to, now, duration, curve));
+    }
+    engine.resume_rendering()?;
+    Ok(())
+  }
+
+  /// Apply the animations at engine time `frame_time`, the target time of the next frame.
+  /// Returns whether any are still running, which needs another frame.
+  pub fn step_animations(&self, frame_time: u64) -> bool {
+    let mut running = false;
+    for view in self.views.read().values() {
+      let FlutterViewKind::Surface(surface_view) = &view.kind;
+      let SurfaceRole::Layer {
+        surface, animation, ..
+      } = &surface_view.role
+      else {
+        continue;
+      };
+      let mut animation = animation.lock();
+      let Some(current) = &*animation else {
+        continue;
+      };
+      let (placement, done) = current.sample(frame_time);
+      // committed with the frame
+      placement.apply(surface.wlr_layer_surface());
+      if done {
+        *animation = None;
+      } else {
+        running = true;
+      }
+    }
+    running
+  }
+
+  /// Apply the `[[surface]]`s of a reloaded config to the views created from it.
+  ///
+  /// Views of surfaces that were added or removed are added or removed, as are views whose
+  /// namespace or output changed. The implicit view can't be recreated that way.
+  ///
+  /// Must be called on the platform thread.
+  pub fn reconfigure(&self, engine: &FlutterEngine, surfaces: &[LayerProps]) -> Result<()> {
+    let default_surfaces;
+    let surfaces = if surfaces.is_empty() {
+      default_surfaces = [LayerProps::default()];
+      &default_surfaces[..]
+    } else {
+      surfaces
+    };
+    for (i, props) in surfaces.iter().enumerate() {
+      props
+        .validate()
+        .with_context(|| format!("invalid surface #{}", i))?;
+    }
+    let state = engine.state();
+
+    *self.view_props.lock() = surfaces[0].clone();
+    let mut existing = vec![false; surfaces.len()];
+    for view in self.views() {
+      let Some(config_index) = view.config_index() else {
+        continue;
+      };
+      if view.removing.load(Ordering::Relaxed) {
+        continue;
+      }
+      let Some(props) = surfaces.get(config_index) else {
+        log::info!(
+          "Removing {}, its surface was removed from the config",
+          view.view_id
+        );
+        self.remove_view(engine, view.view_id)?;
+        continue;
+      };
+      existing[config_index] = true;
+      let current = view.layer_props().context("config views are layer views")?;
+      if config_index != 0
+        && (props.namespace != current.namespace || props.output != current.output)
+      {
+        log::info!(
+          "Recreating {} for its new namespace or output",
+          view.view_id
+        );
+        self.remove_view(engine, view.view_id)?;
+        existing[config_index] = false;
+        continue;
+      }
+      self.set_layer_props(engine, view.view_id, props)?;
+    }
+    for (i, props) in surfaces.iter().enumerate() {
+      if existing[i] {
+        continue;
+      }
+      let view_id = self.next_view_id();
+      self.create_layer_view(&state.opengl_state, view_id, props, None, Some(i), false)?;
+      log::info!("Created {}", view_id);
+    }
+    Ok(())
+  }
+
+  /// Unmap the surface of a layer view. The engine keeps the view, but its frames are dropped
+  /// until it's shown again.
+  ///
+  /// Must be called on the platform thread.
+  pub fn hide_view(&self, view_id: ViewId) -> Result<()> {
+    let view = self.get_mappable_view(view_id)?;
+    let mut visibility = view.visibility.lock();
+    if *visibility == Visibility::Hidden {
+      return Ok(());
+    }
+    let wl_surface = view.wl_surface();
+    wl_surface.attach(None, 0, 0);
+    wl_surface.commit();
+    *visibility = Visibility::Hidden;
+    log::info!("Hid {}", view_id);
+    Ok(())
+  }
+
+  /// Map the surface of a hidden view again. Its frames are presented again once the
+  /// compositor configured it.
+  ///
+  /// Must be called on the platform thread.
+  pub fn show_view(&self, view_id: ViewId) -> Result<()> {
+    let view = self.get_mappable_view(view_id)?;
+    let mut visibility = view.visibility.lock();
+    if *visibility != Visibility::Hidden {
+      return Ok(());
+    }
+    // like the initial commit of a new layer surface
+    view.wl_surface().commit();
+    *visibility = Visibility::Showing;
+    log::info!("Showing {}", view_id);
+    Ok(())
+  }
+
+  /// Have the keys the compositor would take as global shortcuts sent to the view while it
+  /// has the keyboard focus, e.g. for kiosks and lock screens. The compositor may refuse.
+  pub fn set_inhibit_shortcuts(&self, view_id: ViewId, inhibit: bool) -> Result<()> {
+    let view = self
+      .get_view(view_id)
+      .with_context(|| format!("{} not found", view_id))?;
+    let mut inhibitor = view.shortcuts_inhibitor.lock();
+    if !inhibit {
+      *inhibitor = None;
+      return Ok(());
+    }
+    if inhibitor.is_some() {
+      return Ok(());
+    }
+    let manager = self
+      .shortcuts_inhibit_manager
+      .as_ref()
+      .context("keyboard-shortcuts-inhibit-unstable-v1 is not supported by the compositor")?;
+    let seat = self.seat.as_ref().context("no seat")?;
+    *inhibitor = Some(manager.inhibit(view.wl_surface(), seat));
+    Ok(())
+  }
+
+  /// Remove an added view, or hide the implicit view, which can't be removed.
+  pub fn dismiss_view(&self, engine: &FlutterEngine, view_id: ViewId) -> Result<()> {
+    if view_id == ViewId::new(0) {
+      self.hide_view(view_id)
+    } else {
+      self.remove_view(engine, view_id)
+    }
+  }
+
+  /// A layer view that can be hidden and shown again
+  fn get_mappable_view(&self, view_id: ViewId) -> Result<Arc<FlutterView>> {
+    let view = self
+      .get_view(view_id)
+      .with_context(|| format!("{} not found", view_id))?;
+    if view.layer_props().is_none() {
+      anyhow::bail!("only layer surfaces can be hidden");
+    }
+    if view.is_closed() {
+      anyhow::bail!("{} was closed by the compositor", view_id);
+    }
+    Ok(view)
+  }
+
+  pub fn find_view_by_surface(&self, surface: &WlSurface) -> Option<Arc<FlutterView>> {
+    self
+      .views
+      .read()
+      .values()
+      .find(|view| view.wl_surface() == surface)
+      .cloned()
+  }
+
+  /// Set the opaque region of `view`'s surface for its current size. Takes effect with the
+  /// next commit.
+  pub fn apply_opaque_region(&self, view: &FlutterView) -> Result<()> {
+    let wl_surface = view.wl_surface();
+    let region = match &*view.opaque_region.lock() {
+      OpaqueRegion::None => {
+        wl_surface.set_opaque_region(None);
+        return Ok(());
+      }
+      OpaqueRegion::Full => {
+        let Some(current) = view.geometry.lock().current else {
+          // set with the first frame
+          return Ok(());
+        };
+        let size = current.logical_size;
+        let region = Region::new(&self.wl_compositor)?;
+        region.add(0, 0, size.width.get() as i32, size.height.get() as i32);
+        region
+      }
+      OpaqueRegion::Rects(rects) => {
+        let region = Region::new(&self.wl_compositor)?;
+        for rect in rects {
+          region.add(rect.x, rect.y, rect.width, rect.height);
+        }
+        region
+      }
     };
-    map.insert(implicit_view.view_id, implicit_view);
+    wl_surface.set_opaque_region(Some(region.wl_region()));
+    Ok(())
+  }
 
-    Ok(Self { views: map })
+  /// Stop sending events to Dart, which is starting over
+  pub fn cancel_event_listeners(&self) {
+    self.auto_hide_listening.store(false, Ordering::Relaxed);
+    self.popup_listening.store(false, Ordering::Relaxed);
+    self.input_method_listening.store(false, Ordering::Relaxed);
   }
 
-  pub fn get_view(&self, view_id: ViewId) -> Option<&FlutterView> {
-    self.views.get(&view_id)
+  pub fn keyboard_focus(&self) -> Option<ViewId> {
+    *self.keyboard_focus.lock()
+  }
+
+  /// Move the keyboard focus to `view_id`, or away from all views, and tell the framework so
+  /// that key events reach the widgets of that view. Returns the view that had it before.
+  pub fn set_keyboard_focus(
+    &self,
+    engine: &FlutterEngine,
+    view_id: Option<ViewId>,
+  ) -> Result<Option<ViewId>> {
+    let previous = std::mem::replace(&mut *self.keyboard_focus.lock(), view_id);
+    if previous == view_id {
+      return Ok(previous);
+    }
+    let known_to_engine = |view_id: ViewId| {
+      self
+        .get_view(view_id)
+        .is_some_and(|view| view.added_to_engine.load(Ordering::Relaxed))
+    };
+    if let Some(previous) = previous
+      && known_to_engine(previous)
+    {
+      engine.send_view_focus_event(previous, false)?;
+    }
+    if let Some(view_id) = view_id
+      && known_to_engine(view_id)
+    {
+      log::debug!("{} has the keyboard focus", view_id);
+      engine.send_view_focus_event(view_id, true)?;
+    }
+    Ok(previous)
+  }
+
+  /// Tell the framework again which view has the keyboard focus, e.g. after the root isolate
+  /// was started over and forgot.
+  pub fn resend_keyboard_focus(&self, engine: &FlutterEngine) -> Result<()> {
+    let Some(view_id) = self.keyboard_focus() else {
+      return Ok(());
+    };
+    if self
+      .get_view(view_id)
+      .is_some_and(|view| view.added_to_engine.load(Ordering::Relaxed))
+    {
+      engine.send_view_focus_event(view_id, true)?;
+    }
+    Ok(())
+  }
+
+  /// An empty input region lets input through to the surfaces below. Double-buffered.
+  fn apply_input_region(&self, wl_surface: &WlSurface, click_through: bool) -> Result<()> {
+    if click_through {
+      let region = Region::new(&self.wl_compositor)?;
+      wl_surface.set_input_region(Some(region.wl_region()));
+    } else {
+      wl_surface.set_input_region(None);
+    }
+    Ok(())
+  }
+
+  /// Nanoseconds between frames that keep every view within its [`LayerProps::max_fps`].
+  /// `None` if a view is unlimited, as all views are drawn with each frame.
+  pub fn frame_interval(&self) -> Option<u64> {
+    let views = self.views.read();
+    let max_fps = views
+      .values()
+      .try_fold(0, |max_fps, view| Some(view.max_fps()?.get().max(max_fps)))?;
+    (max_fps > 0).then(|| 1_000_000_000 / max_fps as u64)
+  }
+
+  /// No view is visible, so there's no point in producing frames.
+  pub fn all_views_occluded(&self) -> bool {
+    let views = self.views.read();
+    !views.is_empty() && views.values().all(|view| view.is_occluded())
+  }
+
+  /// Render every view at [`SurfaceOptions::low_power_resolution`] of its surface size and
+  /// have the compositor upscale it, or at full resolution again. Saves GPU time on battery
+  /// for views that are mostly decoration, like animated wallpapers.
+  ///
+  /// Must be called on the platform thread.
+  pub fn set_low_power(&self, engine: &FlutterEngine, low_power: bool) -> Result<()> {
+    if self.viewporter.is_none() {
+      anyhow::bail!("viewporter is not supported by the compositor");
+    }
+    if self.low_power.swap(low_power, Ordering::Relaxed) == low_power {
+      return Ok(());
+    }
+    let resolution = self.resolution();
+    for view in self.views() {
+      view.set_resolution(engine, resolution)?;
+    }
+    log::info!("Low-power mode {}", if low_power { "on" } else { "off" });
+    Ok(())
+  }
+
+  pub fn is_low_power(&self) -> bool {
+    self.low_power.load(Ordering::Relaxed)
+  }
+
+  /// fraction of the surface size views are rendered at
+  fn resolution(&self) -> f64 {
+    if self.is_low_power() {
+      self
+        .options
+        .low_power_resolution
+        .unwrap_or(DEFAULT_LOW_POWER_RESOLUTION)
+    } else {
+      1.0
+    }
   }
 }
 
+fn handle_layer_surface_event(
+  engine: &FlutterEngine,
+  event: zwlr_layer_surface_v1::Event,
+  id: &ViewId,
+) {
+  let state = engine.state();
+  let result = || {
+    let Some(this) = state.compositor.get_view(*id) else {
+      // queued before the view was removed
+      log::debug!("Ignored an event from {}, which was removed", id);
+      return Ok(());
+    };
+    let FlutterViewKind::Surface(surface_view) = &this.kind;
+
+    match event {
+      zwlr_layer_surface_v1::Event::Configure {
+        serial,
+        width,
+        height,
+      } => {
+        let _span = trace_span!("configure", view = %this.view_id, width, height);
+        // 0 leaves the size to us, which is what was requested
+        let requested = this.layer_props().unwrap_or_default();
+        let width = NonZero::new(width).or(NonZero::new(requested.width));
+        let height = NonZero::new(height).or(NonZero::new(requested.height));
+        let (Some(width), Some(height)) = (width, height) else {
+          anyhow::bail!("{} was configured without a size", this.view_id);
+        };
+        if let SurfaceRole::Layer {
+          surface,
+          props,
+          animation,
+          ..
+        } = &surface_view.role
+        {
+          let props = props.lock();
+          // a running animation sets the zone every frame
+          if props.exclusive_zone == ExclusiveZone::Auto && animation.lock().is_none() {
+            // committed with the first frame of the new size
+            let zone = props.exclusive_zone_for(width.get(), height.get());
+            surface.wlr_layer_surface().set_exclusive_zone(zone);
+          }
+        }
+        let shown_again = {
+          let mut visibility = this.visibility.lock();
+          let showing = *visibility == Visibility::Showing;
+          if showing {
+            *visibility = Visibility::Shown;
+            // pending callbacks may have been dropped while unmapped
+            this.frame_callback_done();
+          }
+          showing
+        };
+        let added_to_engine = this.added_to_engine.load(Ordering::Relaxed);
+        let size = NonZeroSize { width, height };
+        let wait_for_frame = this
+          .geometry
+          .lock()
+          .configure(size, serial, added_to_engine);
+        if !wait_for_frame {
+          surface_view.role.ack_configure(serial);
+        } else if added_to_engine {
+          // acked in the present callback together with the first frame of this size
+          this.send_window_metrics(engine)?;
+        } else {
+          this.add_to_engine(engine)?;
+          auto_hide::reset(engine, &this)?;
+        }
+        if shown_again {
+          engine.resume_rendering()?;
+        }
+      }
+      zwlr_layer_surface_v1::Event::Closed => {
+        // e.g. its output is gone. Nothing may be committed to the surface anymore.
+        this.closed.store(true, Ordering::Relaxed);
+        if this.view_id == ViewId::new(0) {
+          log::info!("The layer surface of the implicit view was closed, exiting");
+          let _ = state.terminate.unbounded_send(Ok(()));
+        } else {
+          log::info!("The layer surface of {} was closed", this.view_id);
+          state.compositor.remove_view(engine, this.view_id)?;
+        }
+      }
+      _ => {}
+    }
+
+    anyhow::Ok(())
+  };
+  error_in_callback!(state, result(), return ());
+}
+
 pub struct FlutterView {
   pub view_id: ViewId,
   pub kind: FlutterViewKind,
-  pub size: Mutex<(NonZeroSize, /*should resize*/ bool)>,
+  pub geometry: Mutex<ViewGeometry>,
+  /// the output the view was created for. `None` if the compositor chose it.
+  pub output: Option<Output>,
+  /// whether the engine knows the view, or it waits for its first configure
+  added_to_engine: AtomicBool,
+  /// the compositor closed the surface, frames for it are dropped
+  closed: AtomicBool,
+  /// waiting for the engine to remove the view
+  removing: AtomicBool,
+  /// locked while a frame is presented, so that the surface isn't unmapped meanwhile
+  pub visibility: Mutex<Visibility>,
+  /// when the currently pending frame callback was requested
+  frame_callback_requested_at: Mutex<Option<Instant>>,
+  /// see [`SurfaceOptions::opaque`]
+  opaque: bool,
+  /// see [`SurfaceOptions::pixel_ratio`]
+  pixel_ratio: Option<f64>,
+  pub opaque_region: Mutex<OpaqueRegion>,
+  /// `None` if the compositor doesn't support tearing-control-v1
+  tearing_control: Option<TearingControl>,
+  allow_tearing: AtomicBool,
+  /// `None` if the compositor doesn't support content-type-v1
+  content_type: Option<SurfaceContentType>,
+  /// see [`Compositor::set_inhibit_shortcuts`]
+  shortcuts_inhibitor: Mutex<Option<ShortcutsInhibitor>>,
+  /// `None` if the compositor doesn't support viewporter
+  viewport: Option<Viewport>,
+  /// answered with the next presented frame
+  capture_requests: Mutex<Vec<oneshot::Sender<Image>>>,
+  /// answered with the next presented frame and its layers
+  dump_requests: Mutex<Vec<oneshot::Sender<FrameDump>>>,
+  /// see [`LayerProps::auto_hide`]
+  pub auto_hide: Mutex<AutoHideState>,
+  /// the displays the surface is on, the last entered last
+  displays: Mutex<Vec<u64>>,
+}
+
+impl FlutterView {
+  pub fn wl_surface(&self) -> &WlSurface {
+    match &self.kind {
+      FlutterViewKind::Surface(surface_view) => surface_view.role.wl_surface(),
+    }
+  }
+
+  /// Returns true if a frame callback should be requested with the next commit, i.e. none is
+  /// pending yet.
+  pub fn mark_frame_callback_requested(&self) -> bool {
+    let mut requested_at = self.frame_callback_requested_at.lock();
+    if requested_at.is_some() {
+      return false;
+    }
+    *requested_at = Some(Instant::now());
+    true
+  }
+
+  pub fn frame_callback_done(&self) {
+    *self.frame_callback_requested_at.lock() = None;
+  }
+
+  /// Switch between vsync'ed and async presentation. Takes effect with the next frame.
+  pub fn set_allow_tearing(&self, allow_tearing: bool) -> Result<()> {
+    let tearing_control = self
+      .tearing_control
+      .as_ref()
+      .context("tearing-control-v1 is not supported by the compositor")?;
+    tearing_control.set_async(allow_tearing);
+    self.allow_tearing.store(allow_tearing, Ordering::Relaxed);
+    Ok(())
+  }
+
+  pub fn allows_tearing(&self) -> bool {
+    self.allow_tearing.load(Ordering::Relaxed)
+  }
+
+  /// Hint what the surface shows, see [`LayerProps::content_type`]. Takes effect with the
+  /// next frame.
+  fn set_content_type(&self, content_type: ContentType) -> Result<()> {
+    let surface_content_type = self
+      .content_type
+      .as_ref()
+      .context("content-type-v1 is not supported by the compositor")?;
+    surface_content_type.set(content_type.to_wp());
+    Ok(())
+  }
+
+  /// Read back the next frame presented on the view. The caller should schedule one.
+  pub fn request_capture(&self) -> CaptureReceiver {
+    let (tx, rx) = oneshot::channel();
+    self.capture_requests.lock().push(tx);
+    rx
+  }
+
+  /// Requests to answer with the frame being presented
+  pub fn take_capture_requests(&self) -> Vec<oneshot::Sender<Image>> {
+    std::mem::take(&mut *self.capture_requests.lock())
+  }
+
+  /// Read back the next frame presented on the view along with each of its backing stores.
+  /// The caller should schedule one.
+  pub fn request_dump(&self) -> DumpReceiver {
+    let (tx, rx) = oneshot::channel();
+    self.dump_requests.lock().push(tx);
+    rx
+  }
+
+  /// Requests to answer with the frame being presented and its layers
+  pub fn take_dump_requests(&self) -> Vec<oneshot::Sender<FrameDump>> {
+    std::mem::take(&mut *self.dump_requests.lock())
+  }
+
+  pub fn default_opaque_region(&self) -> OpaqueRegion {
+    OpaqueRegion::default_for(self.opaque)
+  }
+
+  pub fn is_closed(&self) -> bool {
+    self.closed.load(Ordering::Relaxed)
+  }
+
+  /// `None` if the view isn't a layer surface
+  pub fn layer_props(&self) -> Option<LayerProps> {
+    let FlutterViewKind::Surface(surface_view) = &self.kind;
+    match &surface_view.role {
+      SurfaceRole::Layer { props, .. } => Some(props.lock().clone()),
+      SurfaceRole::InputPopup(_) => None,
+    }
+  }
+
+  /// Where the surface is once animations end: slid out along its edge if auto-hidden
+  fn resting_placement(
+    &self,
+    props: &LayerProps,
+    configured_size: Option<NonZeroSize>,
+  ) -> Placement {
+    match props.auto_hide {
+      Some(auto_hide) if !self.auto_hide.lock().revealed() => {
+        props.hidden_placement(&auto_hide, configured_size)
+      }
+      _ => props.placement(configured_size),
+    }
+  }
+
+  /// Opacity of the black behind the app. 0 for input popups.
+  pub fn dim(&self) -> f32 {
+    let FlutterViewKind::Surface(surface_view) = &self.kind;
+    match &surface_view.role {
+      SurfaceRole::Layer { props, .. } => props.lock().dim,
+      SurfaceRole::InputPopup(_) => 0.0,
+    }
+  }
+
+  /// see [`LayerProps::max_fps`]
+  pub fn max_fps(&self) -> Option<NonZero<u32>> {
+    let FlutterViewKind::Surface(surface_view) = &self.kind;
+    match &surface_view.role {
+      SurfaceRole::Layer { props, .. } => props.lock().max_fps,
+      SurfaceRole::InputPopup(_) => None,
+    }
+  }
+
+  /// The `[[surface]]` of the config the view was created for
+  pub fn config_index(&self) -> Option<usize> {
+    let FlutterViewKind::Surface(surface_view) = &self.kind;
+    match &surface_view.role {
+      SurfaceRole::Layer { config_index, .. } => *config_index,
+      SurfaceRole::InputPopup(_) => None,
+    }
+  }
+
+  /// The compositor stopped answering frame callbacks, which it does for hidden surfaces,
+  /// closed the surface, or the view was hidden.
+  pub fn is_occluded(&self) -> bool {
+    self.is_closed()
+      || *self.visibility.lock() != Visibility::Shown
+      || self
+        .frame_callback_requested_at
+        .lock()
+        .is_some_and(|requested_at| requested_at.elapsed() > OCCLUSION_TIMEOUT)
+  }
+
+  /// The display the surface entered last, or else the output it was created for
+  fn display_id(&self) -> u64 {
+    self
+      .displays
+      .lock()
+      .last()
+      .copied()
+      .or(self.output.as_ref().map(|output| output.display_id))
+      .unwrap_or(0)
+  }
+
+  /// The surface entered the output of `display_id`.
+  pub fn enter_display(&self, engine: &FlutterEngine, display_id: u64) -> Result<()> {
+    {
+      let mut displays = self.displays.lock();
+      if displays.last() == Some(&display_id) {
+        return Ok(());
+      }
+      displays.retain(|&id| id != display_id);
+      displays.push(display_id);
+    }
+    self.send_window_metrics(engine)
+  }
+
+  /// The surface left the output of `display_id`.
+  pub fn leave_display(&self, engine: &FlutterEngine, display_id: u64) -> Result<()> {
+    let was_current = {
+      let mut displays = self.displays.lock();
+      let was_current = displays.last() == Some(&display_id);
+      displays.retain(|&id| id != display_id);
+      was_current
+    };
+    if was_current {
+      self.send_window_metrics(engine)?;
+    }
+    Ok(())
+  }
+
+  /// Physical pixels per surface coordinate. `None` before the first configure.
+  pub fn buffer_scale(&self) -> Option<f64> {
+    Some(self.geometry.lock().target()?.pixel_ratio())
+  }
+
+  /// The size and scale the view is about to have. `None` before the first configure.
+  fn window_metrics(&self) -> Option<ffi::FlutterWindowMetricsEvent> {
+    let target = self.geometry.lock().target()?;
+    Some(target.window_metrics(self.view_id, self.display_id(), self.pixel_ratio))
+  }
+
+  /// Send the size and scale the view is about to have to the engine, and have a frame drawn for
+  /// them right away instead of with the next animation tick.
+  ///
+  /// Does nothing before the view is added, as [`FlutterView::add_to_engine`] sends them, or
+  /// before the compositor configured its size.
+  pub fn send_window_metrics(&self, engine: &FlutterEngine) -> Result<()> {
+    if !self.added_to_engine.load(Ordering::Relaxed) {
+      return Ok(());
+    }
+    let Some(event) = self.window_metrics() else {
+      return Ok(());
+    };
+    unsafe {
+      ffi::FlutterEngineSendWindowMetricsEvent(engine.engine, &event)
+        .into_flutter_engine_result()?;
+    }
+    engine.schedule_frame()
+  }
+
+  /// Add the view to the engine with its pending geometry. The view is unregistered if the
+  /// engine refuses it.
+  fn add_to_engine(&self, engine: &FlutterEngine) -> Result<()> {
+    if !ffi::features().multi_view {
+      anyhow::bail!(
+        "can't add {}: the engine is too old to show more than the implicit view",
+        self.view_id
+      );
+    }
+    let metrics = self
+      .window_metrics()
+      .with_context(|| format!("{} has no size yet", self.view_id))?;
+    self.added_to_engine.store(true, Ordering::Relaxed);
+    let user_data = Box::new(ViewChangeUserData {
+      shared: engine.shared.clone(),
+      view_id: self.view_id,
+    });
+    let info = ffi::FlutterAddViewInfo {
+      struct_size: size_of::<ffi::FlutterAddViewInfo>(),
+      view_id: self.view_id.raw(),
+      view_metrics: &metrics,
+      user_data: Box::into_raw(user_data) as _,
+      add_view_callback: Some(add_view_callback),
+    };
+    unsafe {
+      if let Err(e) = ffi::FlutterEngineAddView(engine.engine, &info).into_flutter_engine_result() {
+        drop(Box::from_raw(info.user_data as *mut ViewChangeUserData));
+        return Err(e.into());
+      }
+    }
+    Ok(())
+  }
+
+  /// Apply a new integer buffer scale reported by the compositor.
+  ///
+  /// Like a configure, the new scale takes effect with the first frame rendered for it.
+  pub fn set_scale(&self, engine: &FlutterEngine, scale: i32) -> Result<()> {
+    let scale = u32::try_from(scale)
+      .ok()
+      .and_then(NonZero::new)
+      .with_context(|| format!("invalid scale factor {} for {}", scale, self.view_id))?;
+    {
+      let mut geometry = self.geometry.lock();
+      if geometry.scale == scale {
+        return Ok(());
+      }
+      geometry.scale = scale;
+      let Some(target) = geometry.target() else {
+        // used by the first configure
+        return Ok(());
+      };
+      let configure_serial = geometry
+        .pending
+        .and_then(|pending| pending.configure_serial);
+      geometry.pending = Some(PendingGeometry {
+        geometry: SurfaceGeometry { scale, ..target },
+        configure_serial,
+      });
+    }
+    log::info!("{} scale factor changed to {}", self.view_id, scale);
+    self.send_window_metrics(engine)
+  }
+
+  /// Render at `resolution` of the surface size from the next frame on, upscaled by the
+  /// compositor below 1.
+  fn set_resolution(&self, engine: &FlutterEngine, resolution: f64) -> Result<()> {
+    {
+      let mut geometry = self.geometry.lock();
+      geometry.resolution = resolution;
+      let Some(target) = geometry.target() else {
+        // used by the first configure
+        return Ok(());
+      };
+      let configure_serial = geometry
+        .pending
+        .and_then(|pending| pending.configure_serial);
+      geometry.pending = Some(PendingGeometry {
+        geometry: SurfaceGeometry {
+          resolution,
+          ..target
+        },
+        configure_serial,
+      });
+    }
+    self.send_window_metrics(engine)
+  }
+}
+
+struct ViewChangeUserData {
+  shared: Arc<EngineShared>,
+  view_id: ViewId,
+}
+
+extern "C" fn add_view_callback(result: *const ffi::FlutterAddViewResult) {
+  let result = unsafe { &*result };
+  let user_data = unsafe { Box::from_raw(result.user_data as *mut ViewChangeUserData) };
+  let Some(state) = user_data.shared.state.get() else {
+    return;
+  };
+  catch_panic(Some(state), (), || {
+    let view_id = user_data.view_id;
+    let added = result.added;
+    // may be called on any thread
+    let ret = state.task_runner_handle.post_task(move |engine| {
+      let state = engine.state();
+      if !added {
+        log::error!("The engine refused to add {}", view_id);
+        state.compositor.unregister_view(view_id);
+        return;
+      }
+      log::info!("Added {} to the engine", view_id);
+      error_in_callback!(state, engine.schedule_frame(), return ());
+    });
+    error_in_callback!(state, ret, return ());
+  })
+}
+
+extern "C" fn remove_view_callback(result: *const ffi::FlutterRemoveViewResult) {
+  let result = unsafe { &*result };
+  let user_data = unsafe { Box::from_raw(result.user_data as *mut ViewChangeUserData) };
+  let Some(state) = user_data.shared.state.get() else {
+    return;
+  };
+  catch_panic(Some(state), (), || {
+    let view_id = user_data.view_id;
+    let removed = result.removed;
+    // may be called on any thread
+    let ret = state.task_runner_handle.post_task(move |engine| {
+      let state = engine.state();
+      if !removed {
+        log::error!("The engine failed to remove {}", view_id);
+        return;
+      }
+      log::info!("Removed {} from the engine", view_id);
+      state.compositor.unregister_view(view_id);
+      // the surfaces are destroyed with the next frame
+      error_in_callback!(state, engine.schedule_frame(), return ());
+    });
+    error_in_callback!(state, ret, return ());
+  })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ViewGeometry {
+  /// what the surface has now. `None` until the first frame is presented.
+  pub current: Option<SurfaceGeometry>,
+  /// requested by the compositor, waiting for a frame of the matching size
+  pub pending: Option<PendingGeometry>,
+  /// the latest integer buffer scale reported for the surface
+  pub scale: NonZero<u32>,
+  /// the latest fraction of the surface size to render at, see [`Compositor::set_low_power`]
+  pub resolution: f64,
+}
+
+impl ViewGeometry {
+  /// the geometry the engine should render for. `None` before the first configure.
+  pub fn target(&self) -> Option<SurfaceGeometry> {
+    self
+      .pending
+      .map(|pending| pending.geometry)
+      .or(self.current)
+  }
+
+  /// Take a configure of `logical_size`. Returns whether it must wait for a frame of the new
+  /// geometry to be acked, rather than right away as it changes nothing.
+  pub fn configure(
+    &mut self,
+    logical_size: NonZeroSize,
+    serial: u32,
+    added_to_engine: bool,
+  ) -> bool {
+    let target = SurfaceGeometry {
+      logical_size,
+      scale: self.scale,
+      resolution: self.resolution,
+    };
+    if added_to_engine && self.pending.is_none() && Some(target) == self.current {
+      return false;
+    }
+    self.pending = Some(PendingGeometry {
+      geometry: target,
+      configure_serial: Some(serial),
+    });
+    true
+  }
+
+  /// Take a frame of `frame_size` in buffer pixels, switching to the pending geometry if the
+  /// frame was rendered for it. Returns the geometry applied by the frame, or `None` for a frame
+  /// of an outdated size, which must be dropped.
+  pub fn present(&mut self, frame_size: NonZeroSize) -> Option<Option<PendingGeometry>> {
+    match self.pending {
+      Some(pending) if pending.geometry.physical_size() == frame_size => {
+        self.current = Some(pending.geometry);
+        self.pending = None;
+        Some(Some(pending))
+      }
+      _ if self
+        .current
+        .is_some_and(|current| current.physical_size() == frame_size) =>
+      {
+        Some(None)
+      }
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceGeometry {
+  /// size in surface-local coordinates, as configured by the compositor
+  pub logical_size: NonZeroSize,
+  /// integer buffer scale of the surface
+  pub scale: NonZero<u32>,
+  /// fraction of the full size the buffer has. Below 1 the buffer is upscaled with a viewport
+  /// and has a buffer scale of 1.
+  pub resolution: f64,
+}
+
+impl SurfaceGeometry {
+  /// size in buffer pixels
+  pub fn physical_size(&self) -> NonZeroSize {
+    if !self.is_reduced() {
+      return NonZeroSize {
+        width: self.logical_size.width.saturating_mul(self.scale),
+        height: self.logical_size.height.saturating_mul(self.scale),
+      };
+    }
+    let reduce = |length: NonZero<u32>| {
+      let length = (length.get() as f64 * self.pixel_ratio()).round() as u32;
+      NonZero::new(length).unwrap_or(NonZero::<u32>::MIN)
+    };
+    NonZeroSize {
+      width: reduce(self.logical_size.width),
+      height: reduce(self.logical_size.height),
+    }
+  }
+
+  /// buffer pixels per surface coordinate
+  pub fn pixel_ratio(&self) -> f64 {
+    self.scale.get() as f64 * self.resolution
+  }
+
+  /// rendered below the full resolution
+  pub fn is_reduced(&self) -> bool {
+    self.resolution < 1.0
+  }
+
+  /// What the engine is told about a view of this geometry. `pixel_ratio` replaces the buffer
+  /// scale the app lays out for, see [`SurfaceOptions::pixel_ratio`].
+  pub fn window_metrics(
+    &self,
+    view_id: ViewId,
+    display_id: u64,
+    pixel_ratio: Option<f64>,
+  ) -> ffi::FlutterWindowMetricsEvent {
+    let size = self.physical_size();
+    ffi::FlutterWindowMetricsEvent {
+      struct_size: size_of::<ffi::FlutterWindowMetricsEvent>(),
+      width: size.width.get() as usize,
+      height: size.height.get() as usize,
+      pixel_ratio: pixel_ratio.map_or(self.pixel_ratio(), |pixel_ratio| {
+        pixel_ratio * self.resolution
+      }),
+      left: 0,
+      top: 0,
+      physical_view_inset_top: 0.0,
+      physical_view_inset_right: 0.0,
+      physical_view_inset_bottom: 0.0,
+      physical_view_inset_left: 0.0,
+      display_id,
+      view_id: view_id.raw(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PendingGeometry {
+  pub geometry: SurfaceGeometry,
+  /// configure to ack once the geometry is applied
+  pub configure_serial: Option<u32>,
 }
 
 pub enum FlutterViewKind {
-  LayerSurface(LayerSurfaceView),
+  Surface(SurfaceView),
   // Popup,
 }
 
-pub struct LayerSurfaceView {
-  layer_surface: LayerSurface,
-  egl_surface: Mutex<Surface<WindowSurface>>,
+/// A view presented on a wl_surface of its own
+pub struct SurfaceView {
+  // dropped in declaration order: the EGL surface and the syncobj surface go before the
+  // wl_surface they're made for
+  /// created with the first frame, once the size is known
+  egl_surface: Mutex<Option<Surface<WindowSurface>>>,
+  /// `Some` if the surface is synchronized explicitly
+  surface_sync: Option<Mutex<SurfaceSync>>,
+  role: SurfaceRole,
+  /// whether the EGL surface's swap interval is 0, following [`FlutterView::allows_tearing`]
+  swap_async: AtomicBool,
+  /// whether the buffer transform is `Flipped180`, set while GBM buffers rendered bottom-up are
+  /// attached directly
+  buffer_flipped: AtomicBool,
+  /// the EGL surface is replaced before the next frame, see
+  /// [`Policy::RecreateSurface`](crate::error::Policy::RecreateSurface)
+  egl_surface_lost: AtomicBool,
 }
 
-impl LayerSurfaceView {
-  fn new(layer_surface: LayerSurface, opengl_state: &OpenGLState) -> Result<Self> {
-    let wl_surface = layer_surface.wl_surface();
-    let rwh = RawWindowHandle::Wayland(WaylandWindowHandle::new(
-      NonNull::new(wl_surface.id().as_ptr() as _).context("null wl_surface pointer")?,
-    ));
+/// Whether the surface of a view is mapped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+  Shown,
+  /// unmapped by committing a null buffer
+  Hidden,
+  /// mapped again, frames are dropped until the compositor sends a configure
+  Showing,
+}
 
-    let egl_display = &opengl_state.egl_display;
-    let egl_config = &opengl_state.egl_config;
-    let egl_window_surface = {
-      let surface_attributes = SurfaceAttributesBuilder::<WindowSurface>::new().build(
-        rwh,
-        NonZero::new(1600).unwrap(),
-        NonZero::new(900).unwrap(),
-      );
-      unsafe { egl_display.create_window_surface(&egl_config, &surface_attributes)? }
+// one per view, not worth boxing
+#[allow(clippy::large_enum_variant)]
+pub enum SurfaceRole {
+  Layer {
+    surface: LayerSurface,
+    /// as last applied. Animations and auto-hiding move the surface away from their margin
+    /// and exclusive zone meanwhile.
+    props: Mutex<LayerProps>,
+    animation: Mutex<Option<Animation>>,
+    /// the `[[surface]]` of the config the view was created for. `None` for views added by
+    /// Dart.
+    config_index: Option<usize>,
+  },
+  /// placed by the compositor next to the focused text input
+  InputPopup(InputPopupSurface),
+}
+
+impl SurfaceRole {
+  pub fn wl_surface(&self) -> &WlSurface {
+    match self {
+      SurfaceRole::Layer { surface, .. } => surface.wl_surface(),
+      SurfaceRole::InputPopup(popup_surface) => popup_surface.wl_surface(),
+    }
+  }
+
+  /// Ack a configure whose size is applied. Only layer surfaces are configured.
+  pub fn ack_configure(&self, serial: u32) {
+    match self {
+      SurfaceRole::Layer { surface, .. } => surface.wlr_layer_surface().ack_configure(serial),
+      SurfaceRole::InputPopup(_) => {}
+    }
+  }
+}
+
+impl SurfaceView {
+  fn new(
+    role: SurfaceRole,
+    opengl_state: &OpenGLState,
+    explicit_sync: Option<&ExplicitSync>,
+  ) -> Result<Self> {
+    let wl_surface = role.wl_surface();
+    let surface_sync = match (&opengl_state.drm_device, explicit_sync) {
+      (Some(drm_device), Some(explicit_sync)) => Some(Mutex::new(
+        explicit_sync.surface_sync(wl_surface, drm_device)?,
+      )),
+      (Some(_), None) => {
+        log::warn!("linux-drm-syncobj-v1 is not supported. Explicit sync disabled.");
+        None
+      }
+      (None, _) => None,
     };
 
     Ok(Self {
-      layer_surface,
-      egl_surface: Mutex::new(egl_window_surface),
+      egl_surface: Mutex::new(None),
+      surface_sync,
+      role,
+      swap_async: AtomicBool::new(false),
+      buffer_flipped: AtomicBool::new(false),
+      egl_surface_lost: AtomicBool::new(false),
     })
   }
+
+  /// Flip the buffers attached from now on vertically, or stop doing so. Sent along with the
+  /// next commit.
+  pub fn set_buffer_flipped(&self, flipped: bool) {
+    if self.buffer_flipped.swap(flipped, Ordering::Relaxed) != flipped {
+      let transform = if flipped {
+        Transform::Flipped180
+      } else {
+        Transform::Normal
+      };
+      self.role.wl_surface().set_buffer_transform(transform);
+    }
+  }
+
+  /// Have the EGL surface replaced on the raster thread before the next frame.
+  pub fn invalidate_egl_surface(&self) {
+    self.egl_surface_lost.store(true, Ordering::Relaxed);
+  }
+
+  /// Drop the EGL surface locked as `egl_surface` if it was invalidated, so that it's created
+  /// again by [`Self::resize_egl_surface`].
+  ///
+  /// Must be called on the raster thread.
+  pub fn drop_lost_egl_surface(
+    &self,
+    egl_surface: &mut Option<Surface<WindowSurface>>,
+    opengl_state: &OpenGLState,
+  ) -> Result<()> {
+    if !self.egl_surface_lost.swap(false, Ordering::Relaxed) {
+      return Ok(());
+    }
+    if let Some(lost) = egl_surface.take() {
+      opengl_state.release_surface(&lost)?;
+    }
+    Ok(())
+  }
+
+  /// Resize the EGL surface locked as `egl_surface` to `size`, or create it for the first
+  /// frame.
+  ///
+  /// Must be called on the raster thread.
+  pub fn resize_egl_surface(
+    &self,
+    egl_surface: &mut Option<Surface<WindowSurface>>,
+    opengl_state: &OpenGLState,
+    size: NonZeroSize,
+  ) -> Result<()> {
+    if let Some(egl_surface) = egl_surface {
+      egl_surface.resize(&opengl_state.render_context, size.width, size.height);
+      return Ok(());
+    }
+    let rwh = RawWindowHandle::Wayland(WaylandWindowHandle::new(
+      NonNull::new(self.role.wl_surface().id().as_ptr() as _).context("null wl_surface pointer")?,
+    ));
+    let surface_attributes = SurfaceAttributesBuilder::<WindowSurface>::new()
+      .with_srgb(Some(opengl_state.options.srgb))
+      .build(rwh, size.width, size.height);
+    let created = unsafe {
+      opengl_state
+        .egl_display
+        .create_window_surface(&opengl_state.egl_config, &surface_attributes)?
+    };
+    *egl_surface = Some(created);
+    Ok(())
+  }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Part of a surface the compositor doesn't need to blend with what's behind it
+#[derive(Debug, Clone)]
+pub enum OpaqueRegion {
+  None,
+  /// the whole surface, following its size
+  Full,
+  Rects(Vec<LogicalRect>),
+}
+
+impl OpaqueRegion {
+  fn default_for(opaque: bool) -> Self {
+    if opaque {
+      OpaqueRegion::Full
+    } else {
+      OpaqueRegion::None
+    }
+  }
+}
+
+/// A rectangle in surface-local coordinates
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct LogicalRect {
+  pub x: i32,
+  pub y: i32,
+  pub width: i32,
+  pub height: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct NonZeroSize {
   pub width: NonZero<u32>,
   pub height: NonZero<u32>,
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn size(width: u32, height: u32) -> NonZeroSize {
+    NonZeroSize {
+      width: NonZero::new(width).unwrap(),
+      height: NonZero::new(height).unwrap(),
+    }
+  }
+
+  fn geometry(scale: u32) -> ViewGeometry {
+    ViewGeometry {
+      current: None,
+      pending: None,
+      scale: NonZero::new(scale).unwrap(),
+      resolution: 1.0,
+    }
+  }
+
+  #[test]
+  fn first_configure_waits_for_a_frame() {
+    let mut geometry = geometry(2);
+    assert!(geometry.target().is_none());
+    assert!(geometry.configure(size(100, 30), 1, false));
+    assert_eq!(geometry.target().unwrap().physical_size(), size(200, 60));
+    assert!(geometry.present(size(100, 30)).is_none());
+    let applied = geometry.present(size(200, 60)).unwrap().unwrap();
+    assert_eq!(applied.configure_serial, Some(1));
+    assert!(geometry.pending.is_none());
+    assert_eq!(geometry.current, Some(applied.geometry));
+  }
+
+  #[test]
+  fn resize_keeps_presenting_the_old_size_until_a_new_frame() {
+    let mut geometry = geometry(1);
+    geometry.configure(size(100, 30), 1, true);
+    geometry.present(size(100, 30)).unwrap();
+    assert!(geometry.configure(size(100, 40), 2, true));
+    // rendered before the engine learned about the resize
+    assert!(matches!(geometry.present(size(100, 30)), Some(None)));
+    assert_eq!(geometry.target().unwrap().logical_size, size(100, 40));
+    let applied = geometry.present(size(100, 40)).unwrap().unwrap();
+    assert_eq!(applied.configure_serial, Some(2));
+    assert!(geometry.present(size(100, 30)).is_none());
+  }
+
+  #[test]
+  fn unchanged_configure_is_acked_right_away() {
+    let mut geometry = geometry(1);
+    geometry.configure(size(100, 30), 1, true);
+    geometry.present(size(100, 30)).unwrap();
+    assert!(!geometry.configure(size(100, 30), 2, true));
+    assert!(geometry.pending.is_none());
+    // the view must still be added to the engine with it
+    assert!(geometry.configure(size(100, 30), 3, false));
+  }
+
+  #[test]
+  fn window_metrics_follow_scale_and_resolution() {
+    let mut geometry = SurfaceGeometry {
+      logical_size: size(100, 30),
+      scale: NonZero::new(2).unwrap(),
+      resolution: 1.0,
+    };
+    let metrics = geometry.window_metrics(ViewId::new(1), 7, None);
+    assert_eq!((metrics.width, metrics.height), (200, 60));
+    assert_eq!(metrics.pixel_ratio, 2.0);
+    assert_eq!((metrics.view_id, metrics.display_id), (1, 7));
+
+    geometry.resolution = 0.5;
+    let metrics = geometry.window_metrics(ViewId::new(1), 7, Some(1.5));
+    assert_eq!((metrics.width, metrics.height), (100, 30));
+    assert_eq!(metrics.pixel_ratio, 0.75);
+  }
+
+  #[test]
+  fn reduced_resolution_rounds_and_stays_positive() {
+    let geometry = SurfaceGeometry {
+      logical_size: size(101, 1),
+      scale: NonZero::new(1).unwrap(),
+      resolution: 0.5,
+    };
+    assert!(geometry.is_reduced());
+    assert_eq!(geometry.physical_size(), size(51, 1));
+  }
+}
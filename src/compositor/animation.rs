@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::ZwlrLayerSurfaceV1;
+
+/// Margin and exclusive zone of a layer surface, the parts that can be animated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+  /// top, right, bottom, left
+  pub margin: [i32; 4],
+  pub exclusive_zone: i32,
+}
+
+impl Placement {
+  /// Double-buffered
+  pub fn apply(&self, layer_surface: &ZwlrLayerSurfaceV1) {
+    let [top, right, bottom, left] = self.margin;
+    layer_surface.set_margin(top, right, bottom, left);
+    layer_surface.set_exclusive_zone(self.exclusive_zone);
+  }
+
+  fn lerp(self, to: Self, t: f64) -> Self {
+    let lerp = |a: i32, b: i32| (a as f64 + (b - a) as f64 * t).round() as i32;
+    Self {
+      margin: std::array::from_fn(|i| lerp(self.margin[i], to.margin[i])),
+      exclusive_zone: lerp(self.exclusive_zone, to.exclusive_zone),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Curve {
+  Linear,
+  EaseIn,
+  EaseOut,
+  #[default]
+  EaseInOut,
+}
+
+impl Curve {
+  /// Progress along the curve at `t` between 0 and 1
+  fn transform(self, t: f64) -> f64 {
+    match self {
+      Curve::Linear => t,
+      Curve::EaseIn => t * t * t,
+      Curve::EaseOut => 1.0 - (1.0 - t).powi(3),
+      Curve::EaseInOut if t < 0.5 => 4.0 * t * t * t,
+      Curve::EaseInOut => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+    }
+  }
+}
+
+/// A tween of a layer surface's [`Placement`], stepped by the embedder every frame so that it
+/// doesn't wait on round trips to Dart
+#[derive(Debug, Clone)]
+pub struct Animation {
+  from: Placement,
+  to: Placement,
+  /// in engine time, nanoseconds
+  start: u64,
+  duration: u64,
+  curve: Curve,
+}
+
+impl Animation {
+  pub fn new(from: Placement, to: Placement, start: u64, duration: Duration, curve: Curve) -> Self {
+    Self {
+      from,
+      to,
+      start,
+      duration: duration.as_nanos().try_into().unwrap_or(u64::MAX),
+      curve,
+    }
+  }
+
+  /// The placement at engine time `time`, and whether the animation is over by then
+  pub fn sample(&self, time: u64) -> (Placement, bool) {
+    let elapsed = time.saturating_sub(self.start);
+    if elapsed >= self.duration {
+      return (self.to, true);
+    }
+    let t = elapsed as f64 / self.duration as f64;
+    (self.from.lerp(self.to, self.curve.transform(t)), false)
+  }
+}
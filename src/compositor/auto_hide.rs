@@ -0,0 +1,190 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Value;
+use serde_json::json;
+
+use crate::FlutterEngine;
+use crate::channel::MethodCall;
+use crate::channel::MethodResult;
+use crate::compositor::FlutterView;
+use crate::compositor::ViewId;
+
+/// Event channel (`EventChannel` with `JSONMethodCodec` on the Dart side) streaming
+/// `{"viewId", "revealed", "duration"}` whenever an auto-hiding surface starts sliding in or
+/// out, so that the app can animate its contents along
+pub const CHANNEL: &str = "wayflutter/auto_hide";
+
+/// Slide a surface out along its anchored edge while the pointer is away, leaving a strip to
+/// point at to bring it back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct AutoHide {
+  /// logical pixels left on screen while hidden
+  pub hotspot: u32,
+  /// milliseconds the pointer rests on the strip before the surface is revealed
+  pub reveal_delay: u64,
+  /// milliseconds after the pointer left before the surface hides
+  pub hide_delay: u64,
+  /// milliseconds the slide takes
+  pub duration: u64,
+}
+
+impl Default for AutoHide {
+  fn default() -> Self {
+    Self {
+      hotspot: 2,
+      reveal_delay: 150,
+      hide_delay: 700,
+      duration: 200,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct AutoHideState {
+  revealed: bool,
+  hovered: bool,
+  /// bumped to cancel the pending reveal or hide
+  generation: u64,
+}
+
+impl Default for AutoHideState {
+  fn default() -> Self {
+    Self {
+      revealed: true,
+      hovered: false,
+      generation: 0,
+    }
+  }
+}
+
+impl AutoHideState {
+  pub fn revealed(&self) -> bool {
+    self.revealed
+  }
+}
+
+pub fn handle_method_call(engine: &FlutterEngine, call: &MethodCall) -> Option<MethodResult> {
+  let state = engine.state();
+  match call.method.as_str() {
+    "listen" => {
+      state
+        .compositor
+        .auto_hide_listening
+        .store(true, Ordering::Relaxed);
+      Some(Ok(Value::Null))
+    }
+    "cancel" => {
+      state
+        .compositor
+        .auto_hide_listening
+        .store(false, Ordering::Relaxed);
+      Some(Ok(Value::Null))
+    }
+    _ => None,
+  }
+}
+
+/// The pointer entered or left the surface of `view`.
+pub fn set_hovered(engine: &FlutterEngine, view: &FlutterView, hovered: bool) -> Result<()> {
+  let Some(auto_hide) = view.layer_props().and_then(|props| props.auto_hide) else {
+    return Ok(());
+  };
+  let (generation, revealed) = {
+    let mut state = view.auto_hide.lock();
+    state.hovered = hovered;
+    state.generation += 1;
+    (state.generation, state.revealed)
+  };
+  match (hovered, revealed) {
+    (true, false) => schedule(
+      engine,
+      view.view_id,
+      generation,
+      true,
+      auto_hide.reveal_delay,
+    ),
+    (false, true) => schedule(
+      engine,
+      view.view_id,
+      generation,
+      false,
+      auto_hide.hide_delay,
+    ),
+    _ => Ok(()),
+  }
+}
+
+/// Start over from a revealed surface, which hides unless the pointer is on it. For new views
+/// and changed props.
+pub fn reset(engine: &FlutterEngine, view: &FlutterView) -> Result<()> {
+  let generation = {
+    let mut state = view.auto_hide.lock();
+    state.revealed = true;
+    state.generation += 1;
+    if state.hovered {
+      return Ok(());
+    }
+    state.generation
+  };
+  match view.layer_props().and_then(|props| props.auto_hide) {
+    Some(auto_hide) => schedule(
+      engine,
+      view.view_id,
+      generation,
+      false,
+      auto_hide.hide_delay,
+    ),
+    None => Ok(()),
+  }
+}
+
+fn schedule(
+  engine: &FlutterEngine,
+  view_id: ViewId,
+  generation: u64,
+  reveal: bool,
+  delay: u64,
+) -> Result<()> {
+  let state = engine.state();
+  state.task_runner_handle.post_task_after(
+    move |engine| {
+      if let Err(e) = slide(engine, view_id, generation, reveal) {
+        log::error!("failed to slide {}: {:#}", view_id, e);
+      }
+    },
+    Duration::from_millis(delay),
+  )
+}
+
+fn slide(engine: &FlutterEngine, view_id: ViewId, generation: u64, reveal: bool) -> Result<()> {
+  let state = engine.state();
+  let Some(view) = state.compositor.get_view(view_id) else {
+    return Ok(());
+  };
+  {
+    let mut auto_hide = view.auto_hide.lock();
+    if auto_hide.generation != generation {
+      // the pointer came back or left meanwhile
+      return Ok(());
+    }
+    auto_hide.revealed = reveal;
+  }
+  let duration = state.compositor.slide_view(engine, &view)?;
+  if state.compositor.auto_hide_listening.load(Ordering::Relaxed) {
+    crate::channel::send_event(
+      engine,
+      CHANNEL,
+      json!({
+        "viewId": view_id.raw(),
+        "revealed": reveal,
+        "duration": duration.as_millis() as u64,
+      }),
+    )?;
+  }
+  Ok(())
+}
@@ -0,0 +1,438 @@
+use std::num::NonZero;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use gl::types::*;
+use parking_lot::Mutex;
+
+use crate::compositor::capture::Image;
+use crate::opengl::debug;
+use crate::opengl::dmabuf::DmabufImage;
+use crate::wayland::dmabuf::DmabufBuffer;
+use crate::wayland::shm::Shm;
+use crate::wayland::shm::ShmBuffer;
+
+/// GL objects behind an OpenGL backing store.
+///
+/// The engine renders into [`GLBackingStore::render_framebuffer`], or straight into `texture`
+/// for texture-type stores. The result ends up in `texture`, which is what gets sampled when
+/// presenting. With MSAA the engine renders into a multisampled framebuffer instead, so
+/// [`GLBackingStore::resolve`] must be called before sampling.
+#[derive(Debug)]
+pub struct GLBackingStore {
+  pub width: GLsizei,
+  pub height: GLsizei,
+  /// framebuffer with `texture` as its color attachment
+  texture_framebuffer: GLuint,
+  pub texture: GLuint,
+  /// depth/stencil attachment of the render framebuffer. `None` for texture-type stores, where
+  /// the engine brings its own.
+  depth_stencil: Option<GLuint>,
+  multisample: Option<Multisample>,
+  /// storage of `texture` if it's a dmabuf
+  pub dmabuf: Option<DmabufStorage>,
+}
+
+/// A dmabuf both imported into GL and shared with the compositor
+#[derive(Debug)]
+pub struct DmabufStorage {
+  pub image: DmabufImage,
+  pub buffer: DmabufBuffer,
+}
+
+#[derive(Debug)]
+struct Multisample {
+  framebuffer: GLuint,
+  color: GLuint,
+  samples: usize,
+}
+
+impl GLBackingStore {
+  /// Must be called with a GL context current. `samples` must be `None` for a texture-type
+  /// store.
+  pub unsafe fn new(
+    width: GLsizei,
+    height: GLsizei,
+    samples: Option<NonZero<u32>>,
+    texture_target: bool,
+    dmabuf: Option<DmabufStorage>,
+  ) -> Self {
+    use gl::*;
+
+    unsafe {
+      let mut texture_framebuffer: GLuint = 0;
+      GenFramebuffers(1, &mut texture_framebuffer);
+      BindFramebuffer(FRAMEBUFFER, texture_framebuffer);
+
+      let mut texture: GLuint = 0;
+      GenTextures(1, &mut texture);
+      BindTexture(TEXTURE_2D, texture);
+      TexParameteri(TEXTURE_2D, TEXTURE_WRAP_S, CLAMP_TO_EDGE as _);
+      TexParameteri(TEXTURE_2D, TEXTURE_WRAP_T, CLAMP_TO_EDGE as _);
+      TexParameteri(TEXTURE_2D, TEXTURE_MIN_FILTER, NEAREST as _);
+      TexParameteri(TEXTURE_2D, TEXTURE_MAG_FILTER, NEAREST as _);
+      match &dmabuf {
+        Some(dmabuf) => dmabuf.image.bind_texture_storage(),
+        None => TexImage2D(
+          TEXTURE_2D,
+          0,
+          RGBA8 as _,
+          width,
+          height,
+          0,
+          RGBA,
+          UNSIGNED_BYTE,
+          std::ptr::null_mut(),
+        ),
+      }
+      BindTexture(TEXTURE_2D, 0);
+      FramebufferTexture2D(FRAMEBUFFER, COLOR_ATTACHMENT0, TEXTURE_2D, texture, 0);
+
+      let multisample = samples.map(|samples| {
+        let samples = samples.get() as GLsizei;
+
+        let mut framebuffer: GLuint = 0;
+        GenFramebuffers(1, &mut framebuffer);
+        BindFramebuffer(FRAMEBUFFER, framebuffer);
+
+        let mut color: GLuint = 0;
+        GenRenderbuffers(1, &mut color);
+        BindRenderbuffer(RENDERBUFFER, color);
+        RenderbufferStorageMultisample(RENDERBUFFER, samples, RGBA8, width, height);
+        BindRenderbuffer(RENDERBUFFER, 0);
+        FramebufferRenderbuffer(FRAMEBUFFER, COLOR_ATTACHMENT0, RENDERBUFFER, color);
+
+        Multisample {
+          framebuffer,
+          color,
+          samples: samples as usize,
+        }
+      });
+
+      // the depth/stencil buffer goes to whichever framebuffer the engine renders into,
+      // which is still bound here
+      let depth_stencil = (!texture_target).then(|| {
+        let mut depth_stencil: GLuint = 0;
+        GenRenderbuffers(1, &mut depth_stencil);
+        BindRenderbuffer(RENDERBUFFER, depth_stencil);
+        match samples {
+          Some(samples) => RenderbufferStorageMultisample(
+            RENDERBUFFER,
+            samples.get() as GLsizei,
+            DEPTH24_STENCIL8,
+            width,
+            height,
+          ),
+          None => RenderbufferStorage(RENDERBUFFER, DEPTH24_STENCIL8, width, height),
+        }
+        BindRenderbuffer(RENDERBUFFER, 0);
+        FramebufferRenderbuffer(
+          FRAMEBUFFER,
+          DEPTH_STENCIL_ATTACHMENT,
+          RENDERBUFFER,
+          depth_stencil,
+        );
+        depth_stencil
+      });
+
+      Self {
+        width,
+        height,
+        texture_framebuffer,
+        texture,
+        depth_stencil,
+        multisample,
+        dmabuf,
+      }
+    }
+  }
+
+  /// Fail if a framebuffer of the store can't be rendered into.
+  ///
+  /// Must be called with a GL context current.
+  pub unsafe fn check_complete(&self) -> Result<()> {
+    unsafe {
+      debug::check_framebuffer(self.texture_framebuffer)?;
+      if let Some(multisample) = &self.multisample {
+        debug::check_framebuffer(multisample.framebuffer)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// The framebuffer handed to the engine.
+  pub fn render_framebuffer(&self) -> GLuint {
+    match &self.multisample {
+      Some(multisample) => multisample.framebuffer,
+      None => self.texture_framebuffer,
+    }
+  }
+
+  /// Resolve the multisampled framebuffer into `texture`. No-op without MSAA.
+  ///
+  /// Must be called with a GL context current. Framebuffer bindings are preserved.
+  pub unsafe fn resolve(&self) {
+    use gl::*;
+
+    let Some(multisample) = &self.multisample else {
+      return;
+    };
+
+    unsafe {
+      let mut prev_read_framebuffer = 0;
+      GetIntegerv(READ_FRAMEBUFFER_BINDING, &mut prev_read_framebuffer);
+      let mut prev_draw_framebuffer = 0;
+      GetIntegerv(DRAW_FRAMEBUFFER_BINDING, &mut prev_draw_framebuffer);
+
+      BindFramebuffer(READ_FRAMEBUFFER, multisample.framebuffer);
+      BindFramebuffer(DRAW_FRAMEBUFFER, self.texture_framebuffer);
+      BlitFramebuffer(
+        0,
+        0,
+        self.width,
+        self.height,
+        0,
+        0,
+        self.width,
+        self.height,
+        COLOR_BUFFER_BIT,
+        NEAREST,
+      );
+
+      BindFramebuffer(READ_FRAMEBUFFER, prev_read_framebuffer as u32);
+      BindFramebuffer(DRAW_FRAMEBUFFER, prev_draw_framebuffer as u32);
+    }
+  }
+
+  /// Read back what the engine rendered, for debugging.
+  ///
+  /// Must be called with a GL context current, after [`GLBackingStore::resolve`].
+  pub unsafe fn read_back(&self) -> Image {
+    unsafe {
+      Image::read_framebuffer(
+        self.texture_framebuffer,
+        gl::COLOR_ATTACHMENT0,
+        self.width as u32,
+        self.height as u32,
+      )
+    }
+  }
+
+  /// Bytes of GPU memory taken by the store. Every color format has 4 bytes per pixel, as does
+  /// the depth/stencil buffer.
+  pub fn memory(&self) -> usize {
+    let pixels = self.width as usize * self.height as usize;
+    let samples = self
+      .multisample
+      .as_ref()
+      .map(|multisample| multisample.samples);
+    // the texture, and the multisampled color buffer and depth/stencil buffer per sample
+    let buffers = 1 + samples.unwrap_or(0) + self.depth_stencil.map_or(0, |_| samples.unwrap_or(1));
+    pixels * 4 * buffers
+  }
+
+  /// Whether the engine may render into the store, which it can't while the compositor
+  /// still reads the dmabuf.
+  pub fn is_reusable(&self) -> bool {
+    self
+      .dmabuf
+      .as_ref()
+      .is_none_or(|dmabuf| dmabuf.buffer.is_released())
+  }
+
+  /// Must be called with a GL context current.
+  pub unsafe fn destroy(self) {
+    use gl::*;
+
+    unsafe {
+      if let Some(multisample) = &self.multisample {
+        DeleteFramebuffers(1, &multisample.framebuffer);
+        DeleteRenderbuffers(1, &multisample.color);
+      }
+      DeleteFramebuffers(1, &self.texture_framebuffer);
+      DeleteTextures(1, &self.texture);
+      if let Some(depth_stencil) = &self.depth_stencil {
+        DeleteRenderbuffers(1, depth_stencil);
+      }
+    }
+  }
+}
+
+/// Pixels the engine rasterizes on the CPU, see [`crate::opengl::RenderOptions::software`].
+///
+/// The memory is shared with the compositor, so a store covering the whole surface can be
+/// attached as is. Otherwise it's uploaded to `texture` and blitted like GL stores.
+#[derive(Debug)]
+pub struct SoftwareBackingStore {
+  pub width: GLsizei,
+  pub height: GLsizei,
+  pub buffer: ShmBuffer,
+  pub texture: GLuint,
+}
+
+impl SoftwareBackingStore {
+  /// Must be called with a GL context current.
+  pub unsafe fn new(shm: &Shm, width: GLsizei, height: GLsizei) -> Result<Self> {
+    use gl::*;
+
+    let buffer = shm.create_buffer(width as u32, height as u32)?;
+    unsafe {
+      let mut prev_texture = 0;
+      GetIntegerv(TEXTURE_BINDING_2D, &mut prev_texture);
+      let mut texture: GLuint = 0;
+      GenTextures(1, &mut texture);
+      BindTexture(TEXTURE_2D, texture);
+      TexParameteri(TEXTURE_2D, TEXTURE_WRAP_S, CLAMP_TO_EDGE as _);
+      TexParameteri(TEXTURE_2D, TEXTURE_WRAP_T, CLAMP_TO_EDGE as _);
+      TexParameteri(TEXTURE_2D, TEXTURE_MIN_FILTER, NEAREST as _);
+      TexParameteri(TEXTURE_2D, TEXTURE_MAG_FILTER, NEAREST as _);
+      // the pixels are B, G, R, A bytes, which OpenGL ES can't upload as is
+      TexParameteri(TEXTURE_2D, TEXTURE_SWIZZLE_R, BLUE as _);
+      TexParameteri(TEXTURE_2D, TEXTURE_SWIZZLE_B, RED as _);
+      TexImage2D(
+        TEXTURE_2D,
+        0,
+        RGBA8 as _,
+        width,
+        height,
+        0,
+        RGBA,
+        UNSIGNED_BYTE,
+        std::ptr::null(),
+      );
+      BindTexture(TEXTURE_2D, prev_texture as u32);
+      Ok(Self {
+        width,
+        height,
+        buffer,
+        texture,
+      })
+    }
+  }
+
+  /// Copy the pixels into `texture` for the blit.
+  ///
+  /// Must be called with a GL context current. Bindings and pixel store state are preserved.
+  pub unsafe fn upload(&self) {
+    use gl::*;
+
+    unsafe {
+      let get = |name| {
+        let mut value = 0;
+        GetIntegerv(name, &mut value);
+        value
+      };
+      let prev_texture = get(TEXTURE_BINDING_2D);
+      let prev_unpack_buffer = get(PIXEL_UNPACK_BUFFER_BINDING);
+      let prev_alignment = get(UNPACK_ALIGNMENT);
+      let prev_row_length = get(UNPACK_ROW_LENGTH);
+
+      BindTexture(TEXTURE_2D, self.texture);
+      BindBuffer(PIXEL_UNPACK_BUFFER, 0);
+      PixelStorei(UNPACK_ALIGNMENT, 4);
+      PixelStorei(UNPACK_ROW_LENGTH, 0);
+      TexSubImage2D(
+        TEXTURE_2D,
+        0,
+        0,
+        0,
+        self.width,
+        self.height,
+        RGBA,
+        UNSIGNED_BYTE,
+        self.buffer.data() as _,
+      );
+
+      BindTexture(TEXTURE_2D, prev_texture as u32);
+      BindBuffer(PIXEL_UNPACK_BUFFER, prev_unpack_buffer as u32);
+      PixelStorei(UNPACK_ALIGNMENT, prev_alignment);
+      PixelStorei(UNPACK_ROW_LENGTH, prev_row_length);
+    }
+  }
+
+  /// The pixels as they are, for debugging
+  pub fn read_back(&self) -> Image {
+    let data = self
+      .buffer
+      .bytes()
+      .chunks_exact(4)
+      .flat_map(|bgra| [bgra[2], bgra[1], bgra[0], bgra[3]])
+      .collect();
+    let mut image = Image {
+      width: self.width as u32,
+      height: self.height as u32,
+      data,
+    };
+    image.unpremultiply();
+    image
+  }
+
+  /// Must be called with a GL context current.
+  pub unsafe fn destroy(self) {
+    unsafe { gl::DeleteTextures(1, &self.texture) };
+  }
+}
+
+/// Backing stores kept between frames.
+///
+/// With zero-copy presenting, the engine is told not to cache backing stores itself, because
+/// it would render into a store whose dmabuf is still on screen. Instead, collected stores come
+/// back here and are handed out again once the compositor released them.
+#[derive(Debug, Default)]
+pub struct BackingStorePool {
+  /// oldest first
+  stores: Mutex<Vec<GLBackingStore>>,
+  /// drop the pooled stores with the next one put back, on the raster thread
+  trim_requested: AtomicBool,
+}
+
+/// of one size, enough for double buffering with a couple of layers
+const MAX_POOLED_PER_SIZE: usize = 8;
+/// of all sizes, for views of different sizes. Sizes no view has anymore age out.
+const MAX_POOLED: usize = 16;
+
+impl BackingStorePool {
+  pub fn take(&self, width: GLsizei, height: GLsizei) -> Option<GLBackingStore> {
+    let mut stores = self.stores.lock();
+    let index = stores
+      .iter()
+      .position(|store| store.width == width && store.height == height && store.is_reusable())?;
+    Some(stores.swap_remove(index))
+  }
+
+  /// Must be called with a GL context current.
+  pub unsafe fn put(&self, store: GLBackingStore) {
+    let mut stores = self.stores.lock();
+    if self.trim_requested.swap(false, Ordering::Relaxed) {
+      log::debug!("dropping {} pooled backing stores", stores.len());
+      for pooled in stores.drain(..) {
+        unsafe { pooled.destroy() };
+      }
+    }
+    let same_size =
+      |pooled: &GLBackingStore| pooled.width == store.width && pooled.height == store.height;
+    // the oldest of the same size, or of any size
+    let index = if stores.iter().filter(|pooled| same_size(pooled)).count() >= MAX_POOLED_PER_SIZE {
+      stores.iter().position(same_size)
+    } else {
+      (stores.len() >= MAX_POOLED).then_some(0)
+    };
+    let evicted = index.map(|index| stores.remove(index));
+    stores.push(store);
+    drop(stores);
+    if let Some(evicted) = evicted {
+      unsafe { evicted.destroy() };
+    }
+  }
+
+  /// Bytes of GPU memory taken by the pooled stores
+  pub fn memory(&self) -> usize {
+    self.stores.lock().iter().map(GLBackingStore::memory).sum()
+  }
+
+  /// Free the memory of idle stores, under memory pressure.
+  pub fn request_trim(&self) {
+    self.trim_requested.store(true, Ordering::Relaxed);
+  }
+}
@@ -1,98 +1,154 @@
 use std::ffi::c_void;
+use std::num::NonZero;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 
+use anyhow::Context;
+use gl::types::GLsizei;
 use glutin::surface::GlSurface;
+use glutin::surface::SwapInterval;
+use wayland_client::protocol::wl_surface::WlSurface;
 
 use crate::FlutterEngineState;
+use crate::callback::catch_panic;
+use crate::callback::engine_state;
 use crate::compositor::FlutterViewKind;
+use crate::compositor::NonZeroSize;
+use crate::compositor::OpaqueRegion;
 use crate::compositor::ViewId;
+use crate::compositor::Visibility;
+use crate::compositor::backing_store::DmabufStorage;
+use crate::compositor::backing_store::GLBackingStore;
+use crate::compositor::backing_store::SoftwareBackingStore;
+use crate::compositor::capture::FrameDump;
+use crate::compositor::capture::Image;
+use crate::compositor::hud;
 use crate::error_in_callback;
 use crate::ffi;
+use crate::opengl::blit::BlitLayer;
+use crate::opengl::blit::Tint;
+use crate::opengl::fence::GpuFence;
+use crate::opengl::gbm::DRM_FORMAT_ABGR8888;
+use crate::systemd;
+use crate::trace_span;
+
+/// Premultiplied colors of `--debug-damage`, one per frame in turn so that consecutive repaints
+/// of a region stand out
+const DAMAGE_TINTS: [[f32; 4]; 4] = [
+  [0.3, 0.0, 0.0, 0.3],
+  [0.0, 0.3, 0.0, 0.3],
+  [0.0, 0.0, 0.3, 0.3],
+  [0.3, 0.3, 0.0, 0.3],
+];
+
+/// frames presented with `--debug-damage`, picking the tint
+static DAMAGE_FRAMES: AtomicUsize = AtomicUsize::new(0);
 
 pub extern "C" fn create_backing_store_callback(
   config: *const ffi::FlutterBackingStoreConfig,
   backing_store_out: *mut ffi::FlutterBackingStore,
   user_data: *mut c_void,
 ) -> bool {
-  let state = unsafe { &*(user_data as *const FlutterEngineState) };
-
-  let backing_store = unsafe { &mut *backing_store_out };
-  if backing_store.struct_size < size_of::<ffi::FlutterBackingStore>() {
-    let ret = anyhow::Result::<()>::Err(anyhow::anyhow!("Invalid backing store ABI"));
-    error_in_callback!(state, ret);
-  }
-
-  let config = unsafe { &*config };
-  let width = unsafe { config.size.width.to_int_unchecked() };
-  let height = unsafe { config.size.height.to_int_unchecked() };
-
-  error_in_callback!(state, state.opengl_state.make_current_no_surface());
-
-  let (framebuffer, texture, renderbuffer) = unsafe {
-    use gl::types::*;
-    use gl::*;
-
-    let mut framebuffer: GLuint = 0;
-    GenFramebuffers(1, &mut framebuffer);
-    BindFramebuffer(FRAMEBUFFER, framebuffer);
-
-    let mut texture: GLuint = 0;
-    GenTextures(1, &mut texture);
-    BindTexture(TEXTURE_2D, texture);
-    TexParameteri(TEXTURE_2D, TEXTURE_WRAP_S, CLAMP_TO_EDGE as _);
-    TexParameteri(TEXTURE_2D, TEXTURE_WRAP_T, CLAMP_TO_EDGE as _);
-    TexParameteri(TEXTURE_2D, TEXTURE_MIN_FILTER, NEAREST as _);
-    TexParameteri(TEXTURE_2D, TEXTURE_MAG_FILTER, NEAREST as _);
-    TexImage2D(
-      TEXTURE_2D,
-      0,
-      RGBA8 as _,
-      width,
-      height,
-      0,
-      RGBA,
-      UNSIGNED_BYTE,
-      std::ptr::null_mut(),
-    );
-    BindTexture(TEXTURE_2D, 0);
-    FramebufferTexture2D(FRAMEBUFFER, COLOR_ATTACHMENT0, TEXTURE_2D, texture, 0);
-
-    let mut renderbuffer: GLuint = 0;
-    GenRenderbuffers(1, &mut renderbuffer);
-    BindRenderbuffer(RENDERBUFFER, renderbuffer);
-    RenderbufferStorage(RENDERBUFFER, DEPTH24_STENCIL8, width, height);
-    BindRenderbuffer(RENDERBUFFER, 0);
-    FramebufferRenderbuffer(
-      FRAMEBUFFER,
-      DEPTH_STENCIL_ATTACHMENT,
-      RENDERBUFFER,
-      renderbuffer,
-    );
-
-    (framebuffer, texture, renderbuffer)
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return false;
   };
+  catch_panic(Some(state), false, || {
+    let _span = trace_span!("create backing store");
+    let backing_store = unsafe { &mut *backing_store_out };
+    if backing_store.struct_size < size_of::<ffi::FlutterBackingStore>() {
+      let ret = anyhow::Result::<()>::Err(anyhow::anyhow!("Invalid backing store ABI"));
+      error_in_callback!(state, ret);
+    }
 
-  error_in_callback!(state, state.opengl_state.make_not_current());
-
-  extern "C" fn destruction_callback(_: *mut c_void) {} // destruct in collect_backing_store_callback
-
-  backing_store.user_data = user_data;
-  backing_store.type_ = ffi::FlutterBackingStoreType_kFlutterBackingStoreTypeOpenGL;
-  backing_store.did_update = false;
-  backing_store.__bindgen_anon_1 = ffi::FlutterBackingStore__bindgen_ty_1 {
-    open_gl: ffi::FlutterOpenGLBackingStore {
-      type_: ffi::FlutterOpenGLTargetType_kFlutterOpenGLTargetTypeFramebuffer,
-      __bindgen_anon_1: ffi::FlutterOpenGLBackingStore__bindgen_ty_1 {
-        framebuffer: ffi::FlutterOpenGLFramebuffer {
-          target: gl::RGBA8,
-          name: framebuffer,
-          user_data: Box::into_raw(Box::new((framebuffer, texture, renderbuffer))) as _,
+    let config = unsafe { &*config };
+    let width = unsafe { config.size.width.to_int_unchecked() };
+    let height = unsafe { config.size.height.to_int_unchecked() };
+
+    error_in_callback!(state, state.opengl_state.make_current_no_surface());
+    state
+      .frame_stats
+      .raster_started(unsafe { ffi::FlutterEngineGetCurrentTime() });
+
+    extern "C" fn destruction_callback(_: *mut c_void) {} // destruct in collect_backing_store_callback
+
+    if let Some(shm) = &state.compositor.shm {
+      let software_backing_store = error_in_callback!(state, unsafe {
+        SoftwareBackingStore::new(shm, width, height)
+      });
+      backing_store.user_data = user_data;
+      backing_store.type_ = ffi::FlutterBackingStoreType_kFlutterBackingStoreTypeSoftware2;
+      backing_store.did_update = false;
+      backing_store.__bindgen_anon_1 = ffi::FlutterBackingStore__bindgen_ty_1 {
+        software2: ffi::FlutterSoftwareBackingStore2 {
+          struct_size: size_of::<ffi::FlutterSoftwareBackingStore2>(),
+          allocation: software_backing_store.buffer.data() as _,
+          row_bytes: software_backing_store.buffer.stride,
+          height: height as usize,
+          user_data: Box::into_raw(Box::new(software_backing_store)) as _,
           destruction_callback: Some(destruction_callback),
+          // ARGB8888 of wl_shm
+          pixel_format: ffi::FlutterSoftwarePixelFormat_kFlutterSoftwarePixelFormatBGRA8888,
         },
+      };
+      return true;
+    }
+
+    let options = &state.opengl_state.options;
+    let gl_backing_store = match state.compositor.backing_stores.take(width, height) {
+      Some(gl_backing_store) => gl_backing_store,
+      None => unsafe {
+        let dmabuf = allocate_dmabuf(state, width, height);
+        let gl_backing_store = GLBackingStore::new(
+          width,
+          height,
+          options.msaa_samples,
+          options.texture_targets,
+          dmabuf,
+        );
+        if options.gl_debug
+          && let Err(e) = gl_backing_store.check_complete()
+        {
+          log::error!("Backing store of {}x{}: {:#}", width, height, e);
+        }
+        gl_backing_store
       },
-    },
-  };
+    };
+
+    backing_store.user_data = user_data;
+    backing_store.type_ = ffi::FlutterBackingStoreType_kFlutterBackingStoreTypeOpenGL;
+    backing_store.did_update = false;
+    let open_gl = if options.texture_targets {
+      ffi::FlutterOpenGLBackingStore {
+        type_: ffi::FlutterOpenGLTargetType_kFlutterOpenGLTargetTypeTexture,
+        __bindgen_anon_1: ffi::FlutterOpenGLBackingStore__bindgen_ty_1 {
+          texture: ffi::FlutterOpenGLTexture {
+            target: gl::TEXTURE_2D,
+            name: gl_backing_store.texture,
+            format: gl::RGBA8,
+            width: width as usize,
+            height: height as usize,
+            user_data: Box::into_raw(Box::new(gl_backing_store)) as _,
+            destruction_callback: Some(destruction_callback),
+          },
+        },
+      }
+    } else {
+      ffi::FlutterOpenGLBackingStore {
+        type_: ffi::FlutterOpenGLTargetType_kFlutterOpenGLTargetTypeFramebuffer,
+        __bindgen_anon_1: ffi::FlutterOpenGLBackingStore__bindgen_ty_1 {
+          framebuffer: ffi::FlutterOpenGLFramebuffer {
+            target: gl::RGBA8,
+            name: gl_backing_store.render_framebuffer(),
+            user_data: Box::into_raw(Box::new(gl_backing_store)) as _,
+            destruction_callback: Some(destruction_callback),
+          },
+        },
+      }
+    };
+    backing_store.__bindgen_anon_1 = ffi::FlutterBackingStore__bindgen_ty_1 { open_gl };
 
-  true
+    true
+  })
 }
 
 pub extern "C" fn collect_backing_store_callback(
@@ -100,159 +156,488 @@ pub extern "C" fn collect_backing_store_callback(
   user_data: *mut c_void,
 ) -> bool {
   let backing_store = unsafe { &*backing_store };
-  let state = unsafe { &*(user_data as *const FlutterEngineState) };
-  error_in_callback!(state, state.opengl_state.make_current_no_surface());
+  let Some(state) = (unsafe { engine_state(user_data) }) else {
+    return false;
+  };
+  catch_panic(Some(state), false, || {
+    error_in_callback!(state, state.opengl_state.make_current_no_surface());
+
+    unsafe {
+      let store = &backing_store.__bindgen_anon_1;
+      match backing_store.type_ {
+        ffi::FlutterBackingStoreType_kFlutterBackingStoreTypeOpenGL => {
+          let user_data = gl_user_data(&store.open_gl);
+          state
+            .compositor
+            .backing_stores
+            .put(*Box::from_raw(user_data));
+        }
+        ffi::FlutterBackingStoreType_kFlutterBackingStoreTypeSoftware => {
+          Box::from_raw(store.software.user_data as *mut SoftwareBackingStore).destroy();
+        }
+        ffi::FlutterBackingStoreType_kFlutterBackingStoreTypeSoftware2 => {
+          Box::from_raw(store.software2.user_data as *mut SoftwareBackingStore).destroy();
+        }
+        _ => unreachable!("only OpenGL and software backing stores are created"),
+      }
+    };
 
-  unsafe {
-    use gl::types::*;
-    use gl::*;
-    let user_data = backing_store
-      .__bindgen_anon_1
-      .open_gl
-      .__bindgen_anon_1
-      .framebuffer
-      .user_data as *mut (GLuint, GLuint, GLuint);
-    let (framebuffer, texture, renderbuffer) = *Box::from_raw(user_data);
-    DeleteFramebuffers(1, &framebuffer);
-    DeleteTextures(1, &texture);
-    DeleteRenderbuffers(1, &renderbuffer);
+    true
+  })
+}
+
+/// `None` without zero-copy presenting or if the allocation fails, leaving the storage to GL.
+fn allocate_dmabuf(state: &FlutterEngineState, width: i32, height: i32) -> Option<DmabufStorage> {
+  let opengl_state = &state.opengl_state;
+  let allocator = opengl_state.dmabuf_allocator.as_ref()?;
+  let linux_dmabuf = state.compositor.linux_dmabuf.as_ref()?;
+  let modifiers = linux_dmabuf.modifiers(DRM_FORMAT_ABGR8888);
+  if modifiers.is_empty() {
+    log::debug!("The compositor doesn't take ABGR8888 dmabufs (yet)");
+    return None;
+  }
+  let image = allocator.allocate(
+    &opengl_state.egl_display,
+    width as u32,
+    height as u32,
+    DRM_FORMAT_ABGR8888,
+    &modifiers,
+  );
+  let image = match image {
+    Ok(image) => image,
+    Err(e) => {
+      log::warn!("Failed to allocate a dmabuf backing store: {:#}", e);
+      return None;
+    }
   };
+  let buffer = linux_dmabuf.create_buffer(image.dmabuf());
+  Some(DmabufStorage { image, buffer })
+}
 
-  error_in_callback!(state, state.opengl_state.make_not_current());
+/// The backing store of `layer` if it covers the whole surface of physical `size` on its own
+/// and the compositor can take its memory as is.
+unsafe fn full_surface_backing_store(
+  layer: &ffi::FlutterLayer,
+  size: NonZeroSize,
+) -> Option<BackingStore<'_>> {
+  if layer.type_ != ffi::FlutterLayerContentType_kFlutterLayerContentTypeBackingStore
+    || layer.offset.x != 0.0
+    || layer.offset.y != 0.0
+  {
+    return None;
+  }
+  let backing_store = unsafe { backing_store_of(&*layer.__bindgen_anon_1.backing_store) };
+  let (width, height) = match backing_store {
+    BackingStore::Gl(gl_backing_store) => {
+      gl_backing_store.dmabuf.as_ref()?;
+      (gl_backing_store.width, gl_backing_store.height)
+    }
+    BackingStore::Software(software_backing_store) => {
+      (software_backing_store.width, software_backing_store.height)
+    }
+  };
+  (width as u32 == size.width.get() && height as u32 == size.height.get()).then_some(backing_store)
+}
 
-  true
+unsafe fn gl_user_data(open_gl: &ffi::FlutterOpenGLBackingStore) -> *mut GLBackingStore {
+  unsafe {
+    let user_data = match open_gl.type_ {
+      ffi::FlutterOpenGLTargetType_kFlutterOpenGLTargetTypeTexture => {
+        open_gl.__bindgen_anon_1.texture.user_data
+      }
+      _ => open_gl.__bindgen_anon_1.framebuffer.user_data,
+    };
+    user_data as *mut GLBackingStore
+  }
+}
+
+/// What [`create_backing_store_callback`] put into a backing store
+#[derive(Clone, Copy)]
+enum BackingStore<'a> {
+  Gl(&'a GLBackingStore),
+  Software(&'a SoftwareBackingStore),
+}
+
+unsafe fn backing_store_of(backing_store: &ffi::FlutterBackingStore) -> BackingStore<'_> {
+  unsafe {
+    let store = &backing_store.__bindgen_anon_1;
+    match backing_store.type_ {
+      ffi::FlutterBackingStoreType_kFlutterBackingStoreTypeOpenGL => {
+        BackingStore::Gl(&*gl_user_data(&store.open_gl))
+      }
+      ffi::FlutterBackingStoreType_kFlutterBackingStoreTypeSoftware => {
+        BackingStore::Software(&*(store.software.user_data as *const _))
+      }
+      ffi::FlutterBackingStoreType_kFlutterBackingStoreTypeSoftware2 => {
+        BackingStore::Software(&*(store.software2.user_data as *const _))
+      }
+      _ => unreachable!("only OpenGL and software backing stores are created"),
+    }
+  }
+}
+
+/// For engines without multi-view support, which present the implicit view only
+pub extern "C" fn present_layers_callback(
+  layers: *mut *const ffi::FlutterLayer,
+  layers_count: usize,
+  user_data: *mut c_void,
+) -> bool {
+  let present_info = ffi::FlutterPresentViewInfo {
+    struct_size: size_of::<ffi::FlutterPresentViewInfo>(),
+    // the implicit view
+    view_id: 0,
+    layers,
+    layers_count,
+    user_data,
+  };
+  present_view_callback(&present_info)
 }
 
 pub extern "C" fn present_view_callback(present_info: *const ffi::FlutterPresentViewInfo) -> bool {
   let present_info = unsafe { &*present_info };
   let view_id = ViewId::new(present_info.view_id);
-  let state = unsafe { &*(present_info.user_data as *const FlutterEngineState) };
-  let view = match state.compositor.get_view(view_id) {
-    Some(view) => view,
-    None => {
-      log::warn!("{} not found", view_id);
-      return false;
-    }
+  let Some(state) = (unsafe { engine_state(present_info.user_data) }) else {
+    return false;
   };
+  catch_panic(Some(state), false, || {
+    let _span = trace_span!("present", view = %view_id);
+    error_in_callback!(
+      state,
+      state.compositor.collect_retired_views(&state.opengl_state)
+    );
+    let view = match state.compositor.get_view(view_id) {
+      Some(view) => view,
+      None => {
+        log::warn!("{} not found", view_id);
+        return false;
+      }
+    };
 
-  match &view.kind {
-    FlutterViewKind::LayerSurface(layer_surface_view) => {
-      let opengl_state = &state.opengl_state;
-      let egl_surface = &layer_surface_view.egl_surface.lock();
+    if view.is_closed() {
+      // removed from the engine soon
+      state.frame_stats.frame_dropped();
+      return true;
+    }
+    let visibility = view.visibility.lock();
+    if *visibility != Visibility::Shown {
+      state.frame_stats.frame_dropped();
+      return true;
+    }
 
-      let (view_width, view_height, should_resize) = {
-        let mut guard = view.size.lock();
-        let should_resize = guard.1;
-        guard.1 = false;
-        (guard.0.width, guard.0.height, should_resize)
-      };
-      if should_resize {
-        egl_surface.resize(&opengl_state.render_context, view_width, view_height);
-        error_in_callback!(state, opengl_state.make_current(egl_surface));
+    match &view.kind {
+      FlutterViewKind::Surface(surface_view) => {
+        let opengl_state = &state.opengl_state;
+        let mut egl_surface = surface_view.egl_surface.lock();
         error_in_callback!(
           state,
-          egl_surface.swap_buffers(&opengl_state.render_context)
+          surface_view.drop_lost_egl_surface(&mut egl_surface, opengl_state),
+          for view_id
         );
-        error_in_callback!(
-          state,
-          state.task_runner_handle.post_task(|engine| {
-            let _ = engine.schedule_frame();
+
+        let layers = unsafe { *present_info.layers };
+        let layers = unsafe { std::slice::from_raw_parts(layers, present_info.layers_count) };
+
+        // Only switch the surface to a new size once a frame of that size arrives, so that
+        // frames rendered for the old size are never stretched.
+        let frame_size = layers.first().and_then(|layer| {
+          Some(NonZeroSize {
+            width: NonZero::new(layer.size.width.round() as u32)?,
+            height: NonZero::new(layer.size.height.round() as u32)?,
           })
-        );
-        return false;
-      }
+        });
+        let presented =
+          frame_size.and_then(|size| Some((view.geometry.lock().present(size)?, size)));
+        let Some((applied, size)) = presented else {
+          log::debug!(
+            "{}: dropped a frame of outdated size {:?}",
+            view_id,
+            frame_size
+          );
+          state.frame_stats.frame_dropped();
+          return true;
+        };
+        if let Some(applied) = applied {
+          if matches!(*view.opaque_region.lock(), OpaqueRegion::Full) {
+            error_in_callback!(state, state.compositor.apply_opaque_region(&view));
+          }
+          error_in_callback!(
+            state,
+            surface_view.resize_egl_surface(&mut egl_surface, opengl_state, size),
+            for view_id
+          );
+          let wl_surface = surface_view.role.wl_surface();
+          let geometry = applied.geometry;
+          if geometry.is_reduced() {
+            // upscaled by the compositor to the logical size
+            wl_surface.set_buffer_scale(1);
+          } else {
+            wl_surface.set_buffer_scale(geometry.scale.get() as i32);
+          }
+          if let Some(viewport) = &view.viewport {
+            let size = geometry.logical_size;
+            viewport.set_destination(
+              geometry
+                .is_reduced()
+                .then_some((size.width.get(), size.height.get())),
+            );
+          }
+          if let Some(serial) = applied.configure_serial {
+            surface_view.role.ack_configure(serial);
+          }
+        }
 
-      error_in_callback!(state, opengl_state.make_current(egl_surface));
-
-      let layers = unsafe { *present_info.layers };
-      let layers = unsafe { std::slice::from_raw_parts(layers, present_info.layers_count) };
-
-      for layer in layers {
-        let ffi::FlutterPoint {
-          x: offset_x,
-          y: offset_y,
-        } = layer.offset;
-        let offset_x: i32 = unsafe { offset_x.to_int_unchecked() };
-        let offset_y: i32 = unsafe { offset_y.to_int_unchecked() };
-        let ffi::FlutterSize { width, height } = layer.size;
-        let width: i32 = unsafe { width.to_int_unchecked() };
-        let height: i32 = unsafe { height.to_int_unchecked() };
-        let paint_region = unsafe { &*(*layer.backing_store_present_info).paint_region };
-        let paint_region =
-          unsafe { std::slice::from_raw_parts(paint_region.rects, paint_region.rects_count) };
-        let presentation_time = layer.presentation_time;
-
-        log::info!(
-          "offset: ({}, {}), size: ({}, {}), presentation_time: {}",
-          offset_x,
-          offset_y,
-          width,
-          height,
-          presentation_time
+        if egl_surface.is_none() {
+          // dropped after it was lost
+          error_in_callback!(
+            state,
+            surface_view.resize_egl_surface(&mut egl_surface, opengl_state, size),
+            for view_id
+          );
+        }
+        let egl_surface = error_in_callback!(
+          state,
+          egl_surface
+            .as_ref()
+            .context("no EGL surface without a configured size")
         );
-        log::info!("paint_region: {:?}", paint_region);
-
-        match layer.type_ {
-          ffi::FlutterLayerContentType_kFlutterLayerContentTypeBackingStore => {
-            let backing_store = unsafe { &*layer.__bindgen_anon_1.backing_store };
-
-            unsafe {
-              use gl::types::*;
-              use gl::*;
-
-              let (_, texture, _) = *(backing_store
-                .__bindgen_anon_1
-                .open_gl
-                .__bindgen_anon_1
-                .framebuffer
-                .user_data as *mut (GLuint, GLuint, GLuint));
-
-              // save
-              let mut prev_array_buffer = 0;
-              GetIntegerv(ARRAY_BUFFER_BINDING, &mut prev_array_buffer);
-              let mut prev_vertex_array = 0;
-              GetIntegerv(VERTEX_ARRAY_BINDING, &mut prev_vertex_array);
-              let mut prev_draw_framebuffer = 0;
-              GetIntegerv(DRAW_FRAMEBUFFER_BINDING, &mut prev_draw_framebuffer);
-              let mut prev_texture = 0;
-              GetIntegerv(TEXTURE_BINDING_2D, &mut prev_texture);
-
-              BindFramebuffer(DRAW_FRAMEBUFFER, 0);
-
-              // https://github.com/NVIDIA/egl-wayland/issues/48
-              // THANK YOU AMBIGUOUS BIG STATE MACHINE. THANK YOU EGL and OpenGL.
-              DrawBuffer(BACK);
-
-              // TODO: offset, size, paint_region, presentation_time
-              BindVertexArray(opengl_state.vertex_array);
-              BindBuffer(ARRAY_BUFFER, opengl_state.vertex_buffer);
-              BindTexture(TEXTURE_2D, texture);
-              UseProgram(opengl_state.program);
-              DrawArrays(TRIANGLES, 0, 6);
-              error_in_callback!(
-                state,
-                egl_surface.swap_buffers(&opengl_state.render_context)
+        error_in_callback!(state, opengl_state.make_current(egl_surface), for view_id);
+        error_in_callback!(state, unsafe { opengl_state.check_reset() });
+
+        let allow_tearing = view.allows_tearing();
+        if surface_view
+          .swap_async
+          .swap(allow_tearing, Ordering::Relaxed)
+          != allow_tearing
+        {
+          // a blocking swap would wait for vsync regardless of the presentation hint
+          let interval = if allow_tearing {
+            SwapInterval::DontWait
+          } else {
+            SwapInterval::Wait(NonZero::new(1).unwrap())
+          };
+          error_in_callback!(
+            state,
+            egl_surface.set_swap_interval(&opengl_state.render_context, interval),
+            for view_id
+          );
+        }
+
+        let mut surface_sync = surface_view
+          .surface_sync
+          .as_ref()
+          .map(|surface_sync| surface_sync.lock());
+        match &mut surface_sync {
+          // the acquire point set after drawing covers the engine's rendering as well
+          Some(surface_sync) => error_in_callback!(state, surface_sync.wait_for_release()),
+          None => {
+            // make sure the engine finished rendering into the backing stores before they're read
+            let fence = unsafe { opengl_state.fence_kind.insert(&opengl_state.egl_display) };
+            error_in_callback!(state, fence.and_then(|fence| fence.wait()));
+          }
+        }
+
+        let wl_surface = surface_view.role.wl_surface();
+        let capture_requests = view.take_capture_requests();
+        let dump_requests = view.take_dump_requests();
+        let dim = view.dim();
+        let debug_damage = opengl_state.options.debug_damage;
+        let hud_shown = state.hud.is_shown();
+        // captures and dumps read back the blitted frame, dimming draws behind the app, damage
+        // tints and the HUD over it. A buffer of the engine's carries no sync points, which a
+        // syncobj surface requires with every buffer.
+        if surface_view.surface_sync.is_none()
+          && capture_requests.is_empty()
+          && dump_requests.is_empty()
+          && dim == 0.0
+          && !debug_damage
+          && !hud_shown
+          && let [layer] = layers
+          && let Some(backing_store) = unsafe { full_surface_backing_store(layer, size) }
+        {
+          match backing_store {
+            BackingStore::Gl(gl_backing_store) => {
+              unsafe {
+                gl_backing_store.resolve();
+                // the resolve must reach the GPU before the compositor samples the buffer
+                gl::Flush();
+              }
+              let dmabuf = gl_backing_store.dmabuf.as_ref().expect("checked above");
+              dmabuf.buffer.attach(wl_surface);
+              // GL renders bottom-up
+              surface_view.set_buffer_flipped(true);
+            }
+            BackingStore::Software(software_backing_store) => {
+              software_backing_store.buffer.attach(wl_surface);
+              surface_view.set_buffer_flipped(false);
+            }
+          }
+          wl_surface.damage_buffer(0, 0, i32::MAX, i32::MAX);
+          request_feedback(state, wl_surface);
+          if view.mark_frame_callback_requested() {
+            state.frame_clock.request_frame_callback(wl_surface);
+          }
+          wl_surface.commit();
+          return true;
+        }
+
+        let mut blit_layers = Vec::with_capacity(layers.len());
+        let mut tints = Vec::new();
+        let mut dumped_layers = Vec::new();
+        let tint_color = debug_damage.then(|| {
+          let frame = DAMAGE_FRAMES.fetch_add(1, Ordering::Relaxed);
+          DAMAGE_TINTS[frame % DAMAGE_TINTS.len()]
+        });
+        for layer in layers {
+          let ffi::FlutterPoint {
+            x: offset_x,
+            y: offset_y,
+          } = layer.offset;
+          let ffi::FlutterSize { width, height } = layer.size;
+          let presentation_time = layer.presentation_time;
+
+          log::trace!(
+            "offset: ({}, {}), size: ({}, {}), presentation_time: {}",
+            offset_x,
+            offset_y,
+            width,
+            height,
+            presentation_time
+          );
+
+          match layer.type_ {
+            ffi::FlutterLayerContentType_kFlutterLayerContentTypeBackingStore => {
+              let paint_region = unsafe { &*(*layer.backing_store_present_info).paint_region };
+              let paint_region =
+                unsafe { std::slice::from_raw_parts(paint_region.rects, paint_region.rects_count) };
+              log::trace!("paint_region: {:?}", paint_region);
+              if let Some(color) = tint_color {
+                tints.extend(paint_region.iter().map(|rect| Tint {
+                  rect: ffi::FlutterRect {
+                    left: rect.left + offset_x,
+                    top: rect.top + offset_y,
+                    right: rect.right + offset_x,
+                    bottom: rect.bottom + offset_y,
+                  },
+                  color,
+                }));
+              }
+
+              match unsafe { backing_store_of(&*layer.__bindgen_anon_1.backing_store) } {
+                BackingStore::Gl(gl_backing_store) => {
+                  unsafe { gl_backing_store.resolve() };
+                  if !dump_requests.is_empty() {
+                    let image = unsafe { gl_backing_store.read_back() };
+                    dumped_layers.push(((offset_x, offset_y), image));
+                  }
+                  blit_layers.push(BlitLayer {
+                    texture: gl_backing_store.texture,
+                    offset: layer.offset,
+                    size: layer.size,
+                  });
+                }
+                BackingStore::Software(software_backing_store) => {
+                  unsafe { software_backing_store.upload() };
+                  if !dump_requests.is_empty() {
+                    dumped_layers.push(((offset_x, offset_y), software_backing_store.read_back()));
+                  }
+                  // rows are uploaded top to bottom, unlike what GL renders, so flip the quad
+                  blit_layers.push(BlitLayer {
+                    texture: software_backing_store.texture,
+                    offset: ffi::FlutterPoint {
+                      x: offset_x,
+                      y: offset_y + height,
+                    },
+                    size: ffi::FlutterSize {
+                      width,
+                      height: -height,
+                    },
+                  });
+                }
+              }
+            }
+            ffi::FlutterLayerContentType_kFlutterLayerContentTypePlatformView => {
+              let platform_view = unsafe { &*layer.__bindgen_anon_1.platform_view };
+              log::warn!(
+                "There's no platform views now. Ignored. (id: {})",
+                platform_view.identifier
               );
+            }
+            _ => unreachable!(),
+          }
+        }
+
+        if hud_shown {
+          let scale = view
+            .geometry
+            .lock()
+            .current
+            .map_or(1.0, |current| current.pixel_ratio());
+          tints.extend(hud::draw(state, scale));
+        }
 
-              // restore
-              BindBuffer(ARRAY_BUFFER, prev_array_buffer as u32);
-              BindVertexArray(prev_vertex_array as u32);
-              BindFramebuffer(DRAW_FRAMEBUFFER, prev_draw_framebuffer as u32);
-              BindTexture(TEXTURE_2D, prev_texture as u32);
+        unsafe {
+          opengl_state.blitter.blit(
+            size.width.get() as GLsizei,
+            size.height.get() as GLsizei,
+            opengl_state.options.srgb,
+            dim,
+            &blit_layers,
+            &tints,
+          );
+
+          if !capture_requests.is_empty() || !dump_requests.is_empty() {
+            let image = Image::read_back(size.width.get(), size.height.get());
+            for request in capture_requests {
+              let _ = request.send(image.clone());
+            }
+            let dump = FrameDump {
+              layers: dumped_layers,
+              surface: image,
+            };
+            for request in dump_requests {
+              let _ = request.send(dump.clone());
             }
           }
-          ffi::FlutterLayerContentType_kFlutterLayerContentTypePlatformView => {
-            let platform_view = unsafe { &*layer.__bindgen_anon_1.platform_view };
-            log::warn!(
-              "There's no platform views now. Ignored. (id: {})",
-              platform_view.identifier
-            );
+
+          request_feedback(state, wl_surface);
+          if view.mark_frame_callback_requested() {
+            state.frame_clock.request_frame_callback(wl_surface);
+          }
+          if let Some(surface_sync) = &mut surface_sync {
+            let fence = opengl_state.fence_kind.insert(&opengl_state.egl_display);
+            let result = fence.and_then(|fence| match &fence {
+              GpuFence::Native(sync_file) => surface_sync.set_points(sync_file),
+              _ => anyhow::bail!("explicit sync requires a native fence"),
+            });
+            error_in_callback!(state, result);
           }
-          _ => unreachable!(),
+          // the window surface is presented upright, unlike the GBM buffers attached directly
+          surface_view.set_buffer_flipped(false);
+          error_in_callback!(
+            state,
+            egl_surface.swap_buffers(&opengl_state.render_context),
+            for view_id
+          );
         }
-      }
 
-      true
+        true
+      }
     }
+  })
+}
+
+/// Request presentation feedback for the frame about to be committed, or finish its timing right
+/// away if there won't be any.
+fn request_feedback(state: &FlutterEngineState, wl_surface: &WlSurface) {
+  let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
+  state.heartbeat.frame_committed(now);
+  systemd::ready();
+  let frame = state.frame_stats.committed(now);
+  if !state.frame_clock.request_feedback(wl_surface, frame) {
+    let ret = state.task_runner_handle.post_task(move |engine| {
+      let state = engine.state();
+      state.frame_stats.finish(engine, frame, None);
+    });
+    error_in_callback!(state, ret, return ());
   }
 }
@@ -0,0 +1,163 @@
+use std::fs::File;
+#[cfg(feature = "golden")]
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::Context;
+use anyhow::Result;
+use futures::channel::oneshot;
+use gl::types::GLenum;
+use gl::types::GLint;
+use gl::types::GLsizei;
+use gl::types::GLuint;
+
+/// Receives the next frame presented on a view, see [`super::FlutterView::request_capture`].
+pub type CaptureReceiver = oneshot::Receiver<Image>;
+
+/// Receives the pieces of the next frame presented on a view, see
+/// [`super::FlutterView::request_dump`].
+pub type DumpReceiver = oneshot::Receiver<FrameDump>;
+
+/// A presented frame read back from the GPU
+#[derive(Debug, Clone)]
+pub struct Image {
+  pub width: u32,
+  pub height: u32,
+  /// RGBA8 rows from top to bottom, not premultiplied
+  pub data: Vec<u8>,
+}
+
+/// A presented frame with the backing stores it was composited from
+#[derive(Debug, Clone)]
+pub struct FrameDump {
+  /// bottom to top, with their offsets in physical pixels of the surface
+  pub layers: Vec<((f64, f64), Image)>,
+  pub surface: Image,
+}
+
+impl Image {
+  /// Read the back buffer of the current window surface, i.e. the frame about to be swapped.
+  ///
+  /// Must be called with the render context current.
+  pub unsafe fn read_back(width: u32, height: u32) -> Self {
+    unsafe { Self::read_framebuffer(0, gl::BACK, width, height) }
+  }
+
+  /// Read `buffer` of `framebuffer`. Bindings and pixel store state are preserved.
+  ///
+  /// Must be called with a GL context current.
+  pub unsafe fn read_framebuffer(
+    framebuffer: GLuint,
+    buffer: GLenum,
+    width: u32,
+    height: u32,
+  ) -> Self {
+    let mut data = vec![0u8; width as usize * height as usize * 4];
+    unsafe {
+      let mut read_framebuffer = 0;
+      let mut pack_buffer = 0;
+      let mut pack_alignment = 0;
+      let mut read_buffer = 0;
+      gl::GetIntegerv(gl::READ_FRAMEBUFFER_BINDING, &mut read_framebuffer);
+      gl::GetIntegerv(gl::PIXEL_PACK_BUFFER_BINDING, &mut pack_buffer);
+      gl::GetIntegerv(gl::PACK_ALIGNMENT, &mut pack_alignment);
+
+      gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+      gl::GetIntegerv(gl::READ_BUFFER, &mut read_buffer);
+      gl::ReadBuffer(buffer);
+      gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+      gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+      gl::ReadPixels(
+        0,
+        0,
+        width as GLsizei,
+        height as GLsizei,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        data.as_mut_ptr() as _,
+      );
+
+      gl::ReadBuffer(read_buffer as _);
+      gl::BindFramebuffer(gl::READ_FRAMEBUFFER, read_framebuffer as _);
+      gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pack_buffer as _);
+      gl::PixelStorei(gl::PACK_ALIGNMENT, pack_alignment as GLint);
+    }
+
+    // GL reads bottom-up
+    let stride = width as usize * 4;
+    let data = data
+      .chunks_exact(stride)
+      .rev()
+      .flatten()
+      .copied()
+      .collect::<Vec<_>>();
+    let mut image = Self {
+      width,
+      height,
+      data,
+    };
+    image.unpremultiply();
+    image
+  }
+
+  /// The engine renders and the blit composites with premultiplied alpha.
+  pub fn unpremultiply(&mut self) {
+    for pixel in self.data.chunks_exact_mut(4) {
+      let alpha = pixel[3] as u32;
+      if alpha == 0 || alpha == 255 {
+        continue;
+      }
+      for channel in &mut pixel[..3] {
+        *channel = (*channel as u32 * 255 / alpha).min(255) as u8;
+      }
+    }
+  }
+
+  pub fn write_png(&self, path: &Path) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("failed to create {:?}", path))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&self.data)?;
+    writer.finish()?;
+    Ok(())
+  }
+  /// Any PNG, converted to RGBA8 rows
+  #[cfg(feature = "golden")]
+  pub fn read_png(path: &Path) -> Result<Self> {
+    let file = File::open(path).with_context(|| format!("failed to open {:?}", path))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+      .read_info()
+      .with_context(|| format!("invalid PNG {:?}", path))?;
+    let mut data = vec![
+      0;
+      reader
+        .output_buffer_size()
+        .context("the PNG is too large")?
+    ];
+    let info = reader.next_frame(&mut data)?;
+    data.truncate(info.buffer_size());
+    let data = match info.color_type {
+      png::ColorType::Rgba => data,
+      png::ColorType::Rgb => data
+        .chunks_exact(3)
+        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+        .collect(),
+      png::ColorType::GrayscaleAlpha => data
+        .chunks_exact(2)
+        .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+        .collect(),
+      png::ColorType::Grayscale => data.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+      png::ColorType::Indexed => unreachable!("expanded by the transformations"),
+    };
+    Ok(Self {
+      width: info.width,
+      height: info.height,
+      data,
+    })
+  }
+}
@@ -0,0 +1,367 @@
+use std::num::NonZero;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde_json::Map;
+use serde_json::Value;
+use serde_json::json;
+
+use crate::FlutterEngine;
+use crate::channel::MethodCall;
+use crate::channel::MethodError;
+use crate::channel::MethodResult;
+use crate::compositor::FlutterView;
+use crate::compositor::LogicalRect;
+use crate::compositor::NonZeroSize;
+use crate::compositor::OpaqueRegion;
+use crate::compositor::ViewId;
+use crate::compositor::animation::Curve;
+use crate::compositor::layer::ContentType;
+use crate::compositor::layer::ExclusiveZone;
+use crate::compositor::layer::LayerProps;
+
+/// Methods controlling the surfaces behind views
+pub const CHANNEL: &str = "wayflutter/view";
+
+pub fn handle_method_call(engine: &FlutterEngine, call: &MethodCall) -> Option<MethodResult> {
+  match call.method.as_str() {
+    "addView" => Some(add_view(engine, call)),
+    "addOverlayView" => Some(add_overlay_view(engine, call)),
+    "dismissView" => Some(dismiss_view(engine, call)),
+    "addInputPopupView" => Some(add_input_popup_view(engine, call)),
+    "removeView" => Some(remove_view(engine, call)),
+    "hideView" => Some(hide_view(engine, call)),
+    "showView" => Some(show_view(engine, call)),
+    "getViewOutput" => Some(get_view_output(engine, call)),
+    "getViews" => Some(get_views(engine)),
+    "setOpaqueRegion" => Some(set_opaque_region(engine, call)),
+    "setAllowTearing" => Some(set_allow_tearing(engine, call)),
+    "setExclusiveZone" => Some(set_exclusive_zone(engine, call)),
+    "setMaxFps" => Some(set_max_fps(engine, call)),
+    "setContentType" => Some(set_content_type(engine, call)),
+    "setInhibitShortcuts" => Some(set_inhibit_shortcuts(engine, call)),
+    "animateView" => Some(animate_view(engine, call)),
+    _ => None,
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AddInputPopupViewArgs {
+  /// in logical pixels
+  width: NonZero<u32>,
+  height: NonZero<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RemoveViewArgs {
+  view_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ViewArgs {
+  #[serde(default)]
+  view_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetViewOutputArgs {
+  #[serde(default)]
+  view_id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetOpaqueRegionArgs {
+  #[serde(default)]
+  view_id: i64,
+  /// in logical pixels. `null` goes back to the default for the view.
+  rects: Option<Vec<LogicalRect>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetAllowTearingArgs {
+  #[serde(default)]
+  view_id: i64,
+  allow: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetExclusiveZoneArgs {
+  #[serde(default)]
+  view_id: i64,
+  zone: ExclusiveZone,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetMaxFpsArgs {
+  #[serde(default)]
+  view_id: i64,
+  /// `null` for no limit
+  fps: Option<NonZero<u32>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetContentTypeArgs {
+  #[serde(default)]
+  view_id: i64,
+  content_type: ContentType,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetInhibitShortcutsArgs {
+  #[serde(default)]
+  view_id: i64,
+  inhibit: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AnimateViewArgs {
+  #[serde(default)]
+  view_id: i64,
+  /// top, right, bottom, left. Unchanged if missing.
+  margin: Option<[i32; 4]>,
+  exclusive_zone: Option<ExclusiveZone>,
+  /// in milliseconds
+  duration: u64,
+  #[serde(default)]
+  curve: Curve,
+}
+
+fn get_view(engine: &FlutterEngine, view_id: i64) -> Result<Arc<FlutterView>, MethodError> {
+  let state = engine.state();
+  let view_id = ViewId::new(view_id);
+  state
+    .compositor
+    .get_view(view_id)
+    .ok_or_else(|| MethodError::new("no_view", format!("{} not found", view_id)))
+}
+
+/// Returns the id of the new view right away. It's usable once the engine adds it, after the
+/// compositor configured its size.
+fn add_view(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let props: LayerProps = call.args()?;
+  let state = engine.state();
+  let view_id = state.compositor.add_view(engine, &props)?;
+  Ok(Value::from(view_id.raw()))
+}
+
+/// Like `addView` starting from a surface covering the whole output above everything else
+/// and taking the keyboard. The arguments, if any, override its props, e.g. `{"dim": 0.5}`.
+fn add_overlay_view(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let patch: Option<Map<String, Value>> = call.args()?;
+  let props = LayerProps::overlay()
+    .patched(patch.unwrap_or_default())
+    .map_err(|e| MethodError::new("invalid_args", format!("{:#}", e)))?;
+  let state = engine.state();
+  let view_id = state.compositor.add_view(engine, &props)?;
+  Ok(Value::from(view_id.raw()))
+}
+
+/// Remove an added view, or hide the implicit view, which the control socket can show again.
+fn dismiss_view(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: ViewArgs = call.args()?;
+  let state = engine.state();
+  state
+    .compositor
+    .dismiss_view(engine, ViewId::new(args.view_id))?;
+  Ok(Value::Null)
+}
+
+/// Unmap the surface of a layer view. The engine and the state of the app stay alive.
+fn hide_view(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: ViewArgs = call.args()?;
+  let state = engine.state();
+  state.compositor.hide_view(ViewId::new(args.view_id))?;
+  Ok(Value::Null)
+}
+
+/// Map a hidden view again. It's drawn once the compositor configured it.
+fn show_view(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: ViewArgs = call.args()?;
+  let state = engine.state();
+  state.compositor.show_view(ViewId::new(args.view_id))?;
+  Ok(Value::Null)
+}
+
+/// Returns the id of the new view, shown while a text input is focused.
+fn add_input_popup_view(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: AddInputPopupViewArgs = call.args()?;
+  let state = engine.state();
+  let view_id = state.compositor.add_input_popup_view(
+    engine,
+    NonZeroSize {
+      width: args.width,
+      height: args.height,
+    },
+  )?;
+  Ok(Value::from(view_id.raw()))
+}
+
+/// The view is gone once the engine confirms the removal.
+fn remove_view(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: RemoveViewArgs = call.args()?;
+  let state = engine.state();
+  state
+    .compositor
+    .remove_view(engine, ViewId::new(args.view_id))?;
+  Ok(Value::Null)
+}
+
+/// The name of the output the view was created for, like `DP-1`, or `null` if the compositor
+/// placed it.
+fn get_view_output(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: GetViewOutputArgs = call.args()?;
+  let view = get_view(engine, args.view_id)?;
+  let name = view.output.as_ref().and_then(|output| output.name.clone());
+  Ok(Value::from(name))
+}
+
+/// Every view with what identifies its surface: `viewId`, `name`, `namespace` and `output`,
+/// and whether it has the keyboard `focused`.
+/// Input popups have no name and namespace.
+fn get_views(engine: &FlutterEngine) -> MethodResult {
+  let state = engine.state();
+  let focus = state.compositor.keyboard_focus();
+  let views = state
+    .compositor
+    .views()
+    .iter()
+    .map(|view| {
+      let props = view.layer_props();
+      json!({
+        "viewId": view.view_id.raw(),
+        "name": props.as_ref().and_then(|props| props.name.clone()),
+        "namespace": props.map(|props| props.namespace),
+        "output": view.output.as_ref().and_then(|output| output.name.clone()),
+        "focused": focus == Some(view.view_id),
+      })
+    })
+    .collect();
+  Ok(Value::Array(views))
+}
+
+fn set_opaque_region(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: SetOpaqueRegionArgs = call.args()?;
+  let state = engine.state();
+  let view = get_view(engine, args.view_id)?;
+  let region = match args.rects {
+    Some(rects) => OpaqueRegion::Rects(rects),
+    None => view.default_opaque_region(),
+  };
+  *view.opaque_region.lock() = region;
+  state.compositor.apply_opaque_region(&view)?;
+  // opaque regions are double-buffered, commit them with the next frame
+  engine.schedule_frame()?;
+  Ok(Value::Null)
+}
+
+fn set_allow_tearing(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: SetAllowTearingArgs = call.args()?;
+  let view = get_view(engine, args.view_id)?;
+  view
+    .set_allow_tearing(args.allow)
+    .map_err(|e| MethodError::new("unsupported", format!("{:#}", e)))?;
+  Ok(Value::Null)
+}
+
+/// A number of logical pixels, or `"auto"` to follow the size of the surface
+fn set_exclusive_zone(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: SetExclusiveZoneArgs = call.args()?;
+  let state = engine.state();
+  let view = get_view(engine, args.view_id)?;
+  let mut props = view.layer_props().ok_or_else(|| {
+    MethodError::new(
+      "not_layer",
+      format!("{} is not a layer surface", view.view_id),
+    )
+  })?;
+  props.exclusive_zone = args.zone;
+  state
+    .compositor
+    .set_layer_props(engine, view.view_id, &props)?;
+  Ok(Value::Null)
+}
+
+/// Frames per second the view needs at most, see [`LayerProps::max_fps`]
+fn set_max_fps(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: SetMaxFpsArgs = call.args()?;
+  let state = engine.state();
+  let view = get_view(engine, args.view_id)?;
+  let mut props = view.layer_props().ok_or_else(|| {
+    MethodError::new(
+      "not_layer",
+      format!("{} is not a layer surface", view.view_id),
+    )
+  })?;
+  props.max_fps = args.fps;
+  state
+    .compositor
+    .set_layer_props(engine, view.view_id, &props)?;
+  Ok(Value::Null)
+}
+
+/// `none`, `photo`, `video` or `game`, see [`LayerProps::content_type`]
+fn set_content_type(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: SetContentTypeArgs = call.args()?;
+  let state = engine.state();
+  let view = get_view(engine, args.view_id)?;
+  let mut props = view.layer_props().ok_or_else(|| {
+    MethodError::new(
+      "not_layer",
+      format!("{} is not a layer surface", view.view_id),
+    )
+  })?;
+  props.content_type = args.content_type;
+  state
+    .compositor
+    .set_layer_props(engine, view.view_id, &props)?;
+  Ok(Value::Null)
+}
+
+/// Receive the keys the compositor would take as shortcuts while the view has the keyboard
+/// focus
+fn set_inhibit_shortcuts(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: SetInhibitShortcutsArgs = call.args()?;
+  let view = get_view(engine, args.view_id)?;
+  engine
+    .state()
+    .compositor
+    .set_inhibit_shortcuts(view.view_id, args.inhibit)
+    .map_err(|e| MethodError::new("unsupported", format!("{:#}", e)))?;
+  Ok(Value::Null)
+}
+
+/// Slide the surface by tweening its margin and exclusive zone, e.g.
+/// `{"margin": [0, 0, 0, 0], "duration": 200, "curve": "easeOut"}` for a panel hidden behind a
+/// negative margin. The curve is one of `linear`, `easeIn`, `easeOut` and `easeInOut`.
+fn animate_view(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: AnimateViewArgs = call.args()?;
+  let state = engine.state();
+  let view = get_view(engine, args.view_id)?;
+  let props = view.layer_props().ok_or_else(|| {
+    MethodError::new(
+      "not_layer",
+      format!("{} is not a layer surface", view.view_id),
+    )
+  })?;
+  state.compositor.animate_view(
+    engine,
+    view.view_id,
+    args.margin.unwrap_or(props.margin),
+    args.exclusive_zone.unwrap_or(props.exclusive_zone),
+    Duration::from_millis(args.duration),
+    args.curve,
+  )?;
+  Ok(Value::Null)
+}
@@ -0,0 +1,148 @@
+//! An overlay of performance numbers in the top left corner of every view, toggled over the
+//! control socket. The embedder draws it in the blit after the app, from its own bookkeeping,
+//! so it keeps telling the truth while Dart janks.
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use crate::FlutterEngineState;
+use crate::ffi;
+use crate::frame_stats::Durations;
+use crate::opengl::blit::Tint;
+
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+/// with a column between glyphs
+const ADVANCE: usize = GLYPH_WIDTH + 1;
+/// with two rows between lines
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 2;
+/// glyph pixels around the text
+const PADDING: usize = 2;
+/// physical pixels per glyph pixel at scale 1
+const PIXEL_SIZE: f64 = 2.0;
+
+const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.7];
+const FOREGROUND: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+#[derive(Default)]
+pub struct Hud {
+  shown: AtomicBool,
+}
+
+impl Hud {
+  pub fn is_shown(&self) -> bool {
+    self.shown.load(Ordering::Relaxed)
+  }
+
+  /// Show the overlay if it's hidden, hide it otherwise. Returns whether it's shown now.
+  pub fn toggle(&self) -> bool {
+    !self.shown.fetch_xor(true, Ordering::Relaxed)
+  }
+}
+
+/// The overlay for a surface of `scale`, in its physical pixels
+pub fn draw(state: &FlutterEngineState, scale: f64) -> Vec<Tint> {
+  let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
+  let summary = state.frame_stats.summary();
+  let row = |name: &str, durations: Option<Durations>| match durations {
+    Some(durations) => format!(
+      "{:<8}{:>6.1}{:>6.1}{:>6.1}",
+      name, durations.p50, durations.p90, durations.p99
+    ),
+    None => format!("{:<8}{:>6}{:>6}{:>6}", name, "-", "-", "-"),
+  };
+  let lines = [
+    format!("FPS {}", state.frame_stats.fps(now)),
+    format!("{:<8}{:>6}{:>6}{:>6}", "MS", "P50", "P90", "P99"),
+    row("BUILD", summary.build),
+    row("RASTER", summary.raster),
+    row("PRESENT", summary.present),
+    format!("TASKS {}", state.task_runner_handle.pending()),
+    format!(
+      "POOL {:.1}MB",
+      state.compositor.backing_stores.memory() as f64 / (1 << 20) as f64
+    ),
+  ];
+
+  let pixel = PIXEL_SIZE * scale;
+  let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+  let mut tints = vec![Tint {
+    rect: ffi::FlutterRect {
+      left: 0.0,
+      top: 0.0,
+      right: ((columns * ADVANCE - 1 + 2 * PADDING) as f64) * pixel,
+      bottom: ((lines.len() * LINE_HEIGHT - 2 + 2 * PADDING) as f64) * pixel,
+    },
+    color: BACKGROUND,
+  }];
+  for (i, line) in lines.iter().enumerate() {
+    text(&mut tints, line, PADDING, PADDING + i * LINE_HEIGHT, pixel);
+  }
+  tints
+}
+
+/// One rectangle per run of lit glyph pixels in a row, starting at glyph pixel `(x, y)`
+fn text(tints: &mut Vec<Tint>, line: &str, x: usize, y: usize, pixel: f64) {
+  for row in 0..GLYPH_HEIGHT {
+    let lit = line.chars().flat_map(|c| {
+      let bits = glyph(c)[row];
+      (0..ADVANCE)
+        .map(move |column| column < GLYPH_WIDTH && bits >> (GLYPH_WIDTH - 1 - column) & 1 == 1)
+    });
+    let mut run_start = None;
+    for (column, lit) in lit.chain([false]).enumerate() {
+      match (lit, run_start) {
+        (true, None) => run_start = Some(column),
+        (false, Some(start)) => {
+          tints.push(Tint {
+            rect: ffi::FlutterRect {
+              left: (x + start) as f64 * pixel,
+              top: (y + row) as f64 * pixel,
+              right: (x + column) as f64 * pixel,
+              bottom: (y + row + 1) as f64 * pixel,
+            },
+            color: FOREGROUND,
+          });
+          run_start = None;
+        }
+        _ => {}
+      }
+    }
+  }
+}
+
+/// Rows of a 3x5 glyph, the leftmost pixel in the highest bit. Blank for characters the
+/// overlay never shows.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+  match c {
+    '0' => [7, 5, 5, 5, 7],
+    '1' => [2, 6, 2, 2, 7],
+    '2' => [7, 1, 7, 4, 7],
+    '3' => [7, 1, 7, 1, 7],
+    '4' => [5, 5, 7, 1, 1],
+    '5' => [7, 4, 7, 1, 7],
+    '6' => [7, 4, 7, 5, 7],
+    '7' => [7, 1, 1, 1, 1],
+    '8' => [7, 5, 7, 5, 7],
+    '9' => [7, 5, 7, 1, 7],
+    'A' => [2, 5, 7, 5, 5],
+    'B' => [6, 5, 6, 5, 6],
+    'D' => [6, 5, 5, 5, 6],
+    'E' => [7, 4, 6, 4, 7],
+    'F' => [7, 4, 6, 4, 4],
+    'I' => [7, 2, 2, 2, 7],
+    'K' => [5, 5, 6, 5, 5],
+    'L' => [4, 4, 4, 4, 7],
+    'M' => [5, 7, 7, 5, 5],
+    'N' => [6, 5, 5, 5, 5],
+    'O' => [2, 5, 5, 5, 2],
+    'P' => [6, 5, 6, 4, 4],
+    'R' => [6, 5, 6, 5, 5],
+    'S' => [3, 4, 2, 1, 6],
+    'T' => [7, 2, 2, 2, 2],
+    'U' => [5, 5, 5, 5, 7],
+    '.' => [0, 0, 0, 0, 2],
+    '-' => [0, 0, 7, 0, 0],
+    _ => [0; GLYPH_HEIGHT],
+  }
+}
@@ -0,0 +1,100 @@
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::FlutterEngine;
+use crate::channel::MethodCall;
+use crate::channel::MethodResult;
+use crate::wayland::input_method::TextInputState;
+
+/// Method channel and event channel (`EventChannel` with `JSONMethodCodec` on the Dart side)
+/// for typing into the focused text input from an on-screen keyboard. Listening makes wayflutter
+/// the input method of the seat and streams the text input's state, `{"active",
+/// "surroundingText": {"text", "cursor", "anchor"}, "changedByInputMethod", "contentHint",
+/// "contentPurpose"}`, whenever it changes. The `commit` method edits it.
+pub const CHANNEL: &str = "wayflutter/input_method";
+
+/// Changes to the text input, applied at once in the order of the fields
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+struct CommitArgs {
+  /// bytes before and after the cursor to delete, or the selection if both are 0
+  delete_before: u32,
+  delete_after: u32,
+  /// inserted at the cursor
+  text: Option<String>,
+  /// shown at the cursor until replaced by a later commit, none if `None`
+  preedit: Option<Preedit>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct Preedit {
+  text: String,
+  /// byte offsets into `text` the cursor spans, hidden if -1
+  cursor_begin: i32,
+  cursor_end: i32,
+}
+
+pub fn handle_method_call(engine: &FlutterEngine, call: &MethodCall) -> Option<MethodResult> {
+  let state = engine.state();
+  match call.method.as_str() {
+    "listen" => Some(listen(engine)),
+    "cancel" => {
+      state
+        .compositor
+        .input_method_listening
+        .store(false, Ordering::Relaxed);
+      Some(Ok(Value::Null))
+    }
+    "commit" => Some(commit(engine, call)),
+    _ => None,
+  }
+}
+
+fn listen(engine: &FlutterEngine) -> MethodResult {
+  let state = engine.state();
+  // the state comes with the next `done`
+  state.compositor.with_input_method(|_| Ok(()))?;
+  state
+    .compositor
+    .input_method_listening
+    .store(true, Ordering::Relaxed);
+  Ok(Value::Null)
+}
+
+fn commit(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: CommitArgs = call.args()?;
+  let state = engine.state();
+  state.compositor.with_input_method(|input_method| {
+    // the order the compositor applies them in anyway
+    if args.delete_before > 0 || args.delete_after > 0 {
+      input_method.delete_surrounding_text(args.delete_before, args.delete_after);
+    }
+    if let Some(text) = args.text {
+      input_method.commit_string(text);
+    }
+    if let Some(preedit) = args.preedit {
+      input_method.set_preedit_string(preedit.text, preedit.cursor_begin, preedit.cursor_end);
+    }
+    input_method.commit()
+  })?;
+  Ok(Value::Null)
+}
+
+/// Tell Dart the text input changed, if it listens.
+///
+/// Must be called on the platform thread.
+pub fn done(engine: &FlutterEngine, text_input: TextInputState) -> Result<()> {
+  let state = engine.state();
+  if !state
+    .compositor
+    .input_method_listening
+    .load(Ordering::Relaxed)
+  {
+    return Ok(());
+  }
+  crate::channel::send_event(engine, CHANNEL, serde_json::to_value(text_input)?)
+}
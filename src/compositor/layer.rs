@@ -0,0 +1,396 @@
+use std::num::NonZero;
+use std::str::FromStr;
+
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use serde::Serializer;
+use serde::de::Error as _;
+use serde_json::Map;
+use serde_json::Value;
+use smithay_client_toolkit::reexports::protocols::wp::content_type::v1::client::wp_content_type_v1;
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1;
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1;
+use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::ZwlrLayerSurfaceV1;
+use wayland_client::Proxy;
+use wayland_client::protocol::wl_output::WlOutput;
+
+use crate::compositor::NonZeroSize;
+use crate::compositor::ViewId;
+use crate::compositor::animation::Placement;
+use crate::compositor::auto_hide::AutoHide;
+use crate::wayland::layer_shell::CreateLayerSurfaceProp;
+use crate::wayland::layer_shell::LayerSurfaceEventListener;
+use crate::wayland::layer_shell::Margin;
+use crate::wayland::layer_shell::Size;
+
+/// How a view's layer surface is placed, (de)serialized as `camelCase` JSON.
+///
+/// The default is a background covering the whole output, like the implicit view.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct LayerProps {
+  pub layer: Layer,
+  /// edges the surface sticks to
+  pub anchor: Vec<Edge>,
+  /// in logical pixels. 0 stretches the surface between opposite anchors.
+  pub width: u32,
+  pub height: u32,
+  pub exclusive_zone: ExclusiveZone,
+  /// top, right, bottom, left
+  pub margin: [i32; 4],
+  pub keyboard_interactivity: KeyboardInteractivity,
+  pub namespace: String,
+  /// tells the surface apart in the app and on the control socket, unlike the namespace
+  /// which is for the compositor
+  pub name: Option<String>,
+  /// connector name of the output to show the surface on, like `DP-1`, or part of its
+  /// description. `None` leaves it to the compositor.
+  pub output: Option<String>,
+  /// opacity of the black drawn behind the app, from 0 to 1
+  pub dim: f32,
+  /// lets pointer and touch input through to the surfaces below
+  pub click_through: bool,
+  /// slides the surface out along its anchored edge while the pointer is away
+  pub auto_hide: Option<AutoHide>,
+  /// dismissed like a menu on Escape and when it loses the keyboard focus, e.g. to a click
+  /// elsewhere
+  pub popup: bool,
+  /// Draw at most this many frames per second, e.g. 30 for a status bar that doesn't need
+  /// the full refresh rate. Frames are shared by all views, so they're only limited while
+  /// every view is.
+  pub max_fps: Option<NonZero<u32>>,
+  /// what the surface shows, for the compositor to pick scaling and latency policies, e.g.
+  /// `video` for a video wallpaper
+  pub content_type: ContentType,
+}
+
+impl Default for LayerProps {
+  fn default() -> Self {
+    Self {
+      layer: Layer::Background,
+      anchor: vec![Edge::Top, Edge::Bottom, Edge::Left, Edge::Right],
+      width: 0,
+      height: 0,
+      exclusive_zone: ExclusiveZone::Fixed(0),
+      margin: [0; 4],
+      keyboard_interactivity: KeyboardInteractivity::OnDemand,
+      namespace: "wayflutter".to_owned(),
+      name: None,
+      output: None,
+      dim: 0.0,
+      click_through: false,
+      auto_hide: None,
+      popup: false,
+      max_fps: None,
+      content_type: ContentType::None,
+    }
+  }
+}
+
+impl LayerProps {
+  /// Covers the whole output above everything else and takes the keyboard, for launchers,
+  /// session menus and dialogs.
+  pub fn overlay() -> Self {
+    Self {
+      layer: Layer::Overlay,
+      exclusive_zone: ExclusiveZone::Fixed(-1),
+      keyboard_interactivity: KeyboardInteractivity::Exclusive,
+      ..Default::default()
+    }
+  }
+
+  /// Covers the whole background without taking any input
+  pub fn wallpaper() -> Self {
+    Self {
+      keyboard_interactivity: KeyboardInteractivity::None,
+      click_through: true,
+      ..Default::default()
+    }
+  }
+
+  /// These props with the keys of the `camelCase` JSON object `patch` replaced
+  pub fn patched(&self, patch: Map<String, Value>) -> Result<Self> {
+    let Value::Object(mut merged) = serde_json::to_value(self)? else {
+      unreachable!("layer props are a JSON object");
+    };
+    merged.extend(patch);
+    Ok(serde_json::from_value(Value::Object(merged))?)
+  }
+
+  /// Reject what the compositor would treat as a protocol error.
+  pub fn validate(&self) -> Result<()> {
+    let anchored = |edge| self.anchor.contains(&edge);
+    if self.width == 0 && !(anchored(Edge::Left) && anchored(Edge::Right)) {
+      anyhow::bail!("a width of 0 requires anchoring to both left and right");
+    }
+    if self.height == 0 && !(anchored(Edge::Top) && anchored(Edge::Bottom)) {
+      anyhow::bail!("a height of 0 requires anchoring to both top and bottom");
+    }
+    if self.auto_hide.is_some() && self.exclusive_edge().is_none() {
+      anyhow::bail!(
+        "auto-hiding requires anchoring to a single edge, or to an edge and both its neighbors"
+      );
+    }
+    if !(0.0..=1.0).contains(&self.dim) {
+      anyhow::bail!("dim must be between 0 and 1, got {}", self.dim);
+    }
+    Ok(())
+  }
+
+  /// `output` is left to the compositor if `None`.
+  pub(super) fn to_prop(
+    &self,
+    view_id: ViewId,
+    output: Option<WlOutput>,
+    event_listener: LayerSurfaceEventListener<ViewId>,
+  ) -> CreateLayerSurfaceProp<ViewId> {
+    let [top, right, bottom, left] = self.margin;
+    CreateLayerSurfaceProp::builder()
+      .layer(self.layer.to_wlr())
+      .maybe_output(output)
+      .namespace(self.namespace.clone())
+      .anchor(self.wlr_anchor())
+      .size(Size {
+        width: self.width,
+        height: self.height,
+      })
+      .exclusive_zone(self.exclusive_zone_for(self.width, self.height))
+      .margin(Margin {
+        left,
+        right,
+        top,
+        bottom,
+      })
+      .keyboard_interactivity(self.keyboard_interactivity.to_wlr())
+      .user_data(view_id)
+      .event_listener(event_listener)
+      .build()
+  }
+
+  /// Change an existing surface, except for its namespace and output. Double-buffered like
+  /// on creation. `configured_size` is the logical size an automatic exclusive zone follows
+  /// until the next configure.
+  ///
+  /// The layer is left as is before version 2 of the protocol.
+  pub(super) fn apply(
+    &self,
+    layer_surface: &ZwlrLayerSurfaceV1,
+    configured_size: Option<NonZeroSize>,
+  ) {
+    if layer_surface.version() >= 2 {
+      layer_surface.set_layer(self.layer.to_wlr());
+    }
+    layer_surface.set_anchor(self.wlr_anchor());
+    layer_surface.set_size(self.width, self.height);
+    self.placement(configured_size).apply(layer_surface);
+    layer_surface.set_keyboard_interactivity(self.keyboard_interactivity.to_wlr());
+  }
+
+  /// The margin and exclusive zone, which follows `configured_size` if automatic
+  pub fn placement(&self, configured_size: Option<NonZeroSize>) -> Placement {
+    let (width, height) = self.size_or(configured_size);
+    Placement {
+      margin: self.margin,
+      exclusive_zone: self.exclusive_zone_for(width, height),
+    }
+  }
+
+  /// The placement slid out along the anchored edge, leaving the hotspot of `auto_hide` on
+  /// screen. The exclusive zone shrinks to the hotspot as well.
+  pub fn hidden_placement(
+    &self,
+    auto_hide: &AutoHide,
+    configured_size: Option<NonZeroSize>,
+  ) -> Placement {
+    let mut placement = self.placement(configured_size);
+    let (width, height) = self.size_or(configured_size);
+    let (index, size) = match self.exclusive_edge() {
+      Some(Edge::Top) => (0, height),
+      Some(Edge::Right) => (1, width),
+      Some(Edge::Bottom) => (2, height),
+      Some(Edge::Left) => (3, width),
+      None => return placement,
+    };
+    let offset = size.saturating_sub(auto_hide.hotspot);
+    placement.margin[index] -= offset.try_into().unwrap_or(i32::MAX);
+    if placement.exclusive_zone > 0 {
+      placement.exclusive_zone = auto_hide.hotspot.try_into().unwrap_or(i32::MAX);
+    }
+    placement
+  }
+
+  /// The logical size the surface was configured with, or else the requested one
+  fn size_or(&self, configured_size: Option<NonZeroSize>) -> (u32, u32) {
+    configured_size.map_or((self.width, self.height), |size| {
+      (size.width.get(), size.height.get())
+    })
+  }
+
+  /// The exclusive zone of a surface of logical `width` and `height`
+  pub fn exclusive_zone_for(&self, width: u32, height: u32) -> i32 {
+    let ExclusiveZone::Fixed(zone) = self.exclusive_zone else {
+      let size = match self.exclusive_edge() {
+        Some(Edge::Top | Edge::Bottom) => height,
+        Some(Edge::Left | Edge::Right) => width,
+        None => 0,
+      };
+      return size.try_into().unwrap_or(i32::MAX);
+    };
+    zone
+  }
+
+  /// The edge an exclusive zone is reserved along: the only anchored edge, or the one
+  /// anchored together with both edges next to it.
+  fn exclusive_edge(&self) -> Option<Edge> {
+    let anchored = |edge| self.anchor.contains(&edge);
+    match (
+      anchored(Edge::Top),
+      anchored(Edge::Bottom),
+      anchored(Edge::Left),
+      anchored(Edge::Right),
+    ) {
+      (true, false, left, right) if left == right => Some(Edge::Top),
+      (false, true, left, right) if left == right => Some(Edge::Bottom),
+      (top, bottom, true, false) if top == bottom => Some(Edge::Left),
+      (top, bottom, false, true) if top == bottom => Some(Edge::Right),
+      _ => None,
+    }
+  }
+
+  fn wlr_anchor(&self) -> zwlr_layer_surface_v1::Anchor {
+    self
+      .anchor
+      .iter()
+      .fold(zwlr_layer_surface_v1::Anchor::empty(), |anchor, edge| {
+        anchor | edge.to_wlr()
+      })
+  }
+}
+
+/// Space other surfaces keep clear of along the anchored edge, a number or `"auto"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExclusiveZone {
+  /// in logical pixels. -1 overlaps the zones of other surfaces.
+  Fixed(i32),
+  /// follows the configured size of the surface across the anchored edge
+  Auto,
+}
+
+impl FromStr for ExclusiveZone {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(ExclusiveZone::Auto),
+      _ => s
+        .parse()
+        .map(ExclusiveZone::Fixed)
+        .map_err(|_| format!("expected a number or \"auto\", got {:?}", s)),
+    }
+  }
+}
+
+impl Serialize for ExclusiveZone {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self {
+      ExclusiveZone::Fixed(zone) => serializer.serialize_i32(*zone),
+      ExclusiveZone::Auto => serializer.serialize_str("auto"),
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for ExclusiveZone {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+      Fixed(i32),
+      Keyword(String),
+    }
+    match Repr::deserialize(deserializer)? {
+      Repr::Fixed(zone) => Ok(ExclusiveZone::Fixed(zone)),
+      Repr::Keyword(keyword) => keyword.parse().map_err(D::Error::custom),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum Layer {
+  Background,
+  Bottom,
+  Top,
+  Overlay,
+}
+
+impl Layer {
+  fn to_wlr(self) -> zwlr_layer_shell_v1::Layer {
+    match self {
+      Layer::Background => zwlr_layer_shell_v1::Layer::Background,
+      Layer::Bottom => zwlr_layer_shell_v1::Layer::Bottom,
+      Layer::Top => zwlr_layer_shell_v1::Layer::Top,
+      Layer::Overlay => zwlr_layer_shell_v1::Layer::Overlay,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum Edge {
+  Top,
+  Bottom,
+  Left,
+  Right,
+}
+
+impl Edge {
+  fn to_wlr(self) -> zwlr_layer_surface_v1::Anchor {
+    match self {
+      Edge::Top => zwlr_layer_surface_v1::Anchor::Top,
+      Edge::Bottom => zwlr_layer_surface_v1::Anchor::Bottom,
+      Edge::Left => zwlr_layer_surface_v1::Anchor::Left,
+      Edge::Right => zwlr_layer_surface_v1::Anchor::Right,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum KeyboardInteractivity {
+  None,
+  Exclusive,
+  OnDemand,
+}
+
+impl KeyboardInteractivity {
+  fn to_wlr(self) -> zwlr_layer_surface_v1::KeyboardInteractivity {
+    match self {
+      KeyboardInteractivity::None => zwlr_layer_surface_v1::KeyboardInteractivity::None,
+      KeyboardInteractivity::Exclusive => zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive,
+      KeyboardInteractivity::OnDemand => zwlr_layer_surface_v1::KeyboardInteractivity::OnDemand,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum ContentType {
+  None,
+  Photo,
+  Video,
+  Game,
+}
+
+impl ContentType {
+  pub(super) fn to_wp(self) -> wp_content_type_v1::Type {
+    match self {
+      ContentType::None => wp_content_type_v1::Type::None,
+      ContentType::Photo => wp_content_type_v1::Type::Photo,
+      ContentType::Video => wp_content_type_v1::Type::Video,
+      ContentType::Game => wp_content_type_v1::Type::Game,
+    }
+  }
+}
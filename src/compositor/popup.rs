@@ -0,0 +1,64 @@
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use serde_json::Value;
+use serde_json::json;
+
+use crate::FlutterEngine;
+use crate::channel::MethodCall;
+use crate::channel::MethodResult;
+use crate::compositor::ViewId;
+use crate::compositor::Visibility;
+
+/// Event channel (`EventChannel` with `JSONMethodCodec` on the Dart side) streaming
+/// `{"viewId", "reason"}` when a popup view is dismissed, `reason` being `escape` or
+/// `focusLost`
+pub const CHANNEL: &str = "wayflutter/popup";
+
+pub fn handle_method_call(engine: &FlutterEngine, call: &MethodCall) -> Option<MethodResult> {
+  let state = engine.state();
+  match call.method.as_str() {
+    "listen" => {
+      state
+        .compositor
+        .popup_listening
+        .store(true, Ordering::Relaxed);
+      Some(Ok(Value::Null))
+    }
+    "cancel" => {
+      state
+        .compositor
+        .popup_listening
+        .store(false, Ordering::Relaxed);
+      Some(Ok(Value::Null))
+    }
+    _ => None,
+  }
+}
+
+/// Whether `view_id` is a shown popup, which Escape and losing the keyboard focus dismiss
+pub fn is_popup(engine: &FlutterEngine, view_id: ViewId) -> bool {
+  let state = engine.state();
+  state.compositor.get_view(view_id).is_some_and(|view| {
+    *view.visibility.lock() == Visibility::Shown
+      && view.layer_props().is_some_and(|props| props.popup)
+  })
+}
+
+/// Dismiss a popup like the `dismissView` method and tell Dart why.
+pub fn dismiss(engine: &FlutterEngine, view_id: ViewId, reason: &str) -> Result<()> {
+  let state = engine.state();
+  log::debug!("Dismissing {} ({})", view_id, reason);
+  state.compositor.dismiss_view(engine, view_id)?;
+  if state.compositor.popup_listening.load(Ordering::Relaxed) {
+    crate::channel::send_event(
+      engine,
+      CHANNEL,
+      json!({
+        "viewId": view_id.raw(),
+        "reason": reason,
+      }),
+    )?;
+  }
+  Ok(())
+}
@@ -0,0 +1,339 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::compositor::SurfaceOptions;
+use crate::compositor::layer::LayerProps;
+use crate::dart_vm::DartVmOptions;
+use crate::embedder::CrashRestartOptions;
+use crate::error::ErrorPolicies;
+use crate::exception::ExceptionOptions;
+use crate::opengl::RenderOptions;
+use crate::vm_service::VmServiceOptions;
+use crate::watchdog::WatchdogOptions;
+
+pub mod discover;
+
+/// Everything needed to run an app, from the command line or a TOML file.
+///
+/// Keys in the file are `camelCase`, like the arguments of the `wayflutter/view` channel.
+/// Relative paths are resolved against the directory of the file.
+///
+/// ```toml
+/// assetPath = "build/flutter_assets"
+/// icuDataPath = "/usr/share/flutter/icudtl.dat"
+/// # for release builds
+/// aotLibrary = "build/lib/libapp.so"
+/// engineArgs = ["--dart-flags=--verbose-gc"]
+/// opaque = true
+/// # a later launch opens its route in this instance instead
+/// appId = "org.example.Bar"
+///
+/// [render]
+/// msaaSamples = 4
+/// impeller = true
+///
+/// [vmService]
+/// port = 8181
+/// uriFile = "/tmp/wayflutter-vm-service"
+///
+/// [dartVm]
+/// oldGenHeapSize = 128
+/// serialGc = true
+///
+/// [watchdog]
+/// timeout = 10
+/// terminate = true
+///
+/// [crashRestart]
+/// maxAttempts = 3
+///
+/// [errors]
+/// render = "retry"
+/// surfaceLost = "recreateSurface"
+/// protocol = "terminate"
+///
+/// [exceptions]
+/// file = "/tmp/wayflutter-exceptions.log"
+///
+/// # the implicit view
+/// [[surface]]
+/// name = "bar"
+/// namespace = "bar"
+/// layer = "top"
+/// anchor = ["top", "left", "right"]
+/// height = 32
+/// exclusiveZone = "auto"
+///
+/// # added on startup
+/// [[surface]]
+/// output = "HDMI-A-1"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct Config {
+  /// path to the flutter_assets directory
+  pub asset_path: PathBuf,
+  /// path to icudtl.dat
+  pub icu_data_path: PathBuf,
+  /// path to libflutter_engine.so, see [`discover::engine_library`] for the default
+  pub engine_library: Option<PathBuf>,
+  /// path to the libapp.so of a release or profile build, instead of the kernel_blob.bin in
+  /// the assets
+  pub aot_library: Option<PathBuf>,
+  #[serde(default)]
+  pub render: RenderOptions,
+  /// A D-Bus name like `org.example.Bar`. Launching the app again while it runs hands the
+  /// route to the running instance instead of starting another.
+  pub app_id: Option<String>,
+  /// the route the app starts on, `/` by default
+  pub route: Option<String>,
+  /// switches passed to the engine
+  #[serde(default)]
+  pub engine_args: Vec<String>,
+  #[serde(default)]
+  pub vm_service: VmServiceOptions,
+  #[serde(default)]
+  pub dart_vm: DartVmOptions,
+  /// Run Dart on the platform thread instead of a UI thread of the engine's own, like newer
+  /// embedders do. Older engines ignore this.
+  #[serde(default)]
+  pub merged_ui_thread: bool,
+  /// Leave out what the app `print`s from the log. Uncaught exceptions are logged still.
+  #[serde(default)]
+  pub quiet_dart: bool,
+  #[serde(default)]
+  pub crash_restart: CrashRestartOptions,
+  /// Record embedder spans and frame timings into this file, in the Chrome trace event format
+  /// that Perfetto opens. Written on exit.
+  pub trace_output: Option<PathBuf>,
+  /// Start the engine over whenever the app is rebuilt, i.e. its kernel_blob.bin or AOT library
+  /// changes. The surfaces are created anew from the same config, so they disappear briefly.
+  #[serde(default)]
+  pub hot_restart: bool,
+  /// Run the integration tests of the app: exit once `package:integration_test` reports the
+  /// results, failing if any test failed, and print the URI of the VM service on stdout once
+  /// `flutter_driver` can connect. Needs the VM service.
+  #[serde(default)]
+  pub test: bool,
+  /// Start the engine over once the compositor is back after the connection was lost, e.g.
+  /// when it restarted, instead of exiting with [`ConnectionLost::EXIT_CODE`].
+  ///
+  /// [`ConnectionLost::EXIT_CODE`]: crate::ConnectionLost::EXIT_CODE
+  #[serde(default)]
+  pub reconnect: bool,
+  /// Let the app set the color temperature, gamma and brightness of outputs over the
+  /// `wayflutter/gamma` channel, for a night light. Off by default, as only one client at a
+  /// time controls the gamma of an output.
+  #[serde(default)]
+  pub gamma_control: bool,
+  #[serde(default)]
+  pub watchdog: WatchdogOptions,
+  /// how to go on after errors in rendering and talking to the compositor
+  #[serde(default)]
+  pub errors: ErrorPolicies,
+  #[serde(default)]
+  pub exceptions: ExceptionOptions,
+  /// see [`SurfaceOptions`]
+  #[serde(default)]
+  pub opaque: bool,
+  #[serde(default)]
+  pub allow_tearing: bool,
+  #[serde(default)]
+  pub every_output: bool,
+  pub pixel_ratio: Option<f64>,
+  #[serde(default)]
+  pub low_power: bool,
+  pub low_power_resolution: Option<f64>,
+  /// The first is the implicit view, the others are added on startup. A default implicit view
+  /// if empty.
+  #[serde(default, rename = "surface")]
+  pub surfaces: Vec<LayerProps>,
+  /// the file the config was loaded from, for reloading it
+  #[serde(skip)]
+  pub path: Option<PathBuf>,
+}
+
+impl Config {
+  pub fn load(path: &Path) -> Result<Self> {
+    let text =
+      std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    let mut config: Config =
+      toml::from_str(&text).with_context(|| format!("invalid config {:?}", path))?;
+    if let Some(dir) = path.parent() {
+      config.asset_path = dir.join(&config.asset_path);
+      config.icu_data_path = dir.join(&config.icu_data_path);
+      config.engine_library = config.engine_library.map(|path| dir.join(path));
+      config.aot_library = config.aot_library.map(|path| dir.join(path));
+      config.vm_service.uri_file = config.vm_service.uri_file.map(|path| dir.join(path));
+      config.trace_output = config.trace_output.map(|path| dir.join(path));
+      config.exceptions.file = config.exceptions.file.map(|path| dir.join(path));
+    }
+    config.path = Some(path.to_owned());
+    Ok(config)
+  }
+
+  /// Catch mistakes before anything is started.
+  pub fn validate(&self) -> Result<()> {
+    if !self.asset_path.is_dir() {
+      anyhow::bail!("asset path {:?} is not a directory", self.asset_path);
+    }
+    if !self.icu_data_path.is_file() {
+      anyhow::bail!("ICU data {:?} is not a file", self.icu_data_path);
+    }
+    if let Some(aot_library) = &self.aot_library
+      && !aot_library.is_file()
+    {
+      anyhow::bail!("AOT library {:?} is not a file", aot_library);
+    }
+    if self.vm_service.disable && (self.vm_service.host.is_some() || self.vm_service.port.is_some())
+    {
+      anyhow::bail!("the VM service can't be bound when it's disabled");
+    }
+    if self.test && self.vm_service.disable {
+      anyhow::bail!("tests need the VM service");
+    }
+    if let Some(pixel_ratio) = self.pixel_ratio
+      && !(pixel_ratio.is_finite() && pixel_ratio > 0.0)
+    {
+      anyhow::bail!("pixel ratio must be positive, got {}", pixel_ratio);
+    }
+    if let Some(resolution) = self.low_power_resolution
+      && !(resolution > 0.0 && resolution <= 1.0)
+    {
+      anyhow::bail!("low-power resolution must be in (0, 1], got {}", resolution);
+    }
+    for (i, props) in self.surfaces.iter().enumerate() {
+      props
+        .validate()
+        .with_context(|| format!("invalid surface #{}", i))?;
+      if let Some(name) = &props.name
+        && self.surfaces[..i]
+          .iter()
+          .any(|other| other.name.as_ref() == Some(name))
+      {
+        anyhow::bail!("more than one surface is named {:?}", name);
+      }
+    }
+    if self.every_output
+      && let Some(props) = self.surfaces.first()
+      && props.output.is_some()
+    {
+      anyhow::bail!("the implicit view can't have an output when it's shown on every output");
+    }
+    Ok(())
+  }
+
+  /// The AOT library to run with an engine that runs AOT compiled code or not, looked up in the
+  /// bundle layout of `flutter build linux` (`lib/libapp.so` next to `data/flutter_assets`) if
+  /// not given. Fails if the app can't run on the engine.
+  pub fn resolve_aot_library(&self, engine_runs_aot: bool) -> Result<Option<PathBuf>> {
+    if !engine_runs_aot {
+      if let Some(aot_library) = &self.aot_library {
+        anyhow::bail!(
+          "the engine is a debug build, which can't run the AOT compiled {:?}; use a release or \
+           profile engine",
+          aot_library
+        );
+      }
+      if !self.asset_path.join("kernel_blob.bin").is_file() {
+        anyhow::bail!(
+          "no kernel_blob.bin in {:?}, which a debug engine needs; build the app in debug mode",
+          self.asset_path
+        );
+      }
+      return Ok(None);
+    }
+    if let Some(aot_library) = &self.aot_library {
+      return Ok(Some(aot_library.clone()));
+    }
+    let found = ["../../lib/libapp.so", "../lib/libapp.so"]
+      .into_iter()
+      .map(|candidate| self.asset_path.join(candidate))
+      .find(|path| path.is_file())
+      .with_context(|| {
+        format!(
+          "the engine is a release or profile build, which runs AOT compiled apps only, but no \
+           lib/libapp.so was found next to {:?}; pass it with aotLibrary or use a debug engine",
+          self.asset_path
+        )
+      })?;
+    log::info!("found AOT library {:?}", found);
+    Ok(Some(found))
+  }
+
+  pub fn surface_options(&self) -> SurfaceOptions {
+    SurfaceOptions {
+      opaque: self.opaque,
+      allow_tearing: self.allow_tearing,
+      every_output: self.every_output,
+      pixel_ratio: self.pixel_ratio,
+      low_power: self.low_power,
+      low_power_resolution: self.low_power_resolution,
+    }
+  }
+}
+
+/// `wayflutter/config.toml` in `$XDG_CONFIG_HOME`, or in `~/.config` if that's not set
+pub fn default_path() -> Result<PathBuf> {
+  let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+    Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+    _ => {
+      let home = std::env::var_os("HOME").context("neither XDG_CONFIG_HOME nor HOME is set")?;
+      PathBuf::from(home).join(".config")
+    }
+  };
+  Ok(config_dir.join("wayflutter").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A config of the given TOML, with paths that exist
+  fn config(toml: &str) -> Config {
+    let mut config: Config =
+      toml::from_str(&format!("assetPath = \"\"\nicuDataPath = \"\"\n{}", toml)).unwrap();
+    config.asset_path = std::env::temp_dir();
+    config.icu_data_path = std::env::current_exe().unwrap();
+    config
+  }
+
+  #[test]
+  fn minimal_config_is_valid() {
+    config("").validate().unwrap();
+  }
+
+  #[test]
+  fn missing_paths_are_rejected() {
+    let mut missing_assets = config("");
+    missing_assets.asset_path = missing_assets.icu_data_path.clone();
+    assert!(missing_assets.validate().is_err());
+    let mut missing_aot = config("");
+    missing_aot.aot_library = Some(std::env::temp_dir().join("wayflutter-missing/libapp.so"));
+    assert!(missing_aot.validate().is_err());
+  }
+
+  #[test]
+  fn conflicting_options_are_rejected() {
+    for toml in [
+      "test = true\n[vmService]\ndisable = true",
+      "[vmService]\ndisable = true\nport = 8181",
+      "pixelRatio = 0.0",
+      "lowPowerResolution = 1.5",
+      "[[surface]]\nname = \"bar\"\n[[surface]]\nname = \"bar\"",
+      "everyOutput = true\n[[surface]]\noutput = \"HDMI-A-1\"",
+    ] {
+      assert!(config(toml).validate().is_err(), "{}", toml);
+    }
+  }
+
+  #[test]
+  fn unknown_keys_are_rejected() {
+    let toml = "assetPath = \"a\"\nicuDataPath = \"b\"\nassetsPath = \"c\"";
+    assert!(toml::from_str::<Config>(toml).is_err());
+  }
+}
@@ -0,0 +1,67 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Where `flutter build linux` puts the assets, relative to the executable
+const BUNDLE_ASSET_PATH: &str = "data/flutter_assets";
+
+/// Overrides where the engine library is looked up
+const ENGINE_LIBRARY_VAR: &str = "WAYFLUTTER_ENGINE_LIBRARY";
+
+const ENGINE_LIBRARY_NAME: &str = "libflutter_engine.so";
+
+/// icudtl.dat of packaged engines
+const SYSTEM_ICU_DATA_PATHS: &[&str] = &[
+  "/usr/share/flutter/icudtl.dat",
+  "/usr/lib/flutter/icudtl.dat",
+  "/usr/local/share/flutter/icudtl.dat",
+];
+
+/// `data/flutter_assets` next to the executable, for a wayflutter copied into a bundle
+pub fn asset_path() -> Option<PathBuf> {
+  let path = exe_dir()?.join(BUNDLE_ASSET_PATH);
+  path.is_dir().then_some(path)
+}
+
+/// icudtl.dat next to the assets as in a bundle, or next to the executable, or from the Flutter
+/// SDK in `$FLUTTER_ROOT`, or from a system location
+pub fn icu_data_path(asset_path: &Path) -> Option<PathBuf> {
+  let sdk_arch = match std::env::consts::ARCH {
+    "aarch64" => "linux-arm64",
+    _ => "linux-x64",
+  };
+  let candidates = [
+    asset_path.parent().map(|dir| dir.join("icudtl.dat")),
+    exe_dir().map(|dir| dir.join("data/icudtl.dat")),
+    std::env::var_os("FLUTTER_ROOT").map(|root| {
+      PathBuf::from(root)
+        .join("bin/cache/artifacts/engine")
+        .join(sdk_arch)
+        .join("icudtl.dat")
+    }),
+  ];
+  candidates
+    .into_iter()
+    .flatten()
+    .chain(SYSTEM_ICU_DATA_PATHS.iter().map(PathBuf::from))
+    .find(|path| path.is_file())
+}
+
+/// `$WAYFLUTTER_ENGINE_LIBRARY`, or `lib/libflutter_engine.so` next to the executable as in a
+/// bundle, or else the bare name for the dynamic linker to search `LD_LIBRARY_PATH` and the
+/// system for
+pub fn engine_library() -> PathBuf {
+  if let Some(path) = std::env::var_os(ENGINE_LIBRARY_VAR)
+    && !path.is_empty()
+  {
+    return PathBuf::from(path);
+  }
+  exe_dir()
+    .map(|dir| dir.join("lib").join(ENGINE_LIBRARY_NAME))
+    .filter(|path| path.is_file())
+    .unwrap_or_else(|| PathBuf::from(ENGINE_LIBRARY_NAME))
+}
+
+fn exe_dir() -> Option<PathBuf> {
+  let exe = std::env::current_exe().ok()?;
+  exe.parent().map(Path::to_owned)
+}
@@ -0,0 +1,93 @@
+use std::backtrace::Backtrace;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::Once;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use anyhow::Result;
+use parking_lot::Mutex;
+
+use crate::FlutterEngineState;
+use crate::ffi;
+use crate::ipc;
+use crate::logging::recent;
+
+/// The message and backtrace of the last panic, which engine callbacks turn into fatal errors
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+/// Remember the backtrace of panics for the crash report, then report them as before.
+pub fn install_panic_hook() {
+  static INSTALLED: Once = Once::new();
+  INSTALLED.call_once(|| {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+      let backtrace = Backtrace::force_capture();
+      *LAST_PANIC.lock() = Some(format!("{}\n{}", info, backtrace));
+      previous(info);
+    }));
+  });
+}
+
+/// Write what's useful in a bug report about the fatal `error` into a new file in
+/// `$XDG_STATE_HOME/wayflutter`, and log where it is.
+pub fn report(state: &FlutterEngineState, error: &anyhow::Error) {
+  match write(state, error) {
+    Ok(path) => log::error!("crash report written to {:?}", path),
+    Err(e) => log::warn!("failed to write a crash report: {:#}", e),
+  }
+}
+
+fn write(state: &FlutterEngineState, error: &anyhow::Error) -> Result<PathBuf> {
+  let time = SystemTime::now()
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+  let mut text = String::new();
+  writeln!(
+    text,
+    "wayflutter {} crashed at {} (unix time)\n",
+    env!("CARGO_PKG_VERSION"),
+    time
+  )?;
+  // with the backtrace if RUST_BACKTRACE is set
+  writeln!(text, "== error\n{:?}\n", error)?;
+  if let Some(panic) = LAST_PANIC.lock().take() {
+    writeln!(text, "== panic\n{}", panic)?;
+  }
+  writeln!(
+    text,
+    "== engine\nlibrary: {:?}\nembedder API version: {}\nAOT: {}\n",
+    ffi::library_path(),
+    ffi::FLUTTER_ENGINE_VERSION,
+    unsafe { ffi::FlutterEngineRunsAOTCompiledDartCode() }
+  )?;
+  writeln!(text, "== driver\n{}\n", state.opengl_state.driver)?;
+  writeln!(
+    text,
+    "== views\n{}\n",
+    serde_json::to_string_pretty(&ipc::views(state))?
+  )?;
+  writeln!(text, "== log")?;
+  for line in recent::recent() {
+    writeln!(text, "{}", line)?;
+  }
+
+  let dir = dir()?;
+  std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {:?}", dir))?;
+  let path = dir.join(format!("crash-{}-{}.txt", time, std::process::id()));
+  std::fs::write(&path, text).with_context(|| format!("failed to write {:?}", path))?;
+  Ok(path)
+}
+
+/// `wayflutter` in `$XDG_STATE_HOME`, or in `~/.local/state` if that's not set
+fn dir() -> Result<PathBuf> {
+  let state_dir = match std::env::var_os("XDG_STATE_HOME") {
+    Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+    _ => {
+      let home = std::env::var_os("HOME").context("neither XDG_STATE_HOME nor HOME is set")?;
+      PathBuf::from(home).join(".local").join("state")
+    }
+  };
+  Ok(state_dir.join("wayflutter"))
+}
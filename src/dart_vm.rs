@@ -0,0 +1,37 @@
+use serde::Deserialize;
+
+/// Tuning of the Dart VM, e.g. to cap the memory of an app that's always on screen.
+///
+/// Leak tracking isn't among them: the framework compiles it in or out by the
+/// `flutter.memory_allocations` define, in debug builds by default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct DartVmOptions {
+  /// MiB the old generation of the heap may grow to, past which allocations fail. The engine
+  /// sizes it by the memory of the machine if `None`.
+  pub old_gen_heap_size: Option<u32>,
+  /// Collect garbage on the thread running Dart instead of helper threads, for fewer threads
+  /// and less memory at the cost of longer pauses.
+  pub serial_gc: bool,
+  /// VM flags like `--verbose-gc`, of which release engines allow only a few
+  pub flags: Vec<String>,
+}
+
+impl DartVmOptions {
+  /// Engine switches
+  pub fn switches(&self) -> Vec<String> {
+    let mut switches = Vec::new();
+    if self.serial_gc {
+      switches.push("--enable-serial-gc".to_owned());
+    }
+    if !self.flags.is_empty() {
+      switches.push(format!("--dart-flags={}", self.flags.join(",")));
+    }
+    switches
+  }
+
+  /// `dart_old_gen_heap_size` of the project args, where -1 is the engine's default
+  pub fn old_gen_heap_size(&self) -> i64 {
+    self.old_gen_heap_size.map_or(-1, i64::from)
+  }
+}
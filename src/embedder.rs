@@ -0,0 +1,397 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use bon::bon;
+use serde::Deserialize;
+use smol::channel::Receiver;
+use smol::channel::Sender;
+
+use crate::Exit;
+use crate::activation::Activations;
+use crate::channel::MethodHandler;
+use crate::compositor::SurfaceOptions;
+use crate::compositor::layer::LayerProps;
+use crate::config;
+use crate::config::Config;
+use crate::crash;
+use crate::dart_vm::DartVmOptions;
+use crate::error::ConnectionLost;
+use crate::error::ErrorPolicies;
+use crate::exception::ExceptionHook;
+use crate::exception::ExceptionOptions;
+use crate::ffi;
+#[cfg(feature = "golden")]
+use crate::golden::GoldenOptions;
+use crate::instance::InstanceLock;
+use crate::isolate::IsolateEvent;
+use crate::isolate::IsolateHook;
+use crate::opengl;
+use crate::opengl::RenderOptions;
+use crate::semantics::SemanticsUpdate;
+use crate::systemd;
+use crate::trace;
+use crate::vm_service::VmServiceOptions;
+use crate::watchdog::WatchdogOptions;
+
+/// How long to wait for the compositor to come back, see [`Config::reconnect`]
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Start the engine over after a fatal error, e.g. of the GPU driver, instead of exiting.
+/// Surfaces and GL contexts are created anew, and the app starts from scratch.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct CrashRestartOptions {
+  /// restarts in a row before giving up. 0 disables restarting.
+  pub max_attempts: u32,
+  /// milliseconds before the first restart, doubled for every further one
+  pub backoff: u64,
+}
+
+impl Default for CrashRestartOptions {
+  fn default() -> Self {
+    Self {
+      max_attempts: 0,
+      backoff: 500,
+    }
+  }
+}
+
+/// An engine running this long without crashing resets the count of restarts in a row
+const CRASH_FREE_RUN: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// GL context losses in a row, each within [`CRASH_FREE_RUN`] of starting, before giving up
+const MAX_CONTEXT_LOSSES: u32 = 3;
+
+/// A method channel implemented by the program embedding the app
+#[derive(Debug, Clone, Copy)]
+pub struct Plugin {
+  pub channel: &'static str,
+  pub handler: MethodHandler,
+  /// told when the app starts or goes away, e.g. to send it events only while it runs
+  pub on_isolate: Option<IsolateHook>,
+  /// told about exceptions the app didn't handle
+  pub on_exception: Option<ExceptionHook>,
+}
+
+/// An app to run, set up like with the command line or a config file.
+///
+/// ```no_run
+/// use wayflutter::Embedder;
+/// use wayflutter::layer::LayerProps;
+///
+/// let embedder = Embedder::builder()
+///   .asset_path("build/flutter_assets")
+///   .icu_data_path("/usr/share/flutter/icudtl.dat")
+///   .surfaces(vec![LayerProps {
+///     height: 32,
+///     ..Default::default()
+///   }])
+///   .build();
+/// // SAFETY: no other threads are running yet
+/// unsafe { embedder.run() }?;
+/// # anyhow::Ok(())
+/// ```
+pub struct Embedder {
+  config: Config,
+  plugins: Vec<Plugin>,
+  semantics_listeners: Vec<Sender<SemanticsUpdate>>,
+  isolate_listeners: Vec<Sender<IsolateEvent>>,
+  replace: bool,
+  #[cfg(feature = "golden")]
+  golden: Option<GoldenOptions>,
+  quit_tx: Sender<()>,
+  quit_rx: Receiver<()>,
+}
+
+#[bon]
+impl Embedder {
+  /// `surfaces` are described like the `[[surface]]`s of a config file, the first being the
+  /// implicit view. `plugins` are registered after, so they can override, the embedder's own
+  /// channels.
+  #[builder(builder_type = EmbedderBuilder, finish_fn = build)]
+  pub fn new(
+    #[builder(into)] asset_path: PathBuf,
+    #[builder(into)] icu_data_path: PathBuf,
+    #[builder(into)] aot_library: Option<PathBuf>,
+    #[builder(into)] engine_library: Option<PathBuf>,
+    #[builder(default)] render: RenderOptions,
+    #[builder(into)] app_id: Option<String>,
+    #[builder(into)] route: Option<String>,
+    #[builder(default)] engine_args: Vec<String>,
+    #[builder(default)] vm_service: VmServiceOptions,
+    #[builder(default)] dart_vm: DartVmOptions,
+    #[builder(default)] reconnect: bool,
+    #[builder(default)] gamma_control: bool,
+    #[builder(default)] test: bool,
+    #[builder(default)] merged_ui_thread: bool,
+    #[builder(default)] quiet_dart: bool,
+    #[builder(default)] crash_restart: CrashRestartOptions,
+    #[builder(into)] trace_output: Option<PathBuf>,
+    #[builder(default)] watchdog: WatchdogOptions,
+    #[builder(default)] errors: ErrorPolicies,
+    #[builder(default)] exceptions: ExceptionOptions,
+    #[builder(default)] surface_options: SurfaceOptions,
+    #[builder(default)] surfaces: Vec<LayerProps>,
+    #[builder(default)] plugins: Vec<Plugin>,
+  ) -> Self {
+    let SurfaceOptions {
+      opaque,
+      allow_tearing,
+      every_output,
+      pixel_ratio,
+      low_power,
+      low_power_resolution,
+    } = surface_options;
+    let config = Config {
+      asset_path,
+      icu_data_path,
+      engine_library,
+      aot_library,
+      render,
+      app_id,
+      route,
+      engine_args,
+      vm_service,
+      dart_vm,
+      hot_restart: false,
+      test,
+      reconnect,
+      gamma_control,
+      merged_ui_thread,
+      quiet_dart,
+      crash_restart,
+      trace_output,
+      watchdog,
+      errors,
+      exceptions,
+      opaque,
+      allow_tearing,
+      every_output,
+      pixel_ratio,
+      low_power,
+      low_power_resolution,
+      surfaces,
+      path: None,
+    };
+    Self::from_config(config, plugins)
+  }
+
+  pub fn from_config(config: Config, plugins: Vec<Plugin>) -> Self {
+    let (quit_tx, quit_rx) = smol::channel::unbounded();
+    Self {
+      config,
+      plugins,
+      semantics_listeners: Vec::new(),
+      isolate_listeners: Vec::new(),
+      replace: false,
+      #[cfg(feature = "golden")]
+      golden: None,
+      quit_tx,
+      quit_rx,
+    }
+  }
+
+  pub fn handle(&self) -> EmbedderHandle {
+    EmbedderHandle {
+      quit: self.quit_tx.clone(),
+    }
+  }
+
+  /// The changes of the semantics tree of the app, which describes its widgets like for screen
+  /// readers, e.g. to read a label aloud or to automate the app. The engine builds the tree
+  /// only if someone listens, so this must be called before [`Embedder::run`]. Updates of
+  /// restarted engines go to the same stream.
+  pub fn semantics_updates(&mut self) -> Receiver<SemanticsUpdate> {
+    let (tx, rx) = smol::channel::unbounded();
+    self.semantics_listeners.push(tx);
+    rx
+  }
+
+  /// When the root isolate of the app is created or shut down, also for hot and engine
+  /// restarts. Messages to the app are dropped while it's not running.
+  pub fn isolate_events(&mut self) -> Receiver<IsolateEvent> {
+    let (tx, rx) = smol::channel::unbounded();
+    self.isolate_listeners.push(tx);
+    rx
+  }
+
+  /// Ask an instance running with the same config to exit and take over its surfaces, instead
+  /// of failing to start.
+  pub fn replace_running(&mut self) {
+    self.replace = true;
+  }
+
+  /// Compare a frame with a golden PNG instead of running the app, failing if they differ.
+  #[cfg(feature = "golden")]
+  pub fn check_golden(&mut self, options: GoldenOptions) {
+    self.golden = Some(options);
+  }
+
+  /// Run the app until the compositor goes away for good, a fatal error or
+  /// [`EmbedderHandle::quit`].
+  ///
+  /// Loads the engine library, so it may be called once per process only.
+  ///
+  /// # Safety
+  ///
+  /// Choosing the GPU may set `DRI_PRIME`, the only way to select the device of a Wayland EGL
+  /// display with Mesa. No other thread may be running, as modifying the environment races
+  /// with any thread reading it.
+  pub unsafe fn run(self) -> Result<()> {
+    let _stopping = systemd::Stopping;
+    crash::install_panic_hook();
+    let mut config = self.config;
+    config.validate()?;
+
+    {
+      let conn = wayland_client::Connection::connect_to_env()?;
+      // SAFETY: before starting any threads ourselves, and the caller runs none. Engines started
+      // later render on the same GPU.
+      config.render.gpu = unsafe { opengl::gpu::select(&conn, config.render.gpu.as_deref())? };
+    }
+
+    // released after the app id, so that a replacing instance finds it free
+    let instance = InstanceLock::acquire(&config, self.replace)?;
+    let Some(activations) = Activations::claim(config.app_id.as_deref(), config.route.as_deref())?
+    else {
+      return Ok(());
+    };
+    let Some(instance) = &instance else {
+      anyhow::bail!("another instance runs with this config. Pass --replace to take over from it.");
+    };
+
+    let engine_library = config
+      .engine_library
+      .clone()
+      .unwrap_or_else(config::discover::engine_library);
+    ffi::load(&engine_library)?;
+    let _recording = config
+      .trace_output
+      .clone()
+      .map(trace::Recording::start)
+      .transpose()?;
+
+    let mut crashes = 0;
+    let mut context_losses = 0;
+    loop {
+      let started = Instant::now();
+      match smol::block_on(crate::run_flutter(
+        config.clone(),
+        &self.plugins,
+        &self.semantics_listeners,
+        &self.isolate_listeners,
+        &activations,
+        instance,
+        #[cfg(feature = "golden")]
+        self.golden.as_ref(),
+        &self.quit_rx,
+      ))? {
+        Exit::Quit => return Ok(()),
+        Exit::Restart => log::info!("restarting the engine"),
+        Exit::Reconnect => {
+          if smol::block_on(between_runs(&self.quit_rx, wait_for_compositor()))?.is_none() {
+            return Ok(());
+          }
+          log::info!("reconnected, restarting the engine");
+        }
+        Exit::Crashed(e) => {
+          if started.elapsed() > CRASH_FREE_RUN {
+            crashes = 0;
+          }
+          let max_attempts = config.crash_restart.max_attempts;
+          if crashes >= max_attempts {
+            return Err(e.context(format!("gave up after {} restarts in a row", crashes)));
+          }
+          crashes += 1;
+          let delay = Duration::from_millis(config.crash_restart.backoff)
+            .saturating_mul(1 << (crashes - 1).min(16))
+            .min(MAX_BACKOFF);
+          log::error!(
+            "{:#}. Restarting the engine in {:?} ({}/{})",
+            e,
+            delay,
+            crashes,
+            max_attempts
+          );
+          let backoff = async {
+            smol::Timer::after(delay).await;
+            Ok(())
+          };
+          if smol::block_on(between_runs(&self.quit_rx, backoff))?.is_none() {
+            return Ok(());
+          }
+        }
+        Exit::ContextLost(e) => {
+          if started.elapsed() > CRASH_FREE_RUN {
+            context_losses = 0;
+          }
+          if context_losses >= MAX_CONTEXT_LOSSES {
+            return Err(e.context(format!(
+              "gave up after {} context losses in a row",
+              context_losses
+            )));
+          }
+          context_losses += 1;
+          log::warn!("{:#}. Restarting the engine with new contexts", e);
+        }
+      }
+    }
+  }
+}
+
+/// Waits for `wait` between two runs of the engine, pinging the service manager's watchdog
+/// meanwhile. `None` if the embedder is told to quit first.
+async fn between_runs<T>(
+  quit: &Receiver<()>,
+  wait: impl Future<Output = Result<T>>,
+) -> Result<Option<T>> {
+  smol::future::or(
+    async { wait.await.map(Some) },
+    smol::future::or(
+      async {
+        let _ = quit.recv().await;
+        Ok(None)
+      },
+      async { systemd::watchdog().await.map(|()| None) },
+    ),
+  )
+  .await
+}
+
+/// Retries with a growing delay for [`RECONNECT_TIMEOUT`]
+async fn wait_for_compositor() -> Result<()> {
+  let deadline = Instant::now() + RECONNECT_TIMEOUT;
+  let mut delay = Duration::from_millis(100);
+  loop {
+    smol::Timer::after(delay).await;
+    match wayland_client::Connection::connect_to_env() {
+      Ok(_) => return Ok(()),
+      Err(e) if Instant::now() < deadline => {
+        log::debug!("compositor not back yet: {}", e);
+        delay = (delay * 2).min(Duration::from_secs(5));
+      }
+      Err(e) => {
+        return Err(anyhow::Error::new(e).context(format!(
+          "the compositor didn't come back within {:?}",
+          RECONNECT_TIMEOUT
+        )))
+        .context(ConnectionLost);
+      }
+    }
+  }
+}
+
+/// Stops a running [`Embedder`] from any thread
+#[derive(Debug, Clone)]
+pub struct EmbedderHandle {
+  quit: Sender<()>,
+}
+
+impl EmbedderHandle {
+  pub fn quit(&self) {
+    let _ = self.quit.try_send(());
+  }
+}
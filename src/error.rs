@@ -1,5 +1,14 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use serde::Deserialize;
 use thiserror::Error;
 
+use crate::FlutterEngineState;
+use crate::compositor::FlutterViewKind;
+use crate::compositor::ViewId;
 use crate::ffi;
 
 #[derive(Debug, Error)]
@@ -35,3 +44,181 @@ impl FFIFlutterEngineResultExt for ffi::FlutterEngineResult {
     }
   }
 }
+
+/// The Wayland compositor went away or closed the connection over a protocol error
+#[derive(Debug, Error)]
+#[error("lost the connection to the Wayland compositor")]
+pub struct ConnectionLost;
+
+impl ConnectionLost {
+  /// what the wayflutter binary exits with, so that supervisors can tell it from other
+  /// failures. `EX_TEMPFAIL`.
+  pub const EXIT_CODE: u8 = 75;
+}
+
+/// The GL contexts were lost, e.g. to a GPU reset or across some suspend/resume cycles. Only
+/// starting the engine over with new contexts recovers from it.
+#[derive(Debug, Error)]
+#[error("lost the GL context")]
+pub struct ContextLost;
+
+/// What an error in an engine callback is about, which decides how it's handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+  /// a GL or EGL call failed, e.g. the driver ran short of memory for a moment
+  Render,
+  /// the EGL surface of a view can't be drawn to anymore
+  SurfaceLost,
+  /// talking to the compositor failed
+  Protocol,
+  /// the GL contexts are gone, see [`ContextLost`]
+  ContextLost,
+  /// the engine rejected a call, or anything not known to be recoverable
+  Fatal,
+}
+
+impl ErrorKind {
+  pub fn of(error: &anyhow::Error) -> Self {
+    if error.downcast_ref::<ContextLost>().is_some() {
+      return ErrorKind::ContextLost;
+    }
+    for cause in error.chain() {
+      if let Some(e) = cause.downcast_ref::<glutin::error::Error>() {
+        return match e.error_kind() {
+          glutin::error::ErrorKind::BadSurface
+          | glutin::error::ErrorKind::BadCurrentSurface
+          | glutin::error::ErrorKind::BadNativeWindow => ErrorKind::SurfaceLost,
+          glutin::error::ErrorKind::ContextLost => ErrorKind::ContextLost,
+          _ => ErrorKind::Render,
+        };
+      }
+      if cause.is::<FlutterEngineError>() {
+        return ErrorKind::Fatal;
+      }
+      if cause.is::<ConnectionLost>() || cause.is::<wayland_client::DispatchError>() {
+        return ErrorKind::Protocol;
+      }
+    }
+    ErrorKind::Fatal
+  }
+}
+
+/// How to go on after an error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Policy {
+  /// drop the frame and draw the next one
+  Retry,
+  /// like `Retry`, with a new EGL surface for the view
+  RecreateSurface,
+  /// exit with the error
+  Terminate,
+}
+
+/// The [`Policy`] for each recoverable [`ErrorKind`]. Fatal errors always terminate, lost
+/// contexts always start the engine over, and recoverable errors terminate once more than
+/// [`ERROR_BUDGET`] pile up within [`ERROR_WINDOW`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct ErrorPolicies {
+  pub render: Policy,
+  pub surface_lost: Policy,
+  pub protocol: Policy,
+}
+
+impl Default for ErrorPolicies {
+  fn default() -> Self {
+    Self {
+      render: Policy::Retry,
+      surface_lost: Policy::RecreateSurface,
+      protocol: Policy::Terminate,
+    }
+  }
+}
+
+pub const ERROR_BUDGET: usize = 10;
+pub const ERROR_WINDOW: Duration = Duration::from_secs(10);
+
+/// The policies and the recent errors they forgave
+#[derive(Debug)]
+pub struct ErrorTracker {
+  policies: ErrorPolicies,
+  recent: Mutex<VecDeque<Instant>>,
+}
+
+impl ErrorTracker {
+  pub fn new(policies: ErrorPolicies) -> Self {
+    Self {
+      policies,
+      recent: Mutex::default(),
+    }
+  }
+
+  fn policy(&self, kind: ErrorKind) -> Policy {
+    match kind {
+      ErrorKind::Render => self.policies.render,
+      ErrorKind::SurfaceLost => self.policies.surface_lost,
+      ErrorKind::Protocol => self.policies.protocol,
+      ErrorKind::Fatal | ErrorKind::ContextLost => Policy::Terminate,
+    }
+  }
+
+  /// Whether there's budget left for one more error
+  fn forgive(&self) -> bool {
+    let mut recent = self.recent.lock();
+    let now = Instant::now();
+    while recent
+      .front()
+      .is_some_and(|&time| now.duration_since(time) > ERROR_WINDOW)
+    {
+      recent.pop_front();
+    }
+    recent.push_back(now);
+    recent.len() <= ERROR_BUDGET
+  }
+}
+
+/// Handle an error in an engine callback by its policy, see `error_in_callback!`. `view` is the
+/// view it happened to, if any.
+pub fn report(state: &FlutterEngineState, error: anyhow::Error, view: Option<ViewId>) {
+  let kind = ErrorKind::of(&error);
+  if kind == ErrorKind::ContextLost {
+    // `run_flutter` returns to start over on `ContextLost`
+    let error = match error.downcast_ref::<ContextLost>() {
+      Some(_) => error,
+      None => error.context(ContextLost),
+    };
+    let _ = state.terminate.unbounded_send(Err(error));
+    return;
+  }
+  let policy = state.errors.policy(kind);
+  if policy == Policy::Terminate {
+    let _ = state.terminate.unbounded_send(Err(error));
+    return;
+  }
+  if !state.errors.forgive() {
+    let error = error.context(format!(
+      "more than {} errors within {}s",
+      ERROR_BUDGET,
+      ERROR_WINDOW.as_secs()
+    ));
+    let _ = state.terminate.unbounded_send(Err(error));
+    return;
+  }
+  log::warn!(
+    view_id = view.as_ref().map(ViewId::raw);
+    "{:?} error, {:?}: {:#}",
+    kind,
+    policy,
+    error
+  );
+  if policy == Policy::RecreateSurface
+    && let Some(view) = view.and_then(|view| state.compositor.get_view(view))
+  {
+    let FlutterViewKind::Surface(surface_view) = &view.kind;
+    surface_view.invalidate_egl_surface();
+  }
+  if let Err(e) = state.task_runner_handle.schedule_frame() {
+    let _ = state.terminate.unbounded_send(Err(e));
+  }
+}
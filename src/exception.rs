@@ -0,0 +1,88 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::FlutterEngine;
+
+/// What to do with exceptions the app doesn't handle, besides logging them. Dart carries on
+/// after them, so an app that's always on screen may stay broken without anyone noticing.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct ExceptionOptions {
+  /// file to append every unhandled exception to, with the time it happened
+  pub file: Option<PathBuf>,
+}
+
+/// An exception the app didn't handle
+#[derive(Debug, Clone)]
+pub struct DartException {
+  pub error: String,
+  pub stack_trace: Option<String>,
+}
+
+impl DartException {
+  /// From what the engine logs for an exception reaching the root zone: the error after
+  /// `Unhandled Exception: `, then the frames of the stack trace on lines of their own
+  pub fn from_log_message(message: &str) -> Option<Self> {
+    let rest = message.strip_prefix("Unhandled Exception: ")?;
+    // the error may span lines too
+    Some(match rest.find("\n#0") {
+      Some(i) => Self {
+        error: rest[..i].to_owned(),
+        stack_trace: Some(rest[i + 1..].to_owned()),
+      },
+      None => Self {
+        error: rest.to_owned(),
+        stack_trace: None,
+      },
+    })
+  }
+}
+
+/// Called on the platform thread, see [`crate::Plugin::on_exception`]
+pub type ExceptionHook = fn(&FlutterEngine, &DartException);
+
+pub struct Exceptions {
+  options: ExceptionOptions,
+  hooks: Vec<ExceptionHook>,
+}
+
+impl Exceptions {
+  pub fn new(options: ExceptionOptions, hooks: Vec<ExceptionHook>) -> Self {
+    Self { options, hooks }
+  }
+}
+
+/// Write `exception` to the file and call the hooks. It's logged already. Must be
+/// called on the platform thread.
+pub fn report(engine: &FlutterEngine, exception: DartException) {
+  let exceptions = &engine.state().exceptions;
+  if let Some(path) = &exceptions.options.file
+    && let Err(e) = append(path, &exception)
+  {
+    log::warn!("failed to write the exception to {:?}: {:#}", path, e);
+  }
+  for hook in &exceptions.hooks {
+    hook(engine, &exception);
+  }
+}
+
+fn append(path: &Path, exception: &DartException) -> Result<()> {
+  let time = SystemTime::now()
+    .duration_since(SystemTime::UNIX_EPOCH)
+    .unwrap_or_default()
+    .as_secs();
+  let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+  writeln!(file, "unhandled exception at {} (unix time)", time)?;
+  writeln!(file, "{}", exception.error)?;
+  if let Some(stack_trace) = &exception.stack_trace {
+    writeln!(file, "{}", stack_trace.trim_end())?;
+  }
+  writeln!(file)?;
+  Ok(())
+}
@@ -0,0 +1,242 @@
+//! Bindings to the embedder API of the engine, which is loaded at runtime so that one build
+//! works with the engine of whichever Flutter SDK the app was built with.
+
+#![allow(non_upper_case_globals)]
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(dead_code)]
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use anyhow::Result;
+use libloading::Library;
+
+use crate::error::FFIFlutterEngineResultExt;
+
+include!(concat!(env!("OUT_DIR"), "/embedder_bindings.rs"));
+
+#[cfg(test)]
+pub mod fake;
+
+type GetProcAddresses = unsafe extern "C" fn(*mut FlutterEngineProcTable) -> FlutterEngineResult;
+
+struct Engine {
+  path: PathBuf,
+  /// keeps the functions in `procs` loaded. `None` for the fake engine of unit tests.
+  _library: Option<Library>,
+  procs: FlutterEngineProcTable,
+}
+
+static ENGINE: OnceLock<Engine> = OnceLock::new();
+
+/// Load the engine library at `path`, which may be a bare file name searched for like any
+/// shared library. Must be called once before anything else in this module.
+pub fn load(path: &Path) -> Result<()> {
+  // SAFETY: the engine's initializers don't depend on anything from us
+  let library = unsafe { Library::new(path) }
+    .with_context(|| format!("failed to load the engine library {:?}", path))?;
+  let mut procs = FlutterEngineProcTable {
+    struct_size: size_of::<FlutterEngineProcTable>(),
+    // SAFETY: nullable function pointers, filled in by the engine
+    ..unsafe { core::mem::zeroed() }
+  };
+  unsafe {
+    let get_proc_addresses = library
+      .get::<GetProcAddresses>(b"FlutterEngineGetProcAddresses\0")
+      .with_context(|| format!("{:?} is not a Flutter engine", path))?;
+    get_proc_addresses(&mut procs)
+      .into_flutter_engine_result()
+      .context("FlutterEngineGetProcAddresses failed")?;
+  }
+  let missing = missing_procs(&procs, true);
+  if !missing.is_empty() {
+    anyhow::bail!(
+      "the engine {:?} is too old, it lacks {}",
+      path,
+      missing.join(", ")
+    );
+  }
+  log::info!("loaded the engine from {:?}", path);
+  let missing = missing_procs(&procs, false);
+  if !missing.is_empty() {
+    log::warn!(
+      "the engine lacks {}, so {}",
+      missing.join(", "),
+      Features::from_procs(&procs).degradations().join(", ")
+    );
+  }
+  let _ = ENGINE.set(Engine {
+    path: path.to_owned(),
+    _library: Some(library),
+    procs,
+  });
+  Ok(())
+}
+
+/// `None` before [`load`]
+pub fn library_path() -> Option<&'static Path> {
+  ENGINE.get().map(|engine| engine.path.as_path())
+}
+
+fn procs() -> &'static FlutterEngineProcTable {
+  &ENGINE
+    .get()
+    .expect("the engine library is not loaded")
+    .procs
+}
+
+/// What the loaded engine supports beyond what [`load`] requires. Entries of the proc table
+/// are left empty by engines older than them.
+#[derive(Debug, Clone, Copy)]
+pub struct Features {
+  /// Views besides the implicit one, presented with `present_view_callback`. Older engines
+  /// present the implicit view only, with `present_layers_callback`.
+  pub multi_view: bool,
+  pub view_focus: bool,
+  pub display_updates: bool,
+}
+
+impl Features {
+  fn from_procs(procs: &FlutterEngineProcTable) -> Self {
+    Self {
+      multi_view: procs.AddView.is_some() && procs.RemoveView.is_some(),
+      view_focus: procs.SendViewFocusEvent.is_some(),
+      display_updates: procs.NotifyDisplayUpdate.is_some(),
+    }
+  }
+
+  fn degradations(&self) -> Vec<&'static str> {
+    [
+      (self.multi_view, "only the implicit view can be shown"),
+      (
+        self.view_focus,
+        "the app isn't told which view has the keyboard",
+      ),
+      (self.display_updates, "the app isn't told about outputs"),
+    ]
+    .into_iter()
+    .filter(|(supported, _)| !supported)
+    .map(|(_, degradation)| degradation)
+    .collect()
+  }
+}
+
+pub fn features() -> Features {
+  Features::from_procs(procs())
+}
+
+/// Functions of the engine with the signatures of the header, dispatched through the proc
+/// table. `required` ones are checked to be present in [`load`], `optional` ones must be
+/// checked with [`features`] before calling them.
+macro_rules! dispatch {
+  ($(
+    $kind:ident $name:ident => $proc:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;
+  )*) => {
+    $(
+      pub unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+        let f = procs()
+          .$proc
+          .expect(concat!("the engine lacks ", stringify!($name)));
+        unsafe { f($($arg),*) }
+      }
+    )*
+
+    fn missing_procs(procs: &FlutterEngineProcTable, required: bool) -> Vec<&'static str> {
+      let mut missing = Vec::new();
+      $(
+        if (stringify!($kind) == "required") == required && procs.$proc.is_none() {
+          missing.push(stringify!($name));
+        }
+      )*
+      missing
+    }
+  };
+}
+
+dispatch! {
+  required FlutterEngineCreateAOTData => CreateAOTData(
+    source: *const FlutterEngineAOTDataSource,
+    data_out: *mut FlutterEngineAOTData,
+  ) -> FlutterEngineResult;
+  required FlutterEngineCollectAOTData => CollectAOTData(data: FlutterEngineAOTData) -> FlutterEngineResult;
+  required FlutterEngineInitialize => Initialize(
+    version: usize,
+    config: *const FlutterRendererConfig,
+    args: *const FlutterProjectArgs,
+    user_data: *mut std::ffi::c_void,
+    engine_out: *mut FlutterEngine,
+  ) -> FlutterEngineResult;
+  required FlutterEngineDeinitialize => Deinitialize(engine: FlutterEngine) -> FlutterEngineResult;
+  required FlutterEngineRunInitialized => RunInitialized(engine: FlutterEngine) -> FlutterEngineResult;
+  optional FlutterEngineAddView => AddView(
+    engine: FlutterEngine,
+    info: *const FlutterAddViewInfo,
+  ) -> FlutterEngineResult;
+  optional FlutterEngineRemoveView => RemoveView(
+    engine: FlutterEngine,
+    info: *const FlutterRemoveViewInfo,
+  ) -> FlutterEngineResult;
+  optional FlutterEngineSendViewFocusEvent => SendViewFocusEvent(
+    engine: FlutterEngine,
+    event: *const FlutterViewFocusEvent,
+  ) -> FlutterEngineResult;
+  required FlutterEngineSendWindowMetricsEvent => SendWindowMetricsEvent(
+    engine: FlutterEngine,
+    event: *const FlutterWindowMetricsEvent,
+  ) -> FlutterEngineResult;
+  required FlutterEngineSendPointerEvent => SendPointerEvent(
+    engine: FlutterEngine,
+    events: *const FlutterPointerEvent,
+    events_count: usize,
+  ) -> FlutterEngineResult;
+  required FlutterEngineSendPlatformMessage => SendPlatformMessage(
+    engine: FlutterEngine,
+    message: *const FlutterPlatformMessage,
+  ) -> FlutterEngineResult;
+  required FlutterEngineSendPlatformMessageResponse => SendPlatformMessageResponse(
+    engine: FlutterEngine,
+    handle: *const FlutterPlatformMessageResponseHandle,
+    data: *const u8,
+    data_length: usize,
+  ) -> FlutterEngineResult;
+  required FlutterEngineOnVsync => OnVsync(
+    engine: FlutterEngine,
+    baton: isize,
+    frame_start_time_nanos: u64,
+    frame_target_time_nanos: u64,
+  ) -> FlutterEngineResult;
+  required FlutterEngineGetCurrentTime => GetCurrentTime() -> u64;
+  required FlutterEngineTraceEventDurationBegin => TraceEventDurationBegin(name: *const std::ffi::c_char);
+  required FlutterEngineTraceEventDurationEnd => TraceEventDurationEnd(name: *const std::ffi::c_char);
+  required FlutterEngineRunTask => RunTask(engine: FlutterEngine, task: *const FlutterTask) -> FlutterEngineResult;
+  required FlutterEngineRunsAOTCompiledDartCode => RunsAOTCompiledDartCode() -> bool;
+  optional FlutterEngineNotifyDisplayUpdate => NotifyDisplayUpdate(
+    engine: FlutterEngine,
+    update_type: FlutterEngineDisplaysUpdateType,
+    displays: *const FlutterEngineDisplay,
+    display_count: usize,
+  ) -> FlutterEngineResult;
+  required FlutterEngineNotifyLowMemoryWarning => NotifyLowMemoryWarning(
+    engine: FlutterEngine,
+  ) -> FlutterEngineResult;
+  required FlutterEngineScheduleFrame => ScheduleFrame(engine: FlutterEngine) -> FlutterEngineResult;
+  required FlutterEngineUpdateLocales => UpdateLocales(
+    engine: FlutterEngine,
+    locales: *mut *const FlutterLocale,
+    locales_count: usize,
+  ) -> FlutterEngineResult;
+  required FlutterEngineUpdateSemanticsEnabled => UpdateSemanticsEnabled(
+    engine: FlutterEngine,
+    enabled: bool,
+  ) -> FlutterEngineResult;
+  required FlutterEngineDispatchSemanticsAction => DispatchSemanticsAction(
+    engine: FlutterEngine,
+    node_id: u64,
+    action: FlutterSemanticsAction,
+    data: *const u8,
+    data_length: usize,
+  ) -> FlutterEngineResult;
+}
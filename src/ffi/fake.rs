@@ -0,0 +1,38 @@
+//! A stand-in for the engine library in unit tests, for code that only needs engine functions
+//! which work without a running engine, like the clock.
+
+use std::ffi::c_char;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use super::ENGINE;
+use super::Engine;
+use super::FlutterEngineProcTable;
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Fill the proc table with the fakes, unless it's filled already. Functions without a fake
+/// panic when called.
+pub fn load() {
+  let procs = FlutterEngineProcTable {
+    struct_size: size_of::<FlutterEngineProcTable>(),
+    GetCurrentTime: Some(get_current_time),
+    TraceEventDurationBegin: Some(trace_event),
+    TraceEventDurationEnd: Some(trace_event),
+    // SAFETY: nullable function pointers
+    ..unsafe { core::mem::zeroed() }
+  };
+  let _ = ENGINE.set(Engine {
+    path: PathBuf::from("fake"),
+    _library: None,
+    procs,
+  });
+}
+
+/// monotonic, in nanoseconds since the first call
+unsafe extern "C" fn get_current_time() -> u64 {
+  START.get_or_init(Instant::now).elapsed().as_nanos() as u64
+}
+
+unsafe extern "C" fn trace_event(_name: *const c_char) {}
@@ -0,0 +1,365 @@
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::FlutterEngine;
+use crate::trace;
+
+pub mod channel;
+
+/// Frames kept for [`FrameStats::summary`]
+const HISTORY_LEN: usize = 240;
+/// Jank is summarized in the log once per window
+const JANK_WINDOW: Duration = Duration::from_secs(10);
+/// [`FrameStats::fps`] counts the frames committed within this long
+const FPS_WINDOW: u64 = 1_000_000_000;
+/// Bounds for bookkeeping of frames whose end never arrives
+const MAX_IN_FLIGHT: usize = 16;
+
+/// Per-frame timing collected from vsync batons, backing store requests, present callbacks and
+/// presentation feedback.
+///
+/// Timestamps are nanoseconds of `FlutterEngineGetCurrentTime`. A frame is
+/// - built from its vsync until the raster thread requests its first backing store,
+/// - rasterized until it's presented to the view,
+/// - and presented once the compositor puts it on screen.
+#[derive(Default)]
+pub struct FrameStats {
+  inner: Mutex<Inner>,
+  /// a Dart listener on [`channel::CHANNEL`] wants every frame
+  listening: AtomicBool,
+}
+
+#[derive(Default)]
+struct Inner {
+  /// `(frame_start, frame_target)` of answered vsyncs, oldest first
+  vsyncs: VecDeque<(u64, u64)>,
+  /// the frame on the raster thread right now
+  rasterizing: Option<InFlight>,
+  /// committed frames waiting for presentation feedback, by frame id
+  committed: VecDeque<(u64, InFlight)>,
+  next_id: u64,
+  /// when frames were committed within [`FPS_WINDOW`], oldest first
+  commits: VecDeque<u64>,
+  history: VecDeque<FrameTiming>,
+  window: JankWindow,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InFlight {
+  vsync: Option<(u64, u64)>,
+  raster_start: u64,
+  committed_at: u64,
+}
+
+struct JankWindow {
+  started: Instant,
+  frames: u32,
+  janky: u32,
+  /// the slowest janky frame
+  worst: Option<FrameTiming>,
+}
+
+impl Default for JankWindow {
+  fn default() -> Self {
+    Self {
+      started: Instant::now(),
+      frames: 0,
+      janky: 0,
+      worst: None,
+    }
+  }
+}
+
+/// Durations of one frame, in nanoseconds
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameTiming {
+  /// the vsync the frame was built for. `None` if it can't be told.
+  pub frame_start: Option<u64>,
+  pub build: Option<u64>,
+  pub raster: u64,
+  /// from commit until on screen. `None` without presentation feedback.
+  pub present: Option<u64>,
+  /// reached the screen later than the vsync it targeted
+  pub janky: bool,
+}
+
+impl FrameTiming {
+  fn total(&self) -> u64 {
+    self.build.unwrap_or(0) + self.raster + self.present.unwrap_or(0)
+  }
+}
+
+impl FrameStats {
+  /// The engine was told to build a frame for this vsync.
+  pub fn vsync(&self, frame_start: u64, frame_target: u64) {
+    let mut inner = self.inner.lock();
+    inner.vsyncs.push_back((frame_start, frame_target));
+    if inner.vsyncs.len() > MAX_IN_FLIGHT {
+      inner.vsyncs.pop_front();
+    }
+  }
+
+  /// The raster thread requested a backing store at `now`. Only the first request of a frame
+  /// counts.
+  pub fn raster_started(&self, now: u64) {
+    let mut inner = self.inner.lock();
+    if inner.rasterizing.is_some() {
+      return;
+    }
+    // vsyncs that didn't lead to a frame are skipped. Later ones belong to the next frame.
+    let mut vsync = None;
+    while let Some(&(frame_start, _)) = inner.vsyncs.front()
+      && frame_start <= now
+    {
+      vsync = inner.vsyncs.pop_front();
+    }
+    inner.rasterizing = Some(InFlight {
+      vsync,
+      raster_start: now,
+      committed_at: now,
+    });
+  }
+
+  /// The frame being rasterized was thrown away.
+  pub fn frame_dropped(&self) {
+    self.inner.lock().rasterizing = None;
+  }
+
+  /// The frame being rasterized was committed at `now`. Returns the id to
+  /// [`FrameStats::finish`] it with.
+  pub fn committed(&self, now: u64) -> u64 {
+    let mut inner = self.inner.lock();
+    let frame = InFlight {
+      committed_at: now,
+      ..inner.rasterizing.take().unwrap_or(InFlight {
+        vsync: None,
+        raster_start: now,
+        committed_at: now,
+      })
+    };
+    let id = inner.next_id;
+    inner.next_id += 1;
+    inner.committed.push_back((id, frame));
+    if inner.committed.len() > MAX_IN_FLIGHT {
+      inner.committed.pop_front();
+    }
+    inner.commits.push_back(now);
+    while inner
+      .commits
+      .front()
+      .is_some_and(|&commit| commit + FPS_WINDOW < now)
+    {
+      inner.commits.pop_front();
+    }
+    id
+  }
+
+  /// Frames committed in the last second before `now`
+  pub fn fps(&self, now: u64) -> usize {
+    let inner = self.inner.lock();
+    inner
+      .commits
+      .iter()
+      .filter(|&&commit| commit + FPS_WINDOW >= now)
+      .count()
+  }
+
+  /// The compositor replaced the frame before showing it.
+  pub fn discarded(&self, id: u64) {
+    self
+      .inner
+      .lock()
+      .committed
+      .retain(|(frame, _)| *frame != id);
+  }
+
+  /// The frame reached the screen at `presented_at`, or `None` if there's no presentation
+  /// feedback.
+  ///
+  /// Must be called on the platform thread.
+  pub fn finish(&self, engine: &FlutterEngine, id: u64, presented_at: Option<u64>) {
+    let timing = {
+      let mut inner = self.inner.lock();
+      let Some(index) = inner.committed.iter().position(|(frame, _)| *frame == id) else {
+        return;
+      };
+      let (_, frame) = inner.committed.remove(index).unwrap();
+      trace::record_frame(
+        frame.vsync.map(|(frame_start, _)| frame_start),
+        frame.raster_start,
+        frame.committed_at,
+        presented_at,
+      );
+      let end = presented_at.unwrap_or(frame.committed_at);
+      let timing = FrameTiming {
+        frame_start: frame.vsync.map(|(frame_start, _)| frame_start),
+        build: frame
+          .vsync
+          .map(|(frame_start, _)| frame.raster_start.saturating_sub(frame_start)),
+        raster: frame.committed_at - frame.raster_start,
+        present: presented_at.map(|presented_at| presented_at.saturating_sub(frame.committed_at)),
+        // half an interval of slack for the compositor's own latency
+        janky: frame.vsync.is_some_and(|(frame_start, frame_target)| {
+          end > frame_target + (frame_target - frame_start) / 2
+        }),
+      };
+      inner.history.push_back(timing);
+      if inner.history.len() > HISTORY_LEN {
+        inner.history.pop_front();
+      }
+      inner.window.record(timing);
+      timing
+    };
+
+    if self.listening.load(Ordering::Relaxed)
+      && let Err(e) = channel::send_frame(engine, &timing)
+    {
+      log::warn!("failed to send frame timing: {:#}", e);
+    }
+  }
+
+  pub fn set_listening(&self, listening: bool) {
+    self.listening.store(listening, Ordering::Relaxed);
+  }
+
+  /// Statistics over the last frames
+  pub fn summary(&self) -> Summary {
+    let inner = self.inner.lock();
+    let history = &inner.history;
+    Summary {
+      frames: history.len(),
+      janky: history.iter().filter(|timing| timing.janky).count(),
+      build: Durations::of(history.iter().filter_map(|timing| timing.build)),
+      raster: Durations::of(history.iter().map(|timing| timing.raster)),
+      present: Durations::of(history.iter().filter_map(|timing| timing.present)),
+    }
+  }
+}
+
+impl JankWindow {
+  fn record(&mut self, timing: FrameTiming) {
+    self.frames += 1;
+    if timing.janky {
+      self.janky += 1;
+      if self
+        .worst
+        .is_none_or(|worst| timing.total() > worst.total())
+      {
+        self.worst = Some(timing);
+      }
+    }
+    if self.started.elapsed() < JANK_WINDOW {
+      return;
+    }
+    match self.worst {
+      Some(worst) => log::info!(
+        "{}/{} frames janky in the last {}s. Worst: build {}, raster {}, present {}",
+        self.janky,
+        self.frames,
+        JANK_WINDOW.as_secs(),
+        format_millis(worst.build),
+        format_millis(Some(worst.raster)),
+        format_millis(worst.present),
+      ),
+      None => log::debug!(
+        "no janky frames among {} in the last {}s",
+        self.frames,
+        JANK_WINDOW.as_secs()
+      ),
+    }
+    *self = Self::default();
+  }
+}
+
+fn format_millis(nanos: Option<u64>) -> String {
+  match nanos {
+    Some(nanos) => format!("{:.1}ms", nanos as f64 / 1e6),
+    None => "?".to_owned(),
+  }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+  pub frames: usize,
+  pub janky: usize,
+  pub build: Option<Durations>,
+  pub raster: Option<Durations>,
+  pub present: Option<Durations>,
+}
+
+/// Distribution of a duration, in milliseconds
+#[derive(Debug, Clone, Serialize)]
+pub struct Durations {
+  pub avg: f64,
+  pub p50: f64,
+  pub p90: f64,
+  pub p99: f64,
+  pub max: f64,
+}
+
+impl Durations {
+  /// `None` if there are no samples.
+  fn of(nanos: impl Iterator<Item = u64>) -> Option<Self> {
+    let mut millis = nanos.map(|nanos| nanos as f64 / 1e6).collect::<Vec<_>>();
+    if millis.is_empty() {
+      return None;
+    }
+    millis.sort_by(f64::total_cmp);
+    let percentile = |p: f64| millis[((millis.len() - 1) as f64 * p).round() as usize];
+    Some(Self {
+      avg: millis.iter().sum::<f64>() / millis.len() as f64,
+      p50: percentile(0.5),
+      p90: percentile(0.9),
+      p99: percentile(0.99),
+      max: millis[millis.len() - 1],
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MILLI: u64 = 1_000_000;
+
+  #[test]
+  fn durations_of_no_samples() {
+    assert!(Durations::of(std::iter::empty()).is_none());
+  }
+
+  #[test]
+  fn durations_percentiles_are_nearest_rank() {
+    // 1 to 100ms, in no particular order
+    let nanos = (1..=100).map(|i| (i * 37 % 101) * MILLI);
+    let durations = Durations::of(nanos.clone()).unwrap();
+    let expected = nanos.map(|n| n as f64 / 1e6).sum::<f64>() / 100.0;
+    assert_eq!(durations.avg, expected);
+    assert_eq!(durations.p50, 51.0);
+    assert_eq!(durations.p90, 90.0);
+    assert_eq!(durations.p99, 99.0);
+    assert_eq!(durations.max, 100.0);
+  }
+
+  #[test]
+  fn durations_of_one_sample() {
+    let durations = Durations::of([2_500_000].into_iter()).unwrap();
+    assert_eq!(
+      [
+        durations.avg,
+        durations.p50,
+        durations.p90,
+        durations.p99,
+        durations.max
+      ],
+      [2.5; 5]
+    );
+  }
+}
@@ -0,0 +1,30 @@
+use anyhow::Result;
+use serde_json::Value;
+
+use crate::FlutterEngine;
+use crate::channel::MethodCall;
+use crate::channel::MethodResult;
+use crate::frame_stats::FrameTiming;
+
+/// Event channel (`EventChannel` with `JSONMethodCodec` on the Dart side) streaming a
+/// [`FrameTiming`] for every frame
+pub const CHANNEL: &str = "wayflutter/frame_stats";
+
+pub fn handle_method_call(engine: &FlutterEngine, call: &MethodCall) -> Option<MethodResult> {
+  let state = engine.state();
+  match call.method.as_str() {
+    "listen" => {
+      state.frame_stats.set_listening(true);
+      Some(Ok(Value::Null))
+    }
+    "cancel" => {
+      state.frame_stats.set_listening(false);
+      Some(Ok(Value::Null))
+    }
+    _ => None,
+  }
+}
+
+pub(super) fn send_frame(engine: &FlutterEngine, timing: &FrameTiming) -> Result<()> {
+  crate::channel::send_event(engine, CHANNEL, serde_json::to_value(timing)?)
+}
@@ -0,0 +1,311 @@
+//! Night light for a redshift-like widget: the color temperature, gamma and brightness of
+//! outputs, set from Dart over wlr-gamma-control-unstable-v1. Opt-in with
+//! [`crate::config::Config::gamma_control`], as only one client at a time controls the gamma
+//! of an output, which may be wlsunset or the like.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::FlutterEngine;
+use crate::channel::MethodCall;
+use crate::channel::MethodError;
+use crate::channel::MethodResult;
+use crate::wayland::Output;
+use crate::wayland::gamma_control::GammaControl;
+use crate::wayland::gamma_control::GammaControlManager;
+
+/// Method channel (`MethodChannel` with `JSONMethodCodec` on the Dart side), registered if
+/// [`crate::config::Config::gamma_control`] is set
+pub const CHANNEL: &str = "wayflutter/gamma";
+
+/// the temperature of the white the outputs show by default
+const NEUTRAL_TEMPERATURE: u32 = 6500;
+
+/// How the colors of an output are adjusted. The defaults leave them as they are.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct ColorSettings {
+  /// of the white point in Kelvin, lower is warmer
+  pub temperature: u32,
+  pub gamma: f64,
+  /// from 0 to 1
+  pub brightness: f64,
+}
+
+impl Default for ColorSettings {
+  fn default() -> Self {
+    Self {
+      temperature: NEUTRAL_TEMPERATURE,
+      gamma: 1.0,
+      brightness: 1.0,
+    }
+  }
+}
+
+impl ColorSettings {
+  fn validate(&self) -> Result<()> {
+    if !(1000..=25000).contains(&self.temperature) {
+      anyhow::bail!(
+        "temperature must be between 1000 and 25000 K, got {}",
+        self.temperature
+      );
+    }
+    if self.gamma.is_nan() || self.gamma <= 0.0 {
+      anyhow::bail!("gamma must be positive, got {}", self.gamma);
+    }
+    if !(0.0..=1.0).contains(&self.brightness) {
+      anyhow::bail!(
+        "brightness must be between 0 and 1, got {}",
+        self.brightness
+      );
+    }
+    Ok(())
+  }
+
+  /// The red, green and blue ramps of `size` entries each, one after another
+  pub fn ramps(&self, size: usize) -> Vec<u16> {
+    let white_point = white_point(self.temperature);
+    let mut ramps = Vec::with_capacity(3 * size);
+    for channel in white_point {
+      ramps.extend((0..size).map(|i| {
+        let input = i as f64 / (size.max(2) - 1) as f64;
+        let output = input.powf(1.0 / self.gamma) * self.brightness * channel;
+        (output.clamp(0.0, 1.0) * u16::MAX as f64).round() as u16
+      }));
+    }
+    ramps
+  }
+}
+
+/// The red, green and blue of a black body at `temperature`, from 0 to 1, relative to
+/// [`NEUTRAL_TEMPERATURE`]
+fn white_point(temperature: u32) -> [f64; 3] {
+  let [red, green, blue] = black_body(temperature as f64);
+  let [neutral_red, neutral_green, neutral_blue] = black_body(NEUTRAL_TEMPERATURE as f64);
+  [
+    (red / neutral_red).min(1.0),
+    (green / neutral_green).min(1.0),
+    (blue / neutral_blue).min(1.0),
+  ]
+}
+
+/// Tanner Helland's fit of black body colors, from 0 to 1
+fn black_body(temperature: f64) -> [f64; 3] {
+  let t = temperature / 100.0;
+  let red = if t <= 66.0 {
+    255.0
+  } else {
+    329.698727446 * (t - 60.0).powf(-0.1332047592)
+  };
+  let green = if t <= 66.0 {
+    99.4708025861 * t.ln() - 161.1195681661
+  } else {
+    288.1221695283 * (t - 60.0).powf(-0.0755148492)
+  };
+  let blue = if t >= 66.0 {
+    255.0
+  } else if t <= 19.0 {
+    0.0
+  } else {
+    138.5177312231 * (t - 10.0).ln() - 305.0447927307
+  };
+  [red, green, blue].map(|channel| channel.clamp(0.0, 255.0) / 255.0)
+}
+
+/// The gamma of the outputs taken so far
+pub struct GammaControls {
+  /// `None` if the compositor doesn't support wlr-gamma-control-unstable-v1
+  manager: Option<GammaControlManager>,
+  /// by display id
+  controls: Mutex<HashMap<u64, GammaControl>>,
+}
+
+impl GammaControls {
+  pub fn new(manager: Option<GammaControlManager>) -> Self {
+    Self {
+      manager,
+      controls: Mutex::default(),
+    }
+  }
+
+  /// Take the gamma of `outputs` if not yet, and set it.
+  fn set(&self, outputs: &[Output], settings: ColorSettings) -> Result<()> {
+    let manager = self
+      .manager
+      .as_ref()
+      .context("wlr-gamma-control-unstable-v1 is not supported by the compositor")?;
+    let mut controls = self.controls.lock();
+    // e.g. of gone outputs, or taken by another client
+    controls.retain(|_, control| !control.failed());
+    for output in outputs {
+      controls
+        .entry(output.display_id)
+        .or_insert_with(|| manager.get_gamma_control(&output.wl_output))
+        .set(settings)?;
+    }
+    Ok(())
+  }
+
+  /// Hand the gamma of `outputs` back to the compositor, which restores it.
+  fn reset(&self, outputs: &[Output]) {
+    let mut controls = self.controls.lock();
+    for output in outputs {
+      controls.remove(&output.display_id);
+    }
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetArgs {
+  /// see [`LayerProps::output`], every output if `None`
+  ///
+  /// [`LayerProps::output`]: crate::layer::LayerProps::output
+  output: Option<String>,
+  #[serde(flatten)]
+  settings: ColorSettings,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResetArgs {
+  output: Option<String>,
+}
+
+pub fn handle_method_call(engine: &FlutterEngine, call: &MethodCall) -> Option<MethodResult> {
+  match call.method.as_str() {
+    "set" => Some(set(engine, call)),
+    "reset" => Some(reset(engine, call)),
+    _ => None,
+  }
+}
+
+/// e.g. `{"output": "DP-1", "temperature": 4000}`, the settings of [`ColorSettings`]
+fn set(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: SetArgs = call.args()?;
+  args
+    .settings
+    .validate()
+    .map_err(|e| MethodError::new("invalid_args", format!("{:#}", e)))?;
+  let state = engine.state();
+  let outputs = outputs(engine, args.output.as_deref())?;
+  state
+    .gamma
+    .set(&outputs, args.settings)
+    .map_err(|e| MethodError::new("unsupported", format!("{:#}", e)))?;
+  Ok(Value::Null)
+}
+
+fn reset(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: ResetArgs = call.args()?;
+  let outputs = outputs(engine, args.output.as_deref())?;
+  engine.state().gamma.reset(&outputs);
+  Ok(Value::Null)
+}
+
+/// The output named `name`, or every output
+fn outputs(engine: &FlutterEngine, name: Option<&str>) -> Result<Vec<Output>> {
+  let compositor = &engine.state().compositor;
+  match name {
+    Some(name) => Ok(vec![compositor.find_output(name)?]),
+    None => Ok(compositor.outputs()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The last entry of the red, green and blue ramps
+  fn brightest(ramps: &[u16], size: usize) -> [u16; 3] {
+    [ramps[size - 1], ramps[2 * size - 1], ramps[3 * size - 1]]
+  }
+
+  #[test]
+  fn defaults_leave_colors_alone() {
+    let ramps = ColorSettings::default().ramps(256);
+    assert_eq!(ramps.len(), 3 * 256);
+    for channel in ramps.chunks(256) {
+      assert!(
+        channel
+          .iter()
+          .enumerate()
+          .all(|(i, &v)| v == i as u16 * 257)
+      );
+    }
+  }
+
+  #[test]
+  fn warmer_dims_blue_and_keeps_red() {
+    let settings = ColorSettings {
+      temperature: 3000,
+      ..Default::default()
+    };
+    let [red, green, blue] = brightest(&settings.ramps(256), 256);
+    assert_eq!(red, u16::MAX);
+    assert!(blue < green && green < red);
+  }
+
+  #[test]
+  fn brightness_and_gamma_scale_the_ramps() {
+    let settings = ColorSettings {
+      brightness: 0.5,
+      ..Default::default()
+    };
+    assert_eq!(brightest(&settings.ramps(16), 16), [32768; 3]);
+    let settings = ColorSettings {
+      gamma: 2.0,
+      ..Default::default()
+    };
+    let ramps = settings.ramps(3);
+    // 0.5 ^ (1 / 2)
+    assert_eq!(ramps[..3], [0, 46340, u16::MAX]);
+  }
+
+  #[test]
+  fn ramps_of_one_entry() {
+    assert_eq!(ColorSettings::default().ramps(1), [0; 3]);
+  }
+
+  #[test]
+  fn validate_rejects_out_of_range_settings() {
+    assert!(ColorSettings::default().validate().is_ok());
+    let invalid = [
+      ColorSettings {
+        temperature: 500,
+        ..Default::default()
+      },
+      ColorSettings {
+        gamma: 0.0,
+        ..Default::default()
+      },
+      ColorSettings {
+        gamma: f64::NAN,
+        ..Default::default()
+      },
+      ColorSettings {
+        brightness: 1.5,
+        ..Default::default()
+      },
+    ];
+    for settings in invalid {
+      assert!(settings.validate().is_err(), "{:?}", settings);
+    }
+  }
+
+  #[test]
+  fn settings_deserialize_with_defaults() {
+    let settings: ColorSettings =
+      serde_json::from_value(serde_json::json!({ "temperature": 4000 })).unwrap();
+    assert_eq!(settings.temperature, 4000);
+    assert_eq!(settings.gamma, 1.0);
+    assert!(
+      serde_json::from_value::<ColorSettings>(serde_json::json!({ "kelvin": 4000 })).is_err()
+    );
+  }
+}
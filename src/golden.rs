@@ -0,0 +1,191 @@
+//! Regression checks of the rendering pipeline against golden PNGs, behind the `golden`
+//! feature. The app runs as usual, so run it in a headless compositor for CI, e.g. sway with
+//! `WLR_BACKENDS=headless WLR_RENDERER=pixman`, and keep animations out of the test app, as
+//! frames follow the clock.
+//!
+//! The goldens belong to the app: a frame depends on its snapshot and on the engine it was
+//! built for, so only the comparison itself is tested here.
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use crate::FlutterEngine;
+use crate::compositor::ViewId;
+use crate::compositor::capture::Image;
+
+/// How long a frame may take once scheduled
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Compare a frame of the implicit view with a PNG, then exit.
+#[derive(Debug, Clone)]
+pub struct GoldenOptions {
+  /// the expected frame
+  pub path: PathBuf,
+  /// frames presented before the one compared, which let layout and images settle
+  pub frames: u32,
+  /// how far a channel of a pixel may be off, for drivers rounding differently
+  pub tolerance: u8,
+  /// how many pixels may be off by more than `tolerance`
+  pub max_diff_pixels: usize,
+  /// write the frame to `path` instead of comparing
+  pub update: bool,
+}
+
+impl GoldenOptions {
+  pub fn new(path: PathBuf) -> Self {
+    Self {
+      path,
+      frames: 3,
+      tolerance: 2,
+      max_diff_pixels: 0,
+      update: false,
+    }
+  }
+}
+
+/// Resolves once the frame was compared. On a mismatch, the frame and where it differs are
+/// written next to the golden as `NAME.actual.png` and `NAME.diff.png`.
+pub async fn check(engine: &FlutterEngine, options: &GoldenOptions) -> Result<()> {
+  let state = engine.state();
+  let view = state
+    .compositor
+    .get_view(ViewId::new(0))
+    .context("no implicit view")?;
+  // nothing is drawn before the app runs
+  state.root_isolate.running().await;
+  let mut image = None;
+  for _ in 0..=options.frames {
+    let capture = view.request_capture();
+    engine.schedule_frame()?;
+    image = Some(
+      smol::future::or(
+        async { capture.await.context("the frame was dropped") },
+        async {
+          smol::Timer::after(FRAME_TIMEOUT).await;
+          Err(anyhow::anyhow!(
+            "timed out waiting for a frame. Is the implicit view visible?"
+          ))
+        },
+      )
+      .await?,
+    );
+  }
+  let image = image.expect("at least one frame");
+
+  let path = options.path.clone();
+  if options.update {
+    smol::unblock(move || image.write_png(&path)).await?;
+    log::info!("updated golden {:?}", options.path);
+    return Ok(());
+  }
+  let (tolerance, max_diff_pixels) = (options.tolerance, options.max_diff_pixels);
+  smol::unblock(move || compare(&image, &path, tolerance, max_diff_pixels)).await
+}
+
+fn compare(actual: &Image, path: &Path, tolerance: u8, max_diff_pixels: usize) -> Result<()> {
+  let golden = Image::read_png(path)?;
+  if (actual.width, actual.height) != (golden.width, golden.height) {
+    actual.write_png(&sibling(path, "actual"))?;
+    anyhow::bail!(
+      "the frame is {}x{}, but golden {:?} is {}x{}",
+      actual.width,
+      actual.height,
+      path,
+      golden.width,
+      golden.height
+    );
+  }
+
+  let (diff_pixels, diff) = diff(actual, &golden, tolerance);
+  if diff_pixels <= max_diff_pixels {
+    log::info!("frame matches golden {:?}", path);
+    return Ok(());
+  }
+  actual.write_png(&sibling(path, "actual"))?;
+  diff.write_png(&sibling(path, "diff"))?;
+  anyhow::bail!(
+    "{} pixels differ from golden {:?} by more than {}",
+    diff_pixels,
+    path,
+    tolerance
+  );
+}
+
+/// Count the pixels of which a channel is off by more than `tolerance`, and mark them red in an
+/// otherwise transparent image. The images must have the same size.
+fn diff(actual: &Image, golden: &Image, tolerance: u8) -> (usize, Image) {
+  let mut diff = Image {
+    width: actual.width,
+    height: actual.height,
+    data: vec![0; actual.data.len()],
+  };
+  let mut diff_pixels = 0;
+  for ((actual, golden), diff) in actual
+    .data
+    .chunks_exact(4)
+    .zip(golden.data.chunks_exact(4))
+    .zip(diff.data.chunks_exact_mut(4))
+  {
+    if actual
+      .iter()
+      .zip(golden)
+      .any(|(a, g)| a.abs_diff(*g) > tolerance)
+    {
+      diff_pixels += 1;
+      diff.copy_from_slice(&[255, 0, 0, 255]);
+    }
+  }
+  (diff_pixels, diff)
+}
+
+/// `bar.actual.png` for `bar.png`
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+  let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+  path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn image(pixels: &[[u8; 4]]) -> Image {
+    Image {
+      width: pixels.len() as u32,
+      height: 1,
+      data: pixels.concat(),
+    }
+  }
+
+  #[test]
+  fn diff_within_tolerance() {
+    let golden = image(&[[10, 20, 30, 255], [0, 0, 0, 0]]);
+    let actual = image(&[[12, 18, 30, 255], [0, 0, 2, 0]]);
+    let (diff_pixels, diff) = diff(&actual, &golden, 2);
+    assert_eq!(diff_pixels, 0);
+    assert!(diff.data.iter().all(|&x| x == 0));
+  }
+
+  #[test]
+  fn diff_marks_pixels_off_in_any_channel() {
+    let golden = image(&[[10, 20, 30, 255], [0, 0, 0, 0], [0, 0, 0, 255]]);
+    let actual = image(&[[10, 20, 30, 252], [0, 0, 0, 0], [5, 0, 0, 255]]);
+    let (diff_pixels, diff) = diff(&actual, &golden, 2);
+    assert_eq!(diff_pixels, 2);
+    assert_eq!(
+      diff.data,
+      [[255, 0, 0, 255], [0, 0, 0, 0], [255, 0, 0, 255]].concat()
+    );
+  }
+
+  #[test]
+  fn sibling_keeps_the_directory() {
+    assert_eq!(
+      sibling(Path::new("goldens/bar.png"), "diff"),
+      Path::new("goldens/bar.diff.png")
+    );
+  }
+}
@@ -0,0 +1,115 @@
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+use smol::Async;
+
+/// How long the files must stay unchanged before restarting, since builds write them in steps
+const SETTLE_DELAY: Duration = Duration::from_millis(300);
+
+/// The files an app is loaded from, watched through their directories because builds replace
+/// them rather than write them in place
+pub struct Watcher {
+  inotify: Async<OwnedFd>,
+  /// the file name to react to
+  name: OsString,
+}
+
+impl Watcher {
+  /// Watch the `kernel_blob.bin` in `asset_path`, or `aot_library` for AOT compiled apps.
+  pub fn new(asset_path: &Path, aot_library: Option<&Path>) -> Result<Self> {
+    let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+    if fd < 0 {
+      return Err(std::io::Error::last_os_error()).context("inotify_init1 failed");
+    }
+    let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+    let (dir, name) = match aot_library {
+      Some(aot_library) => (
+        aot_library
+          .parent()
+          .filter(|dir| !dir.as_os_str().is_empty())
+          .unwrap_or(Path::new(".")),
+        aot_library
+          .file_name()
+          .with_context(|| format!("AOT library {:?} has no file name", aot_library))?,
+      ),
+      None => (asset_path, OsStr::new("kernel_blob.bin")),
+    };
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    let wd = unsafe {
+      libc::inotify_add_watch(
+        inotify.as_raw_fd(),
+        path.as_ptr(),
+        libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE,
+      )
+    };
+    if wd < 0 {
+      return Err(std::io::Error::last_os_error())
+        .with_context(|| format!("failed to watch {:?}", dir));
+    }
+    log::info!("restarting when {:?} in {:?} changes", name, dir);
+    Ok(Self {
+      inotify: Async::new(inotify)?,
+      name: name.to_owned(),
+    })
+  }
+
+  /// Resolves once a watched file changed and then settled.
+  pub async fn changed(&self) -> Result<()> {
+    while !self.read_changes().await? {}
+    loop {
+      let settled = smol::future::or(
+        async {
+          smol::Timer::after(SETTLE_DELAY).await;
+          Ok(true)
+        },
+        async { self.read_changes().await.map(|_| false) },
+      )
+      .await?;
+      if settled {
+        return Ok(());
+      }
+    }
+  }
+
+  /// Waits for events, and returns whether any was about a watched file.
+  async fn read_changes(&self) -> Result<bool> {
+    let mut buf = [0u8; 4096];
+    let len = self
+      .inotify
+      .read_with(|fd| {
+        let n = unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr() as _, buf.len()) };
+        if n < 0 {
+          Err(std::io::Error::last_os_error())
+        } else {
+          Ok(n as usize)
+        }
+      })
+      .await?;
+
+    let mut changed = false;
+    let mut offset = 0;
+    // struct inotify_event, followed by a NUL padded name
+    while offset + size_of::<libc::inotify_event>() <= len {
+      let event =
+        unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const libc::inotify_event) };
+      let name_start = offset + size_of::<libc::inotify_event>();
+      let name_end = name_start + event.len as usize;
+      let name = buf[name_start..name_end.min(len)]
+        .split(|&b| b == 0)
+        .next()
+        .unwrap_or_default();
+      changed |= OsStr::from_bytes(name) == self.name;
+      offset = name_end;
+    }
+    Ok(changed)
+  }
+}
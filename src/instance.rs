@@ -0,0 +1,272 @@
+//! One instance per config, held by an abstract socket the kernel frees when the process exits
+//! however it exits. A later launch with the same config fails, or with `--replace` asks the
+//! running one to exit and takes over its surfaces, like waybar and mako reload.
+
+use std::ffi::OsStr;
+use std::io::ErrorKind;
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use futures::AsyncBufReadExt;
+use smol::net::unix::UnixListener;
+
+use crate::config::Config;
+
+/// How long the running instance may take to exit when replaced
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(10);
+const REPLACE_REQUEST: &str = "replace";
+/// How long a connection may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// Pause after a failed request, so errors that last, like running out of file descriptors,
+/// don't spin
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Held while the app runs
+pub struct InstanceLock {
+  listener: UnixListener,
+  id: String,
+}
+
+impl InstanceLock {
+  /// `None` if another instance with this config runs and `replace` isn't set.
+  pub fn acquire(config: &Config, replace: bool) -> Result<Option<Self>> {
+    let id = id(config);
+    let uid = unsafe { libc::getuid() };
+    let addr = SocketAddr::from_abstract_name(format!("wayflutter/{}/{}", uid, id))?;
+    let deadline = Instant::now() + REPLACE_TIMEOUT;
+    let mut asked = false;
+    loop {
+      match std::os::unix::net::UnixListener::bind_addr(&addr) {
+        Ok(listener) => {
+          return Ok(Some(Self {
+            listener: listener.try_into()?,
+            id,
+          }));
+        }
+        Err(e) if e.kind() == ErrorKind::AddrInUse => {}
+        Err(e) => return Err(e).context("failed to bind the instance socket"),
+      }
+      if !replace {
+        return Ok(None);
+      }
+      if !asked {
+        log::info!("asking the running instance to exit");
+        // it may be exiting already
+        if let Ok(mut stream) = UnixStream::connect_addr(&addr) {
+          writeln!(stream, "{}", REPLACE_REQUEST)?;
+        }
+        asked = true;
+      }
+      if Instant::now() > deadline {
+        anyhow::bail!(
+          "the running instance didn't exit within {:?}",
+          REPLACE_TIMEOUT
+        );
+      }
+      std::thread::sleep(Duration::from_millis(100));
+    }
+  }
+
+  /// See [`id`].
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// Resolves once a later launch asks to take over. Never fails, as nothing a client sends
+  /// should stop the app.
+  pub async fn replaced(&self) -> Result<()> {
+    loop {
+      match self.next_request().await {
+        Ok(true) => {
+          log::info!("replaced by another instance");
+          return Ok(());
+        }
+        Ok(false) => {}
+        Err(e) => {
+          log::warn!("Instance socket: {:#}", e);
+          smol::Timer::after(RETRY_DELAY).await;
+        }
+      }
+    }
+  }
+
+  /// Whether the next connection asks to take over. Only processes of our user may, as any
+  /// user can connect to an abstract socket.
+  async fn next_request(&self) -> Result<bool> {
+    let (stream, _) = self
+      .listener
+      .accept()
+      .await
+      .context("failed to accept a connection")?;
+    let uid = peer_uid(&stream).context("failed to get the credentials of a connection")?;
+    if uid != unsafe { libc::getuid() } {
+      anyhow::bail!("ignoring a connection of uid {}", uid);
+    }
+    let line = smol::future::or(
+      async {
+        let mut line = String::new();
+        smol::io::BufReader::new(stream)
+          .read_line(&mut line)
+          .await
+          .context("failed to read the request")?;
+        anyhow::Ok(line)
+      },
+      async {
+        smol::Timer::after(REQUEST_TIMEOUT).await;
+        anyhow::bail!("no request within {:?}", REQUEST_TIMEOUT)
+      },
+    )
+    .await?;
+    Ok(line.trim_end() == REPLACE_REQUEST)
+  }
+}
+
+/// The user of the process at the other end of `socket`
+fn peer_uid(socket: &impl AsRawFd) -> std::io::Result<libc::uid_t> {
+  let mut cred = libc::ucred {
+    pid: 0,
+    uid: 0,
+    gid: 0,
+  };
+  let mut len = size_of::<libc::ucred>() as libc::socklen_t;
+  let ret = unsafe {
+    libc::getsockopt(
+      socket.as_raw_fd(),
+      libc::SOL_SOCKET,
+      libc::SO_PEERCRED,
+      &mut cred as *mut libc::ucred as *mut libc::c_void,
+      &mut len,
+    )
+  };
+  if ret < 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+  Ok(cred.uid)
+}
+
+/// Tells the instances of a user apart by their config file, app id or assets, as one runs per
+/// config. The hash must not change between builds, or an instance built with another
+/// toolchain wouldn't be found.
+pub fn id(config: &Config) -> String {
+  match (&config.path, &config.app_id) {
+    (Some(path), _) => id_of("config", canonical(path).as_os_str()),
+    (None, Some(app_id)) => id_of("app", OsStr::new(app_id)),
+    (None, None) => id_of("assets", canonical(&config.asset_path).as_os_str()),
+  }
+}
+
+/// The [`id`] of the instance running with the config file, the assets directory or the app
+/// id `instance`
+pub fn id_for(instance: &str) -> String {
+  let path = Path::new(instance);
+  if path.is_file() {
+    id_of("config", canonical(path).as_os_str())
+  } else if path.is_dir() {
+    id_of("assets", canonical(path).as_os_str())
+  } else {
+    id_of("app", OsStr::new(instance))
+  }
+}
+
+fn id_of(kind: &str, key: &OsStr) -> String {
+  format!(
+    "{:016x}",
+    fnv1a([kind.as_bytes(), b"\0", key.as_bytes()].concat())
+  )
+}
+
+fn canonical(path: &Path) -> PathBuf {
+  std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+/// 64-bit FNV-1a, stable unlike [`std::hash::DefaultHasher`]
+fn fnv1a(bytes: impl AsRef<[u8]>) -> u64 {
+  bytes
+    .as_ref()
+    .iter()
+    .fold(0xcbf29ce484222325, |hash, &byte| {
+      (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn fnv1a_matches_the_reference() {
+    assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+    assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+  }
+
+  /// A lock on a socket of its own, and its address
+  fn lock(test: &str) -> (InstanceLock, SocketAddr) {
+    let name = format!("wayflutter-test/{}/{}", std::process::id(), test);
+    let addr = SocketAddr::from_abstract_name(name).unwrap();
+    let listener = std::os::unix::net::UnixListener::bind_addr(&addr).unwrap();
+    let lock = InstanceLock {
+      listener: listener.try_into().unwrap(),
+      id: test.to_owned(),
+    };
+    (lock, addr)
+  }
+
+  #[test]
+  fn bad_requests_dont_end_the_instance() {
+    let (lock, addr) = lock("bad-requests");
+    // gone right away
+    drop(UnixStream::connect_addr(&addr).unwrap());
+    let mut stream = UnixStream::connect_addr(&addr).unwrap();
+    stream.write_all(b"\xff\xfe\n").unwrap();
+    let mut stream = UnixStream::connect_addr(&addr).unwrap();
+    writeln!(stream, "reload").unwrap();
+    // stays silent
+    let _silent = UnixStream::connect_addr(&addr).unwrap();
+    let mut stream = UnixStream::connect_addr(&addr).unwrap();
+    writeln!(stream, "{}", REPLACE_REQUEST).unwrap();
+    smol::block_on(lock.replaced()).unwrap();
+  }
+
+  #[test]
+  fn commands_name_the_instance_like_its_config() {
+    let dir = std::env::temp_dir();
+    let exe = std::env::current_exe().unwrap();
+    assert_eq!(
+      id_for(exe.to_str().unwrap()),
+      id_of("config", canonical(&exe).as_os_str())
+    );
+    assert_eq!(
+      id_for(dir.to_str().unwrap()),
+      id_of("assets", canonical(&dir).as_os_str())
+    );
+    assert_eq!(
+      id_for("org.example.Bar"),
+      id_of("app", OsStr::new("org.example.Bar"))
+    );
+    assert_ne!(
+      id_of("app", OsStr::new("a")),
+      id_of("config", OsStr::new("a"))
+    );
+  }
+
+  #[test]
+  fn peers_are_our_user() {
+    let (lock, addr) = lock("peer-uid");
+    let client = UnixStream::connect_addr(&addr).unwrap();
+    let (server, _) = smol::block_on(lock.listener.accept()).unwrap();
+    let uid = unsafe { libc::getuid() };
+    assert_eq!(peer_uid(&client).unwrap(), uid);
+    assert_eq!(peer_uid(&server).unwrap(), uid);
+  }
+}
@@ -0,0 +1,162 @@
+//! Running the integration tests of an app in CI, see [`crate::config::Config::test`]. Tests
+//! of `package:integration_test` report their results over its plugin channel, and
+//! `flutter drive` connects to the VM service once the driver extension is registered.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+use futures::AsyncReadExt;
+use futures::AsyncWriteExt;
+use serde::Deserialize;
+use serde_json::Value;
+use smol::channel::Receiver;
+use smol::channel::Sender;
+use smol::net::TcpStream;
+
+use crate::FlutterEngine;
+use crate::channel::MethodCall;
+use crate::channel::MethodResult;
+use crate::vm_service;
+
+/// Method channel of `IntegrationTestWidgetsFlutterBinding`, with `StandardMethodCodec`
+pub const CHANNEL: &str = "plugins.flutter.io/integration_test";
+
+/// The service extension of `enableFlutterDriverExtension`
+const DRIVER_EXTENSION: &str = "ext.flutter.driver";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The results the tests reported, by test name
+pub struct TestResults {
+  tx: Sender<BTreeMap<String, Value>>,
+  rx: Receiver<BTreeMap<String, Value>>,
+}
+
+impl Default for TestResults {
+  fn default() -> Self {
+    let (tx, rx) = smol::channel::bounded(1);
+    Self { tx, rx }
+  }
+}
+
+pub fn handle_method_call(engine: &FlutterEngine, call: &MethodCall) -> Option<MethodResult> {
+  match call.method.as_str() {
+    "allTestsFinished" => Some(all_tests_finished(engine, call)),
+    _ => None,
+  }
+}
+
+#[derive(Deserialize)]
+struct Finished {
+  /// `"success"`, or the details of the failure
+  results: BTreeMap<String, Value>,
+}
+
+fn all_tests_finished(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let Finished { results } = call.args()?;
+  let _ = engine.state().test_results.tx.try_send(results);
+  Ok(Value::Null)
+}
+
+/// Resolves once the tests finished, with an error if any failed. Also prints the URI of the
+/// VM service on stdout once the driver extension is there, for `flutter drive
+/// --use-existing-app`.
+pub async fn run(engine: &FlutterEngine) -> Result<()> {
+  let state = engine.state();
+  let results = smol::future::or(
+    async {
+      state
+        .test_results
+        .rx
+        .recv()
+        .await
+        .map_err(anyhow::Error::from)
+    },
+    async {
+      let uri = wait_for_driver().await;
+      log::info!("driver extension registered");
+      println!("{}", uri);
+      std::future::pending().await
+    },
+  )
+  .await?;
+
+  let mut failed = 0;
+  for (name, result) in &results {
+    match result.as_str() {
+      Some("success") => log::info!("PASS {}", name),
+      _ => {
+        failed += 1;
+        match result {
+          Value::String(details) => log::error!("FAIL {}\n{}", name, details),
+          _ => log::error!("FAIL {}", name),
+        }
+      }
+    }
+  }
+  if failed > 0 {
+    anyhow::bail!("{} of {} tests failed", failed, results.len());
+  }
+  log::info!("all {} tests passed", results.len());
+  Ok(())
+}
+
+/// The URI of the VM service, once an isolate has the driver extension
+async fn wait_for_driver() -> String {
+  loop {
+    if let Some(uri) = vm_service::uri() {
+      match has_driver(&uri).await {
+        Ok(true) => return uri,
+        Ok(false) => {}
+        // e.g. while the service starts
+        Err(e) => log::debug!("failed to look for the driver extension: {:#}", e),
+      }
+    }
+    smol::Timer::after(POLL_INTERVAL).await;
+  }
+}
+
+async fn has_driver(uri: &str) -> Result<bool> {
+  let vm = get(uri, "getVM").await?;
+  let isolates = vm["isolates"].as_array().cloned().unwrap_or_default();
+  for isolate in isolates {
+    let Some(id) = isolate["id"].as_str() else {
+      continue;
+    };
+    let isolate = get(uri, &format!("getIsolate?isolateId={}", id)).await?;
+    let has_driver = isolate["extensionRPCs"]
+      .as_array()
+      .is_some_and(|rpcs| rpcs.iter().any(|rpc| rpc == DRIVER_EXTENSION));
+    if has_driver {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+
+/// Call a method of the VM service over its plain HTTP interface, where the method is the
+/// last segment of the path and its parameters are in the query.
+async fn get(uri: &str, method: &str) -> Result<Value> {
+  let rest = uri
+    .strip_prefix("http://")
+    .with_context(|| format!("unexpected VM service URI {:?}", uri))?;
+  let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+  let mut stream = TcpStream::connect(host)
+    .await
+    .with_context(|| format!("failed to connect to the VM service at {}", host))?;
+  let request = format!("GET /{}{} HTTP/1.0\r\nHost: {}\r\n\r\n", path, method, host);
+  stream.write_all(request.as_bytes()).await?;
+  let mut response = Vec::new();
+  stream.read_to_end(&mut response).await?;
+  let response = String::from_utf8_lossy(&response);
+  let (_, body) = response
+    .split_once("\r\n\r\n")
+    .context("malformed response from the VM service")?;
+  let mut body: Value = serde_json::from_str(body)?;
+  if let Some(error) = body.get("error") {
+    anyhow::bail!("{} failed: {}", method, error);
+  }
+  Ok(body["result"].take())
+}
@@ -0,0 +1,596 @@
+use std::ffi::OsStr;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use futures::AsyncBufReadExt;
+use futures::AsyncWriteExt;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::channel::oneshot;
+use futures::stream::FuturesUnordered;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::Map;
+use serde_json::Value;
+use serde_json::json;
+use smol::net::unix::UnixListener;
+use smol::net::unix::UnixStream;
+
+use crate::FlutterEngine;
+use crate::FlutterEngineState;
+use crate::cli::Command;
+use crate::compositor::Compositor;
+use crate::compositor::ViewId;
+use crate::compositor::Visibility;
+use crate::config::Config;
+use crate::instance;
+use crate::semantics;
+use crate::wayland::inject;
+use crate::wayland::inject::PointerAction;
+
+/// How long a screenshot or dump waits for the view to present a frame
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a semantics dump waits for the tree once semantics are turned on
+const SEMANTICS_TIMEOUT: Duration = Duration::from_secs(2);
+/// Pause after a failed accept, so errors that last, like running out of file descriptors,
+/// don't spin
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// A request to a running instance, sent as one line of JSON
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+  Screenshot {
+    view: ViewRef,
+    path: PathBuf,
+  },
+  Dump {
+    view: ViewRef,
+    dir: PathBuf,
+  },
+  Stats,
+  Views,
+  Semantics,
+  Hide {
+    view: ViewRef,
+  },
+  Show {
+    view: ViewRef,
+  },
+  Toggle {
+    view: ViewRef,
+  },
+  Configure {
+    view: ViewRef,
+    props: Map<String, Value>,
+  },
+  Reload,
+  Move {
+    view: ViewRef,
+    x: f64,
+    y: f64,
+  },
+  Click {
+    view: ViewRef,
+    x: f64,
+    y: f64,
+    button: PointerButton,
+  },
+  Scroll {
+    view: ViewRef,
+    x: f64,
+    y: f64,
+    dx: f64,
+    dy: f64,
+  },
+  Key {
+    view: ViewRef,
+    keys: Vec<String>,
+  },
+  Type {
+    view: ViewRef,
+    text: String,
+  },
+  Hud,
+  LowPower {
+    switch: Switch,
+  },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Switch {
+  On,
+  Off,
+  Toggle,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum PointerButton {
+  Left,
+  Right,
+  Middle,
+  Back,
+  Forward,
+}
+
+/// A view by id, or by the name of its surface
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ViewRef {
+  Id(i64),
+  Name(String),
+}
+
+impl FromStr for ViewRef {
+  type Err = std::convert::Infallible;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    Ok(match s.parse() {
+      Ok(id) => ViewRef::Id(id),
+      Err(_) => ViewRef::Name(s.to_owned()),
+    })
+  }
+}
+
+impl ViewRef {
+  fn resolve(&self, compositor: &Compositor) -> Result<ViewId> {
+    match self {
+      ViewRef::Id(id) => Ok(ViewId::new(*id)),
+      ViewRef::Name(name) => compositor
+        .find_view_by_name(name)
+        .with_context(|| format!("no view named {:?}", name)),
+    }
+  }
+}
+
+/// The answer to a [`Request`], sent as one line of JSON
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+  /// `null` for requests without a result
+  Ok(Value),
+  Error(String),
+}
+
+/// `$WAYFLUTTER_SOCKET`, or the socket of the instance with the [`instance::id`] `id` in
+/// `$XDG_RUNTIME_DIR/wayflutter`
+pub fn socket_path(id: &str) -> Result<PathBuf> {
+  if let Some(path) = std::env::var_os("WAYFLUTTER_SOCKET") {
+    return Ok(path.into());
+  }
+  Ok(socket_dir()?.join(format!("{}.sock", id)))
+}
+
+fn socket_dir() -> Result<PathBuf> {
+  let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").context("XDG_RUNTIME_DIR is not set")?;
+  Ok(PathBuf::from(runtime_dir).join("wayflutter"))
+}
+
+/// The socket of the instance running with the config file, assets or app id `instance`, or
+/// else of the only instance running
+fn find_socket(instance: Option<&str>) -> Result<PathBuf> {
+  if let Some(path) = std::env::var_os("WAYFLUTTER_SOCKET") {
+    return Ok(path.into());
+  }
+  if let Some(instance) = instance {
+    return socket_path(&instance::id_for(instance));
+  }
+  let dir = socket_dir()?;
+  let running = std::fs::read_dir(&dir)
+    .into_iter()
+    .flatten()
+    .filter_map(|entry| Some(entry.ok()?.path()))
+    .filter(|path| path.extension() == Some(OsStr::new("sock")))
+    // not left behind by an instance that didn't exit cleanly
+    .filter(|path| std::os::unix::net::UnixStream::connect(path).is_ok())
+    .collect::<Vec<_>>();
+  match <[PathBuf; 1]>::try_from(running) {
+    Ok([path]) => Ok(path),
+    Err(running) if running.is_empty() => {
+      anyhow::bail!("no control socket in {:?}. Is wayflutter running?", dir)
+    }
+    Err(running) => anyhow::bail!(
+      "{} instances are running, pick one with --instance",
+      running.len()
+    ),
+  }
+}
+
+/// Send `command` to the running instance, or to the one `instance` names (see
+/// [`Args::instance`]), and wait until it's done.
+///
+/// [`Args::instance`]: crate::cli::Args::instance
+pub fn run_command(command: Command, instance: Option<&str>) -> Result<()> {
+  let request = match command {
+    Command::Screenshot { view, path } => Request::Screenshot {
+      view,
+      // the instance may run in another working directory
+      path: std::path::absolute(path)?,
+    },
+    Command::Dump { view, dir } => Request::Dump {
+      view,
+      dir: std::path::absolute(dir)?,
+    },
+    Command::Stats => Request::Stats,
+    Command::Views => Request::Views,
+    Command::Semantics => Request::Semantics,
+    Command::Hide { view } => Request::Hide { view },
+    Command::Show { view } => Request::Show { view },
+    Command::Toggle { view } => Request::Toggle { view },
+    Command::Configure { view, props } => Request::Configure {
+      view,
+      props: serde_json::from_str(&props).context("PROPS is not a JSON object")?,
+    },
+    Command::Reload => Request::Reload,
+    Command::Move { view, x, y } => Request::Move { view, x, y },
+    Command::Click { view, x, y, button } => Request::Click { view, x, y, button },
+    Command::Scroll { view, x, y, dx, dy } => Request::Scroll { view, x, y, dx, dy },
+    Command::Key { view, keys } => Request::Key { view, keys },
+    Command::Type { view, text } => Request::Type { view, text },
+    Command::Hud => Request::Hud,
+    Command::LowPower { switch } => Request::LowPower { switch },
+  };
+
+  let socket_path = find_socket(instance)?;
+  let mut stream = std::os::unix::net::UnixStream::connect(&socket_path).with_context(|| {
+    format!(
+      "failed to connect to {:?}. Is wayflutter running?",
+      socket_path
+    )
+  })?;
+  let mut line = serde_json::to_string(&request)?;
+  line.push('\n');
+  stream.write_all(line.as_bytes())?;
+
+  let mut line = String::new();
+  BufReader::new(stream).read_line(&mut line)?;
+  match serde_json::from_str(&line).context("invalid response")? {
+    Response::Ok(Value::Null) => Ok(()),
+    Response::Ok(result) => {
+      println!("{}", serde_json::to_string_pretty(&result)?);
+      Ok(())
+    }
+    Response::Error(e) => anyhow::bail!("{}", e),
+  }
+}
+
+/// Answer requests on the control socket until the app exits.
+///
+/// Never fails the app because the socket can't be set up. `id` is the [`instance::id`].
+pub async fn serve(engine: &FlutterEngine, id: &str) -> Result<()> {
+  let listener = match Listener::bind(id) {
+    Ok(listener) => listener,
+    Err(e) => {
+      log::warn!("Control socket disabled: {:#}", e);
+      return std::future::pending().await;
+    }
+  };
+
+  let mut connections = FuturesUnordered::new();
+  loop {
+    futures::select! {
+        accepted = listener.listener.accept().fuse() => match accepted {
+          Ok((stream, _)) => connections.push(handle_connection(engine, stream)),
+          Err(e) => {
+            log::warn!("Control socket: failed to accept a connection: {}", e);
+            smol::Timer::after(ACCEPT_RETRY_DELAY).await;
+          }
+        },
+        result = connections.select_next_some() => {
+          if let Err(e) = result {
+            log::warn!("Control socket: {:#}", e);
+          }
+        },
+    }
+  }
+}
+
+/// Removes the socket file when dropped
+struct Listener {
+  listener: UnixListener,
+  path: PathBuf,
+}
+
+impl Listener {
+  fn bind(id: &str) -> Result<Self> {
+    let path = socket_path(id)?;
+    if let Some(dir) = path.parent() {
+      std::fs::create_dir_all(dir).with_context(|| format!("failed to create {:?}", dir))?;
+    }
+    if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+      anyhow::bail!("another instance is listening on {:?}", path);
+    }
+    // left behind by an instance that didn't exit cleanly
+    let _ = std::fs::remove_file(&path);
+    let listener =
+      UnixListener::bind(&path).with_context(|| format!("failed to bind {:?}", path))?;
+    log::info!("Control socket: {:?}", path);
+    Ok(Self { listener, path })
+  }
+}
+
+impl Drop for Listener {
+  fn drop(&mut self) {
+    let _ = std::fs::remove_file(&self.path);
+  }
+}
+
+async fn handle_connection(engine: &FlutterEngine, mut stream: UnixStream) -> Result<()> {
+  let mut line = String::new();
+  smol::io::BufReader::new(stream.clone())
+    .read_line(&mut line)
+    .await?;
+  if line.is_empty() {
+    // a client looking for running instances
+    return Ok(());
+  }
+  let response = match serde_json::from_str::<Request>(&line) {
+    Ok(request) => match handle_request(engine, request).await {
+      Ok(result) => Response::Ok(result),
+      Err(e) => Response::Error(format!("{:#}", e)),
+    },
+    Err(e) => Response::Error(format!("invalid request: {}", e)),
+  };
+  let mut line = serde_json::to_string(&response)?;
+  line.push('\n');
+  stream.write_all(line.as_bytes()).await?;
+  Ok(())
+}
+
+/// The views with their outputs, sizes and state, also for crash reports
+pub(crate) fn views(state: &FlutterEngineState) -> Value {
+  let focus = state.compositor.keyboard_focus();
+  let views = state
+    .compositor
+    .views()
+    .iter()
+    .map(|view| {
+      let geometry = view.geometry.lock().current;
+      json!({
+        "view": view.view_id.raw(),
+        "output": view.output.as_ref().and_then(|output| output.name.clone()),
+        "width": geometry.map(|geometry| geometry.logical_size.width),
+        "height": geometry.map(|geometry| geometry.logical_size.height),
+        "scale": geometry.map(|geometry| geometry.scale),
+        "visibility": format!("{:?}", *view.visibility.lock()).to_lowercase(),
+        "closed": view.is_closed(),
+        "focused": focus == Some(view.view_id),
+        "layer": view.layer_props(),
+      })
+    })
+    .collect();
+  Value::Array(views)
+}
+
+/// Schedule a frame of `view_id` and wait for what `request` asks for it, once the app runs.
+async fn next_frame<T>(
+  engine: &FlutterEngine,
+  view_id: ViewId,
+  request: impl FnOnce() -> oneshot::Receiver<T>,
+) -> Result<T> {
+  let state = engine.state();
+  smol::future::or(
+    async {
+      // nothing is drawn before the app runs
+      state.root_isolate.running().await;
+      let receiver = request();
+      engine.schedule_frame()?;
+      receiver.await.context("the frame was dropped")
+    },
+    async {
+      smol::Timer::after(CAPTURE_TIMEOUT).await;
+      Err(anyhow::anyhow!(
+        "timed out waiting for a frame. Is {} visible?",
+        view_id
+      ))
+    },
+  )
+  .await
+}
+
+async fn handle_request(engine: &FlutterEngine, request: Request) -> Result<Value> {
+  let state = engine.state();
+  match request {
+    Request::Screenshot { view, path } => {
+      let view_id = view.resolve(&state.compositor)?;
+      let view = state
+        .compositor
+        .get_view(view_id)
+        .with_context(|| format!("{} not found", view_id))?;
+      let image = next_frame(engine, view_id, || view.request_capture()).await?;
+      smol::unblock(move || image.write_png(&path)).await?;
+      Ok(Value::Null)
+    }
+    Request::Dump { view, dir } => {
+      let view_id = view.resolve(&state.compositor)?;
+      let view = state
+        .compositor
+        .get_view(view_id)
+        .with_context(|| format!("{} not found", view_id))?;
+      let dump = next_frame(engine, view_id, || view.request_dump()).await?;
+      smol::unblock(move || {
+        std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {:?}", dir))?;
+        let mut files = Vec::new();
+        for (i, ((x, y), image)) in dump.layers.iter().enumerate() {
+          let path = dir.join(format!("{:02}-layer.png", i));
+          image.write_png(&path)?;
+          files.push(json!({
+            "path": path,
+            "x": x,
+            "y": y,
+            "width": image.width,
+            "height": image.height,
+          }));
+        }
+        let path = dir.join(format!("{:02}-surface.png", dump.layers.len()));
+        dump.surface.write_png(&path)?;
+        files.push(json!({
+          "path": path,
+          "width": dump.surface.width,
+          "height": dump.surface.height,
+        }));
+        Ok(Value::Array(files))
+      })
+      .await
+    }
+    Request::Stats => Ok(serde_json::to_value(state.frame_stats.summary())?),
+    Request::Views => Ok(views(state)),
+    Request::Semantics => {
+      if semantics::turn_on(engine)? {
+        // the engine sends the whole tree once it's turned on
+        let deadline = Instant::now() + SEMANTICS_TIMEOUT;
+        while semantics::dump(state).is_null() && Instant::now() < deadline {
+          smol::Timer::after(Duration::from_millis(50)).await;
+        }
+      }
+      Ok(semantics::dump(state))
+    }
+    Request::Hide { view } => {
+      state
+        .compositor
+        .hide_view(view.resolve(&state.compositor)?)?;
+      Ok(Value::Null)
+    }
+    Request::Show { view } => {
+      state
+        .compositor
+        .show_view(view.resolve(&state.compositor)?)?;
+      Ok(Value::Null)
+    }
+    Request::Toggle { view } => {
+      let view_id = view.resolve(&state.compositor)?;
+      let hidden = state
+        .compositor
+        .get_view(view_id)
+        .is_some_and(|view| *view.visibility.lock() != Visibility::Shown);
+      if hidden {
+        state.compositor.show_view(view_id)?;
+      } else {
+        state.compositor.hide_view(view_id)?;
+      }
+      Ok(Value::Null)
+    }
+    Request::Configure { view, props } => {
+      let view_id = view.resolve(&state.compositor)?;
+      let current = state
+        .compositor
+        .get_view(view_id)
+        .with_context(|| format!("{} not found", view_id))?
+        .layer_props()
+        .with_context(|| format!("{} is not a layer surface", view_id))?;
+      let props = current.patched(props)?;
+      state.compositor.set_layer_props(engine, view_id, &props)?;
+      Ok(Value::Null)
+    }
+    Request::Reload => {
+      let path = state
+        .config_path
+        .as_ref()
+        .context("wayflutter wasn't started with a config file")?;
+      let config = Config::load(path)?;
+      config.validate()?;
+      state.compositor.reconfigure(engine, &config.surfaces)?;
+      log::info!("Reloaded the surfaces from {:?}", path);
+      Ok(Value::Null)
+    }
+    Request::Move { view, x, y } => {
+      let view_id = view.resolve(&state.compositor)?;
+      inject::pointer(engine, view_id, (x, y), PointerAction::Move)?;
+      Ok(Value::Null)
+    }
+    Request::Click { view, x, y, button } => {
+      let view_id = view.resolve(&state.compositor)?;
+      inject::pointer(engine, view_id, (x, y), PointerAction::Click(button))?;
+      Ok(Value::Null)
+    }
+    Request::Scroll { view, x, y, dx, dy } => {
+      let view_id = view.resolve(&state.compositor)?;
+      inject::pointer(engine, view_id, (x, y), PointerAction::Scroll { dx, dy })?;
+      Ok(Value::Null)
+    }
+    Request::Key { view, keys } => {
+      inject::keys(engine, view.resolve(&state.compositor)?, &keys)?;
+      Ok(Value::Null)
+    }
+    Request::Type { view, text } => {
+      inject::text(engine, view.resolve(&state.compositor)?, &text)?;
+      Ok(Value::Null)
+    }
+    Request::Hud => {
+      let shown = state.hud.toggle();
+      log::info!("HUD {}", if shown { "shown" } else { "hidden" });
+      // redraw without waiting for the app to
+      engine.schedule_frame()?;
+      Ok(Value::Null)
+    }
+    Request::LowPower { switch } => {
+      let low_power = match switch {
+        Switch::On => true,
+        Switch::Off => false,
+        Switch::Toggle => !state.compositor.is_low_power(),
+      };
+      state.compositor.set_low_power(engine, low_power)?;
+      Ok(Value::Null)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+
+  use super::*;
+
+  #[test]
+  fn requests_are_tagged_with_the_command() {
+    let request = Request::Click {
+      view: ViewRef::Name("bar".to_owned()),
+      x: 10.0,
+      y: 20.5,
+      button: PointerButton::Left,
+    };
+    assert_eq!(
+      serde_json::to_value(&request).unwrap(),
+      json!({ "command": "click", "view": "bar", "x": 10.0, "y": 20.5, "button": "left" }),
+    );
+    let request = Request::LowPower {
+      switch: Switch::Toggle,
+    };
+    assert_eq!(
+      serde_json::to_value(&request).unwrap(),
+      json!({ "command": "low_power", "switch": "toggle" }),
+    );
+  }
+
+  #[test]
+  fn requests_parse_from_a_line() {
+    let request = serde_json::from_str(r#"{"command":"screenshot","view":0,"path":"/tmp/a.png"}"#);
+    let Ok(Request::Screenshot {
+      view: ViewRef::Id(0),
+      path,
+    }) = request
+    else {
+      panic!("unexpected {:?}", request);
+    };
+    assert_eq!(path, PathBuf::from("/tmp/a.png"));
+    let request = serde_json::from_str(r#"{"command":"stats"}"#);
+    assert!(matches!(request, Ok(Request::Stats)), "{:?}", request);
+    assert!(serde_json::from_str::<Request>(r#"{"command":"reboot"}"#).is_err());
+  }
+
+  #[test]
+  fn view_refs_are_ids_or_names() {
+    assert!(matches!("3".parse(), Ok(ViewRef::Id(3))));
+    assert!(matches!("-1".parse(), Ok(ViewRef::Id(-1))));
+    assert!(matches!("bar".parse(), Ok(ViewRef::Name(name)) if name == "bar"));
+  }
+}
@@ -0,0 +1,110 @@
+use futures::channel::oneshot;
+use parking_lot::Mutex;
+use smol::channel::Sender;
+
+use crate::FlutterEngine;
+
+/// The root isolate, which runs the `main` of the app, started or gone. Messages sent to the
+/// app while it's not running are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsolateEvent {
+  /// `main` is about to run, also after a hot restart
+  Created,
+  /// before a hot restart or when the engine shuts down
+  ShutDown,
+}
+
+/// Called on the platform thread, see [`crate::Plugin::on_isolate`]
+pub type IsolateHook = fn(&FlutterEngine, IsolateEvent);
+
+/// Whether the root isolate runs, and who wants to know
+pub struct RootIsolate {
+  running: Mutex<bool>,
+  waiters: Mutex<Vec<oneshot::Sender<()>>>,
+  hooks: Vec<IsolateHook>,
+  listeners: Vec<Sender<IsolateEvent>>,
+}
+
+impl RootIsolate {
+  pub fn new(hooks: Vec<IsolateHook>, listeners: Vec<Sender<IsolateEvent>>) -> Self {
+    Self {
+      running: Mutex::new(false),
+      waiters: Mutex::new(Vec::new()),
+      hooks,
+      listeners,
+    }
+  }
+
+  /// Resolves once the root isolate runs
+  pub async fn running(&self) {
+    let rx = {
+      let running = self.running.lock();
+      if *running {
+        return;
+      }
+      let (tx, rx) = oneshot::channel();
+      self.waiters.lock().push(tx);
+      rx
+    };
+    // the sender is dropped only with the engine
+    let _ = rx.await;
+  }
+
+  /// Returns whether it changed
+  fn set_running(&self, running: bool) -> bool {
+    let mut current = self.running.lock();
+    if *current == running {
+      return false;
+    }
+    *current = running;
+    if running {
+      for waiter in self.waiters.lock().drain(..) {
+        let _ = waiter.send(());
+      }
+    }
+    true
+  }
+}
+
+/// Must be called on the platform thread.
+pub fn created(engine: &FlutterEngine) {
+  let state = engine.state();
+  if !state.root_isolate.set_running(true) {
+    return;
+  }
+  log::debug!("root isolate created");
+  // sent before the isolate ran, and dropped
+  if let Err(e) = state.compositor.resend_keyboard_focus(engine) {
+    log::warn!("failed to send the keyboard focus: {:#}", e);
+  }
+  notify(engine, IsolateEvent::Created);
+}
+
+/// Must be called on the platform thread.
+pub fn shut_down(engine: &FlutterEngine) {
+  if !engine.state().root_isolate.set_running(false) {
+    return;
+  }
+  log::debug!("root isolate shut down");
+  notify(engine, IsolateEvent::ShutDown);
+}
+
+fn notify(engine: &FlutterEngine, event: IsolateEvent) {
+  let state = engine.state();
+  for listener in &state.root_isolate.listeners {
+    // or it stopped listening
+    let _ = listener.try_send(event);
+  }
+  for hook in &state.root_isolate.hooks {
+    hook(engine, event);
+  }
+}
+
+/// Tells everyone the isolate is gone when the engine shuts down
+pub struct ShutDownGuard<'a>(pub &'a FlutterEngine);
+
+impl Drop for ShutDownGuard<'_> {
+  fn drop(&mut self) {
+    shut_down(self.0);
+  }
+}
@@ -0,0 +1,813 @@
+//! Flutter apps as layer-shell surfaces on Wayland, for bars, docks, launchers, overlays and
+//! wallpapers.
+//!
+//! The `wayflutter` binary is a thin command line interface over [`Embedder`], which other
+//! programs can use to embed their own apps with their own method channels.
+
+mod activation;
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
+mod callback;
+mod channel;
+pub mod cli;
+mod compositor;
+pub mod config;
+mod crash;
+mod dart_vm;
+mod embedder;
+mod error;
+mod exception;
+mod ffi;
+mod frame_stats;
+mod gamma;
+#[cfg(feature = "golden")]
+mod golden;
+mod hot_restart;
+mod instance;
+mod integration_test;
+pub mod ipc;
+mod isolate;
+mod locale;
+pub mod logging;
+mod memory_pressure;
+mod opengl;
+mod output_power;
+mod semantics;
+mod systemd;
+mod task_runner;
+mod trace;
+mod vm_service;
+mod watchdog;
+mod wayland;
+#[macro_use]
+mod macros;
+
+use std::ffi::CString;
+use std::ffi::c_void;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::thread::ThreadId;
+
+use anyhow::Context;
+use anyhow::Result;
+use error::FFIFlutterEngineResultExt;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::channel::mpsc::UnboundedSender;
+use parking_lot::Mutex;
+
+use crate::activation::Activations;
+use crate::channel::Channels;
+use crate::channel::Codec;
+pub use crate::channel::MethodCall;
+pub use crate::channel::MethodError;
+pub use crate::channel::MethodHandler;
+pub use crate::channel::MethodResult;
+pub use crate::channel::send_event;
+use crate::compositor::Compositor;
+pub use crate::compositor::SurfaceOptions;
+use crate::compositor::ViewId;
+pub use crate::compositor::auto_hide::AutoHide;
+use crate::compositor::hud::Hud;
+pub use crate::compositor::layer;
+use crate::config::Config;
+pub use crate::dart_vm::DartVmOptions;
+pub use crate::embedder::CrashRestartOptions;
+pub use crate::embedder::Embedder;
+pub use crate::embedder::EmbedderBuilder;
+pub use crate::embedder::EmbedderHandle;
+pub use crate::embedder::Plugin;
+pub use crate::error::ConnectionLost;
+use crate::error::ContextLost;
+pub use crate::error::ErrorPolicies;
+use crate::error::ErrorTracker;
+pub use crate::error::Policy;
+pub use crate::exception::DartException;
+pub use crate::exception::ExceptionHook;
+pub use crate::exception::ExceptionOptions;
+use crate::exception::Exceptions;
+#[cfg(feature = "golden")]
+pub use crate::golden::GoldenOptions;
+use crate::frame_stats::FrameStats;
+use crate::gamma::GammaControls;
+use crate::instance::InstanceLock;
+use crate::integration_test::TestResults;
+pub use crate::isolate::IsolateEvent;
+pub use crate::isolate::IsolateHook;
+use crate::isolate::RootIsolate;
+use crate::opengl::OpenGLState;
+pub use crate::opengl::RenderOptions;
+use crate::output_power::OutputPowers;
+use crate::semantics::Semantics;
+pub use crate::semantics::SemanticsAction;
+pub use crate::semantics::SemanticsFlag;
+pub use crate::semantics::SemanticsNode;
+pub use crate::semantics::SemanticsUpdate;
+use crate::task_runner::TaskRunnerHandle;
+use crate::task_runner::make_task_runner;
+use crate::task_runner::render::RenderTaskRunner;
+pub use crate::vm_service::VmServiceOptions;
+use crate::watchdog::Heartbeat;
+use crate::watchdog::Watchdog;
+pub use crate::watchdog::WatchdogOptions;
+use crate::wayland::WaylandClient;
+use crate::wayland::inject::InjectedPointer;
+use crate::wayland::presentation::FrameClock;
+
+/// Why [`run_flutter`] returned
+enum Exit {
+  Quit,
+  /// the app was rebuilt, see [`Config::hot_restart`]
+  Restart,
+  /// the compositor went away, see [`Config::reconnect`]
+  Reconnect,
+  /// a fatal error in a callback, see [`Config::crash_restart`]
+  Crashed(anyhow::Error),
+  /// the GL contexts were lost, see [`ContextLost`]
+  ContextLost(anyhow::Error),
+}
+
+#[cfg_attr(feature = "golden", allow(clippy::too_many_arguments))]
+async fn run_flutter(
+  config: Config,
+  plugins: &[Plugin],
+  semantics_listeners: &[smol::channel::Sender<SemanticsUpdate>],
+  isolate_listeners: &[smol::channel::Sender<IsolateEvent>],
+  activations: &Activations,
+  instance: &InstanceLock,
+  #[cfg(feature = "golden")] golden: Option<&golden::GoldenOptions>,
+  quit: &smol::channel::Receiver<()>,
+) -> Result<Exit> {
+  let surface_options = config.surface_options();
+  let aot_library = config.resolve_aot_library(FlutterEngine::runs_aot_compiled_code())?;
+  let Config {
+    path: config_path,
+    asset_path,
+    icu_data_path,
+    render: render_options,
+    route,
+    engine_args,
+    vm_service,
+    dart_vm,
+    hot_restart,
+    test,
+    reconnect,
+    gamma_control,
+    merged_ui_thread,
+    quiet_dart,
+    crash_restart,
+    watchdog,
+    errors,
+    exceptions,
+    surfaces,
+    ..
+  } = config;
+  let mut surfaces = surfaces.into_iter();
+  let layer_props = surfaces.next().unwrap_or_default();
+  let extra_layer_props = surfaces.collect::<Vec<_>>();
+  if !ffi::features().multi_view && (!extra_layer_props.is_empty() || surface_options.every_output)
+  {
+    anyhow::bail!(
+      "the engine is too old to show more than one view; update it or leave out the extra \
+       surfaces and every_output"
+    );
+  }
+
+  let conn = wayland_client::Connection::connect_to_env()?;
+
+  let mut switches = vm_service.switches();
+  switches.extend(dart_vm.switches());
+  if let Some(route) = route {
+    switches.push(format!("--route={}", route));
+  }
+  if render_options.impeller {
+    switches.push("--enable-impeller=true".to_owned());
+  }
+  // later switches override earlier ones
+  switches.extend_from_slice(&engine_args);
+
+  let watcher = hot_restart
+    .then(|| hot_restart::Watcher::new(&asset_path, aot_library.as_deref()))
+    .transpose()?;
+  let app_changed = async {
+    match &watcher {
+      Some(watcher) => watcher.changed().await,
+      None => std::future::pending().await,
+    }
+  };
+
+  let startup = trace_span!("startup");
+  // before the engine, which is told whether to cache backing stores
+  let opengl_state = OpenGLState::init(&conn, render_options)?;
+  let engine = FlutterEngine::init(
+    &asset_path,
+    &icu_data_path,
+    aot_library.as_deref(),
+    &switches,
+    dart_vm.old_gen_heap_size(),
+    merged_ui_thread,
+    opengl_state.dmabuf_allocator.is_some(),
+  )?;
+
+  let (terminate_tx, mut terminate_rx) = futures::channel::mpsc::unbounded();
+
+  let wayland_client = WaylandClient::new(&conn, &engine)?;
+
+  let compositor = Compositor::init(
+    &wayland_client,
+    &opengl_state,
+    surface_options,
+    layer_props,
+    &extra_layer_props,
+  )?;
+
+  let gamma_control_manager = gamma_control
+    .then(|| wayland_client.gamma_control_manager())
+    .flatten();
+  if gamma_control && gamma_control_manager.is_none() {
+    log::warn!("Gamma control disabled: wlr-gamma-control-unstable-v1 is not supported");
+  }
+
+  let output_powers = OutputPowers::new(wayland_client.output_power_manager());
+  output_powers.track(&compositor.outputs());
+
+  let mut channels = Channels::default();
+  channels.register(
+    compositor::channel::CHANNEL,
+    compositor::channel::handle_method_call,
+  );
+  channels.register(
+    frame_stats::channel::CHANNEL,
+    frame_stats::channel::handle_method_call,
+  );
+  channels.register(
+    compositor::auto_hide::CHANNEL,
+    compositor::auto_hide::handle_method_call,
+  );
+  channels.register(
+    compositor::popup::CHANNEL,
+    compositor::popup::handle_method_call,
+  );
+  channels.register(
+    compositor::input_method::CHANNEL,
+    compositor::input_method::handle_method_call,
+  );
+  channels.register(
+    logging::channel::CHANNEL,
+    logging::channel::handle_method_call,
+  );
+  channels.register(output_power::CHANNEL, output_power::handle_method_call);
+  if gamma_control {
+    channels.register(gamma::CHANNEL, gamma::handle_method_call);
+  }
+  if test {
+    channels.register_with_codec(
+      integration_test::CHANNEL,
+      Codec::Standard,
+      integration_test::handle_method_call,
+    );
+  }
+  for plugin in plugins {
+    channels.register(plugin.channel, plugin.handler);
+  }
+
+  let frame_clock = wayland_client.frame_clock();
+
+  let (task_runner, task_runner_handle) = make_task_runner(&engine);
+
+  engine.init_state(FlutterEngineState {
+    terminate: terminate_tx,
+    compositor,
+    opengl_state,
+    task_runner_handle,
+    channels,
+    frame_clock,
+    frame_stats: FrameStats::default(),
+    heartbeat: Heartbeat::default(),
+    errors: ErrorTracker::new(errors),
+    parked_vsync_baton: Mutex::new(None),
+    config_path,
+    quiet_dart,
+    semantics: Semantics::new(semantics_listeners.to_vec()),
+    exceptions: Exceptions::new(
+      exceptions,
+      plugins
+        .iter()
+        .filter_map(|plugin| plugin.on_exception)
+        .collect(),
+    ),
+    root_isolate: RootIsolate::new(
+      plugins
+        .iter()
+        .filter_map(|plugin| plugin.on_isolate)
+        .collect(),
+      isolate_listeners.to_vec(),
+    ),
+    injected_pointer: InjectedPointer::default(),
+    test_results: TestResults::default(),
+    hud: Hud::default(),
+    gamma: GammaControls::new(gamma_control_manager),
+    output_powers,
+  })?;
+
+  unsafe {
+    engine.run()?;
+  }
+  let _isolate = isolate::ShutDownGuard(&engine);
+  engine.state().compositor.notify_displays(&engine)?;
+  locale::update(&engine)?;
+  semantics::enable(&engine)?;
+  drop(startup);
+  let _watchdog = Watchdog::spawn(&engine, watchdog)?;
+
+  let golden_checked = async {
+    #[cfg(feature = "golden")]
+    if let Some(options) = golden {
+      return golden::check(&engine, options).await;
+    }
+    std::future::pending::<Result<()>>().await
+  };
+  let tests_finished = async {
+    if test {
+      integration_test::run(&engine).await
+    } else {
+      std::future::pending().await
+    }
+  };
+
+  let catch_fatal_errors = async move {
+    terminate_rx
+      .next()
+      .await
+      .context("terminate event channel closed")?
+      .context("fatal error")?;
+    anyhow::Ok(())
+  };
+
+  futures::select! {
+      result = wayland_client.run().fuse() => match result {
+        Err(e) if reconnect && e.downcast_ref::<ConnectionLost>().is_some() => {
+          log::error!("{:#}", e);
+          return Ok(Exit::Reconnect);
+        }
+        result => { result?; }
+      },
+      result = catch_fatal_errors.fuse() => {
+        let result = match result {
+          Err(e) if e.downcast_ref::<ContextLost>().is_some() => {
+            return Ok(Exit::ContextLost(e));
+          }
+          result => result,
+        };
+        if let Err(e) = &result {
+          crash::report(engine.state(), e);
+        }
+        match result {
+          Err(e) if crash_restart.max_attempts > 0 => return Ok(Exit::Crashed(e)),
+          result => result?,
+        }
+      },
+      result = task_runner.fuse() => { result?; },
+      result = ipc::serve(&engine, instance.id()).fuse() => result?,
+      result = activations.serve(&engine).fuse() => result?,
+      result = memory_pressure::watch(&engine).fuse() => result?,
+      result = systemd::watchdog().fuse() => result?,
+      _ = quit.recv().fuse() => return Ok(Exit::Quit),
+      result = instance.replaced().fuse() => {
+        result?;
+        return Ok(Exit::Quit);
+      },
+      result = tests_finished.fuse() => {
+        result?;
+        return Ok(Exit::Quit);
+      },
+      result = golden_checked.fuse() => {
+        result?;
+        return Ok(Exit::Quit);
+      },
+      result = app_changed.fuse() => {
+        result?;
+        return Ok(Exit::Restart);
+      },
+  }
+
+  anyhow::Ok(Exit::Quit)
+}
+
+/// A running engine, given to [`MethodHandler`]s
+pub struct FlutterEngine {
+  engine: *mut ffi::_FlutterEngine,
+  /// outlives the engine, whose callbacks get a pointer to it
+  shared: Arc<EngineShared>,
+  /// dropped after the engine is deinitialized
+  render_task_runner: RenderTaskRunner,
+  /// collected after the engine is deinitialized
+  aot_data: Option<AotData>,
+}
+
+impl Drop for FlutterEngine {
+  fn drop(&mut self) {
+    unsafe {
+      let _ = ffi::FlutterEngineDeinitialize(self.engine);
+    }
+  }
+}
+
+/// The snapshots of an AOT compiled app, which must outlive the engine
+struct AotData(ffi::FlutterEngineAOTData);
+
+impl AotData {
+  fn load(path: &Path) -> Result<Self> {
+    let elf_path = CString::new(path.as_os_str().as_bytes())?;
+    let source = ffi::FlutterEngineAOTDataSource {
+      type_: ffi::FlutterEngineAOTDataSourceType_kFlutterEngineAOTDataSourceTypeElfPath,
+      __bindgen_anon_1: ffi::FlutterEngineAOTDataSource__bindgen_ty_1 {
+        elf_path: elf_path.as_ptr(),
+      },
+    };
+    let mut data = std::ptr::null_mut();
+    unsafe {
+      ffi::FlutterEngineCreateAOTData(&source, &mut data)
+        .into_flutter_engine_result()
+        .with_context(|| format!("failed to load AOT library {:?}", path))?;
+    }
+    Ok(Self(data))
+  }
+}
+
+impl Drop for AotData {
+  fn drop(&mut self) {
+    unsafe {
+      let _ = ffi::FlutterEngineCollectAOTData(self.0);
+    }
+  }
+}
+
+impl FlutterEngine {
+  /// setup config and project args and initialize the engine
+  ///
+  /// `switches` are engine command line switches like `--enable-impeller=true`.
+  /// `aot_library` is the `libapp.so` of a release or profile build, which needs an engine
+  /// built in the same mode. `old_gen_heap_size` is in MiB, see [`DartVmOptions`].
+  /// `zero_copy` pools backing stores in [`Compositor::backing_stores`] instead of the engine's
+  /// cache, which would render into a store still on screen.
+  fn init(
+    asset_path: &Path,
+    icu_data_path: &Path,
+    aot_library: Option<&Path>,
+    switches: &[String],
+    old_gen_heap_size: i64,
+    merged_ui_thread: bool,
+    zero_copy: bool,
+  ) -> Result<Self> {
+    let mut ret = Self {
+      engine: std::ptr::null_mut(),
+      shared: Arc::new(EngineShared {
+        platform_thread_id: std::thread::current().id(),
+        state: OnceLock::new(),
+      }),
+      render_task_runner: RenderTaskRunner::spawn()?,
+      aot_data: aot_library.map(AotData::load).transpose()?,
+    };
+
+    let renderer_config = ffi::FlutterRendererConfig {
+      type_: ffi::FlutterRendererType_kOpenGL,
+      __bindgen_anon_1: ffi::FlutterRendererConfig__bindgen_ty_1 {
+        open_gl: ffi::FlutterOpenGLRendererConfig {
+          struct_size: size_of::<ffi::FlutterOpenGLRendererConfig>(),
+          make_current: Some(callback::make_current),
+          clear_current: Some(callback::clear_current),
+          present: None,
+          fbo_callback: None,
+          make_resource_current: Some(callback::make_resource_current),
+          fbo_reset_after_present: false,
+          surface_transformation: None,
+          gl_proc_resolver: Some(callback::gl_proc_resolver),
+          gl_external_texture_frame_callback: None,
+          fbo_with_frame_info_callback: Some(callback::fbo_with_frame_info_callback),
+          present_with_info: Some(callback::present_with_info),
+          populate_existing_damage: None,
+        },
+      },
+    };
+
+    let multi_view = ffi::features().multi_view;
+    let flutter_compositor = ffi::FlutterCompositor {
+      struct_size: size_of::<ffi::FlutterCompositor>(),
+      user_data: ret.user_data(),
+      create_backing_store_callback: Some(compositor::callback::create_backing_store_callback),
+      collect_backing_store_callback: Some(compositor::callback::collect_backing_store_callback),
+      // older engines only know this one, newer ones refuse both
+      present_layers_callback: (!multi_view)
+        .then_some(compositor::callback::present_layers_callback as _),
+      avoid_backing_store_cache: zero_copy,
+      present_view_callback: multi_view.then_some(compositor::callback::present_view_callback as _),
+    };
+
+    let asset_path = CString::new(asset_path.as_os_str().as_bytes())?;
+    let icu_data_path = CString::new(icu_data_path.as_os_str().as_bytes())?;
+    // the engine skips the first argument like a program name
+    let switches = std::iter::once("wayflutter")
+      .chain(switches.iter().map(String::as_str))
+      .map(CString::new)
+      .collect::<Result<Vec<_>, _>>()?;
+    let argv = switches.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+
+    let platform_task_runner = ffi::FlutterTaskRunnerDescription {
+      struct_size: size_of::<ffi::FlutterTaskRunnerDescription>(),
+      user_data: ret.user_data(),
+      runs_task_on_current_thread_callback: Some(callback::runs_task_on_current_thread_callback),
+      post_task_callback: Some(callback::post_task_callback),
+      identifier: 1,
+      destruction_callback: None,
+    };
+
+    let render_task_runner = ret.render_task_runner.description();
+
+    let custom_task_runners = ffi::FlutterCustomTaskRunners {
+      struct_size: size_of::<ffi::FlutterCustomTaskRunners>(),
+      platform_task_runner: &platform_task_runner as _,
+      render_task_runner: &render_task_runner as _,
+      thread_priority_setter: Some(task_runner::priority::thread_priority_setter),
+      // the same runner as the platform's merges the threads
+      ui_task_runner: if merged_ui_thread {
+        &platform_task_runner as _
+      } else {
+        std::ptr::null()
+      },
+    };
+
+    let project_args = unsafe {
+      ffi::FlutterProjectArgs {
+        struct_size: size_of::<ffi::FlutterProjectArgs>(),
+        assets_path: asset_path.as_ptr(),
+        icu_data_path: icu_data_path.as_ptr(),
+        log_message_callback: Some(callback::log_message_callback),
+        platform_message_callback: Some(callback::platform_message_callback),
+        vsync_callback: Some(callback::vsync_callback),
+        command_line_argc: argv.len() as _,
+        command_line_argv: argv.as_ptr(),
+        custom_task_runners: &custom_task_runners as _,
+        compositor: &flutter_compositor as _,
+        on_pre_engine_restart_callback: Some(callback::on_pre_engine_restart_callback),
+        root_isolate_create_callback: Some(callback::root_isolate_create_callback),
+        compute_platform_resolved_locale_callback: Some(
+          callback::compute_platform_resolved_locale_callback,
+        ),
+        update_semantics_callback2: Some(callback::update_semantics_callback),
+        dart_old_gen_heap_size: old_gen_heap_size,
+        aot_data: ret
+          .aot_data
+          .as_ref()
+          .map_or(std::ptr::null_mut(), |data| data.0),
+        ..core::mem::zeroed()
+      }
+    };
+
+    log::info!("init flutter engine");
+    let engine = flutter_engine_init(ret.user_data(), &renderer_config, &project_args)?;
+    ret.engine = engine;
+    ret.render_task_runner.set_engine(engine);
+    Ok(ret)
+  }
+
+  /// Whether the linked engine is a release or profile build, which runs AOT compiled apps
+  /// only
+  fn runs_aot_compiled_code() -> bool {
+    unsafe { ffi::FlutterEngineRunsAOTCompiledDartCode() }
+  }
+
+  /// The user data of every engine callback, see [`callback::engine_shared`]
+  fn user_data(&self) -> *mut c_void {
+    Arc::as_ptr(&self.shared) as *mut c_void
+  }
+
+  fn init_state(&self, state: FlutterEngineState) -> Result<()> {
+    self
+      .shared
+      .state
+      .set(state)
+      .map_err(|_| anyhow::anyhow!("engine state initialized twice"))
+  }
+
+  /// Panics before [`run_flutter`] has set up the state, which is done before the engine runs
+  fn state(&self) -> &FlutterEngineState {
+    self.try_state().expect("engine state not initialized")
+  }
+
+  /// `None` while the engine is being set up
+  fn try_state(&self) -> Option<&FlutterEngineState> {
+    self.shared.state.get()
+  }
+
+  unsafe fn run(&self) -> Result<()> {
+    log::info!("run flutter engine");
+    unsafe {
+      ffi::FlutterEngineRunInitialized(self.engine).into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  fn notify_low_memory(&self) -> Result<()> {
+    unsafe {
+      ffi::FlutterEngineNotifyLowMemoryWarning(self.engine).into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  /// Have the framework build the semantics tree and send updates of it, or stop.
+  fn update_semantics_enabled(&self, enabled: bool) -> Result<()> {
+    unsafe {
+      ffi::FlutterEngineUpdateSemanticsEnabled(self.engine, enabled)
+        .into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  /// Have the framework carry out `action` on a semantics node, e.g. for a screen reader.
+  /// `data` are the arguments of the action encoded with the standard message codec, if any.
+  fn dispatch_semantics_action(
+    &self,
+    node_id: i32,
+    action: SemanticsAction,
+    data: &[u8],
+  ) -> Result<()> {
+    unsafe {
+      ffi::FlutterEngineDispatchSemanticsAction(
+        self.engine,
+        node_id as u64,
+        action as ffi::FlutterSemanticsAction,
+        data.as_ptr(),
+        data.len(),
+      )
+      .into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  fn schedule_frame(&self) -> Result<()> {
+    unsafe {
+      ffi::FlutterEngineScheduleFrame(self.engine).into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  /// Answer the vsync baton parked while no view was visible, and draw a frame.
+  fn resume_rendering(&self) -> Result<()> {
+    let state = self.state();
+    let parked_baton = state.parked_vsync_baton.lock().take();
+    if let Some(baton) = parked_baton {
+      self.answer_vsync(baton)?;
+    }
+    self.schedule_frame()
+  }
+
+  fn on_vsync(&self, baton: isize, frame_start_nanos: u64, frame_target_nanos: u64) -> Result<()> {
+    unsafe {
+      ffi::FlutterEngineOnVsync(self.engine, baton, frame_start_nanos, frame_target_nanos)
+        .into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  /// `data` of `None` tells the sender the message isn't handled.
+  fn send_platform_message_response(
+    &self,
+    handle: *const ffi::FlutterPlatformMessageResponseHandle,
+    data: Option<&[u8]>,
+  ) -> Result<()> {
+    let (data, len) = match data {
+      Some(data) => (data.as_ptr(), data.len()),
+      None => (std::ptr::null(), 0),
+    };
+    unsafe {
+      ffi::FlutterEngineSendPlatformMessageResponse(self.engine, handle, data, len)
+        .into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  /// Must be called on the platform thread.
+  fn send_platform_message(&self, channel: &str, data: &[u8]) -> Result<()> {
+    let channel = CString::new(channel)?;
+    let message = ffi::FlutterPlatformMessage {
+      struct_size: size_of::<ffi::FlutterPlatformMessage>(),
+      channel: channel.as_ptr(),
+      message: data.as_ptr(),
+      message_size: data.len(),
+      response_handle: std::ptr::null(),
+    };
+    unsafe {
+      ffi::FlutterEngineSendPlatformMessage(self.engine, &message).into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  /// Tell the framework which view has the keyboard focus.
+  fn send_view_focus_event(&self, view_id: ViewId, focused: bool) -> Result<()> {
+    if !ffi::features().view_focus {
+      return Ok(());
+    }
+    let event = ffi::FlutterViewFocusEvent {
+      struct_size: size_of::<ffi::FlutterViewFocusEvent>(),
+      view_id: view_id.raw(),
+      state: if focused {
+        ffi::FlutterViewFocusState_kFocused
+      } else {
+        ffi::FlutterViewFocusState_kUnfocused
+      },
+      direction: ffi::FlutterViewFocusDirection_kUndefined,
+    };
+    unsafe {
+      ffi::FlutterEngineSendViewFocusEvent(self.engine, &event).into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  fn send_pointer_events(&self, events: &[ffi::FlutterPointerEvent]) -> Result<()> {
+    unsafe {
+      ffi::FlutterEngineSendPointerEvent(self.engine, events.as_ptr(), events.len())
+        .into_flutter_engine_result()?;
+    }
+    Ok(())
+  }
+
+  /// Answer a vsync baton with the next vsync predicted by the frame clock.
+  fn answer_vsync(&self, baton: isize) -> Result<()> {
+    let state = self.state();
+    let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
+    state.heartbeat.vsync_settled();
+    let (frame_start, frame_target) = state.frame_clock.next_frame(now);
+    state.frame_clock.frame_started(frame_start);
+    state.frame_stats.vsync(frame_start, frame_target);
+    let animating = state.compositor.step_animations(frame_target);
+    self.on_vsync(baton, frame_start, frame_target)?;
+    if animating {
+      self.schedule_frame()?;
+    }
+    Ok(())
+  }
+}
+
+fn flutter_engine_init(
+  user_data: *const c_void,
+  renderer_config: &ffi::FlutterRendererConfig,
+  project_args: &ffi::FlutterProjectArgs,
+) -> Result<ffi::FlutterEngine> {
+  unsafe {
+    let mut engine: ffi::FlutterEngine = std::ptr::null_mut();
+    let engine_out: *mut ffi::FlutterEngine = &mut engine as *mut _;
+    ffi::FlutterEngineInitialize(
+      ffi::FLUTTER_ENGINE_VERSION as usize,
+      renderer_config as _,
+      project_args as _,
+      user_data as _,
+      engine_out,
+    )
+    .into_flutter_engine_result()?;
+    Ok(engine)
+  }
+}
+
+/// Shared by a [`FlutterEngine`] with the callbacks of the engine
+struct EngineShared {
+  platform_thread_id: ThreadId,
+  /// set once by [`FlutterEngine::init_state`], before the engine runs
+  state: OnceLock<FlutterEngineState>,
+}
+
+/// Read only. Need interior mutability if necessary.
+struct FlutterEngineState
+where
+  Self: Sync,
+{
+  terminate: UnboundedSender<anyhow::Result<()>>,
+  opengl_state: OpenGLState,
+  compositor: Compositor,
+  task_runner_handle: TaskRunnerHandle,
+  /// method channels handled by the embedder
+  channels: Channels,
+  frame_clock: FrameClock,
+  frame_stats: FrameStats,
+  heartbeat: Heartbeat,
+  /// policies for errors in callbacks
+  errors: ErrorTracker,
+  /// vsync baton held back while no view is visible
+  parked_vsync_baton: Mutex<Option<isize>>,
+  /// reloaded over the control socket
+  config_path: Option<PathBuf>,
+  /// see [`Config::quiet_dart`]
+  quiet_dart: bool,
+  semantics: Semantics,
+  root_isolate: RootIsolate,
+  exceptions: Exceptions,
+  injected_pointer: InjectedPointer,
+  test_results: TestResults,
+  hud: Hud,
+  gamma: GammaControls,
+  output_powers: OutputPowers,
+}
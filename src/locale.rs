@@ -0,0 +1,315 @@
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::sync::OnceLock;
+
+use anyhow::Result;
+
+use crate::FlutterEngine;
+use crate::error::FFIFlutterEngineResultExt;
+use crate::ffi;
+
+/// A locale of the user's preferences, from a POSIX locale name like `sr_RS.UTF-8@latin`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Locale {
+  language: String,
+  country: Option<String>,
+  script: Option<String>,
+}
+
+impl Locale {
+  /// `None` for `C` and `POSIX`, which aren't languages
+  fn parse(name: &str) -> Option<Self> {
+    let (name, modifier) = match name.split_once('@') {
+      Some((name, modifier)) => (name, Some(modifier)),
+      None => (name, None),
+    };
+    let name = name.split('.').next().unwrap_or_default();
+    let (language, country) = match name.split_once('_') {
+      Some((language, country)) => (language, Some(country)),
+      None => (name, None),
+    };
+    if language.is_empty() || language == "C" || language == "POSIX" {
+      return None;
+    }
+    // the modifiers glibc uses for scripts
+    let script = modifier.and_then(|modifier| match modifier {
+      "latin" => Some("Latn"),
+      "cyrillic" => Some("Cyrl"),
+      "devanagari" => Some("Deva"),
+      _ => None,
+    });
+    Some(Self {
+      language: language.to_owned(),
+      country: country.filter(|c| !c.is_empty()).map(str::to_owned),
+      script: script.map(str::to_owned),
+    })
+  }
+
+  /// Whether `supported` is this locale or one it falls back to, like `de` for `de_AT`
+  fn falls_back_to(&self, supported: &ffi::FlutterLocale) -> bool {
+    let matches = |code: *const std::ffi::c_char, ours: Option<&str>| {
+      // SAFETY: null or a C string, valid during the callback
+      match unsafe { optional_str(code) } {
+        None => true,
+        Some(code) => ours == Some(code),
+      }
+    };
+    matches(supported.language_code, Some(&self.language))
+      && matches(supported.country_code, self.country.as_deref())
+      && matches(supported.script_code, self.script.as_deref())
+  }
+}
+
+/// An empty string counts as unset.
+unsafe fn optional_str<'a>(ptr: *const std::ffi::c_char) -> Option<&'a str> {
+  if ptr.is_null() {
+    return None;
+  }
+  unsafe { CStr::from_ptr(ptr) }
+    .to_str()
+    .ok()
+    .filter(|s| !s.is_empty())
+}
+
+/// The user's languages in order, like gettext reads them: `$LANGUAGE`, then the first set of
+/// `$LC_ALL`, `$LC_MESSAGES` and `$LANG`. `$LANGUAGE` is ignored under the `C` locale.
+fn preferred() -> &'static [Locale] {
+  static PREFERRED: OnceLock<Vec<Locale>> = OnceLock::new();
+  PREFERRED.get_or_init(|| preferred_in(|name| std::env::var(name).ok()))
+}
+
+fn preferred_in(var: impl Fn(&str) -> Option<String>) -> Vec<Locale> {
+  let var = |name| var(name).filter(|value| !value.is_empty());
+  let Some(base) = var("LC_ALL")
+    .or_else(|| var("LC_MESSAGES"))
+    .or_else(|| var("LANG"))
+    .and_then(|name| Locale::parse(&name))
+  else {
+    return Vec::new();
+  };
+  let mut locales = Vec::new();
+  for locale in var("LANGUAGE")
+    .iter()
+    .flat_map(|list| list.split(':'))
+    .filter_map(Locale::parse)
+    .chain([base])
+  {
+    if !locales.contains(&locale) {
+      locales.push(locale);
+    }
+  }
+  locales
+}
+
+/// Tell the engine the user's languages, for `PlatformDispatcher.locales`.
+pub fn update(engine: &FlutterEngine) -> Result<()> {
+  let preferred = preferred();
+  if preferred.is_empty() {
+    return Ok(());
+  }
+  let strings = preferred
+    .iter()
+    .map(|locale| {
+      let c_string = |s: Option<&str>| s.map(CString::new).transpose();
+      anyhow::Ok([
+        c_string(Some(&locale.language))?,
+        c_string(locale.country.as_deref())?,
+        c_string(locale.script.as_deref())?,
+      ])
+    })
+    .collect::<Result<Vec<_>>>()?;
+  let ptr = |s: &Option<CString>| s.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+  let locales = strings
+    .iter()
+    .map(|[language, country, script]| ffi::FlutterLocale {
+      struct_size: size_of::<ffi::FlutterLocale>(),
+      language_code: ptr(language),
+      country_code: ptr(country),
+      script_code: ptr(script),
+      variant_code: std::ptr::null(),
+    })
+    .collect::<Vec<_>>();
+  let mut locale_ptrs = locales
+    .iter()
+    .map(|locale| locale as *const _)
+    .collect::<Vec<_>>();
+  unsafe {
+    ffi::FlutterEngineUpdateLocales(engine.engine, locale_ptrs.as_mut_ptr(), locale_ptrs.len())
+      .into_flutter_engine_result()?;
+  }
+  Ok(())
+}
+
+/// The first of the app's `supported` locales the user's languages fall back to, like glibc
+/// drops the country of `de_AT` to find `de`, but never picks `de_DE` for it. The most specific
+/// one wins, and `None` leaves it to the framework.
+pub fn resolve<'a>(supported: &[&'a ffi::FlutterLocale]) -> Option<&'a ffi::FlutterLocale> {
+  resolve_for(preferred(), supported)
+}
+
+fn resolve_for<'a>(
+  preferred: &[Locale],
+  supported: &[&'a ffi::FlutterLocale],
+) -> Option<&'a ffi::FlutterLocale> {
+  let specificity = |locale: &ffi::FlutterLocale| {
+    // SAFETY: valid during the callback
+    unsafe {
+      [locale.country_code, locale.script_code]
+        .into_iter()
+        .filter(|&code| optional_str(code).is_some())
+        .count()
+    }
+  };
+  preferred.iter().find_map(|locale| {
+    supported
+      .iter()
+      .copied()
+      // the first of equally specific ones
+      .rev()
+      .filter(|supported| locale.falls_back_to(supported))
+      .max_by_key(|supported| specificity(supported))
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn locale(language: &str, country: Option<&str>, script: Option<&str>) -> Locale {
+    Locale {
+      language: language.to_owned(),
+      country: country.map(str::to_owned),
+      script: script.map(str::to_owned),
+    }
+  }
+
+  #[test]
+  fn parse_posix_names() {
+    assert_eq!(
+      Locale::parse("de_AT.UTF-8"),
+      Some(locale("de", Some("AT"), None))
+    );
+    assert_eq!(
+      Locale::parse("sr_RS.UTF-8@latin"),
+      Some(locale("sr", Some("RS"), Some("Latn")))
+    );
+    assert_eq!(Locale::parse("en"), Some(locale("en", None, None)));
+    assert_eq!(
+      Locale::parse("de_.UTF-8@euro"),
+      Some(locale("de", None, None))
+    );
+    assert_eq!(Locale::parse("C.UTF-8"), None);
+    assert_eq!(Locale::parse("POSIX"), None);
+    assert_eq!(Locale::parse(""), None);
+  }
+
+  #[test]
+  fn preferred_lists_language_then_the_base_locale() {
+    let env = |vars: &'static [(&'static str, &'static str)]| {
+      move |name: &str| {
+        vars
+          .iter()
+          .find(|(var, _)| *var == name)
+          .map(|(_, value)| (*value).to_owned())
+      }
+    };
+    assert_eq!(
+      preferred_in(env(&[("LANGUAGE", "fr:de_AT"), ("LANG", "de_AT.UTF-8")])),
+      [locale("fr", None, None), locale("de", Some("AT"), None)],
+    );
+    assert_eq!(
+      preferred_in(env(&[
+        ("LC_ALL", "en_GB.UTF-8"),
+        ("LC_MESSAGES", ""),
+        ("LANG", "de_DE")
+      ])),
+      [locale("en", Some("GB"), None)],
+    );
+    assert_eq!(
+      preferred_in(env(&[("LC_MESSAGES", ""), ("LANG", "ja_JP.UTF-8")])),
+      [locale("ja", Some("JP"), None)],
+    );
+    // gettext ignores $LANGUAGE under the C locale
+    assert_eq!(preferred_in(env(&[("LANGUAGE", "fr"), ("LANG", "C")])), []);
+  }
+
+  /// An app's supported locale, with the C strings it points to
+  struct Supported {
+    _strings: [Option<CString>; 3],
+    locale: ffi::FlutterLocale,
+  }
+
+  fn supported(language: &str, country: Option<&str>, script: Option<&str>) -> Supported {
+    let strings = [Some(language), country, script].map(|s| s.map(|s| CString::new(s).unwrap()));
+    let ptr = |s: &Option<CString>| s.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+    let locale = ffi::FlutterLocale {
+      struct_size: size_of::<ffi::FlutterLocale>(),
+      language_code: ptr(&strings[0]),
+      country_code: ptr(&strings[1]),
+      script_code: ptr(&strings[2]),
+      variant_code: std::ptr::null(),
+    };
+    Supported {
+      _strings: strings,
+      locale,
+    }
+  }
+
+  /// The index in `supported` of the resolved locale
+  fn resolve_index(preferred: &[Locale], supported: &[Supported]) -> Option<usize> {
+    let locales = supported.iter().map(|s| &s.locale).collect::<Vec<_>>();
+    let resolved = resolve_for(preferred, &locales)?;
+    locales
+      .iter()
+      .position(|&locale| std::ptr::eq(locale, resolved))
+  }
+
+  #[test]
+  fn resolve_falls_back_but_never_to_another_country() {
+    let app = [
+      supported("en", None, None),
+      supported("de", Some("DE"), None),
+      supported("de", None, None),
+    ];
+    assert_eq!(
+      resolve_index(&[locale("de", Some("AT"), None)], &app),
+      Some(2)
+    );
+    assert_eq!(
+      resolve_index(&[locale("de", Some("DE"), None)], &app),
+      Some(1)
+    );
+    assert_eq!(resolve_index(&[locale("ja", Some("JP"), None)], &app), None);
+  }
+
+  #[test]
+  fn resolve_takes_the_first_preference_that_matches() {
+    let app = [supported("en", None, None), supported("fr", None, None)];
+    let preferred = [
+      locale("ja", None, None),
+      locale("fr", Some("CA"), None),
+      locale("en", None, None),
+    ];
+    assert_eq!(resolve_index(&preferred, &app), Some(1));
+  }
+
+  #[test]
+  fn resolve_prefers_the_most_specific_then_the_first() {
+    let app = [
+      supported("sr", None, None),
+      supported("sr", None, Some("Cyrl")),
+      supported("sr", None, Some("Latn")),
+      supported("sr", Some("RS"), Some("Latn")),
+      supported("sr", Some("RS"), Some("Latn")),
+    ];
+    assert_eq!(
+      resolve_index(&[locale("sr", Some("RS"), Some("Latn"))], &app),
+      Some(3)
+    );
+    assert_eq!(
+      resolve_index(&[locale("sr", Some("ME"), Some("Latn"))], &app),
+      Some(2)
+    );
+    assert_eq!(resolve_index(&[locale("sr", None, None)], &app), Some(0));
+  }
+}
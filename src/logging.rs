@@ -0,0 +1,52 @@
+use anyhow::Result;
+use log::Level;
+use log::LevelFilter;
+use log::Log;
+
+pub mod channel;
+mod journal;
+pub(crate) mod recent;
+
+/// Log to the systemd journal with structured fields if `journal`, else to stderr. Levels are
+/// filtered with `RUST_LOG` like `env_logger`, `info` by default.
+pub fn init(journal: bool) -> Result<()> {
+  let stderr = env_logger::builder()
+    .filter_level(LevelFilter::Info)
+    .parse_default_env()
+    .build();
+  log::set_max_level(stderr.filter());
+  let logger: Box<dyn Log> = if journal {
+    Box::new(journal::JournalLogger::new(stderr)?)
+  } else {
+    Box::new(stderr)
+  };
+  log::set_boxed_logger(Box::new(recent::RecentLogger(logger)))?;
+  Ok(())
+}
+
+/// The level of a message from the engine's log callback. The engine prefixes what it logs
+/// itself with its severity, like `[ERROR:flutter/...]`, while `print` from Dart has none.
+pub fn engine_level(message: &str) -> Level {
+  let severity = message
+    .strip_prefix('[')
+    .and_then(|message| message.split_once(':'))
+    .map(|(severity, _)| severity);
+  match severity {
+    Some("FATAL" | "ERROR") => Level::Error,
+    Some("WARNING") => Level::Warn,
+    _ if message.starts_with("Unhandled Exception:") => Level::Error,
+    _ => Level::Info,
+  }
+}
+
+/// The level of a Dart log record, from the numeric levels of `dart:developer` and
+/// `package:logging`: `SEVERE` is 1000, `WARNING` 900, `INFO` 800 and `FINE` 500.
+pub fn dart_level(level: i64) -> Level {
+  match level {
+    1000.. => Level::Error,
+    900.. => Level::Warn,
+    800.. => Level::Info,
+    500.. => Level::Debug,
+    _ => Level::Trace,
+  }
+}
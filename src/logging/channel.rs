@@ -0,0 +1,70 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::FlutterEngine;
+use crate::channel::MethodCall;
+use crate::channel::MethodResult;
+use crate::exception;
+use crate::exception::DartException;
+
+/// Method channel (`MethodChannel` with `JSONMethodCodec` on the Dart side) taking log records
+/// of the app, e.g. from a `package:logging` listener, into the embedder's log at their level
+pub const CHANNEL: &str = "wayflutter/log";
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Record {
+  /// see [`super::dart_level`]
+  level: i64,
+  message: String,
+  #[serde(default)]
+  logger_name: String,
+  error: Option<String>,
+  stack_trace: Option<String>,
+}
+
+/// An exception caught by `FlutterError.onError` or `PlatformDispatcher.onError`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Exception {
+  error: String,
+  stack_trace: Option<String>,
+}
+
+pub fn handle_method_call(engine: &FlutterEngine, call: &MethodCall) -> Option<MethodResult> {
+  match call.method.as_str() {
+    "log" => Some(log(call)),
+    "exception" => Some(exception(engine, call)),
+    _ => None,
+  }
+}
+
+fn log(call: &MethodCall) -> MethodResult {
+  let record: Record = call.args()?;
+  let level = super::dart_level(record.level);
+  let logger = record.logger_name.as_str();
+  let mut message = record.message;
+  for detail in [record.error, record.stack_trace].into_iter().flatten() {
+    message.push('\n');
+    message.push_str(&detail);
+  }
+  if logger.is_empty() {
+    log::log!(target: "dart", level, channel = CHANNEL; "{}", message);
+  } else {
+    log::log!(target: "dart", level, channel = CHANNEL, logger; "[{}] {}", logger, message);
+  }
+  Ok(Value::Null)
+}
+
+/// Logged and handled like one the engine logs, see [`crate::exception::report`]
+fn exception(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let Exception { error, stack_trace } = call.args()?;
+  match &stack_trace {
+    Some(stack_trace) => {
+      log::error!(target: "dart", channel = CHANNEL; "Unhandled exception: {}\n{}", error, stack_trace)
+    }
+    None => log::error!(target: "dart", channel = CHANNEL; "Unhandled exception: {}", error),
+  }
+  exception::report(engine, DartException { error, stack_trace });
+  Ok(Value::Null)
+}
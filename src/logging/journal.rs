@@ -0,0 +1,113 @@
+use std::os::unix::net::UnixDatagram;
+
+use anyhow::Context;
+use anyhow::Result;
+use log::Level;
+use log::Log;
+use log::Metadata;
+use log::Record;
+use log::kv;
+
+/// journald's socket for its native protocol, see `systemd.journal-fields(7)`
+const SOCKET: &str = "/run/systemd/journal/socket";
+
+const IDENTIFIER: &str = "wayflutter";
+
+/// Sends records to journald with their key-values as fields, e.g. `TAG`, `VIEW_ID` and
+/// `CHANNEL`. Records that can't be sent, like ones too large for a datagram, go to stderr.
+pub struct JournalLogger {
+  socket: UnixDatagram,
+  /// filters by `RUST_LOG` too
+  stderr: env_logger::Logger,
+}
+
+impl JournalLogger {
+  /// `stderr` filters the records and takes those journald doesn't.
+  pub fn new(stderr: env_logger::Logger) -> Result<Self> {
+    let socket = UnixDatagram::unbound().context("failed to create a socket for journald")?;
+    socket
+      .connect(SOCKET)
+      .with_context(|| format!("failed to connect to journald at {}", SOCKET))?;
+    Ok(Self { socket, stderr })
+  }
+}
+
+impl Log for JournalLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    self.stderr.enabled(metadata)
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.stderr.matches(record) {
+      return;
+    }
+    if self.socket.send(&encode(record)).is_err() {
+      self.stderr.log(record);
+    }
+  }
+
+  fn flush(&self) {}
+}
+
+fn encode(record: &Record) -> Vec<u8> {
+  let mut fields = Fields(Vec::new());
+  fields.add("MESSAGE", &record.args().to_string());
+  fields.add("PRIORITY", priority(record.level()));
+  fields.add("SYSLOG_IDENTIFIER", IDENTIFIER);
+  fields.add("TARGET", record.target());
+  if let Some(file) = record.file() {
+    fields.add("CODE_FILE", file);
+  }
+  if let Some(line) = record.line() {
+    fields.add("CODE_LINE", &line.to_string());
+  }
+  let _ = record.key_values().visit(&mut fields);
+  fields.0
+}
+
+/// syslog priorities
+fn priority(level: Level) -> &'static str {
+  match level {
+    Level::Error => "3",
+    Level::Warn => "4",
+    Level::Info => "6",
+    Level::Debug | Level::Trace => "7",
+  }
+}
+
+struct Fields(Vec<u8>);
+
+impl Fields {
+  fn add(&mut self, name: &str, value: &str) {
+    let buf = &mut self.0;
+    buf.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+      // binary-safe form: the name, then the length of the value as little-endian u64
+      buf.push(b'\n');
+      buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+      buf.push(b'=');
+    }
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(b'\n');
+  }
+}
+
+impl<'kvs> kv::VisitSource<'kvs> for Fields {
+  fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+    // journal field names are uppercase letters, digits and underscores, not starting with one
+    let name: String = key
+      .as_str()
+      .trim_start_matches('_')
+      .chars()
+      .map(|c| match c {
+        'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+        _ => '_',
+      })
+      .collect();
+    if !name.is_empty() {
+      self.add(&name, &value.to_string());
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,44 @@
+use std::collections::VecDeque;
+
+use log::Log;
+use log::Metadata;
+use log::Record;
+use parking_lot::Mutex;
+
+/// records kept for crash reports
+const CAPACITY: usize = 200;
+
+static RECENT: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// Keeps the last records that pass the filter of the logger it wraps
+pub struct RecentLogger(pub Box<dyn Log>);
+
+impl Log for RecentLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    self.0.enabled(metadata)
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.0.enabled(record.metadata()) {
+      return;
+    }
+    let line = format!("{} {}: {}", record.level(), record.target(), record.args());
+    {
+      let mut recent = RECENT.lock();
+      if recent.len() == CAPACITY {
+        recent.pop_front();
+      }
+      recent.push_back(line);
+    }
+    self.0.log(record);
+  }
+
+  fn flush(&self) {
+    self.0.flush();
+  }
+}
+
+/// The last records logged, oldest first
+pub fn recent() -> Vec<String> {
+  RECENT.lock().iter().cloned().collect()
+}
@@ -1,21 +1,46 @@
 /// Used in engine callbacks.
 ///
-/// Sends termination signal to the main event loop and returns false if $result is an error.
+/// Hands the error to its policy, see `error::report`, and returns false if $result is an
+/// error. `for $view` names the view it happened to, so that its surface can be recreated.
 #[macro_export]
 macro_rules! error_in_callback {
   ($state:ident, $result:expr) => {
     error_in_callback!($state, $result, return false)
   };
 
+  ($state:ident, $result:expr, for $view:expr) => {
+    error_in_callback!($state, $result, for $view, return false)
+  };
+
   ($state:ident, $result:expr, return $return_value:expr) => {
     match $result {
       Ok(v) => v,
       Err(e) => {
-        let _ = $state
-          .terminate
-          .unbounded_send(::anyhow::Result::Err(::anyhow::Error::from(e)));
+        $crate::error::report(&$state, ::anyhow::Error::from(e), None);
+        return $return_value;
+      }
+    }
+  };
+
+  ($state:ident, $result:expr, for $view:expr, return $return_value:expr) => {
+    match $result {
+      Ok(v) => v,
+      Err(e) => {
+        $crate::error::report(&$state, ::anyhow::Error::from(e), Some($view));
         return $return_value;
       }
     }
   };
 }
+
+/// Enters a `trace::Span` named $name, with `tracing` fields after it, e.g.
+/// `let _span = trace_span!("present", view = %view_id);`
+#[macro_export]
+macro_rules! trace_span {
+  ($name:literal $(, $($fields:tt)*)?) => {
+    $crate::trace::Span::enter(
+      ::tracing::info_span!($name $(, $($fields)*)?),
+      concat!($name, "\0"),
+    )
+  };
+}
@@ -0,0 +1,150 @@
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+
+use crate::FlutterEngine;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// The engine is told at most this often while the pressure lasts
+const COOLDOWN: Duration = Duration::from_secs(30);
+/// percentage of the last 10 seconds some task stalled on memory
+const PSI_THRESHOLD: f64 = 10.0;
+
+/// Where memory pressure is read from
+enum Source {
+  /// `memory.events` of our cgroup v2, whose `high` and `max` counters grow when the cgroup
+  /// hits its limits, if it has any
+  Cgroup { events: PathBuf, last: u64 },
+  /// pressure stall information, of our cgroup or else system-wide
+  Psi { pressure: PathBuf },
+}
+
+impl Source {
+  fn detect() -> Option<Self> {
+    let cgroup = cgroup_dir();
+    if let Some(cgroup) = &cgroup
+      && ["memory.high", "memory.max"]
+        .iter()
+        .any(|limit| read_limit(&cgroup.join(limit)).is_some())
+    {
+      let events = cgroup.join("memory.events");
+      if let Some(last) = read_cgroup_events(&events) {
+        return Some(Source::Cgroup { events, last });
+      }
+    }
+    cgroup
+      .map(|cgroup| cgroup.join("memory.pressure"))
+      .into_iter()
+      .chain([PathBuf::from("/proc/pressure/memory")])
+      .find(|pressure| read_psi(pressure).is_some())
+      .map(|pressure| Source::Psi { pressure })
+  }
+
+  fn pressured(&mut self) -> bool {
+    match self {
+      Source::Cgroup { events, last } => {
+        let Some(count) = read_cgroup_events(events) else {
+          return false;
+        };
+        let grew = count > *last;
+        *last = count;
+        grew
+      }
+      Source::Psi { pressure } => read_psi(pressure).is_some_and(|avg10| avg10 > PSI_THRESHOLD),
+    }
+  }
+}
+
+/// Tell the engine to free what it can, and drop pooled backing stores, whenever memory runs
+/// low. Never resolves.
+pub async fn watch(engine: &FlutterEngine) -> Result<()> {
+  let Some(mut source) = Source::detect() else {
+    log::debug!("no memory pressure information available");
+    return std::future::pending().await;
+  };
+  let mut last_notified: Option<Instant> = None;
+  loop {
+    smol::Timer::after(POLL_INTERVAL).await;
+    if !source.pressured() || last_notified.is_some_and(|last| last.elapsed() < COOLDOWN) {
+      continue;
+    }
+    last_notified = Some(Instant::now());
+    log::info!("memory is running low, trimming caches");
+    let state = engine.state();
+    state.compositor.backing_stores.request_trim();
+    engine.notify_low_memory()?;
+  }
+}
+
+/// The directory of the cgroup v2 of this process
+fn cgroup_dir() -> Option<PathBuf> {
+  let cgroups = std::fs::read_to_string("/proc/self/cgroup").ok()?;
+  let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+  Some(PathBuf::from("/sys/fs/cgroup").join(path.trim_start_matches('/')))
+}
+
+/// `memory.high` or `memory.max` in bytes, `None` if unlimited. systemd creates the files of
+/// every cgroup, so only a limit tells whether the cgroup's events mean anything.
+fn read_limit(path: &Path) -> Option<u64> {
+  parse_limit(&std::fs::read_to_string(path).ok()?)
+}
+
+fn parse_limit(limit: &str) -> Option<u64> {
+  limit.trim().parse().ok()
+}
+
+/// Sum of the `high` and `max` counters
+fn read_cgroup_events(path: &Path) -> Option<u64> {
+  Some(parse_cgroup_events(&std::fs::read_to_string(path).ok()?))
+}
+
+fn parse_cgroup_events(events: &str) -> u64 {
+  events
+    .lines()
+    .filter_map(|line| line.split_once(' '))
+    .filter(|(key, _)| matches!(*key, "high" | "max"))
+    .filter_map(|(_, count)| count.trim().parse::<u64>().ok())
+    .sum()
+}
+
+/// `avg10` of the `some` line of a pressure file like /proc/pressure/memory
+fn read_psi(path: &Path) -> Option<f64> {
+  parse_psi(&std::fs::read_to_string(path).ok()?)
+}
+
+fn parse_psi(pressure: &str) -> Option<f64> {
+  let some = pressure.lines().find(|line| line.starts_with("some "))?;
+  some
+    .split_whitespace()
+    .find_map(|field| field.strip_prefix("avg10="))?
+    .parse()
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn unlimited_cgroups_have_no_limit() {
+    assert_eq!(parse_limit("max\n"), None);
+    assert_eq!(parse_limit("536870912\n"), Some(536870912));
+  }
+
+  #[test]
+  fn cgroup_events_count_limit_hits() {
+    let events = "low 0\nhigh 12\nmax 3\noom 1\noom_kill 1\noom_group_kill 0\n";
+    assert_eq!(parse_cgroup_events(events), 15);
+  }
+
+  #[test]
+  fn psi_reads_avg10_of_some() {
+    let pressure = "some avg10=12.50 avg60=3.10 avg300=0.80 total=123456\n\
+                    full avg10=4.00 avg60=1.00 avg300=0.20 total=45678\n";
+    assert_eq!(parse_psi(pressure), Some(12.5));
+    assert_eq!(parse_psi(""), None);
+  }
+}
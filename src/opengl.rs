@@ -1,6 +1,11 @@
+use std::cell::Cell;
 use std::ffi::CStr;
 use std::ffi::CString;
+use std::num::NonZero;
+use std::path::Path;
+use std::path::PathBuf;
 use std::ptr::NonNull;
+use std::sync::Arc;
 
 use anyhow::Context;
 use anyhow::Result;
@@ -8,35 +13,125 @@ use glutin::api::egl::config::Config;
 use glutin::api::egl::context::PossiblyCurrentContext;
 use glutin::api::egl::display::Display;
 use glutin::api::egl::surface::Surface;
+use glutin::config::Api;
+use glutin::config::ColorBufferType;
 use glutin::config::ConfigTemplate;
+use glutin::config::ConfigTemplateBuilder;
+use glutin::context::ContextApi;
 use glutin::context::ContextAttributesBuilder;
+use glutin::context::Robustness;
+use glutin::context::Version;
+use glutin::display::AsRawDisplay;
+use glutin::display::DisplayFeatures;
+use glutin::display::RawDisplay;
+use glutin::prelude::GlConfig;
 use glutin::prelude::GlDisplay;
 use glutin::prelude::NotCurrentGlContext;
 use glutin::prelude::PossiblyCurrentGlContext;
+use glutin::surface::AsRawSurface;
+use glutin::surface::RawSurface;
 use glutin::surface::WindowSurface;
+use glutin_egl_sys::egl;
 use raw_window_handle::RawDisplayHandle;
 use raw_window_handle::WaylandDisplayHandle;
+use serde::Deserialize;
 use wayland_client::Connection;
 
+use crate::error::ContextLost;
+use crate::opengl::blit::Blitter;
+use crate::opengl::dmabuf::DmabufAllocator;
+use crate::opengl::drm_syncobj::DrmDevice;
+use crate::opengl::fence::FenceKind;
+
+pub mod blit;
+pub mod debug;
+pub mod dmabuf;
+pub mod drm_syncobj;
+pub mod fence;
+pub mod gbm;
+pub mod gpu;
+
 #[derive(Debug)]
 pub struct OpenGLState {
   pub egl_display: Display,
   pub egl_config: Config,
   /// only used for the rasterizing thread after creation
   pub render_context: PossiblyCurrentContext,
-  pub program: gl::types::GLuint,
-  pub vertex_array: gl::types::GLuint,
-  pub vertex_buffer: gl::types::GLuint,
+  /// draws backing stores onto window surfaces, owned by the render context
+  pub blitter: Blitter,
   /// only used for the flutter engine after creation
   pub resource_context: PossiblyCurrentContext,
+  pub options: RenderOptions,
+  /// how frames are synchronized with the GPU before presenting
+  pub fence_kind: FenceKind,
+  /// render node for timeline synchronization objects and buffer allocation, only opened for
+  /// explicit sync or zero-copy presenting
+  pub drm_device: Option<Arc<DrmDevice>>,
+  /// `Some` if backing stores are allocated as dmabufs, see [`RenderOptions::zero_copy`]
+  pub dmabuf_allocator: Option<DmabufAllocator>,
+  /// vendor and version strings of EGL and GL, for crash reports
+  pub driver: String,
+  /// whether the contexts report GPU resets, see [`OpenGLState::check_reset`]
+  pub robust: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct RenderOptions {
+  /// samples per pixel of the backing store framebuffers. `None` disables MSAA.
+  pub msaa_samples: Option<NonZero<u32>>,
+  /// Use an sRGB window surface, and blend in linear space when blitting.
+  ///
+  /// Either way the pixels the engine produces reach the screen unchanged. Backing stores stay
+  /// linear RGBA8, which is all the engine renders into, and the blit decodes what it samples
+  /// from them before the window surface encodes again.
+  pub srgb: bool,
+  /// Use 10 bits per color channel for window surfaces.
+  ///
+  /// Backing stores stay 8-bit, as Skia only renders into RGBA8 framebuffers. Only blending in
+  /// the blit, like dimming, gains precision.
+  pub deep_color: bool,
+  /// Attach acquire/release timeline points to each commit instead of relying on implicit
+  /// synchronization.
+  pub explicit_sync: bool,
+  /// Allocate backing stores as dmabufs and hand a frame consisting of a single full-surface
+  /// layer to the compositor as is, skipping the blit.
+  ///
+  /// Relies on implicit synchronization, so it's turned off with `explicit_sync`.
+  pub zero_copy: bool,
+  /// render node of the GPU to render on, see [`gpu::select`]. `None` leaves it to the EGL
+  /// implementation.
+  pub gpu: Option<PathBuf>,
+  /// The engine renders with Impeller, which needs an OpenGL ES 3 context.
+  pub impeller: bool,
+  /// Tint the regions the engine repainted in each frame, in a color that changes from frame
+  /// to frame. Turns off `zero_copy`'s direct presentation, as the tint is drawn in the blit.
+  pub debug_damage: bool,
+  /// Rasterize on the CPU into shared memory, which is presented as is when a frame is a single
+  /// full-surface layer. Slow, but takes the GPU driver out of the picture when debugging.
+  ///
+  /// Skia only, and without `explicit_sync`. A new buffer is mapped for every frame.
+  pub software: bool,
+  /// Hand backing stores to the engine as textures rather than framebuffers. It then renders
+  /// into them with depth/stencil buffers of its own, and without MSAA.
+  pub texture_targets: bool,
+  /// Create debug contexts and log what KHR_debug reports, and check that the framebuffers of
+  /// backing stores are complete.
+  pub gl_debug: bool,
 }
 
 /// Manully check contexts
 unsafe impl Sync for OpenGLState {}
 
+/// EGL may destroy a context from any thread, deferred while it's current somewhere
+unsafe impl Send for OpenGLState {}
+
 impl OpenGLState {
-  pub fn init(conn: &Connection) -> Result<Self> {
+  pub fn init(conn: &Connection, mut options: RenderOptions) -> Result<Self> {
     let display = get_egl_display(conn)?;
+    if let Some(gpu) = &options.gpu {
+      check_gpu(&display, gpu);
+    }
 
     gl::load_with(|symbol| {
       let Ok(address) = CString::new(symbol) else {
@@ -46,15 +141,79 @@ impl OpenGLState {
       display.get_proc_address(&address)
     });
 
-    let config = unsafe {
-      display
-        .find_configs(ConfigTemplate::default())?
-        .next()
-        .context("no egl config found")?
+    let config = choose_config(&display, &mut options)?;
+    let fence_kind = FenceKind::detect(&display);
+    if options.explicit_sync && fence_kind != FenceKind::NativeFence {
+      log::warn!("Explicit sync disabled: EGL_ANDROID_native_fence_sync is not supported");
+      options.explicit_sync = false;
+    }
+    if options.software && options.impeller {
+      log::warn!("Impeller can't render into software backing stores. Disabled.");
+      options.software = false;
+    }
+    if options.texture_targets && options.msaa_samples.is_some() {
+      log::warn!("MSAA needs framebuffer backing stores. Disabled.");
+      options.msaa_samples = None;
+    }
+    if options.zero_copy && options.software {
+      log::warn!("Zero-copy presenting doesn't apply to software backing stores. Disabled.");
+      options.zero_copy = false;
+    }
+    if options.explicit_sync && options.software {
+      log::warn!("Explicit sync doesn't apply to software backing stores. Disabled.");
+      options.explicit_sync = false;
+    }
+    if options.zero_copy && options.explicit_sync {
+      log::warn!("Zero-copy presenting relies on implicit sync. Disabled.");
+      options.zero_copy = false;
+    }
+    let drm_device = if options.explicit_sync || options.zero_copy {
+      let drm_device = open_drm_device(&display);
+      if let Err(e) = &drm_device {
+        log::warn!("Explicit sync and zero-copy presenting disabled: {:#}", e);
+        options.explicit_sync = false;
+        options.zero_copy = false;
+      }
+      drm_device.ok()
+    } else {
+      None
+    };
+    let dmabuf_allocator = match &drm_device {
+      Some(drm_device) if options.zero_copy => {
+        let allocator = DmabufAllocator::new(&display, drm_device);
+        if let Err(e) = &allocator {
+          log::warn!("Zero-copy presenting disabled: {:#}", e);
+          options.zero_copy = false;
+        }
+        allocator.ok()
+      }
+      _ => None,
     };
 
+    let context_api = if options.impeller {
+      ContextApi::Gles(Some(Version::new(3, 0)))
+    } else {
+      ContextApi::OpenGl(None)
+    };
+    let robust = display
+      .supported_features()
+      .contains(DisplayFeatures::CONTEXT_ROBUSTNESS);
+    if !robust {
+      log::warn!(
+        "GPU resets can't be detected: EGL_EXT_create_context_robustness is not supported"
+      );
+    }
+    let robustness = if robust {
+      Robustness::RobustLoseContextOnReset
+    } else {
+      Robustness::NotRobust
+    };
     let render_context = unsafe {
-      let context_attributes = ContextAttributesBuilder::new().build(None);
+      let context_attributes = ContextAttributesBuilder::new()
+        .with_context_api(context_api)
+        .with_debug(options.gl_debug)
+        .with_robustness(robustness)
+        .build(None);
       display
         .create_context(&config, &context_attributes)?
         .treat_as_possibly_current()
@@ -62,6 +221,9 @@ impl OpenGLState {
 
     let resource_context = unsafe {
       let context_attributes = ContextAttributesBuilder::new()
+        .with_context_api(context_api)
+        .with_debug(options.gl_debug)
+        .with_robustness(robustness)
         .with_sharing(&render_context)
         .build(None);
       display
@@ -69,95 +231,222 @@ impl OpenGLState {
         .treat_as_possibly_current()
     };
 
-    render_context.make_current_surfaceless()?;
-
-    let program = compile_shader_and_link_program()?;
-    let (vertex_array, vertex_buffer) = unsafe {
-      use gl::types::*;
-      use gl::*;
-
-      let vertices: [GLfloat; _] = [
-        -1.0, 1.0, 0.0, 1.0, -1.0, -1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, -1.0,
-        -1.0, 0.0, 0.0, 1.0, -1.0, 1.0, 0.0,
-      ]; // rectangle vertices with texture coords
-
-      let mut vertex_array = 0;
-      GenVertexArrays(1, &mut vertex_array);
-      let mut vertex_buffer = 0;
-      GenBuffers(1, &mut vertex_buffer);
-
-      BindVertexArray(vertex_array);
-      BindBuffer(ARRAY_BUFFER, vertex_buffer);
-
-      BufferData(
-        ARRAY_BUFFER,
-        (vertices.len() * size_of::<GLfloat>()) as isize,
-        vertices.as_ptr() as _,
-        STATIC_DRAW,
-      );
+    if options.gl_debug {
+      // the callback is set per context
+      resource_context.make_current_surfaceless()?;
+      if unsafe { debug::enable() } {
+        log::info!("GL debug output enabled");
+      } else {
+        log::warn!("GL debug output disabled: KHR_debug is not supported");
+        options.gl_debug = false;
+      }
+      resource_context.make_not_current_in_place()?;
+    }
 
-      let position_loc: GLuint = GetAttribLocation(program, c"position".as_ptr()) as _;
-      EnableVertexAttribArray(position_loc);
-      VertexAttribPointer(
-        position_loc,
-        2,
-        FLOAT,
-        FALSE,
-        (4 * size_of::<GLfloat>()) as _,
-        0 as _,
-      );
-      let texcoord_loc: GLuint = GetAttribLocation(program, c"in_texcoord".as_ptr()) as _;
-      EnableVertexAttribArray(texcoord_loc);
-      VertexAttribPointer(
-        texcoord_loc,
-        2,
-        FLOAT,
-        FALSE,
-        (4 * size_of::<GLfloat>()) as _,
-        (2 * size_of::<GLfloat>()) as _,
-      );
+    render_context.make_current_surfaceless()?;
+    let driver = unsafe { driver_info(&display) };
+    log::debug!("{}", driver);
+    if options.gl_debug {
+      unsafe { debug::enable() };
+    }
 
-      BindBuffer(ARRAY_BUFFER, 0);
-      BindVertexArray(0);
+    if let Some(samples) = options.msaa_samples {
+      let mut max_samples = 0;
+      unsafe { gl::GetIntegerv(gl::MAX_SAMPLES, &mut max_samples) };
+      let clamped = samples.get().min(max_samples.max(0) as u32);
+      if clamped < 2 {
+        log::warn!(
+          "MSAA is not supported (GL_MAX_SAMPLES = {}). Disabled.",
+          max_samples
+        );
+        options.msaa_samples = None;
+      } else {
+        if clamped != samples.get() {
+          log::warn!(
+            "{}x MSAA is not supported. Fall back to {}x.",
+            samples,
+            clamped
+          );
+        }
+        options.msaa_samples = NonZero::new(clamped);
+      }
+    }
 
-      render_context.make_not_current_in_place()?;
+    let blitter = unsafe { Blitter::new(options.impeller)? };
 
-      (vertex_array, vertex_buffer)
-    };
+    render_context.make_not_current_in_place()?;
 
     Ok(Self {
       egl_display: display,
       egl_config: config,
       render_context,
-      program,
-      vertex_array,
-      vertex_buffer,
+      blitter,
       resource_context,
+      options,
+      fence_kind,
+      drm_device,
+      dmabuf_allocator,
+      driver,
+      robust,
     })
   }
 
+  /// Fail with [`ContextLost`] if the GPU was reset. The contexts are unusable then, and so is
+  /// everything in them.
+  ///
+  /// Must be called with the render context current.
+  pub unsafe fn check_reset(&self) -> Result<()> {
+    if !self.robust {
+      return Ok(());
+    }
+    let reason = match unsafe { gl::GetGraphicsResetStatus() } {
+      gl::NO_ERROR => return Ok(()),
+      gl::GUILTY_CONTEXT_RESET => "caused by this context",
+      gl::INNOCENT_CONTEXT_RESET => "caused by another context",
+      _ => "cause unknown",
+    };
+    Err(anyhow::anyhow!("GPU reset, {}", reason).context(ContextLost))
+  }
+
+  /// Make the render context current for rendering into framebuffer objects.
+  ///
+  /// Any surface already bound will do, so this is free while the context is current.
   pub fn make_current_no_surface(&self) -> Result<()> {
+    if RENDER_BINDING.get() != RenderBinding::NotCurrent {
+      return Ok(());
+    }
     self
       .render_context
       .make_current_surfaceless()
       .context("failed to make context current with EGL_NO_SURFACE")?;
+    RENDER_BINDING.set(RenderBinding::Surfaceless);
     Ok(())
   }
 
   pub fn make_current(&self, surface: &Surface<WindowSurface>) -> Result<()> {
+    let binding = RenderBinding::Surface(surface.raw_surface());
+    if RENDER_BINDING.get() == binding {
+      return Ok(());
+    }
     self
       .render_context
       .make_current(surface)
       .context("failed to make context current")?;
+    RENDER_BINDING.set(binding);
     Ok(())
   }
 
-  pub fn make_not_current(&self) -> Result<()> {
-    self.render_context.make_not_current_in_place()?;
+  /// Unbind `surface` if the render context is current on it, so that it can be destroyed.
+  pub fn release_surface(&self, surface: &Surface<WindowSurface>) -> Result<()> {
+    if RENDER_BINDING.get() != RenderBinding::Surface(surface.raw_surface()) {
+      return Ok(());
+    }
+    self
+      .render_context
+      .make_current_surfaceless()
+      .context("failed to make context current with EGL_NO_SURFACE")?;
+    RENDER_BINDING.set(RenderBinding::Surfaceless);
     Ok(())
   }
 }
 
+/// What the render context is bound to on this thread.
+///
+/// The render context is only used on the raster thread, so it just stays current there.
+/// Every `eglMakeCurrent` is an expensive driver roundtrip, and the callbacks around a frame
+/// would otherwise switch it several times.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RenderBinding {
+  NotCurrent,
+  Surfaceless,
+  Surface(RawSurface),
+}
+
+thread_local! {
+  static RENDER_BINDING: Cell<RenderBinding> = const { Cell::new(RenderBinding::NotCurrent) };
+}
+
+/// Pick an EGL config satisfying `options`, turning off the options no config supports.
+fn choose_config(display: &Display, options: &mut RenderOptions) -> Result<Config> {
+  let template = if options.impeller {
+    // Impeller relies on stencil buffers for clipping
+    ConfigTemplateBuilder::new()
+      .with_api(Api::GLES3)
+      .with_stencil_size(8)
+      .build()
+  } else {
+    ConfigTemplate::default()
+  };
+  let configs = unsafe { display.find_configs(template)? }.collect::<Vec<_>>();
+  let first = configs.first().cloned().context("no egl config found")?;
+
+  if options.deep_color && options.srgb {
+    log::warn!("There's no 10-bit sRGB format. sRGB rendering disabled.");
+    options.srgb = false;
+  }
+
+  if options.deep_color {
+    let deep_color_config = configs.iter().find(|config| {
+      matches!(
+        config.color_buffer_type(),
+        Some(ColorBufferType::Rgb {
+          r_size: 10,
+          g_size: 10,
+          b_size: 10,
+        })
+      )
+    });
+    match deep_color_config {
+      Some(config) => return Ok(config.clone()),
+      None => {
+        log::warn!("No 10-bit EGL config found. 10-bit color disabled.");
+        options.deep_color = false;
+      }
+    }
+  }
+
+  if options.srgb {
+    match configs.iter().find(|config| config.srgb_capable()) {
+      Some(config) => return Ok(config.clone()),
+      None => {
+        log::warn!("No sRGB capable EGL config found. sRGB rendering disabled.");
+        options.srgb = false;
+      }
+    }
+  }
+
+  Ok(first)
+}
+
+/// Open the render node of the EGL display's device.
+fn open_drm_device(display: &Display) -> Result<Arc<DrmDevice>> {
+  let device = display.device()?;
+  let path = device
+    .drm_render_device_node_path()
+    .context("EGL device has no DRM render node")?;
+  DrmDevice::open(path)
+}
+
+/// Warn if the EGL implementation didn't pick the GPU with render node `gpu`.
+fn check_gpu(display: &Display, gpu: &Path) {
+  let render_node = display
+    .device()
+    .ok()
+    .and_then(|device| device.drm_render_device_node_path());
+  match render_node {
+    Some(render_node) if render_node == gpu => {}
+    Some(render_node) => log::warn!(
+      "Asked for {}, but the EGL implementation renders on {}",
+      gpu.display(),
+      render_node.display()
+    ),
+    None => log::warn!(
+      "Can't tell whether the EGL implementation renders on {}",
+      gpu.display()
+    ),
+  }
+}
+
 fn get_egl_display(conn: &Connection) -> Result<Display> {
   // SAFETY: trust `wayland-client` crate and `libwayland`...
   let display = unsafe {
@@ -171,76 +460,38 @@ fn get_egl_display(conn: &Connection) -> Result<Display> {
   Ok(display)
 }
 
-const VERTEX_SHADER_SRC: &CStr = c"
-#version 330 core
-
-in vec2 position;
-in vec2 in_texcoord;
-out vec2 texcoord;
-
-void main() {
-    gl_Position = vec4(position, 0.0, 1.0);
-    texcoord = in_texcoord;
-}
-";
-
-const FRAGMENT_SHADER_SRC: &CStr = c"
-#version 330 core
-
-out vec4 color;
-in vec2 texcoord;
-uniform sampler2D tex;
-
-void main() {
-    color = texture(tex, texcoord);
-}
-";
-
-fn compile_shader_and_link_program() -> Result<gl::types::GLuint> {
-  use gl::types::*;
-  use gl::*;
-
-  unsafe fn compile(type_: GLenum, src: &CStr) -> Result<GLuint> {
-    unsafe {
-      let shader = CreateShader(type_);
-      ShaderSource(shader, 1, &src.as_ptr(), std::ptr::null());
-      CompileShader(shader);
-      let mut compile_status = 0;
-      GetShaderiv(shader, COMPILE_STATUS, &mut compile_status);
-      if compile_status == FALSE as i32 {
-        let mut log = [0i8; 512];
-        let mut log_len = 0;
-        GetShaderInfoLog(shader, 512, &mut log_len, log.as_mut_ptr());
-        let log: [u8; 512] = std::mem::transmute(log);
-        let log = String::from_utf8_lossy(&log[..log_len as usize]);
-        anyhow::bail!("Failed to compile shader: {}", log);
-      }
-      Ok(shader)
+/// Must be called with a GL context current.
+unsafe fn driver_info(display: &Display) -> String {
+  let egl = display.egl();
+  let raw_display = raw_egl_display(display);
+  let egl_string = |name| unsafe {
+    let ptr = egl.QueryString(raw_display, name as _);
+    if ptr.is_null() {
+      return String::new();
     }
-  }
-
-  let program = unsafe {
-    let vertex_shader = compile(VERTEX_SHADER, VERTEX_SHADER_SRC)?;
-    let fragment_shader = compile(FRAGMENT_SHADER, FRAGMENT_SHADER_SRC)?;
-    let program = CreateProgram();
-    AttachShader(program, vertex_shader);
-    AttachShader(program, fragment_shader);
-    LinkProgram(program);
-    let mut link_status = 0;
-    GetProgramiv(program, LINK_STATUS, &mut link_status);
-    if link_status == FALSE as i32 {
-      let mut log = [0i8; 512];
-      let mut log_len = 0;
-      GetProgramInfoLog(program, 512, &mut log_len, log.as_mut_ptr());
-      let log: [u8; 512] = std::mem::transmute(log);
-      let log = String::from_utf8_lossy(&log[..log_len as usize]);
-      anyhow::bail!("Failed to link program: {}", log);
-    }
-    DeleteShader(vertex_shader);
-    DeleteShader(fragment_shader);
-
-    program
+    CStr::from_ptr(ptr).to_string_lossy().into_owned()
+  };
+  let gl_string = |name| unsafe {
+    let ptr = gl::GetString(name);
+    if ptr.is_null() {
+      return String::new();
+    }
+    CStr::from_ptr(ptr as _).to_string_lossy().into_owned()
   };
+  format!(
+    "EGL vendor: {}\nEGL version: {}\nGL vendor: {}\nGL renderer: {}\nGL version: {}",
+    egl_string(egl::VENDOR),
+    egl_string(egl::VERSION),
+    gl_string(gl::VENDOR),
+    gl_string(gl::RENDERER),
+    gl_string(gl::VERSION),
+  )
+}
 
-  Ok(program)
+fn raw_egl_display(display: &Display) -> glutin_egl_sys::egl::types::EGLDisplay {
+  match display.raw_display() {
+    RawDisplay::Egl(raw) => raw,
+    #[allow(unreachable_patterns)]
+    _ => unreachable!(),
+  }
 }
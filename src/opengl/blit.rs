@@ -0,0 +1,429 @@
+use std::ffi::CStr;
+
+use anyhow::Result;
+use gl::types::*;
+
+use crate::ffi;
+
+/// Draws backing store textures onto the default framebuffer.
+///
+/// The render context is shared with the engine, so the blit must leave no trace in its GL
+/// state. The blitter owns its program, vertex array and sampler object, and [`Blitter::blit`]
+/// restores every piece of state it touches.
+#[derive(Debug)]
+pub struct Blitter {
+  program: GLuint,
+  uniforms: Uniforms,
+  /// with the vertex buffer of the unit square
+  vertex_array: GLuint,
+  /// keeps texture parameters of backing stores out of the picture
+  sampler: GLuint,
+  /// the render context is OpenGL ES rather than desktop OpenGL
+  gles: bool,
+}
+
+#[derive(Debug)]
+struct Uniforms {
+  rect: GLint,
+  viewport_size: GLint,
+  solid: GLint,
+  solid_color: GLint,
+  decode_srgb: GLint,
+}
+
+/// A texture to draw, premultiplied like everything the engine renders
+#[derive(Debug)]
+pub struct BlitLayer {
+  pub texture: GLuint,
+  /// in physical pixels of the surface
+  pub offset: ffi::FlutterPoint,
+  /// a negative height flips the texture vertically
+  pub size: ffi::FlutterSize,
+}
+
+/// A rectangle of solid color drawn over the layers
+#[derive(Debug)]
+pub struct Tint {
+  /// in physical pixels of the surface
+  pub rect: ffi::FlutterRect,
+  /// premultiplied
+  pub color: [GLfloat; 4],
+}
+
+impl Blitter {
+  /// Must be called with the render context current.
+  pub unsafe fn new(gles: bool) -> Result<Self> {
+    use gl::*;
+
+    let program = compile_shader_and_link_program(gles)?;
+    unsafe {
+      let vertices: [GLfloat; _] = [0.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0, 1.0, 1.0]; // unit square, y pointing down
+
+      let mut prev_vertex_array = 0;
+      GetIntegerv(VERTEX_ARRAY_BINDING, &mut prev_vertex_array);
+      let mut prev_array_buffer = 0;
+      GetIntegerv(ARRAY_BUFFER_BINDING, &mut prev_array_buffer);
+
+      let mut vertex_array = 0;
+      GenVertexArrays(1, &mut vertex_array);
+      let mut vertex_buffer = 0;
+      GenBuffers(1, &mut vertex_buffer);
+
+      BindVertexArray(vertex_array);
+      BindBuffer(ARRAY_BUFFER, vertex_buffer);
+
+      BufferData(
+        ARRAY_BUFFER,
+        (vertices.len() * size_of::<GLfloat>()) as isize,
+        vertices.as_ptr() as _,
+        STATIC_DRAW,
+      );
+
+      let position_loc: GLuint = GetAttribLocation(program, c"position".as_ptr()) as _;
+      EnableVertexAttribArray(position_loc);
+      VertexAttribPointer(
+        position_loc,
+        2,
+        FLOAT,
+        FALSE,
+        (2 * size_of::<GLfloat>()) as _,
+        0 as _,
+      );
+
+      BindBuffer(ARRAY_BUFFER, prev_array_buffer as u32);
+      BindVertexArray(prev_vertex_array as u32);
+
+      let mut sampler = 0;
+      GenSamplers(1, &mut sampler);
+      SamplerParameteri(sampler, TEXTURE_WRAP_S, CLAMP_TO_EDGE as _);
+      SamplerParameteri(sampler, TEXTURE_WRAP_T, CLAMP_TO_EDGE as _);
+      SamplerParameteri(sampler, TEXTURE_MIN_FILTER, LINEAR as _);
+      SamplerParameteri(sampler, TEXTURE_MAG_FILTER, LINEAR as _);
+
+      let uniform = |name: &CStr| GetUniformLocation(program, name.as_ptr());
+      let uniforms = Uniforms {
+        rect: uniform(c"rect"),
+        viewport_size: uniform(c"viewport_size"),
+        solid: uniform(c"solid"),
+        solid_color: uniform(c"solid_color"),
+        decode_srgb: uniform(c"decode_srgb"),
+      };
+
+      Ok(Self {
+        program,
+        uniforms,
+        vertex_array,
+        sampler,
+        gles,
+      })
+    }
+  }
+
+  /// Clear the default framebuffer of the current surface to black of opacity `dim`, draw
+  /// `layers` bottom to top, then `tints` over them.
+  ///
+  /// `srgb` decodes what's drawn to linear and enables sRGB encoding on write to the (then sRGB)
+  /// window surface, so blending happens in linear space. Otherwise values pass through
+  /// untouched. OpenGL ES always encodes for sRGB surfaces.
+  ///
+  /// Must be called with the render context current.
+  pub unsafe fn blit(
+    &self,
+    width: GLsizei,
+    height: GLsizei,
+    srgb: bool,
+    dim: f32,
+    layers: &[BlitLayer],
+    tints: &[Tint],
+  ) {
+    use gl::*;
+
+    unsafe {
+      let saved = SavedState::capture(self.gles);
+
+      BindFramebuffer(DRAW_FRAMEBUFFER, 0);
+      // https://github.com/NVIDIA/egl-wayland/issues/48
+      // THANK YOU AMBIGUOUS BIG STATE MACHINE. THANK YOU EGL and OpenGL.
+      if self.gles {
+        DrawBuffers(1, &BACK);
+      } else {
+        DrawBuffer(BACK);
+        set_enabled(FRAMEBUFFER_SRGB, srgb);
+      }
+      Viewport(0, 0, width, height);
+      for capability in [DEPTH_TEST, STENCIL_TEST, SCISSOR_TEST, CULL_FACE] {
+        Disable(capability);
+      }
+      ColorMask(TRUE, TRUE, TRUE, TRUE);
+      // premultiplied
+      ClearColor(0.0, 0.0, 0.0, dim);
+      Clear(COLOR_BUFFER_BIT);
+      Enable(BLEND);
+      BlendEquation(FUNC_ADD);
+      BlendFunc(ONE, ONE_MINUS_SRC_ALPHA);
+
+      UseProgram(self.program);
+      BindVertexArray(self.vertex_array);
+      ActiveTexture(TEXTURE0);
+      BindSampler(0, self.sampler);
+      Uniform2f(self.uniforms.viewport_size, width as _, height as _);
+      Uniform1i(self.uniforms.decode_srgb, srgb as _);
+      Uniform1i(self.uniforms.solid, FALSE as _);
+      for layer in layers {
+        self.set_rect(layer.offset, layer.size);
+        BindTexture(TEXTURE_2D, layer.texture);
+        DrawArrays(TRIANGLES, 0, 6);
+      }
+      Uniform1i(self.uniforms.solid, TRUE as _);
+      for tint in tints {
+        let ffi::FlutterRect {
+          left,
+          top,
+          right,
+          bottom,
+        } = tint.rect;
+        self.set_rect(
+          ffi::FlutterPoint { x: left, y: top },
+          ffi::FlutterSize {
+            width: right - left,
+            height: bottom - top,
+          },
+        );
+        let [r, g, b, a] = tint.color;
+        Uniform4f(self.uniforms.solid_color, r, g, b, a);
+        DrawArrays(TRIANGLES, 0, 6);
+      }
+
+      saved.restore();
+    }
+  }
+
+  /// Place the unit square at `offset` with `size`, in physical pixels of the surface. A
+  /// negative height flips it vertically.
+  unsafe fn set_rect(&self, offset: ffi::FlutterPoint, size: ffi::FlutterSize) {
+    unsafe {
+      gl::Uniform4f(
+        self.uniforms.rect,
+        offset.x as _,
+        offset.y as _,
+        size.width as _,
+        size.height as _,
+      );
+    }
+  }
+}
+
+/// GL state touched by [`Blitter::blit`]
+struct SavedState {
+  program: GLint,
+  vertex_array: GLint,
+  active_texture: GLint,
+  texture: GLint,
+  sampler: GLint,
+  draw_framebuffer: GLint,
+  viewport: [GLint; 4],
+  color_mask: [GLboolean; 4],
+  clear_color: [GLfloat; 4],
+  /// src rgb, dst rgb, src alpha, dst alpha
+  blend_func: [GLint; 4],
+  /// rgb, alpha
+  blend_equation: [GLint; 2],
+  capabilities: Vec<(GLenum, GLboolean)>,
+}
+
+impl SavedState {
+  unsafe fn capture(gles: bool) -> Self {
+    use gl::*;
+
+    unsafe {
+      let get = |name| {
+        let mut value = 0;
+        GetIntegerv(name, &mut value);
+        value
+      };
+      let active_texture = get(ACTIVE_TEXTURE);
+      // texture and sampler bindings are per unit, and the blit uses unit 0
+      ActiveTexture(TEXTURE0);
+      let texture = get(TEXTURE_BINDING_2D);
+      let sampler = get(SAMPLER_BINDING);
+      let mut viewport = [0; 4];
+      GetIntegerv(VIEWPORT, viewport.as_mut_ptr());
+      let mut color_mask = [TRUE; 4];
+      GetBooleanv(COLOR_WRITEMASK, color_mask.as_mut_ptr());
+      let mut clear_color = [0.0; 4];
+      GetFloatv(COLOR_CLEAR_VALUE, clear_color.as_mut_ptr());
+      let blend_func = [
+        BLEND_SRC_RGB,
+        BLEND_DST_RGB,
+        BLEND_SRC_ALPHA,
+        BLEND_DST_ALPHA,
+      ]
+      .map(get);
+      let blend_equation = [BLEND_EQUATION_RGB, BLEND_EQUATION_ALPHA].map(get);
+      // GL_FRAMEBUFFER_SRGB is desktop only
+      let capabilities = [
+        FRAMEBUFFER_SRGB,
+        BLEND,
+        DEPTH_TEST,
+        STENCIL_TEST,
+        SCISSOR_TEST,
+        CULL_FACE,
+      ]
+      .into_iter()
+      .skip(gles as usize)
+      .map(|capability| (capability, IsEnabled(capability)))
+      .collect();
+
+      Self {
+        program: get(CURRENT_PROGRAM),
+        vertex_array: get(VERTEX_ARRAY_BINDING),
+        active_texture,
+        texture,
+        sampler,
+        draw_framebuffer: get(DRAW_FRAMEBUFFER_BINDING),
+        viewport,
+        color_mask,
+        clear_color,
+        blend_func,
+        blend_equation,
+        capabilities,
+      }
+    }
+  }
+
+  unsafe fn restore(&self) {
+    use gl::*;
+
+    unsafe {
+      UseProgram(self.program as u32);
+      BindVertexArray(self.vertex_array as u32);
+      ActiveTexture(TEXTURE0);
+      BindTexture(TEXTURE_2D, self.texture as u32);
+      BindSampler(0, self.sampler as u32);
+      ActiveTexture(self.active_texture as u32);
+      BindFramebuffer(DRAW_FRAMEBUFFER, self.draw_framebuffer as u32);
+      let [x, y, width, height] = self.viewport;
+      Viewport(x, y, width, height);
+      let [r, g, b, a] = self.color_mask;
+      ColorMask(r, g, b, a);
+      let [r, g, b, a] = self.clear_color;
+      ClearColor(r, g, b, a);
+      let [src_rgb, dst_rgb, src_alpha, dst_alpha] = self.blend_func.map(|x| x as GLenum);
+      BlendFuncSeparate(src_rgb, dst_rgb, src_alpha, dst_alpha);
+      let [rgb, alpha] = self.blend_equation.map(|x| x as GLenum);
+      BlendEquationSeparate(rgb, alpha);
+      for &(capability, enabled) in &self.capabilities {
+        set_enabled(capability, enabled == TRUE);
+      }
+    }
+  }
+}
+
+unsafe fn set_enabled(capability: GLenum, enabled: bool) {
+  unsafe {
+    if enabled {
+      gl::Enable(capability);
+    } else {
+      gl::Disable(capability);
+    }
+  }
+}
+
+const DESKTOP_SHADER_HEADER: &CStr = c"#version 330 core\n";
+
+const GLES_SHADER_HEADER: &CStr = c"#version 300 es\nprecision highp float;\n";
+
+const VERTEX_SHADER_SRC: &CStr = c"
+in vec2 position;
+out vec2 texcoord;
+// x, y, width, height in pixels
+uniform vec4 rect;
+uniform vec2 viewport_size;
+
+void main() {
+    vec2 pixel = rect.xy + position * rect.zw;
+    gl_Position = vec4(pixel.x / viewport_size.x * 2.0 - 1.0,
+                       1.0 - pixel.y / viewport_size.y * 2.0, 0.0, 1.0);
+    // textures rendered by GL are stored bottom-up
+    texcoord = vec2(position.x, 1.0 - position.y);
+}
+";
+
+const FRAGMENT_SHADER_SRC: &CStr = c"
+out vec4 color;
+in vec2 texcoord;
+uniform sampler2D tex;
+uniform bool solid;
+uniform vec4 solid_color;
+uniform bool decode_srgb;
+
+// from premultiplied sRGB
+vec4 decode(vec4 c) {
+    if (!decode_srgb || c.a == 0.0) {
+        return c;
+    }
+    vec3 e = c.rgb / c.a;
+    vec3 decoded = mix(e / 12.92, pow((e + 0.055) / 1.055, vec3(2.4)), step(0.04045, e));
+    return vec4(decoded * c.a, c.a);
+}
+
+void main() {
+    color = decode(solid ? solid_color : texture(tex, texcoord));
+}
+";
+
+fn compile_shader_and_link_program(gles: bool) -> Result<gl::types::GLuint> {
+  use gl::types::*;
+  use gl::*;
+
+  let header = if gles {
+    GLES_SHADER_HEADER
+  } else {
+    DESKTOP_SHADER_HEADER
+  };
+
+  let compile = |type_: GLenum, src: &CStr| -> Result<GLuint> {
+    unsafe {
+      let shader = CreateShader(type_);
+      let sources = [header.as_ptr(), src.as_ptr()];
+      ShaderSource(shader, 2, sources.as_ptr(), std::ptr::null());
+      CompileShader(shader);
+      let mut compile_status = 0;
+      GetShaderiv(shader, COMPILE_STATUS, &mut compile_status);
+      if compile_status == FALSE as i32 {
+        let mut log = [0i8; 512];
+        let mut log_len = 0;
+        GetShaderInfoLog(shader, 512, &mut log_len, log.as_mut_ptr());
+        let log: [u8; 512] = std::mem::transmute(log);
+        let log = String::from_utf8_lossy(&log[..log_len as usize]);
+        anyhow::bail!("Failed to compile shader: {}", log);
+      }
+      Ok(shader)
+    }
+  };
+
+  let program = unsafe {
+    let vertex_shader = compile(VERTEX_SHADER, VERTEX_SHADER_SRC)?;
+    let fragment_shader = compile(FRAGMENT_SHADER, FRAGMENT_SHADER_SRC)?;
+    let program = CreateProgram();
+    AttachShader(program, vertex_shader);
+    AttachShader(program, fragment_shader);
+    LinkProgram(program);
+    let mut link_status = 0;
+    GetProgramiv(program, LINK_STATUS, &mut link_status);
+    if link_status == FALSE as i32 {
+      let mut log = [0i8; 512];
+      let mut log_len = 0;
+      GetProgramInfoLog(program, 512, &mut log_len, log.as_mut_ptr());
+      let log: [u8; 512] = std::mem::transmute(log);
+      let log = String::from_utf8_lossy(&log[..log_len as usize]);
+      anyhow::bail!("Failed to link program: {}", log);
+    }
+    DeleteShader(vertex_shader);
+    DeleteShader(fragment_shader);
+
+    program
+  };
+
+  Ok(program)
+}
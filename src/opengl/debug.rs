@@ -0,0 +1,108 @@
+//! `--gl-debug`: GL errors and driver warnings reported by KHR_debug, which otherwise only show
+//! up as black surfaces.
+
+use std::ffi::CStr;
+use std::ffi::c_void;
+
+use anyhow::Result;
+use gl::types::GLchar;
+use gl::types::GLenum;
+use gl::types::GLsizei;
+use gl::types::GLuint;
+
+/// Log the debug messages of the current context, on the thread that caused them.
+///
+/// Must be called with a debug context current. Returns false if KHR_debug isn't supported.
+pub unsafe fn enable() -> bool {
+  if !gl::DebugMessageCallback::is_loaded() {
+    return false;
+  }
+  unsafe {
+    gl::Enable(gl::DEBUG_OUTPUT);
+    gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+    gl::DebugMessageCallback(Some(callback), std::ptr::null());
+  }
+  true
+}
+
+extern "system" fn callback(
+  source: GLenum,
+  type_: GLenum,
+  id: GLuint,
+  severity: GLenum,
+  length: GLsizei,
+  message: *const GLchar,
+  _user_param: *mut c_void,
+) {
+  let message = if length < 0 {
+    unsafe { CStr::from_ptr(message) }.to_string_lossy()
+  } else {
+    let bytes = unsafe { std::slice::from_raw_parts(message as *const u8, length as usize) };
+    String::from_utf8_lossy(bytes)
+  };
+  let level = match severity {
+    gl::DEBUG_SEVERITY_HIGH => log::Level::Error,
+    gl::DEBUG_SEVERITY_MEDIUM => log::Level::Warn,
+    gl::DEBUG_SEVERITY_LOW => log::Level::Info,
+    _ => log::Level::Debug,
+  };
+  log::log!(
+    level,
+    "GL {} from {} (id {:#x}): {}",
+    type_name(type_),
+    source_name(source),
+    id,
+    message.trim_end()
+  );
+}
+
+fn source_name(source: GLenum) -> &'static str {
+  match source {
+    gl::DEBUG_SOURCE_API => "API",
+    gl::DEBUG_SOURCE_WINDOW_SYSTEM => "window system",
+    gl::DEBUG_SOURCE_SHADER_COMPILER => "shader compiler",
+    gl::DEBUG_SOURCE_THIRD_PARTY => "third party",
+    gl::DEBUG_SOURCE_APPLICATION => "application",
+    _ => "other",
+  }
+}
+
+fn type_name(type_: GLenum) -> &'static str {
+  match type_ {
+    gl::DEBUG_TYPE_ERROR => "error",
+    gl::DEBUG_TYPE_DEPRECATED_BEHAVIOR => "deprecated behavior",
+    gl::DEBUG_TYPE_UNDEFINED_BEHAVIOR => "undefined behavior",
+    gl::DEBUG_TYPE_PORTABILITY => "portability issue",
+    gl::DEBUG_TYPE_PERFORMANCE => "performance issue",
+    gl::DEBUG_TYPE_MARKER => "marker",
+    _ => "message",
+  }
+}
+
+/// Fail if `framebuffer` can't be rendered into.
+///
+/// Must be called with a GL context current. The framebuffer binding is preserved.
+pub unsafe fn check_framebuffer(framebuffer: GLuint) -> Result<()> {
+  let status = unsafe {
+    let mut prev_framebuffer = 0;
+    gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut prev_framebuffer);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+    let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+    gl::BindFramebuffer(gl::FRAMEBUFFER, prev_framebuffer as GLuint);
+    status
+  };
+  let reason = match status {
+    gl::FRAMEBUFFER_COMPLETE => return Ok(()),
+    gl::FRAMEBUFFER_INCOMPLETE_ATTACHMENT => "an attachment is incomplete",
+    gl::FRAMEBUFFER_INCOMPLETE_MISSING_ATTACHMENT => "no attachment",
+    gl::FRAMEBUFFER_INCOMPLETE_MULTISAMPLE => "attachments differ in samples",
+    gl::FRAMEBUFFER_UNSUPPORTED => "the combination of formats is unsupported",
+    _ => "unknown status",
+  };
+  anyhow::bail!(
+    "framebuffer {} is incomplete: {} ({:#x})",
+    framebuffer,
+    reason,
+    status
+  );
+}
@@ -0,0 +1,163 @@
+use std::ffi::c_void;
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+
+use anyhow::Result;
+use gl::types::GLenum;
+use glutin::api::egl::display::Display;
+use glutin::display::GetDisplayExtensions;
+use glutin::prelude::GlDisplay;
+use glutin_egl_sys::egl;
+
+use crate::opengl::drm_syncobj::DrmDevice;
+use crate::opengl::gbm::DRM_FORMAT_MOD_INVALID;
+use crate::opengl::gbm::Dmabuf;
+use crate::opengl::gbm::GbmBuffer;
+use crate::opengl::gbm::GbmDevice;
+use crate::opengl::raw_egl_display;
+
+// from EGL_EXT_image_dma_buf_import(_modifiers)
+const EGL_LINUX_DMA_BUF_EXT: egl::types::EGLenum = 0x3270;
+const EGL_LINUX_DRM_FOURCC_EXT: egl::types::EGLint = 0x3271;
+/// fd, offset, pitch, modifier lo, modifier hi of each plane
+const EGL_DMA_BUF_PLANE_ATTRIBS: [[egl::types::EGLint; 5]; 4] = [
+  [0x3272, 0x3273, 0x3274, 0x3443, 0x3444],
+  [0x3275, 0x3276, 0x3277, 0x3445, 0x3446],
+  [0x3278, 0x3279, 0x327A, 0x3447, 0x3448],
+  [0x3440, 0x3441, 0x3442, 0x3449, 0x344A],
+];
+
+/// glEGLImageTargetTexture2DOES
+type ImageTargetTexture2D = unsafe extern "system" fn(target: GLenum, image: *const c_void);
+
+/// Allocates dmabufs on the render node and imports them into GL.
+#[derive(Debug)]
+pub struct DmabufAllocator {
+  gbm: Arc<GbmDevice>,
+  image_target_texture_2d: ImageTargetTexture2D,
+}
+
+impl DmabufAllocator {
+  pub fn new(display: &Display, drm_device: &Arc<DrmDevice>) -> Result<Self> {
+    let extensions = display.extensions();
+    for extension in ["EGL_KHR_image_base", "EGL_EXT_image_dma_buf_import"] {
+      if !extensions.contains(extension) {
+        anyhow::bail!("{} is not supported", extension);
+      }
+    }
+    let image_target_texture_2d = display.get_proc_address(c"glEGLImageTargetTexture2DOES");
+    if image_target_texture_2d.is_null() {
+      anyhow::bail!("GL_OES_EGL_image is not supported");
+    }
+    Ok(Self {
+      gbm: GbmDevice::new(drm_device)?,
+      image_target_texture_2d: unsafe {
+        std::mem::transmute::<*const c_void, ImageTargetTexture2D>(image_target_texture_2d)
+      },
+    })
+  }
+
+  /// Allocate a buffer in one of `modifiers` and import it as an EGLImage.
+  pub fn allocate(
+    &self,
+    display: &Display,
+    width: u32,
+    height: u32,
+    format: u32,
+    modifiers: &[u64],
+  ) -> Result<DmabufImage> {
+    let explicit_modifiers = modifiers
+      .iter()
+      .copied()
+      .filter(|&modifier| modifier != DRM_FORMAT_MOD_INVALID)
+      .collect::<Vec<_>>();
+    let buffer = self
+      .gbm
+      .create_buffer(width, height, format, &explicit_modifiers)?;
+    let dmabuf = buffer.export()?;
+
+    let mut attribs = vec![
+      egl::WIDTH as egl::types::EGLint,
+      width as _,
+      egl::HEIGHT as _,
+      height as _,
+      EGL_LINUX_DRM_FOURCC_EXT,
+      format as _,
+    ];
+    for (plane, [fd, offset, pitch, modifier_lo, modifier_hi]) in
+      dmabuf.planes.iter().zip(EGL_DMA_BUF_PLANE_ATTRIBS)
+    {
+      attribs.extend([
+        fd,
+        plane.fd.as_raw_fd(),
+        offset,
+        plane.offset as _,
+        pitch,
+        plane.stride as _,
+      ]);
+      if dmabuf.modifier != DRM_FORMAT_MOD_INVALID {
+        attribs.extend([
+          modifier_lo,
+          dmabuf.modifier as u32 as _,
+          modifier_hi,
+          (dmabuf.modifier >> 32) as u32 as _,
+        ]);
+      }
+    }
+    attribs.push(egl::NONE as _);
+
+    let image = unsafe {
+      display.egl().CreateImageKHR(
+        raw_egl_display(display),
+        egl::NO_CONTEXT,
+        EGL_LINUX_DMA_BUF_EXT,
+        std::ptr::null_mut(),
+        attribs.as_ptr(),
+      )
+    };
+    if image == egl::NO_IMAGE_KHR {
+      anyhow::bail!("eglCreateImageKHR(EGL_LINUX_DMA_BUF_EXT) failed");
+    }
+
+    Ok(DmabufImage {
+      display: display.clone(),
+      image,
+      image_target_texture_2d: self.image_target_texture_2d,
+      dmabuf,
+      _buffer: buffer,
+    })
+  }
+}
+
+/// A dmabuf usable as GL texture storage
+#[derive(Debug)]
+pub struct DmabufImage {
+  display: Display,
+  image: egl::types::EGLImageKHR,
+  image_target_texture_2d: ImageTargetTexture2D,
+  dmabuf: Dmabuf,
+  _buffer: GbmBuffer,
+}
+
+/// EGLImages aren't bound to a context or thread
+unsafe impl Send for DmabufImage {}
+
+impl DmabufImage {
+  pub fn dmabuf(&self) -> &Dmabuf {
+    &self.dmabuf
+  }
+
+  /// Use the buffer as storage of the texture bound to `TEXTURE_2D`.
+  ///
+  /// Must be called with a GL context current.
+  pub unsafe fn bind_texture_storage(&self) {
+    unsafe { (self.image_target_texture_2d)(gl::TEXTURE_2D, self.image) };
+  }
+}
+
+impl Drop for DmabufImage {
+  fn drop(&mut self) {
+    let display = raw_egl_display(&self.display);
+    unsafe { self.display.egl().DestroyImageKHR(display, self.image) };
+  }
+}
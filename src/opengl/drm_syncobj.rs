@@ -0,0 +1,215 @@
+use std::fs::OpenOptions;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::BorrowedFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Context;
+use anyhow::Result;
+
+// from drm.h
+const DRM_SYNCOBJ_FD_TO_HANDLE_FLAGS_IMPORT_SYNC_FILE: u32 = 1 << 0;
+const DRM_SYNCOBJ_WAIT_FLAGS_WAIT_FOR_SUBMIT: u32 = 1 << 1;
+
+#[repr(C)]
+struct DrmSyncobjCreate {
+  handle: u32,
+  flags: u32,
+}
+
+#[repr(C)]
+struct DrmSyncobjDestroy {
+  handle: u32,
+  pad: u32,
+}
+
+#[repr(C)]
+struct DrmSyncobjHandle {
+  handle: u32,
+  flags: u32,
+  fd: i32,
+  pad: u32,
+}
+
+#[repr(C)]
+struct DrmSyncobjTransfer {
+  src_handle: u32,
+  dst_handle: u32,
+  src_point: u64,
+  dst_point: u64,
+  flags: u32,
+  pad: u32,
+}
+
+#[repr(C)]
+struct DrmSyncobjTimelineWait {
+  handles: u64,
+  points: u64,
+  timeout_nsec: i64,
+  count_handles: u32,
+  flags: u32,
+  first_signaled: u32,
+  pad: u32,
+}
+
+/// `DRM_IOWR(nr, T)`
+const fn drm_iowr<T>(nr: u32) -> u32 {
+  (3 << 30) | ((size_of::<T>() as u32) << 16) | ((b'd' as u32) << 8) | nr
+}
+
+const DRM_IOCTL_SYNCOBJ_CREATE: u32 = drm_iowr::<DrmSyncobjCreate>(0xBF);
+const DRM_IOCTL_SYNCOBJ_DESTROY: u32 = drm_iowr::<DrmSyncobjDestroy>(0xC0);
+const DRM_IOCTL_SYNCOBJ_HANDLE_TO_FD: u32 = drm_iowr::<DrmSyncobjHandle>(0xC1);
+const DRM_IOCTL_SYNCOBJ_FD_TO_HANDLE: u32 = drm_iowr::<DrmSyncobjHandle>(0xC2);
+const DRM_IOCTL_SYNCOBJ_TIMELINE_WAIT: u32 = drm_iowr::<DrmSyncobjTimelineWait>(0xCA);
+const DRM_IOCTL_SYNCOBJ_TRANSFER: u32 = drm_iowr::<DrmSyncobjTransfer>(0xCC);
+
+/// An opened DRM render node
+#[derive(Debug)]
+pub struct DrmDevice {
+  fd: OwnedFd,
+}
+
+impl DrmDevice {
+  pub fn open(path: &Path) -> Result<Arc<Self>> {
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .custom_flags(libc::O_CLOEXEC)
+      .open(path)
+      .with_context(|| format!("failed to open {}", path.display()))?;
+    Ok(Arc::new(Self { fd: file.into() }))
+  }
+
+  fn ioctl<T>(&self, request: u32, arg: &mut T) -> std::io::Result<()> {
+    loop {
+      let ret = unsafe { libc::ioctl(self.fd.as_raw_fd(), request as _, arg as *mut T) };
+      if ret == 0 {
+        return Ok(());
+      }
+      let err = std::io::Error::last_os_error();
+      match err.raw_os_error() {
+        Some(libc::EINTR) | Some(libc::EAGAIN) => continue,
+        _ => return Err(err),
+      }
+    }
+  }
+
+  fn create_syncobj(&self) -> Result<u32> {
+    let mut create = DrmSyncobjCreate {
+      handle: 0,
+      flags: 0,
+    };
+    self
+      .ioctl(DRM_IOCTL_SYNCOBJ_CREATE, &mut create)
+      .context("DRM_IOCTL_SYNCOBJ_CREATE failed")?;
+    Ok(create.handle)
+  }
+
+  fn destroy_syncobj(&self, handle: u32) {
+    let mut destroy = DrmSyncobjDestroy { handle, pad: 0 };
+    if let Err(e) = self.ioctl(DRM_IOCTL_SYNCOBJ_DESTROY, &mut destroy) {
+      log::warn!("failed to destroy DRM syncobj {}: {}", handle, e);
+    }
+  }
+}
+
+impl AsFd for DrmDevice {
+  fn as_fd(&self) -> BorrowedFd<'_> {
+    self.fd.as_fd()
+  }
+}
+
+/// A DRM timeline synchronization object
+#[derive(Debug)]
+pub struct DrmTimeline {
+  device: Arc<DrmDevice>,
+  handle: u32,
+}
+
+impl DrmTimeline {
+  pub fn new(device: &Arc<DrmDevice>) -> Result<Self> {
+    Ok(Self {
+      device: device.clone(),
+      handle: device.create_syncobj()?,
+    })
+  }
+
+  /// Export the timeline as a file descriptor to share it with the compositor.
+  pub fn export(&self) -> Result<OwnedFd> {
+    let mut args = DrmSyncobjHandle {
+      handle: self.handle,
+      flags: 0,
+      fd: -1,
+      pad: 0,
+    };
+    self
+      .device
+      .ioctl(DRM_IOCTL_SYNCOBJ_HANDLE_TO_FD, &mut args)
+      .context("DRM_IOCTL_SYNCOBJ_HANDLE_TO_FD failed")?;
+    Ok(unsafe { OwnedFd::from_raw_fd(args.fd) })
+  }
+
+  /// Make `point` signal together with the sync file `sync_file`.
+  pub fn import_sync_file(&self, point: u64, sync_file: &OwnedFd) -> Result<()> {
+    // sync files can only be imported into binary syncobjs, so go through a temporary one
+    let temporary = self.device.create_syncobj()?;
+    let result = (|| {
+      let mut import = DrmSyncobjHandle {
+        handle: temporary,
+        flags: DRM_SYNCOBJ_FD_TO_HANDLE_FLAGS_IMPORT_SYNC_FILE,
+        fd: sync_file.as_raw_fd(),
+        pad: 0,
+      };
+      self
+        .device
+        .ioctl(DRM_IOCTL_SYNCOBJ_FD_TO_HANDLE, &mut import)
+        .context("DRM_IOCTL_SYNCOBJ_FD_TO_HANDLE failed")?;
+      let mut transfer = DrmSyncobjTransfer {
+        src_handle: temporary,
+        dst_handle: self.handle,
+        src_point: 0,
+        dst_point: point,
+        flags: 0,
+        pad: 0,
+      };
+      self
+        .device
+        .ioctl(DRM_IOCTL_SYNCOBJ_TRANSFER, &mut transfer)
+        .context("DRM_IOCTL_SYNCOBJ_TRANSFER failed")?;
+      anyhow::Ok(())
+    })();
+    self.device.destroy_syncobj(temporary);
+    result
+  }
+
+  /// Block until `point` is signaled, also waiting for it to be submitted in the first place.
+  pub fn wait(&self, point: u64) -> Result<()> {
+    let handles = [self.handle];
+    let points = [point];
+    let mut wait = DrmSyncobjTimelineWait {
+      handles: handles.as_ptr() as u64,
+      points: points.as_ptr() as u64,
+      timeout_nsec: i64::MAX,
+      count_handles: 1,
+      flags: DRM_SYNCOBJ_WAIT_FLAGS_WAIT_FOR_SUBMIT,
+      first_signaled: 0,
+      pad: 0,
+    };
+    self
+      .device
+      .ioctl(DRM_IOCTL_SYNCOBJ_TIMELINE_WAIT, &mut wait)
+      .context("DRM_IOCTL_SYNCOBJ_TIMELINE_WAIT failed")?;
+    Ok(())
+  }
+}
+
+impl Drop for DrmTimeline {
+  fn drop(&mut self) {
+    self.device.destroy_syncobj(self.handle);
+  }
+}
@@ -0,0 +1,147 @@
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+
+use anyhow::Result;
+use glutin::api::egl::display::Display;
+use glutin::display::GetDisplayExtensions;
+use glutin_egl_sys::egl;
+
+use crate::opengl::raw_egl_display;
+
+/// How to wait for the GPU to finish a frame before handing it to the compositor.
+///
+/// Some drivers let the blit (or the compositor) read a backing store while the engine's
+/// rendering into it is still in flight, which shows up as half-rendered frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenceKind {
+  /// EGL_ANDROID_native_fence_sync: a sync file signaled by the kernel
+  NativeFence,
+  /// EGL_KHR_fence_sync
+  Fence,
+  /// no fence extension, `glFinish`
+  Finish,
+}
+
+impl FenceKind {
+  pub fn detect(display: &Display) -> Self {
+    let extensions = display.extensions();
+    let kind = if extensions.contains("EGL_ANDROID_native_fence_sync") {
+      FenceKind::NativeFence
+    } else if extensions.contains("EGL_KHR_fence_sync") {
+      FenceKind::Fence
+    } else {
+      FenceKind::Finish
+    };
+    log::info!("GPU synchronization: {:?}", kind);
+    kind
+  }
+
+  /// Insert a fence after all GL commands issued so far on the current context.
+  ///
+  /// Must be called with a GL context current.
+  pub unsafe fn insert(self, display: &Display) -> Result<GpuFence<'_>> {
+    let egl = display.egl();
+    let raw_display = raw_egl_display(display);
+    let attribs = [egl::NONE as egl::types::EGLint];
+
+    unsafe {
+      match self {
+        FenceKind::NativeFence => {
+          let sync = egl.CreateSyncKHR(
+            raw_display,
+            egl::SYNC_NATIVE_FENCE_ANDROID,
+            attribs.as_ptr(),
+          );
+          if sync == egl::NO_SYNC {
+            anyhow::bail!("eglCreateSyncKHR(EGL_SYNC_NATIVE_FENCE_ANDROID) failed");
+          }
+          // the fence only gets a file descriptor once it's flushed
+          gl::Flush();
+          let fd = egl.DupNativeFenceFDANDROID(raw_display, sync);
+          egl.DestroySyncKHR(raw_display, sync);
+          if fd == egl::NO_NATIVE_FENCE_FD_ANDROID {
+            log::debug!("no native fence fd. Fall back to glFinish.");
+            gl::Finish();
+            return Ok(GpuFence::Signaled);
+          }
+          Ok(GpuFence::Native(OwnedFd::from_raw_fd(fd)))
+        }
+        FenceKind::Fence => {
+          let sync = egl.CreateSyncKHR(raw_display, egl::SYNC_FENCE_KHR, attribs.as_ptr());
+          if sync == egl::NO_SYNC {
+            anyhow::bail!("eglCreateSyncKHR(EGL_SYNC_FENCE_KHR) failed");
+          }
+          Ok(GpuFence::Egl { display, sync })
+        }
+        FenceKind::Finish => {
+          gl::Finish();
+          Ok(GpuFence::Signaled)
+        }
+      }
+    }
+  }
+}
+
+/// A point in the GPU command stream, see [`FenceKind::insert`].
+pub enum GpuFence<'a> {
+  Native(OwnedFd),
+  Egl {
+    display: &'a Display,
+    sync: egl::types::EGLSyncKHR,
+  },
+  /// already waited for when inserted
+  Signaled,
+}
+
+impl GpuFence<'_> {
+  /// Block until the GPU has passed the fence.
+  pub fn wait(self) -> Result<()> {
+    match &self {
+      GpuFence::Native(fd) => {
+        let mut pollfd = libc::pollfd {
+          fd: fd.as_raw_fd(),
+          events: libc::POLLIN,
+          revents: 0,
+        };
+        loop {
+          let ret = unsafe { libc::poll(&mut pollfd, 1, -1) };
+          if ret >= 0 {
+            break;
+          }
+          let err = std::io::Error::last_os_error();
+          if err.kind() != std::io::ErrorKind::Interrupted {
+            return Err(anyhow::Error::new(err).context("failed to wait for native fence"));
+          }
+        }
+      }
+      GpuFence::Egl { display, sync } => {
+        let ret = unsafe {
+          display.egl().ClientWaitSyncKHR(
+            raw_egl_display(display),
+            *sync,
+            egl::SYNC_FLUSH_COMMANDS_BIT as _,
+            egl::FOREVER,
+          )
+        };
+        if ret == egl::FALSE as egl::types::EGLint {
+          anyhow::bail!("eglClientWaitSyncKHR failed");
+        }
+      }
+      GpuFence::Signaled => {}
+    }
+    Ok(())
+  }
+}
+
+impl Drop for GpuFence<'_> {
+  fn drop(&mut self) {
+    if let GpuFence::Egl { display, sync } = self {
+      unsafe {
+        display
+          .egl()
+          .DestroySyncKHR(raw_egl_display(display), *sync)
+      };
+    }
+  }
+}
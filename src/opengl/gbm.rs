@@ -0,0 +1,243 @@
+use std::ffi::c_int;
+use std::ffi::c_uint;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use anyhow::Context;
+use anyhow::Result;
+use libloading::Library;
+
+use crate::opengl::drm_syncobj::DrmDevice;
+
+// from drm_fourcc.h and gbm.h
+pub const DRM_FORMAT_ABGR8888: u32 = u32::from_le_bytes(*b"AB24");
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+const GBM_BO_USE_RENDERING: u32 = 1 << 2;
+
+const LIBRARY: &str = "libgbm.so.1";
+
+#[repr(C)]
+struct RawGbmDevice {
+  _private: [u8; 0],
+}
+
+#[repr(C)]
+struct RawGbmBo {
+  _private: [u8; 0],
+}
+
+/// The functions of libgbm, which is only loaded when zero-copy presentation is enabled
+#[derive(Debug)]
+struct Gbm {
+  create_device: unsafe extern "C" fn(fd: c_int) -> *mut RawGbmDevice,
+  device_destroy: unsafe extern "C" fn(gbm: *mut RawGbmDevice),
+  bo_create: unsafe extern "C" fn(
+    gbm: *mut RawGbmDevice,
+    width: u32,
+    height: u32,
+    format: u32,
+    flags: u32,
+  ) -> *mut RawGbmBo,
+  bo_create_with_modifiers: unsafe extern "C" fn(
+    gbm: *mut RawGbmDevice,
+    width: u32,
+    height: u32,
+    format: u32,
+    modifiers: *const u64,
+    count: c_uint,
+  ) -> *mut RawGbmBo,
+  bo_destroy: unsafe extern "C" fn(bo: *mut RawGbmBo),
+  bo_get_modifier: unsafe extern "C" fn(bo: *mut RawGbmBo) -> u64,
+  bo_get_plane_count: unsafe extern "C" fn(bo: *mut RawGbmBo) -> c_int,
+  bo_get_fd_for_plane: unsafe extern "C" fn(bo: *mut RawGbmBo, plane: c_int) -> c_int,
+  bo_get_offset: unsafe extern "C" fn(bo: *mut RawGbmBo, plane: c_int) -> u32,
+  bo_get_stride_for_plane: unsafe extern "C" fn(bo: *mut RawGbmBo, plane: c_int) -> u32,
+  /// keeps the functions above loaded
+  _library: Library,
+}
+
+impl Gbm {
+  fn load() -> Result<Self> {
+    // SAFETY: libgbm's initializers don't depend on anything from us
+    let library =
+      unsafe { Library::new(LIBRARY) }.with_context(|| format!("failed to load {}", LIBRARY))?;
+    unsafe {
+      Ok(Self {
+        create_device: symbol(&library, b"gbm_create_device\0")?,
+        device_destroy: symbol(&library, b"gbm_device_destroy\0")?,
+        bo_create: symbol(&library, b"gbm_bo_create\0")?,
+        bo_create_with_modifiers: symbol(&library, b"gbm_bo_create_with_modifiers\0")?,
+        bo_destroy: symbol(&library, b"gbm_bo_destroy\0")?,
+        bo_get_modifier: symbol(&library, b"gbm_bo_get_modifier\0")?,
+        bo_get_plane_count: symbol(&library, b"gbm_bo_get_plane_count\0")?,
+        bo_get_fd_for_plane: symbol(&library, b"gbm_bo_get_fd_for_plane\0")?,
+        bo_get_offset: symbol(&library, b"gbm_bo_get_offset\0")?,
+        bo_get_stride_for_plane: symbol(&library, b"gbm_bo_get_stride_for_plane\0")?,
+        _library: library,
+      })
+    }
+  }
+}
+
+/// Look up the function `name`, nul-terminated, whose signature must be `T`.
+unsafe fn symbol<T: Copy>(library: &Library, name: &[u8]) -> Result<T> {
+  let symbol = unsafe { library.get::<T>(name) }.with_context(|| {
+    format!(
+      "{} lacks {}",
+      LIBRARY,
+      String::from_utf8_lossy(&name[..name.len() - 1])
+    )
+  })?;
+  Ok(*symbol)
+}
+
+/// A GBM device allocating buffers on the DRM render node
+#[derive(Debug)]
+pub struct GbmDevice {
+  gbm: Gbm,
+  raw: NonNull<RawGbmDevice>,
+  /// must outlive `raw`
+  _drm_device: Arc<DrmDevice>,
+}
+
+/// libgbm is thread safe
+unsafe impl Send for GbmDevice {}
+unsafe impl Sync for GbmDevice {}
+
+impl GbmDevice {
+  pub fn new(drm_device: &Arc<DrmDevice>) -> Result<Arc<Self>> {
+    let gbm = Gbm::load()?;
+    let raw = unsafe { (gbm.create_device)(drm_device.as_fd().as_raw_fd()) };
+    let raw = NonNull::new(raw).context("gbm_create_device failed")?;
+    Ok(Arc::new(Self {
+      gbm,
+      raw,
+      _drm_device: drm_device.clone(),
+    }))
+  }
+
+  /// Allocate a buffer the GPU can render into.
+  ///
+  /// Only `modifiers` are considered if there are any, otherwise the driver picks a layout
+  /// shared implicitly.
+  pub fn create_buffer(
+    self: &Arc<Self>,
+    width: u32,
+    height: u32,
+    format: u32,
+    modifiers: &[u64],
+  ) -> Result<GbmBuffer> {
+    let raw = unsafe {
+      if modifiers.is_empty() {
+        (self.gbm.bo_create)(
+          self.raw.as_ptr(),
+          width,
+          height,
+          format,
+          GBM_BO_USE_RENDERING,
+        )
+      } else {
+        (self.gbm.bo_create_with_modifiers)(
+          self.raw.as_ptr(),
+          width,
+          height,
+          format,
+          modifiers.as_ptr(),
+          modifiers.len() as c_uint,
+        )
+      }
+    };
+    let raw = NonNull::new(raw)
+      .with_context(|| format!("failed to allocate a {}x{} GBM buffer", width, height))?;
+    Ok(GbmBuffer {
+      raw,
+      width,
+      height,
+      format,
+      implicit_modifier: modifiers.is_empty(),
+      device: self.clone(),
+    })
+  }
+}
+
+impl Drop for GbmDevice {
+  fn drop(&mut self) {
+    unsafe { (self.gbm.device_destroy)(self.raw.as_ptr()) };
+  }
+}
+
+#[derive(Debug)]
+pub struct GbmBuffer {
+  raw: NonNull<RawGbmBo>,
+  width: u32,
+  height: u32,
+  format: u32,
+  /// allocated without a modifier list, so the layout mustn't be passed on explicitly
+  implicit_modifier: bool,
+  device: Arc<GbmDevice>,
+}
+
+unsafe impl Send for GbmBuffer {}
+
+impl GbmBuffer {
+  /// Export the planes as dmabufs.
+  pub fn export(&self) -> Result<Dmabuf> {
+    let gbm = &self.device.gbm;
+    let raw = self.raw.as_ptr();
+    let plane_count = unsafe { (gbm.bo_get_plane_count)(raw) };
+    let planes = (0..plane_count)
+      .map(|plane| {
+        let fd = unsafe { (gbm.bo_get_fd_for_plane)(raw, plane) };
+        if fd < 0 {
+          anyhow::bail!("failed to export plane {} of a GBM buffer", plane);
+        }
+        Ok(DmabufPlane {
+          fd: unsafe { OwnedFd::from_raw_fd(fd) },
+          offset: unsafe { (gbm.bo_get_offset)(raw, plane) },
+          stride: unsafe { (gbm.bo_get_stride_for_plane)(raw, plane) },
+        })
+      })
+      .collect::<Result<Vec<_>>>()?;
+    let modifier = if self.implicit_modifier {
+      DRM_FORMAT_MOD_INVALID
+    } else {
+      unsafe { (gbm.bo_get_modifier)(raw) }
+    };
+    Ok(Dmabuf {
+      width: self.width,
+      height: self.height,
+      format: self.format,
+      modifier,
+      planes,
+    })
+  }
+}
+
+impl Drop for GbmBuffer {
+  fn drop(&mut self) {
+    unsafe { (self.device.gbm.bo_destroy)(self.raw.as_ptr()) };
+  }
+}
+
+/// A buffer shared through dmabuf file descriptors
+#[derive(Debug)]
+pub struct Dmabuf {
+  pub width: u32,
+  pub height: u32,
+  /// DRM fourcc
+  pub format: u32,
+  /// `DRM_FORMAT_MOD_INVALID` for an implicit layout
+  pub modifier: u64,
+  pub planes: Vec<DmabufPlane>,
+}
+
+#[derive(Debug)]
+pub struct DmabufPlane {
+  pub fd: OwnedFd,
+  pub offset: u32,
+  pub stride: u32,
+}
@@ -0,0 +1,132 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use glutin::api::egl::device::Device;
+use wayland_client::Connection;
+
+use crate::wayland::dmabuf::query_main_device;
+
+/// A GPU enumerated through EGL_EXT_device_enumeration
+#[derive(Debug, Clone)]
+pub struct Gpu {
+  pub name: Option<&'static str>,
+  pub render_node: PathBuf,
+  pub primary_node: Option<PathBuf>,
+}
+
+impl Gpu {
+  /// Whether `dev` (a `st_rdev`) is one of the GPU's device nodes.
+  fn has_node(&self, dev: u64) -> bool {
+    [Some(&self.render_node), self.primary_node.as_ref()]
+      .into_iter()
+      .flatten()
+      .any(|node| std::fs::metadata(node).is_ok_and(|metadata| metadata.rdev() == dev))
+  }
+
+  /// The `DRI_PRIME` value selecting this GPU in Mesa, from its PCI address.
+  fn dri_prime_tag(&self) -> Result<String> {
+    let node_name = self
+      .render_node
+      .file_name()
+      .context("invalid render node")?;
+    let device = Path::new("/sys/class/drm").join(node_name).join("device");
+    let device =
+      std::fs::canonicalize(&device).with_context(|| format!("failed to resolve {:?}", device))?;
+    if !device.starts_with("/sys/devices/pci") {
+      anyhow::bail!("{:?} is not a PCI device", self.render_node);
+    }
+    let pci_address = device
+      .file_name()
+      .and_then(|name| name.to_str())
+      .context("invalid PCI address")?;
+    Ok(format!("pci-{}", pci_address.replace([':', '.'], "_")))
+  }
+}
+
+impl std::fmt::Display for Gpu {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.render_node.display())?;
+    if let Some(name) = self.name {
+      write!(f, " ({})", name)?;
+    }
+    Ok(())
+  }
+}
+
+/// GPUs with a render node. Empty if the EGL implementation can't enumerate devices.
+pub fn enumerate() -> Vec<Gpu> {
+  let devices = match Device::query_devices() {
+    Ok(devices) => devices,
+    Err(e) => {
+      log::debug!("Can't enumerate EGL devices: {}", e);
+      return Vec::new();
+    }
+  };
+  devices
+    .filter_map(|device| {
+      Some(Gpu {
+        name: device.name(),
+        render_node: device.drm_render_device_node_path()?.to_owned(),
+        primary_node: device.drm_device_node_path().map(Path::to_owned),
+      })
+    })
+    .collect()
+}
+
+/// Pick the GPU to render on: `requested` (a DRM device node), or else the device the
+/// compositor composites on, so that frames don't cross GPUs.
+///
+/// Returns the render node of the chosen GPU, or `None` to leave it to the EGL
+/// implementation.
+///
+/// # Safety
+///
+/// Modifies the environment, so no other thread may be running.
+pub unsafe fn select(conn: &Connection, requested: Option<&Path>) -> Result<Option<PathBuf>> {
+  let gpus = enumerate();
+  for gpu in &gpus {
+    log::info!("GPU: {}", gpu);
+  }
+
+  let gpu = match requested {
+    Some(requested) => {
+      let dev = std::fs::metadata(requested)
+        .with_context(|| format!("failed to stat {:?}", requested))?
+        .rdev();
+      gpus
+        .iter()
+        .find(|gpu| gpu.has_node(dev))
+        .with_context(|| format!("{:?} is not a GPU known to EGL", requested))?
+    }
+    // chosen by the user already
+    None if std::env::var_os("DRI_PRIME").is_some() => return Ok(None),
+    None if gpus.len() > 1 => {
+      let main_device = query_main_device(conn).unwrap_or_else(|e| {
+        log::debug!("Failed to query the compositor's main device: {:#}", e);
+        None
+      });
+      match main_device.and_then(|dev| gpus.iter().find(|gpu| gpu.has_node(dev))) {
+        Some(gpu) => gpu,
+        None => return Ok(None),
+      }
+    }
+    None => return Ok(None),
+  };
+
+  // Mesa has no other way to choose the device of a Wayland EGL display
+  match gpu.dri_prime_tag() {
+    Ok(tag) => {
+      log::info!("Rendering on {}", gpu);
+      unsafe { std::env::set_var("DRI_PRIME", tag) };
+    }
+    Err(e) if requested.is_some() => return Err(e.context(format!("can't select {}", gpu))),
+    Err(e) => {
+      log::debug!("Can't select {}: {:#}", gpu, e);
+      return Ok(None);
+    }
+  }
+  Ok(Some(gpu.render_node.clone()))
+}
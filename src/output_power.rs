@@ -0,0 +1,140 @@
+//! Turning outputs off and on from Dart, e.g. for a "turn off screens" button in a bar, over
+//! wlr-output-power-management-unstable-v1.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::Value;
+use serde_json::json;
+use wayland_client::protocol::wl_output::WlOutput;
+
+use crate::FlutterEngine;
+use crate::channel::MethodCall;
+use crate::channel::MethodError;
+use crate::channel::MethodResult;
+use crate::wayland::Output;
+use crate::wayland::output_power::OutputPower;
+use crate::wayland::output_power::OutputPowerManager;
+
+/// Method channel (`MethodChannel` with `JSONMethodCodec` on the Dart side)
+pub const CHANNEL: &str = "wayflutter/output_power";
+
+/// The power modes of the outputs, tracked from when they're connected
+pub struct OutputPowers {
+  /// `None` if the compositor doesn't support wlr-output-power-management-unstable-v1
+  manager: Option<OutputPowerManager>,
+  /// by display id
+  powers: Mutex<HashMap<u64, (WlOutput, OutputPower)>>,
+}
+
+impl OutputPowers {
+  pub fn new(manager: Option<OutputPowerManager>) -> Self {
+    Self {
+      manager,
+      powers: Mutex::default(),
+    }
+  }
+
+  /// Track the power mode of `outputs` not tracked yet. The compositor tells them with the
+  /// next dispatch.
+  pub fn track(&self, outputs: &[Output]) {
+    let Some(manager) = &self.manager else {
+      return;
+    };
+    let mut powers = self.powers.lock();
+    for output in outputs {
+      powers.entry(output.display_id).or_insert_with(|| {
+        (
+          output.wl_output.clone(),
+          manager.get_output_power(&output.wl_output),
+        )
+      });
+    }
+  }
+
+  /// Must be called on the platform thread.
+  pub fn output_removed(&self, wl_output: &WlOutput) {
+    self
+      .powers
+      .lock()
+      .retain(|_, (output, _)| output != wl_output);
+  }
+
+  fn set_on(&self, outputs: &[Output], on: bool) -> Result<()> {
+    self
+      .manager
+      .as_ref()
+      .context("wlr-output-power-management-unstable-v1 is not supported by the compositor")?;
+    // another client may have taken over since
+    self.powers.lock().retain(|_, (_, power)| !power.failed());
+    self.track(outputs);
+    let powers = self.powers.lock();
+    for output in outputs {
+      if let Some((_, power)) = powers.get(&output.display_id) {
+        power.set_on(on);
+      }
+    }
+    Ok(())
+  }
+
+  /// `None` if unknown
+  fn is_on(&self, output: &Output) -> Option<bool> {
+    let powers = self.powers.lock();
+    let (_, power) = powers.get(&output.display_id)?;
+    (!power.failed()).then(|| power.is_on()).flatten()
+  }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SetModeArgs {
+  /// see [`LayerProps::output`], every output if `None`
+  ///
+  /// [`LayerProps::output`]: crate::layer::LayerProps::output
+  output: Option<String>,
+  on: bool,
+}
+
+pub fn handle_method_call(engine: &FlutterEngine, call: &MethodCall) -> Option<MethodResult> {
+  match call.method.as_str() {
+    "setMode" => Some(set_mode(engine, call)),
+    "getModes" => Some(get_modes(engine)),
+    _ => None,
+  }
+}
+
+/// e.g. `{"on": false}` to turn every output off
+fn set_mode(engine: &FlutterEngine, call: &MethodCall) -> MethodResult {
+  let args: SetModeArgs = call.args()?;
+  let state = engine.state();
+  let outputs = match &args.output {
+    Some(name) => vec![state.compositor.find_output(name)?],
+    None => state.compositor.outputs(),
+  };
+  state
+    .output_powers
+    .set_on(&outputs, args.on)
+    .map_err(|e| MethodError::new("unsupported", format!("{:#}", e)))?;
+  Ok(Value::Null)
+}
+
+/// `[{"output", "displayId", "on"}]` for every connected output, `on` being `null` if unknown
+fn get_modes(engine: &FlutterEngine) -> MethodResult {
+  let state = engine.state();
+  let modes = state
+    .compositor
+    .outputs()
+    .iter()
+    .map(|output| {
+      json!({
+        "output": output.name,
+        "displayId": output.display_id,
+        "on": state.output_powers.is_on(output),
+      })
+    })
+    .collect();
+  Ok(Value::Array(modes))
+}
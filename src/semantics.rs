@@ -0,0 +1,619 @@
+//! The semantics tree of the app, which describes its widgets for assistive technologies, mirrored
+//! from the engine's updates.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::ffi::c_char;
+use std::sync::Arc;
+#[cfg(feature = "dbus")]
+use std::sync::OnceLock;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde_json::Value;
+use serde_json::json;
+use smol::channel::Sender;
+
+use crate::FlutterEngine;
+use crate::FlutterEngineState;
+use crate::compositor::ViewId;
+use crate::ffi;
+
+#[cfg(feature = "dbus")]
+mod atspi;
+
+/// Id of the root node, which covers the implicit view
+pub const ROOT: i32 = 0;
+
+/// A boolean property of a [`SemanticsNode`], after `SemanticsFlag` of the framework
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SemanticsFlag {
+  HasCheckedState = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagHasCheckedState,
+  IsChecked = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsChecked,
+  IsSelected = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsSelected,
+  IsButton = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsButton,
+  IsTextField = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsTextField,
+  IsFocused = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsFocused,
+  HasEnabledState = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagHasEnabledState,
+  IsEnabled = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsEnabled,
+  IsInMutuallyExclusiveGroup =
+    ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsInMutuallyExclusiveGroup,
+  IsHeader = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsHeader,
+  IsObscured = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsObscured,
+  ScopesRoute = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagScopesRoute,
+  NamesRoute = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagNamesRoute,
+  IsHidden = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsHidden,
+  IsImage = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsImage,
+  IsLiveRegion = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsLiveRegion,
+  HasToggledState = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagHasToggledState,
+  IsToggled = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsToggled,
+  HasImplicitScrolling = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagHasImplicitScrolling,
+  IsMultiline = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsMultiline,
+  IsReadOnly = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsReadOnly,
+  IsFocusable = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsFocusable,
+  IsLink = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsLink,
+  IsSlider = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsSlider,
+  IsKeyboardKey = ffi::FlutterSemanticsFlag_kFlutterSemanticsFlagIsKeyboardKey,
+}
+
+impl SemanticsFlag {
+  pub const ALL: [Self; 25] = [
+    Self::HasCheckedState,
+    Self::IsChecked,
+    Self::IsSelected,
+    Self::IsButton,
+    Self::IsTextField,
+    Self::IsFocused,
+    Self::HasEnabledState,
+    Self::IsEnabled,
+    Self::IsInMutuallyExclusiveGroup,
+    Self::IsHeader,
+    Self::IsObscured,
+    Self::ScopesRoute,
+    Self::NamesRoute,
+    Self::IsHidden,
+    Self::IsImage,
+    Self::IsLiveRegion,
+    Self::HasToggledState,
+    Self::IsToggled,
+    Self::HasImplicitScrolling,
+    Self::IsMultiline,
+    Self::IsReadOnly,
+    Self::IsFocusable,
+    Self::IsLink,
+    Self::IsSlider,
+    Self::IsKeyboardKey,
+  ];
+
+  /// As the framework names it, like `isButton`
+  pub fn name(self) -> String {
+    lower_first(&format!("{:?}", self))
+  }
+}
+
+/// What can be done to a [`SemanticsNode`], after `SemanticsAction` of the framework
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SemanticsAction {
+  Tap = ffi::FlutterSemanticsAction_kFlutterSemanticsActionTap,
+  LongPress = ffi::FlutterSemanticsAction_kFlutterSemanticsActionLongPress,
+  ScrollLeft = ffi::FlutterSemanticsAction_kFlutterSemanticsActionScrollLeft,
+  ScrollRight = ffi::FlutterSemanticsAction_kFlutterSemanticsActionScrollRight,
+  ScrollUp = ffi::FlutterSemanticsAction_kFlutterSemanticsActionScrollUp,
+  ScrollDown = ffi::FlutterSemanticsAction_kFlutterSemanticsActionScrollDown,
+  Increase = ffi::FlutterSemanticsAction_kFlutterSemanticsActionIncrease,
+  Decrease = ffi::FlutterSemanticsAction_kFlutterSemanticsActionDecrease,
+  ShowOnScreen = ffi::FlutterSemanticsAction_kFlutterSemanticsActionShowOnScreen,
+  MoveCursorForwardByCharacter =
+    ffi::FlutterSemanticsAction_kFlutterSemanticsActionMoveCursorForwardByCharacter,
+  MoveCursorBackwardByCharacter =
+    ffi::FlutterSemanticsAction_kFlutterSemanticsActionMoveCursorBackwardByCharacter,
+  SetSelection = ffi::FlutterSemanticsAction_kFlutterSemanticsActionSetSelection,
+  Copy = ffi::FlutterSemanticsAction_kFlutterSemanticsActionCopy,
+  Cut = ffi::FlutterSemanticsAction_kFlutterSemanticsActionCut,
+  Paste = ffi::FlutterSemanticsAction_kFlutterSemanticsActionPaste,
+  DidGainAccessibilityFocus =
+    ffi::FlutterSemanticsAction_kFlutterSemanticsActionDidGainAccessibilityFocus,
+  DidLoseAccessibilityFocus =
+    ffi::FlutterSemanticsAction_kFlutterSemanticsActionDidLoseAccessibilityFocus,
+  CustomAction = ffi::FlutterSemanticsAction_kFlutterSemanticsActionCustomAction,
+  Dismiss = ffi::FlutterSemanticsAction_kFlutterSemanticsActionDismiss,
+  MoveCursorForwardByWord =
+    ffi::FlutterSemanticsAction_kFlutterSemanticsActionMoveCursorForwardByWord,
+  MoveCursorBackwardByWord =
+    ffi::FlutterSemanticsAction_kFlutterSemanticsActionMoveCursorBackwardByWord,
+  SetText = ffi::FlutterSemanticsAction_kFlutterSemanticsActionSetText,
+  Focus = ffi::FlutterSemanticsAction_kFlutterSemanticsActionFocus,
+}
+
+impl SemanticsAction {
+  pub const ALL: [Self; 23] = [
+    Self::Tap,
+    Self::LongPress,
+    Self::ScrollLeft,
+    Self::ScrollRight,
+    Self::ScrollUp,
+    Self::ScrollDown,
+    Self::Increase,
+    Self::Decrease,
+    Self::ShowOnScreen,
+    Self::MoveCursorForwardByCharacter,
+    Self::MoveCursorBackwardByCharacter,
+    Self::SetSelection,
+    Self::Copy,
+    Self::Cut,
+    Self::Paste,
+    Self::DidGainAccessibilityFocus,
+    Self::DidLoseAccessibilityFocus,
+    Self::CustomAction,
+    Self::Dismiss,
+    Self::MoveCursorForwardByWord,
+    Self::MoveCursorBackwardByWord,
+    Self::SetText,
+    Self::Focus,
+  ];
+
+  /// As the framework names it, like `longPress`
+  pub fn name(self) -> String {
+    lower_first(&format!("{:?}", self))
+  }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rect {
+  pub left: f64,
+  pub top: f64,
+  pub right: f64,
+  pub bottom: f64,
+}
+
+impl Rect {
+  fn from_points(points: [(f64, f64); 4]) -> Self {
+    let (xs, ys) = (points.map(|(x, _)| x), points.map(|(_, y)| y));
+    Self {
+      left: xs.into_iter().fold(f64::INFINITY, f64::min),
+      top: ys.into_iter().fold(f64::INFINITY, f64::min),
+      right: xs.into_iter().fold(f64::NEG_INFINITY, f64::max),
+      bottom: ys.into_iter().fold(f64::NEG_INFINITY, f64::max),
+    }
+  }
+
+  fn corners(&self) -> [(f64, f64); 4] {
+    [
+      (self.left, self.top),
+      (self.right, self.top),
+      (self.right, self.bottom),
+      (self.left, self.bottom),
+    ]
+  }
+}
+
+/// What a [`SemanticsNode`] is, from its flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+  Button,
+  TextField,
+  PasswordField,
+  Slider,
+  Link,
+  Image,
+  Header,
+  RadioButton,
+  CheckBox,
+  ToggleButton,
+  /// text
+  Label,
+  /// none of the above
+  Group,
+}
+
+/// A widget, or a group of them, as seen by assistive technologies
+#[derive(Debug, Clone)]
+pub struct SemanticsNode {
+  pub id: i32,
+  flags: ffi::FlutterSemanticsFlag,
+  /// the actions the node supports
+  actions: ffi::FlutterSemanticsAction,
+  pub label: String,
+  pub hint: String,
+  pub value: String,
+  pub tooltip: String,
+  /// in the node's own coordinates
+  pub rect: Rect,
+  /// from the node's coordinates to its parent's, row-major 3x3
+  pub transform: [f64; 9],
+  /// in traversal order
+  pub children: Vec<i32>,
+}
+
+impl SemanticsNode {
+  /// # Safety
+  ///
+  /// `node` must be valid, like during the update callback.
+  pub(crate) unsafe fn from_raw(node: &ffi::FlutterSemanticsNode2) -> Self {
+    let t = &node.transform;
+    let children = if node.child_count == 0 {
+      Vec::new()
+    } else {
+      unsafe { std::slice::from_raw_parts(node.children_in_traversal_order, node.child_count) }
+        .to_vec()
+    };
+    unsafe {
+      Self {
+        id: node.id,
+        flags: node.flags,
+        actions: node.actions,
+        label: string(node.label),
+        hint: string(node.hint),
+        value: string(node.value),
+        tooltip: string(node.tooltip),
+        rect: Rect {
+          left: node.rect.left,
+          top: node.rect.top,
+          right: node.rect.right,
+          bottom: node.rect.bottom,
+        },
+        transform: [
+          t.scaleX, t.skewX, t.transX, t.skewY, t.scaleY, t.transY, t.pers0, t.pers1, t.pers2,
+        ],
+        children,
+      }
+    }
+  }
+
+  pub fn has_flag(&self, flag: SemanticsFlag) -> bool {
+    self.flags & flag as ffi::FlutterSemanticsFlag != 0
+  }
+
+  pub fn supports(&self, action: SemanticsAction) -> bool {
+    self.actions & action as ffi::FlutterSemanticsAction != 0
+  }
+
+  pub fn role(&self) -> Role {
+    let flag = |flag| self.has_flag(flag);
+    if flag(SemanticsFlag::IsButton) {
+      Role::Button
+    } else if flag(SemanticsFlag::IsTextField) {
+      if flag(SemanticsFlag::IsObscured) {
+        Role::PasswordField
+      } else {
+        Role::TextField
+      }
+    } else if flag(SemanticsFlag::IsSlider) {
+      Role::Slider
+    } else if flag(SemanticsFlag::IsLink) {
+      Role::Link
+    } else if flag(SemanticsFlag::IsImage) {
+      Role::Image
+    } else if flag(SemanticsFlag::IsHeader) {
+      Role::Header
+    } else if flag(SemanticsFlag::HasCheckedState) {
+      if flag(SemanticsFlag::IsInMutuallyExclusiveGroup) {
+        Role::RadioButton
+      } else {
+        Role::CheckBox
+      }
+    } else if flag(SemanticsFlag::HasToggledState) {
+      Role::ToggleButton
+    } else if !self.label.is_empty() {
+      Role::Label
+    } else {
+      Role::Group
+    }
+  }
+
+  fn to_parent(&self, (x, y): (f64, f64)) -> (f64, f64) {
+    let [sx, kx, tx, ky, sy, ty, p0, p1, p2] = self.transform;
+    let w = p0 * x + p1 * y + p2;
+    let w = if w == 0.0 { 1.0 } else { w };
+    ((sx * x + kx * y + tx) / w, (ky * x + sy * y + ty) / w)
+  }
+}
+
+fn lower_first(name: &str) -> String {
+  let mut chars = name.chars();
+  chars
+    .next()
+    .map(|first| first.to_lowercase().chain(chars).collect())
+    .unwrap_or_default()
+}
+
+/// Empty for null
+unsafe fn string(ptr: *const c_char) -> String {
+  if ptr.is_null() {
+    return String::new();
+  }
+  unsafe { CStr::from_ptr(ptr) }
+    .to_string_lossy()
+    .into_owned()
+}
+
+/// A change of the semantics tree of the app, see [`Embedder::semantics_updates`]
+///
+/// [`Embedder::semantics_updates`]: crate::Embedder::semantics_updates
+#[derive(Debug, Clone)]
+pub struct SemanticsUpdate {
+  /// added or changed, as they are now
+  pub nodes: Vec<SemanticsNode>,
+  /// ids of the nodes no longer in the tree
+  pub removed: Vec<i32>,
+}
+
+/// What an update changed in the [`SemanticsTree`]
+#[derive(Debug, Default)]
+pub struct Changes {
+  pub added: Vec<i32>,
+  /// with their former parents
+  pub removed: Vec<(i32, Option<i32>)>,
+  /// whose label changed
+  pub renamed: Vec<i32>,
+  /// the node that just got the keyboard focus
+  pub focused: Option<i32>,
+}
+
+#[derive(Debug)]
+pub struct SemanticsTree {
+  nodes: HashMap<i32, SemanticsNode>,
+  parents: HashMap<i32, i32>,
+  /// physical pixels per surface coordinate of the implicit view
+  scale: f64,
+}
+
+impl Default for SemanticsTree {
+  fn default() -> Self {
+    Self {
+      nodes: HashMap::new(),
+      parents: HashMap::new(),
+      scale: 1.0,
+    }
+  }
+}
+
+impl SemanticsTree {
+  pub fn get(&self, id: i32) -> Option<&SemanticsNode> {
+    self.nodes.get(&id)
+  }
+
+  pub fn parent(&self, id: i32) -> Option<i32> {
+    self.parents.get(&id).copied()
+  }
+
+  /// Replace the updated nodes, and drop the ones no longer reachable from the root.
+  pub fn apply(&mut self, nodes: Vec<SemanticsNode>) -> Changes {
+    let mut changes = Changes::default();
+    for node in nodes {
+      let id = node.id;
+      let focused = node.has_flag(SemanticsFlag::IsFocused);
+      match self.nodes.insert(id, node) {
+        Some(old) => {
+          if old.label != self.nodes[&id].label {
+            changes.renamed.push(id);
+          }
+          if focused && !old.has_flag(SemanticsFlag::IsFocused) {
+            changes.focused = Some(id);
+          }
+        }
+        None => {
+          changes.added.push(id);
+          if focused {
+            changes.focused = Some(id);
+          }
+        }
+      }
+    }
+
+    let old_parents = std::mem::take(&mut self.parents);
+    let mut reachable = HashSet::new();
+    let mut stack = vec![ROOT];
+    while let Some(id) = stack.pop() {
+      let Some(node) = self.nodes.get(&id) else {
+        continue;
+      };
+      if !reachable.insert(id) {
+        continue;
+      }
+      for &child in &node.children {
+        self.parents.insert(child, id);
+        stack.push(child);
+      }
+    }
+    self.nodes.retain(|id, _| {
+      let keep = reachable.contains(id);
+      if !keep {
+        changes.removed.push((*id, old_parents.get(id).copied()));
+      }
+      keep
+    });
+    changes.added.retain(|id| reachable.contains(id));
+    changes.renamed.retain(|id| reachable.contains(id));
+    changes.focused = changes.focused.filter(|id| reachable.contains(id));
+    changes
+  }
+
+  /// Drop all nodes, as if removed by an update.
+  pub fn clear(&mut self) -> Changes {
+    let removed = self.nodes.keys().map(|&id| (id, self.parent(id))).collect();
+    self.nodes.clear();
+    self.parents.clear();
+    Changes {
+      removed,
+      ..Default::default()
+    }
+  }
+
+  /// The bounding box of `id` in surface coordinates of the view
+  pub fn bounds(&self, id: i32) -> Option<Rect> {
+    let node = self.nodes.get(&id)?;
+    let mut corners = node.rect.corners();
+    let mut current = Some(node);
+    while let Some(node) = current {
+      corners = corners.map(|point| node.to_parent(point));
+      current = self
+        .parent(node.id)
+        .and_then(|parent| self.nodes.get(&parent));
+    }
+    Some(Rect::from_points(
+      corners.map(|(x, y)| (x / self.scale, y / self.scale)),
+    ))
+  }
+}
+
+/// The semantics of the app and what mirrors it
+pub struct Semantics {
+  tree: Arc<Mutex<SemanticsTree>>,
+  /// whether the engine sends updates
+  enabled: AtomicBool,
+  /// see [`Embedder::semantics_updates`](crate::Embedder::semantics_updates)
+  listeners: Vec<Sender<SemanticsUpdate>>,
+  #[cfg(feature = "dbus")]
+  bridge: OnceLock<atspi::Bridge>,
+}
+
+impl Semantics {
+  pub fn new(listeners: Vec<Sender<SemanticsUpdate>>) -> Self {
+    Self {
+      tree: Arc::default(),
+      enabled: AtomicBool::new(false),
+      listeners,
+      #[cfg(feature = "dbus")]
+      bridge: OnceLock::new(),
+    }
+  }
+}
+
+/// Have the engine send semantics updates if anyone listens: AT-SPI, e.g. for Orca, if the
+/// desktop has accessibility turned on, or the program embedding the app.
+pub fn enable(engine: &FlutterEngine) -> Result<()> {
+  let state = engine.state();
+  #[cfg(feature = "dbus")]
+  match atspi::Bridge::connect(
+    state.semantics.tree.clone(),
+    state.task_runner_handle.clone(),
+  ) {
+    Ok(Some(bridge)) => {
+      let _ = state.semantics.bridge.set(bridge);
+    }
+    Ok(None) => log::debug!("accessibility is turned off on the desktop"),
+    Err(e) => log::warn!("assistive technologies can't see the app: {:#}", e),
+  }
+  #[cfg(feature = "dbus")]
+  let bridged = state.semantics.bridge.get().is_some();
+  #[cfg(not(feature = "dbus"))]
+  let bridged = false;
+  if bridged || !state.semantics.listeners.is_empty() {
+    turn_on(engine)?;
+  }
+  Ok(())
+}
+
+/// Have the engine send semantics updates from now on. Returns whether they were off, in which
+/// case the tree fills with the next update.
+pub fn turn_on(engine: &FlutterEngine) -> Result<bool> {
+  let state = engine.state();
+  if state.semantics.enabled.swap(true, Ordering::Relaxed) {
+    return Ok(false);
+  }
+  if let Err(e) = engine.update_semantics_enabled(true) {
+    state.semantics.enabled.store(false, Ordering::Relaxed);
+    return Err(e);
+  }
+  Ok(true)
+}
+
+/// The tree from the root, with bounds in surface coordinates of the implicit view. `null` if
+/// it's empty.
+pub fn dump(state: &FlutterEngineState) -> Value {
+  let tree = state.semantics.tree.lock();
+  dump_node(&tree, ROOT)
+}
+
+fn dump_node(tree: &SemanticsTree, id: i32) -> Value {
+  let Some(node) = tree.get(id) else {
+    return Value::Null;
+  };
+  let bounds = tree.bounds(id).unwrap_or_default();
+  let flags = SemanticsFlag::ALL
+    .into_iter()
+    .filter(|&flag| node.has_flag(flag))
+    .map(SemanticsFlag::name)
+    .collect::<Vec<_>>();
+  let actions = SemanticsAction::ALL
+    .into_iter()
+    .filter(|&action| node.supports(action))
+    .map(SemanticsAction::name)
+    .collect::<Vec<_>>();
+  json!({
+    "id": id,
+    "role": lower_first(&format!("{:?}", node.role())),
+    "label": node.label,
+    "hint": node.hint,
+    "value": node.value,
+    "tooltip": node.tooltip,
+    "rect": {
+      "left": bounds.left,
+      "top": bounds.top,
+      "right": bounds.right,
+      "bottom": bounds.bottom,
+    },
+    "flags": flags,
+    "actions": actions,
+    "children": node
+      .children
+      .iter()
+      .map(|&child| dump_node(tree, child))
+      .collect::<Vec<_>>(),
+  })
+}
+
+/// From the update callback, on the platform thread.
+pub fn update(state: &FlutterEngineState, nodes: Vec<SemanticsNode>) {
+  let scale = state
+    .compositor
+    .get_view(ViewId::new(0))
+    .and_then(|view| view.buffer_scale())
+    .unwrap_or(1.0);
+  let listened = !state.semantics.listeners.is_empty();
+  let updated = listened.then(|| nodes.clone());
+  let changes = {
+    let mut tree = state.semantics.tree.lock();
+    tree.scale = scale;
+    tree.apply(nodes)
+  };
+  notify(state, updated, &changes);
+}
+
+/// Forget the tree before a hot restart, as the new isolate builds it again with fresh ids.
+pub fn reset(state: &FlutterEngineState) {
+  let changes = state.semantics.tree.lock().clear();
+  let listened = !state.semantics.listeners.is_empty();
+  notify(state, listened.then(Vec::new), &changes);
+}
+
+/// Tell the listeners and the bridge. `updated` is `None` if nobody listens.
+fn notify(state: &FlutterEngineState, updated: Option<Vec<SemanticsNode>>, changes: &Changes) {
+  if let Some(mut nodes) = updated {
+    let removed = changes
+      .removed
+      .iter()
+      .map(|(id, _)| *id)
+      .collect::<Vec<_>>();
+    nodes.retain(|node| !removed.contains(&node.id));
+    let update = SemanticsUpdate { nodes, removed };
+    for listener in &state.semantics.listeners {
+      // or it stopped listening
+      let _ = listener.try_send(update.clone());
+    }
+  }
+  #[cfg(feature = "dbus")]
+  if let Some(bridge) = state.semantics.bridge.get()
+    && let Err(e) = bridge.update(changes)
+  {
+    log::warn!("failed to update the accessibility tree: {:#}", e);
+  }
+  #[cfg(not(feature = "dbus"))]
+  let _ = changes;
+}
@@ -0,0 +1,808 @@
+//! The AT-SPI2 side of the semantics tree: every node is an accessible object on the
+//! accessibility bus, under an application object embedded into the desktop's registry.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use anyhow::Result;
+use parking_lot::Mutex;
+use zbus::blocking::Connection;
+use zbus::blocking::connection;
+use zbus::interface;
+use zbus::names::BusName;
+use zbus::zvariant::ObjectPath;
+use zbus::zvariant::OwnedObjectPath;
+use zbus::zvariant::OwnedValue;
+use zbus::zvariant::Value;
+
+use super::Role;
+use super::SemanticsAction;
+use super::SemanticsFlag;
+use super::SemanticsNode;
+use super::SemanticsTree;
+use crate::task_runner::TaskRunnerHandle;
+
+const ROOT_PATH: &str = "/org/a11y/atspi/accessible/root";
+const REGISTRY: &str = "org.a11y.atspi.Registry";
+const EVENT_OBJECT: &str = "org.a11y.atspi.Event.Object";
+
+// AtspiRole
+const ROLE_CHECK_BOX: u32 = 7;
+const ROLE_IMAGE: u32 = 27;
+const ROLE_LABEL: u32 = 29;
+const ROLE_PANEL: u32 = 39;
+const ROLE_PASSWORD_TEXT: u32 = 40;
+const ROLE_PUSH_BUTTON: u32 = 43;
+const ROLE_RADIO_BUTTON: u32 = 44;
+const ROLE_SLIDER: u32 = 51;
+const ROLE_TOGGLE_BUTTON: u32 = 62;
+const ROLE_APPLICATION: u32 = 75;
+const ROLE_ENTRY: u32 = 79;
+const ROLE_HEADING: u32 = 83;
+const ROLE_LINK: u32 = 88;
+
+// AtspiStateType, bits of two u32s
+const STATE_CHECKED: u32 = 4;
+const STATE_EDITABLE: u32 = 7;
+const STATE_ENABLED: u32 = 8;
+const STATE_FOCUSABLE: u32 = 11;
+const STATE_FOCUSED: u32 = 12;
+const STATE_MULTI_LINE: u32 = 17;
+const STATE_PRESSED: u32 = 20;
+const STATE_SELECTABLE: u32 = 22;
+const STATE_SELECTED: u32 = 23;
+const STATE_SENSITIVE: u32 = 24;
+const STATE_SHOWING: u32 = 25;
+const STATE_SINGLE_LINE: u32 = 26;
+const STATE_VISIBLE: u32 = 30;
+const STATE_CHECKABLE: u32 = 41;
+const STATE_READ_ONLY: u32 = 43;
+
+// AtspiCoordType
+const COORD_TYPE_PARENT: u32 = 2;
+
+// AtspiComponentLayer
+const LAYER_WIDGET: u32 = 3;
+
+/// What the `Action` interface offers of the actions a node supports, with their names and
+/// descriptions
+const ACTIONS: [(SemanticsAction, &str, &str); 10] = [
+  (SemanticsAction::Tap, "click", "Activates the widget"),
+  (
+    SemanticsAction::LongPress,
+    "long press",
+    "Presses the widget for long",
+  ),
+  (
+    SemanticsAction::Increase,
+    "increment",
+    "Increases the value",
+  ),
+  (
+    SemanticsAction::Decrease,
+    "decrement",
+    "Decreases the value",
+  ),
+  (
+    SemanticsAction::ScrollUp,
+    "scroll up",
+    "Scrolls the content up",
+  ),
+  (
+    SemanticsAction::ScrollDown,
+    "scroll down",
+    "Scrolls the content down",
+  ),
+  (
+    SemanticsAction::ScrollLeft,
+    "scroll left",
+    "Scrolls the content left",
+  ),
+  (
+    SemanticsAction::ScrollRight,
+    "scroll right",
+    "Scrolls the content right",
+  ),
+  (
+    SemanticsAction::ShowOnScreen,
+    "show",
+    "Scrolls the widget into view",
+  ),
+  (SemanticsAction::Dismiss, "dismiss", "Dismisses the widget"),
+];
+
+/// Bus name and path of an accessible object, `(so)` on the bus
+type Reference = (String, OwnedObjectPath);
+
+/// The application object for `None`, a node otherwise
+fn path(id: Option<i32>) -> ObjectPath<'static> {
+  match id {
+    None => ObjectPath::from_static_str_unchecked(ROOT_PATH),
+    Some(id) => ObjectPath::from_string_unchecked(format!("{}/{}", ROOT_PATH, id)),
+  }
+}
+
+/// What AT-SPI references nothing with
+fn null_reference() -> Reference {
+  (
+    String::new(),
+    ObjectPath::from_static_str_unchecked("/org/a11y/atspi/null").into(),
+  )
+}
+
+/// Shared by the objects on the bus
+struct Shared {
+  tree: Arc<Mutex<SemanticsTree>>,
+  bus_name: String,
+  /// the desktop's root, parent of the application object. Set once embedded.
+  registry: OnceLock<Reference>,
+  /// actions are dispatched on the platform thread
+  task_runner_handle: TaskRunnerHandle,
+}
+
+impl Shared {
+  fn reference(&self, id: Option<i32>) -> Reference {
+    (self.bus_name.clone(), path(id).into())
+  }
+}
+
+pub struct Bridge {
+  conn: Connection,
+  shared: Arc<Shared>,
+}
+
+impl Bridge {
+  /// `None` if accessibility is turned off on the desktop
+  pub fn connect(
+    tree: Arc<Mutex<SemanticsTree>>,
+    task_runner_handle: TaskRunnerHandle,
+  ) -> Result<Option<Self>> {
+    let session = Connection::session().context("failed to connect to the session bus")?;
+    let enabled: OwnedValue = session
+      .call_method(
+        Some("org.a11y.Bus"),
+        "/org/a11y/bus",
+        Some("org.freedesktop.DBus.Properties"),
+        "Get",
+        &("org.a11y.Status", "IsEnabled"),
+      )
+      .context("failed to ask whether accessibility is turned on")?
+      .body()
+      .deserialize()?;
+    if !bool::try_from(enabled)? {
+      return Ok(None);
+    }
+    let address: String = session
+      .call_method(
+        Some("org.a11y.Bus"),
+        "/org/a11y/bus",
+        Some("org.a11y.Bus"),
+        "GetAddress",
+        &(),
+      )
+      .context("failed to find the accessibility bus")?
+      .body()
+      .deserialize()?;
+    let conn = connection::Builder::address(address.as_str())?
+      .build()
+      .context("failed to connect to the accessibility bus")?;
+    let bus_name = conn
+      .unique_name()
+      .context("no unique name on the accessibility bus")?
+      .to_string();
+    let shared = Arc::new(Shared {
+      tree,
+      bus_name,
+      registry: OnceLock::new(),
+      task_runner_handle,
+    });
+    let bridge = Self { conn, shared };
+    // the registry asks about the application right away
+    bridge
+      .conn
+      .object_server()
+      .at(ROOT_PATH, Application { id: 0 })?;
+    bridge.register(None)?;
+    // a single `(so)` argument each way
+    let (registry,): (Reference,) = bridge
+      .conn
+      .call_method(
+        Some(REGISTRY),
+        ROOT_PATH,
+        Some("org.a11y.atspi.Socket"),
+        "Embed",
+        &((bridge.shared.bus_name.as_str(), path(None)),),
+      )
+      .context("failed to register with the accessibility registry")?
+      .body()
+      .deserialize()?;
+    let _ = bridge.shared.registry.set(registry);
+    log::info!("exposing the app to assistive technologies");
+    Ok(Some(bridge))
+  }
+
+  fn object(&self, id: Option<i32>) -> Object {
+    Object {
+      id,
+      shared: self.shared.clone(),
+    }
+  }
+
+  fn register(&self, id: Option<i32>) -> Result<()> {
+    let server = self.conn.object_server();
+    let path = path(id);
+    server.at(path.clone(), Accessible(self.object(id)))?;
+    if id.is_some() {
+      server.at(path.clone(), Component(self.object(id)))?;
+      server.at(path.clone(), ValueInterface(self.object(id)))?;
+      server.at(path, Action(self.object(id)))?;
+    }
+    Ok(())
+  }
+
+  fn unregister(&self, id: i32) -> Result<()> {
+    let server = self.conn.object_server();
+    let path = path(Some(id));
+    server.remove::<Accessible, _>(path.clone())?;
+    server.remove::<Component, _>(path.clone())?;
+    server.remove::<ValueInterface, _>(path.clone())?;
+    server.remove::<Action, _>(path)?;
+    Ok(())
+  }
+
+  /// Register and unregister the nodes an update added and removed, and tell listeners, like
+  /// screen readers, what changed.
+  pub fn update(&self, changes: &super::Changes) -> Result<()> {
+    for &(id, parent) in &changes.removed {
+      self.unregister(id)?;
+      // or it was never shown
+      if parent.is_some() || id == super::ROOT {
+        self.children_changed(parent, "remove", id)?;
+      }
+    }
+    for &id in &changes.added {
+      self.register(Some(id))?;
+      let parent = self.shared.tree.lock().parent(id);
+      self.children_changed(parent, "add", id)?;
+    }
+    for &id in &changes.renamed {
+      let name = Value::from(self.object(Some(id)).name());
+      self.emit(Some(id), "PropertyChange", "accessible-name", 0, name)?;
+    }
+    if let Some(id) = changes.focused {
+      self.emit(Some(id), "StateChanged", "focused", 1, Value::from(0i32))?;
+    }
+    Ok(())
+  }
+
+  /// `parent` of `None` is the application object, whose only child is the root.
+  fn children_changed(&self, parent: Option<i32>, kind: &str, child: i32) -> Result<()> {
+    let index = match parent {
+      None => 0,
+      Some(parent) => {
+        let tree = self.shared.tree.lock();
+        tree
+          .get(parent)
+          .and_then(|node| node.children.iter().position(|&id| id == child))
+          .map_or(-1, |index| index as i32)
+      }
+    };
+    let child = Value::from(self.shared.reference(Some(child)));
+    self.emit(parent, "ChildrenChanged", kind, index, child)
+  }
+
+  /// An event in the `(siiva{sv})` form of AT-SPI 2.
+  fn emit(
+    &self,
+    id: Option<i32>,
+    signal: &str,
+    kind: &str,
+    detail: i32,
+    data: Value<'_>,
+  ) -> Result<()> {
+    let properties: HashMap<&str, Value<'_>> = HashMap::new();
+    self.conn.emit_signal(
+      None::<BusName<'_>>,
+      path(id),
+      EVENT_OBJECT,
+      signal,
+      &(kind, detail, 0, data, properties),
+    )?;
+    Ok(())
+  }
+}
+
+/// An object on the bus, the application for an `id` of `None`
+struct Object {
+  id: Option<i32>,
+  shared: Arc<Shared>,
+}
+
+impl Object {
+  /// `default` if the node is gone, e.g. asked for just as an update removed it
+  fn with<T>(&self, default: T, f: impl FnOnce(&SemanticsTree, &SemanticsNode) -> T) -> T {
+    let Some(id) = self.id else {
+      return default;
+    };
+    let tree = self.shared.tree.lock();
+    match tree.get(id) {
+      Some(node) => f(&tree, node),
+      None => default,
+    }
+  }
+
+  fn children(&self) -> Vec<i32> {
+    match self.id {
+      None => {
+        let tree = self.shared.tree.lock();
+        tree
+          .get(super::ROOT)
+          .map(|_| vec![super::ROOT])
+          .unwrap_or_default()
+      }
+      Some(_) => self.with(Vec::new(), |_, node| node.children.clone()),
+    }
+  }
+
+  fn name(&self) -> String {
+    match self.id {
+      None => "wayflutter".to_owned(),
+      Some(_) => self.with(String::new(), |_, node| node.label.clone()),
+    }
+  }
+
+  fn role(&self) -> u32 {
+    match self.id {
+      None => ROLE_APPLICATION,
+      Some(_) => self.with(ROLE_PANEL, |_, node| role(node)),
+    }
+  }
+
+  /// Have the framework carry out `action` on the node, if it supports it. Whether it does.
+  fn dispatch(&self, action: SemanticsAction) -> bool {
+    let Some(id) = self.id else {
+      return false;
+    };
+    if !self.with(false, |_, node| node.supports(action)) {
+      return false;
+    }
+    let posted = self.shared.task_runner_handle.post_task(move |engine| {
+      if let Err(e) = engine.dispatch_semantics_action(id, action, &[]) {
+        log::warn!("failed to dispatch a semantics action to {}: {:#}", id, e);
+      }
+    });
+    posted.is_ok()
+  }
+
+  /// The entries of [`ACTIONS`] the node supports
+  fn actions(&self) -> Vec<(SemanticsAction, &'static str, &'static str)> {
+    self.with(Vec::new(), |_, node| {
+      ACTIONS
+        .into_iter()
+        .filter(|(action, _, _)| node.supports(*action))
+        .collect()
+    })
+  }
+
+  /// in surface coordinates of the view
+  fn extents(&self, coord_type: u32) -> (i32, i32, i32, i32) {
+    self.with((0, 0, 0, 0), |tree, node| {
+      let Some(bounds) = tree.bounds(node.id) else {
+        return (0, 0, 0, 0);
+      };
+      // there are no screen coordinates on Wayland, so those are the view's too
+      let (x, y) = match tree.parent(node.id).and_then(|parent| tree.bounds(parent)) {
+        Some(parent) if coord_type == COORD_TYPE_PARENT => (parent.left, parent.top),
+        _ => (0.0, 0.0),
+      };
+      (
+        (bounds.left - x).round() as i32,
+        (bounds.top - y).round() as i32,
+        (bounds.right - bounds.left).round() as i32,
+        (bounds.bottom - bounds.top).round() as i32,
+      )
+    })
+  }
+}
+
+fn role(node: &SemanticsNode) -> u32 {
+  match node.role() {
+    Role::Button => ROLE_PUSH_BUTTON,
+    Role::TextField => ROLE_ENTRY,
+    Role::PasswordField => ROLE_PASSWORD_TEXT,
+    Role::Slider => ROLE_SLIDER,
+    Role::Link => ROLE_LINK,
+    Role::Image => ROLE_IMAGE,
+    Role::Header => ROLE_HEADING,
+    Role::RadioButton => ROLE_RADIO_BUTTON,
+    Role::CheckBox => ROLE_CHECK_BOX,
+    Role::ToggleButton => ROLE_TOGGLE_BUTTON,
+    Role::Label => ROLE_LABEL,
+    Role::Group => ROLE_PANEL,
+  }
+}
+
+fn role_name(role: u32) -> &'static str {
+  match role {
+    ROLE_CHECK_BOX => "check box",
+    ROLE_IMAGE => "image",
+    ROLE_LABEL => "label",
+    ROLE_PASSWORD_TEXT => "password text",
+    ROLE_PUSH_BUTTON => "push button",
+    ROLE_RADIO_BUTTON => "radio button",
+    ROLE_SLIDER => "slider",
+    ROLE_TOGGLE_BUTTON => "toggle button",
+    ROLE_APPLICATION => "application",
+    ROLE_ENTRY => "entry",
+    ROLE_HEADING => "heading",
+    ROLE_LINK => "link",
+    _ => "panel",
+  }
+}
+
+fn states(node: &SemanticsNode) -> Vec<u32> {
+  let flag = |flag| node.has_flag(flag);
+  let text_field = flag(SemanticsFlag::IsTextField);
+  let read_only = flag(SemanticsFlag::IsReadOnly);
+  let disabled = flag(SemanticsFlag::HasEnabledState) && !flag(SemanticsFlag::IsEnabled);
+  let toggled = flag(SemanticsFlag::IsToggled);
+  let multiline = flag(SemanticsFlag::IsMultiline);
+  let states = [
+    (!disabled, STATE_ENABLED),
+    (!disabled, STATE_SENSITIVE),
+    (!flag(SemanticsFlag::IsHidden), STATE_VISIBLE),
+    (!flag(SemanticsFlag::IsHidden), STATE_SHOWING),
+    (
+      text_field || flag(SemanticsFlag::IsFocusable),
+      STATE_FOCUSABLE,
+    ),
+    (flag(SemanticsFlag::IsFocused), STATE_FOCUSED),
+    (flag(SemanticsFlag::IsChecked) || toggled, STATE_CHECKED),
+    (toggled, STATE_PRESSED),
+    (
+      flag(SemanticsFlag::HasCheckedState) || flag(SemanticsFlag::HasToggledState),
+      STATE_CHECKABLE,
+    ),
+    (
+      flag(SemanticsFlag::IsInMutuallyExclusiveGroup),
+      STATE_SELECTABLE,
+    ),
+    (flag(SemanticsFlag::IsSelected), STATE_SELECTED),
+    (text_field && !read_only, STATE_EDITABLE),
+    (read_only, STATE_READ_ONLY),
+    (text_field && multiline, STATE_MULTI_LINE),
+    (text_field && !multiline, STATE_SINGLE_LINE),
+  ];
+  let mut bits = vec![0u32; 2];
+  for (_, state) in states.into_iter().filter(|(set, _)| *set) {
+    bits[(state / 32) as usize] |= 1 << (state % 32);
+  }
+  bits
+}
+
+/// The numeric value of a slider, like `42` of "42%"
+fn numeric_value(node: &SemanticsNode) -> Option<f64> {
+  node.value.trim().trim_end_matches('%').trim().parse().ok()
+}
+
+struct Application {
+  id: i32,
+}
+
+#[interface(name = "org.a11y.atspi.Application")]
+impl Application {
+  #[zbus(property)]
+  fn toolkit_name(&self) -> String {
+    "wayflutter".to_owned()
+  }
+
+  #[zbus(property)]
+  fn version(&self) -> String {
+    env!("CARGO_PKG_VERSION").to_owned()
+  }
+
+  #[zbus(property)]
+  fn atspi_version(&self) -> String {
+    "2.1".to_owned()
+  }
+
+  /// assigned by the registry
+  #[zbus(property)]
+  fn id(&self) -> i32 {
+    self.id
+  }
+
+  #[zbus(property)]
+  fn set_id(&mut self, id: i32) {
+    self.id = id;
+  }
+
+  fn get_locale(&self, _lctype: u32) -> String {
+    std::env::var("LANG").unwrap_or_default()
+  }
+}
+
+struct Accessible(Object);
+
+#[interface(name = "org.a11y.atspi.Accessible")]
+impl Accessible {
+  #[zbus(property)]
+  fn name(&self) -> String {
+    self.0.name()
+  }
+
+  /// what else is said about the node after its name
+  #[zbus(property)]
+  fn description(&self) -> String {
+    self.0.with(String::new(), |_, node| {
+      [&node.value, &node.hint, &node.tooltip]
+        .into_iter()
+        .filter(|text| !text.is_empty())
+        .cloned()
+        .collect::<Vec<_>>()
+        .join(", ")
+    })
+  }
+
+  #[zbus(property)]
+  fn parent(&self) -> Reference {
+    let shared = &self.0.shared;
+    match self.0.id {
+      None => shared
+        .registry
+        .get()
+        .cloned()
+        .unwrap_or_else(null_reference),
+      // the root's is the application
+      Some(id) => shared.reference(shared.tree.lock().parent(id)),
+    }
+  }
+
+  #[zbus(property)]
+  fn child_count(&self) -> i32 {
+    self.0.children().len() as i32
+  }
+
+  #[zbus(property)]
+  fn locale(&self) -> String {
+    String::new()
+  }
+
+  #[zbus(property)]
+  fn accessible_id(&self) -> String {
+    self.0.id.map(|id| id.to_string()).unwrap_or_default()
+  }
+
+  fn get_child_at_index(&self, index: i32) -> (Reference,) {
+    let child = usize::try_from(index)
+      .ok()
+      .and_then(|index| self.0.children().get(index).copied());
+    match child {
+      Some(child) => (self.0.shared.reference(Some(child)),),
+      None => (null_reference(),),
+    }
+  }
+
+  fn get_children(&self) -> Vec<Reference> {
+    self
+      .0
+      .children()
+      .into_iter()
+      .map(|child| self.0.shared.reference(Some(child)))
+      .collect()
+  }
+
+  fn get_index_in_parent(&self) -> i32 {
+    let Some(id) = self.0.id else {
+      return -1;
+    };
+    let tree = self.0.shared.tree.lock();
+    let Some(parent) = tree.parent(id) else {
+      // the root, the only child of the application
+      return 0;
+    };
+    tree
+      .get(parent)
+      .and_then(|parent| parent.children.iter().position(|&child| child == id))
+      .map_or(-1, |index| index as i32)
+  }
+
+  fn get_relation_set(&self) -> Vec<(u32, Vec<Reference>)> {
+    Vec::new()
+  }
+
+  fn get_role(&self) -> u32 {
+    self.0.role()
+  }
+
+  fn get_role_name(&self) -> String {
+    role_name(self.0.role()).to_owned()
+  }
+
+  fn get_localized_role_name(&self) -> String {
+    role_name(self.0.role()).to_owned()
+  }
+
+  fn get_state(&self) -> Vec<u32> {
+    self.0.with(vec![0; 2], |_, node| states(node))
+  }
+
+  fn get_attributes(&self) -> HashMap<String, String> {
+    self.0.with(HashMap::new(), |_, node| {
+      HashMap::from([("toolkit".to_owned(), "flutter".to_owned())])
+        .into_iter()
+        .chain((!node.value.is_empty()).then(|| ("value".to_owned(), node.value.clone())))
+        .collect()
+    })
+  }
+
+  fn get_application(&self) -> (Reference,) {
+    (self.0.shared.reference(None),)
+  }
+
+  fn get_interfaces(&self) -> Vec<String> {
+    let mut interfaces = vec!["org.a11y.atspi.Accessible".to_owned()];
+    match self.0.id {
+      None => interfaces.push("org.a11y.atspi.Application".to_owned()),
+      Some(_) => {
+        interfaces.push("org.a11y.atspi.Component".to_owned());
+        if !self.0.actions().is_empty() {
+          interfaces.push("org.a11y.atspi.Action".to_owned());
+        }
+        if self.0.role() == ROLE_SLIDER {
+          interfaces.push("org.a11y.atspi.Value".to_owned());
+        }
+      }
+    }
+    interfaces
+  }
+}
+
+struct Component(Object);
+
+#[interface(name = "org.a11y.atspi.Component")]
+impl Component {
+  fn contains(&self, x: i32, y: i32, coord_type: u32) -> bool {
+    let (left, top, width, height) = self.0.extents(coord_type);
+    (left..left + width).contains(&x) && (top..top + height).contains(&y)
+  }
+
+  fn get_extents(&self, coord_type: u32) -> ((i32, i32, i32, i32),) {
+    (self.0.extents(coord_type),)
+  }
+
+  fn get_position(&self, coord_type: u32) -> (i32, i32) {
+    let (x, y, _, _) = self.0.extents(coord_type);
+    (x, y)
+  }
+
+  fn get_size(&self) -> (i32, i32) {
+    let (_, _, width, height) = self.0.extents(0);
+    (width, height)
+  }
+
+  fn get_layer(&self) -> u32 {
+    LAYER_WIDGET
+  }
+
+  #[zbus(name = "GetMDIZOrder")]
+  fn get_mdi_z_order(&self) -> i16 {
+    0
+  }
+
+  fn get_alpha(&self) -> f64 {
+    1.0
+  }
+
+  /// Moves the input focus with newer engines, the accessibility focus otherwise
+  fn grab_focus(&self) -> bool {
+    self.0.dispatch(SemanticsAction::Focus)
+      || self.0.dispatch(SemanticsAction::DidGainAccessibilityFocus)
+  }
+
+  fn scroll_to(&self, _scroll_type: u32) -> bool {
+    self.0.dispatch(SemanticsAction::ShowOnScreen)
+  }
+}
+
+/// `Value` for sliders, whose values the app formats as text, mostly percentages
+struct ValueInterface(Object);
+
+#[interface(name = "org.a11y.atspi.Value")]
+impl ValueInterface {
+  #[zbus(property)]
+  fn minimum_value(&self) -> f64 {
+    0.0
+  }
+
+  #[zbus(property)]
+  fn maximum_value(&self) -> f64 {
+    100.0
+  }
+
+  #[zbus(property)]
+  fn minimum_increment(&self) -> f64 {
+    0.0
+  }
+
+  #[zbus(property)]
+  fn current_value(&self) -> f64 {
+    self
+      .0
+      .with(0.0, |_, node| numeric_value(node).unwrap_or(0.0))
+  }
+
+  /// Steps the slider towards `value`, as the framework only increases and decreases it
+  #[zbus(property)]
+  fn set_current_value(&mut self, value: f64) {
+    let current = self.current_value();
+    if value > current {
+      self.0.dispatch(SemanticsAction::Increase);
+    } else if value < current {
+      self.0.dispatch(SemanticsAction::Decrease);
+    }
+  }
+
+  #[zbus(property)]
+  fn text(&self) -> String {
+    self.0.with(String::new(), |_, node| node.value.clone())
+  }
+}
+
+/// The actions of [`ACTIONS`] the node supports, by index
+struct Action(Object);
+
+#[interface(name = "org.a11y.atspi.Action")]
+impl Action {
+  #[zbus(property)]
+  fn n_actions(&self) -> i32 {
+    self.0.actions().len() as i32
+  }
+
+  fn get_description(&self, index: i32) -> String {
+    self
+      .entry(index)
+      .map(|(_, _, description)| description.to_owned())
+      .unwrap_or_default()
+  }
+
+  fn get_name(&self, index: i32) -> String {
+    self
+      .entry(index)
+      .map(|(_, name, _)| name.to_owned())
+      .unwrap_or_default()
+  }
+
+  fn get_localized_name(&self, index: i32) -> String {
+    self.get_name(index)
+  }
+
+  fn get_key_binding(&self, _index: i32) -> String {
+    String::new()
+  }
+
+  /// name, localized name and description of each
+  fn get_actions(&self) -> Vec<(String, String, String)> {
+    self
+      .0
+      .actions()
+      .into_iter()
+      .map(|(_, name, description)| (name.to_owned(), name.to_owned(), description.to_owned()))
+      .collect()
+  }
+
+  fn do_action(&self, index: i32) -> bool {
+    self
+      .entry(index)
+      .is_some_and(|(action, _, _)| self.0.dispatch(action))
+  }
+}
+
+impl Action {
+  fn entry(&self, index: i32) -> Option<(SemanticsAction, &'static str, &'static str)> {
+    let index = usize::try_from(index).ok()?;
+    self.0.actions().get(index).copied()
+  }
+}
@@ -0,0 +1,71 @@
+//! The service manager protocol of `sd_notify(3)`, for running as a `Type=notify` user service
+//! with `WatchdogSec=`. Everything here does nothing outside systemd.
+
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::SocketAddr;
+use std::os::unix::net::UnixDatagram;
+use std::sync::Once;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+
+/// Tell the service manager the app is up, once it has presented its first frame.
+pub fn ready() {
+  static READY: Once = Once::new();
+  READY.call_once(|| notify("READY=1"));
+}
+
+/// Tell the service manager the app is exiting on purpose, when dropped
+pub struct Stopping;
+
+impl Drop for Stopping {
+  fn drop(&mut self) {
+    notify("STOPPING=1");
+  }
+}
+
+/// Keep the service manager's watchdog from killing the app, as long as the event loop runs.
+/// Never resolves.
+pub async fn watchdog() -> Result<()> {
+  let Some(interval) = watchdog_interval() else {
+    return std::future::pending().await;
+  };
+  log::debug!("pinging the systemd watchdog every {:?}", interval / 2);
+  loop {
+    notify("WATCHDOG=1");
+    smol::Timer::after(interval / 2).await;
+  }
+}
+
+/// `$WATCHDOG_USEC`, if meant for this process
+fn watchdog_interval() -> Option<Duration> {
+  if let Some(pid) = std::env::var_os("WATCHDOG_PID")
+    && pid.to_str()?.parse() != Ok(std::process::id())
+  {
+    return None;
+  }
+  let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+  Some(Duration::from_micros(usec)).filter(|interval| !interval.is_zero())
+}
+
+/// Logs failures only, as the app runs fine without the service manager knowing.
+fn notify(state: &str) {
+  if let Err(e) = try_notify(state) {
+    log::warn!("failed to notify systemd of {}: {:#}", state, e);
+  }
+}
+
+fn try_notify(state: &str) -> Result<()> {
+  let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+    return Ok(());
+  };
+  let path = path.to_str().context("NOTIFY_SOCKET is not UTF-8")?;
+  let addr = match path.strip_prefix('@') {
+    Some(name) => SocketAddr::from_abstract_name(name)?,
+    None => SocketAddr::from_pathname(path)?,
+  };
+  let socket = UnixDatagram::unbound()?;
+  socket.send_to_addr(state.as_bytes(), &addr)?;
+  Ok(())
+}
@@ -1,114 +1,301 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::convert::Infallible;
-use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
 
 use anyhow::Result;
+use futures::FutureExt;
 use futures::StreamExt;
 use futures::channel::mpsc;
-use smol::LocalExecutor;
 
 use crate::FlutterEngine;
+use crate::ffi;
+use crate::trace_span;
 
-type NormalTask = Box<dyn FnOnce(&FlutterEngine) + Send + 'static>;
+pub mod priority;
+pub mod render;
 
-pub trait AsyncTask {
-  fn run<'a>(&mut self, engine: &'a FlutterEngine) -> Pin<Box<dyn Future<Output = ()> + 'a>>;
-}
+/// Tasks get the engine, or whatever unit tests run them with.
+type NormalTask<E> = Box<dyn FnOnce(&E) + Send + 'static>;
 
-/// Trait object cannot consume self, so we take the actual AsyncFnOnce from Some(f).
-impl<F> AsyncTask for Option<F>
+enum Task<E>
 where
-  F: AsyncFnOnce(&FlutterEngine) + 'static,
+  Self: Send,
 {
-  fn run<'a>(&mut self, engine: &'a FlutterEngine) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
-    if let Some(f) = self.take() {
-      Box::pin(f(engine))
-    } else {
-      Box::pin(futures::future::ready(()))
-    }
+  Normal(NormalTask<E>),
+  /// run once the engine time reaches `target_time_nanos`
+  Delayed {
+    target_time_nanos: u64,
+    task: NormalTask<E>,
+  },
+}
+
+/// A delayed task in the runner's queue
+struct Timer<E> {
+  target_time_nanos: u64,
+  /// tie breaker keeping tasks with the same target time in posting order
+  seq: u64,
+  task: NormalTask<E>,
+}
+
+impl<E> PartialEq for Timer<E> {
+  fn eq(&self, other: &Self) -> bool {
+    (self.target_time_nanos, self.seq) == (other.target_time_nanos, other.seq)
   }
 }
 
-enum Task
-where
-  Self: Send,
-{
-  Normal(NormalTask),
-  Async(Box<dyn AsyncTask + Send>),
+impl<E> Eq for Timer<E> {}
+
+impl<E> PartialOrd for Timer<E> {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
 }
 
-#[derive(Clone)]
-pub struct TaskRunnerHandle
+impl<E> Ord for Timer<E> {
+  fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    (self.target_time_nanos, self.seq).cmp(&(other.target_time_nanos, other.seq))
+  }
+}
+
+pub struct TaskRunnerHandle<E: 'static = FlutterEngine>
 where
   Self: Sync,
 {
-  tx: mpsc::UnboundedSender<Task>,
+  tx: mpsc::UnboundedSender<Task<E>>,
+  /// posted and not picked up by the runner yet
+  pending: Arc<AtomicUsize>,
 }
 
-impl TaskRunnerHandle {
-  pub fn post_task(&self, task: impl FnOnce(&FlutterEngine) + Send + 'static) -> Result<()> {
-    let ret = self.tx.unbounded_send(Task::Normal(Box::new(task)));
-    match ret {
-      Ok(()) => Ok(()),
-      Err(_) => Err(anyhow::anyhow!("Failed to post task"))?,
+// derived, it would require `E: Clone`
+impl<E> Clone for TaskRunnerHandle<E> {
+  fn clone(&self) -> Self {
+    Self {
+      tx: self.tx.clone(),
+      pending: self.pending.clone(),
     }
   }
+}
+
+impl<E> TaskRunnerHandle<E> {
+  /// Tasks waiting for the platform thread, for the watchdog
+  pub fn pending(&self) -> usize {
+    self.pending.load(Ordering::Relaxed)
+  }
 
-  pub fn post_async_task(
-    &self,
-    task: impl AsyncFnOnce(&FlutterEngine) + Send + 'static,
-  ) -> Result<()> {
-    let ret = self.tx.unbounded_send(Task::Async(Box::new(Some(task))));
+  fn send(&self, task: Task<E>) -> Result<(), mpsc::TrySendError<Task<E>>> {
+    self.pending.fetch_add(1, Ordering::Relaxed);
+    self.tx.unbounded_send(task).inspect_err(|_| {
+      self.pending.fetch_sub(1, Ordering::Relaxed);
+    })
+  }
+
+  pub fn post_task(&self, task: impl FnOnce(&E) + Send + 'static) -> Result<()> {
+    let ret = self.send(Task::Normal(Box::new(task)));
     match ret {
       Ok(()) => Ok(()),
-      Err(_) => Err(anyhow::anyhow!("Failed to post async task"))?,
+      Err(_) => Err(anyhow::anyhow!("Failed to post task"))?,
     }
   }
 
   pub fn post_task_after(
     &self,
-    task: impl FnOnce(&FlutterEngine) + Send + 'static,
+    task: impl FnOnce(&E) + Send + 'static,
     delay: Duration,
   ) -> Result<()> {
     if delay.is_zero() {
-      self.post_task(task)?;
-    } else {
-      self.post_async_task(async move |engine| {
-        smol::Timer::after(delay).await;
-        task(engine);
-      })?;
+      return self.post_task(task);
     }
-    Ok(())
+    let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
+    let delay = delay.as_nanos().try_into().unwrap_or(u64::MAX);
+    self.post_task_at(task, now.saturating_add(delay))
+  }
+
+  /// Run `task` once `FlutterEngineGetCurrentTime` reaches `target_time_nanos`. Tasks due at the
+  /// same time run in posting order.
+  pub fn post_task_at(
+    &self,
+    task: impl FnOnce(&E) + Send + 'static,
+    target_time_nanos: u64,
+  ) -> Result<()> {
+    let ret = self.send(Task::Delayed {
+      target_time_nanos,
+      task: Box::new(task),
+    });
+    match ret {
+      Ok(()) => Ok(()),
+      Err(_) => Err(anyhow::anyhow!("Failed to post delayed task"))?,
+    }
+  }
+}
+
+impl TaskRunnerHandle {
+  /// [`FlutterEngine::schedule_frame`] from any thread
+  pub fn schedule_frame(&self) -> Result<()> {
+    self.post_task(|engine| {
+      if let Err(e) = engine.schedule_frame() {
+        log::error!("failed to schedule a frame: {}", e);
+      }
+    })
   }
 }
 
-pub fn make_task_runner<'a>(
-  engine: &'a FlutterEngine,
+pub fn make_task_runner<'a, E>(
+  engine: &'a E,
 ) -> (
   impl Future<Output = Result<Infallible>> + 'a,
-  TaskRunnerHandle,
+  TaskRunnerHandle<E>,
 ) {
-  let ex = LocalExecutor::new();
-  let (tx, rx) = mpsc::unbounded::<Task>();
+  let (tx, rx) = mpsc::unbounded::<Task<E>>();
+  let pending = Arc::new(AtomicUsize::new(0));
 
-  let runner = async move {
-    let receiving = async {
+  let runner = {
+    let pending = pending.clone();
+    async move {
       let mut rx = rx;
-      while let Some(task) = rx.next().await {
+      // delayed tasks, serviced by a single timer for the earliest
+      let mut timers = BinaryHeap::<Reverse<Timer<E>>>::new();
+      let mut next_seq = 0;
+      loop {
+        let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
+        while let Some(Reverse(timer)) = timers.peek()
+          && timer.target_time_nanos <= now
+        {
+          let Reverse(timer) = timers.pop().unwrap();
+          let _span = trace_span!("platform task");
+          (timer.task)(engine);
+        }
+        let next_timer = timers
+          .peek()
+          .map(|Reverse(timer)| Duration::from_nanos(timer.target_time_nanos - now));
+        let timer_expired = async {
+          match next_timer {
+            Some(delay) => {
+              smol::Timer::after(delay).await;
+            }
+            None => std::future::pending().await,
+          }
+        };
+
+        let task = futures::select! {
+          task = rx.next() => task,
+          () = timer_expired.fuse() => continue,
+        };
+        let Some(task) = task else {
+          anyhow::bail!("all task senders dropped");
+        };
+        pending.fetch_sub(1, Ordering::Relaxed);
         match task {
           Task::Normal(task) => {
+            let _span = trace_span!("platform task");
             task(engine);
           }
-          Task::Async(mut task) => {
-            ex.spawn(task.run(engine)).detach();
+          Task::Delayed {
+            target_time_nanos,
+            task,
+          } => {
+            timers.push(Reverse(Timer {
+              target_time_nanos,
+              seq: next_seq,
+              task,
+            }));
+            next_seq += 1;
           }
         }
       }
-      anyhow::bail!("all task senders dropped");
-    };
-
-    ex.run(receiving).await
+    }
   };
 
-  (runner, TaskRunnerHandle { tx })
+  (runner, TaskRunnerHandle { tx, pending })
+}
+
+#[cfg(test)]
+mod tests {
+  use parking_lot::Mutex;
+
+  use super::*;
+
+  const MILLI: u64 = 1_000_000;
+
+  type Log = Mutex<Vec<u32>>;
+
+  /// Post tasks with `post`, given the engine time, run them for a while and return the order
+  /// they ran in.
+  fn run(post: impl FnOnce(&TaskRunnerHandle<Log>, u64)) -> Vec<u32> {
+    ffi::fake::load();
+    let log = Log::default();
+    let (runner, handle) = make_task_runner(&log);
+    post(&handle, unsafe { ffi::FlutterEngineGetCurrentTime() });
+    smol::block_on(smol::future::or(
+      async {
+        let Err(e) = runner.await;
+        panic!("the runner stopped: {}", e);
+      },
+      async {
+        smol::Timer::after(Duration::from_millis(200)).await;
+      },
+    ));
+    assert_eq!(handle.pending(), 0);
+    log.into_inner()
+  }
+
+  fn record(id: u32) -> impl FnOnce(&Log) + Send + 'static {
+    move |log| log.lock().push(id)
+  }
+
+  #[test]
+  fn tasks_run_in_posting_order() {
+    let order = run(|handle, _| {
+      for id in 0..3 {
+        handle.post_task(record(id)).unwrap();
+      }
+      handle.post_task_after(record(3), Duration::ZERO).unwrap();
+    });
+    assert_eq!(order, [0, 1, 2, 3]);
+  }
+
+  #[test]
+  fn timers_run_by_target_time_then_posting_order() {
+    let order = run(|handle, now| {
+      handle.post_task_at(record(4), now + 60 * MILLI).unwrap();
+      handle.post_task_at(record(2), now + 30 * MILLI).unwrap();
+      handle.post_task_at(record(3), now + 30 * MILLI).unwrap();
+      handle.post_task(record(0)).unwrap();
+      handle
+        .post_task_after(record(1), Duration::from_millis(10))
+        .unwrap();
+    });
+    assert_eq!(order, [0, 1, 2, 3, 4]);
+  }
+
+  #[test]
+  fn overdue_timers_run_right_away() {
+    let order = run(|handle, now| {
+      handle
+        .post_task_at(record(0), now.saturating_sub(MILLI))
+        .unwrap();
+      handle.post_task_at(record(1), 0).unwrap();
+      handle.post_task_at(record(2), now + 20 * MILLI).unwrap();
+    });
+    assert_eq!(order, [0, 1, 2]);
+  }
+
+  #[test]
+  fn heap_pops_the_earliest_timer_first_and_ties_in_posting_order() {
+    let mut heap = BinaryHeap::new();
+    for (seq, target_time_nanos) in [(0, 30), (1, 10), (2, 30), (3, 20), (4, 10)] {
+      heap.push(Reverse(Timer::<Log> {
+        target_time_nanos,
+        seq,
+        task: Box::new(|_| {}),
+      }));
+    }
+    let popped = std::iter::from_fn(|| heap.pop())
+      .map(|Reverse(timer)| (timer.target_time_nanos, timer.seq))
+      .collect::<Vec<_>>();
+    assert_eq!(popped, [(10, 1), (10, 4), (20, 3), (30, 0), (30, 2)]);
+  }
 }
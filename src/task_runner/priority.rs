@@ -0,0 +1,82 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use crate::callback::catch_panic;
+use crate::ffi;
+
+/// `struct sched_attr` (SCHED_ATTR_SIZE_VER0)
+#[repr(C)]
+struct SchedAttr {
+  size: u32,
+  sched_policy: u32,
+  sched_flags: u64,
+  sched_nice: i32,
+  sched_priority: u32,
+  sched_runtime: u64,
+  sched_deadline: u64,
+  sched_period: u64,
+}
+
+/// Only warn about missing privileges once, every thread would hit it.
+static WARNED_NO_PERMISSION: AtomicBool = AtomicBool::new(false);
+
+/// `(policy, nice)` for a priority
+fn sched_params(priority: ffi::FlutterThreadPriority) -> (libc::c_int, i32) {
+  match priority {
+    ffi::FlutterThreadPriority_kBackground => (libc::SCHED_BATCH, 10),
+    ffi::FlutterThreadPriority_kDisplay => (libc::SCHED_OTHER, -5),
+    ffi::FlutterThreadPriority_kRaster => (libc::SCHED_OTHER, -5),
+    _ => (libc::SCHED_OTHER, 0),
+  }
+}
+
+fn sched_setattr(policy: libc::c_int, nice: i32) -> std::io::Result<()> {
+  let attr = SchedAttr {
+    size: size_of::<SchedAttr>() as u32,
+    sched_policy: policy as u32,
+    sched_flags: 0,
+    sched_nice: nice,
+    sched_priority: 0,
+    sched_runtime: 0,
+    sched_deadline: 0,
+    sched_period: 0,
+  };
+  // pid 0 is the calling thread
+  let ret = unsafe { libc::syscall(libc::SYS_sched_setattr, 0, &attr as *const SchedAttr, 0) };
+  if ret == 0 {
+    Ok(())
+  } else {
+    Err(std::io::Error::last_os_error())
+  }
+}
+
+/// Set the scheduling priority of the calling thread.
+///
+/// Raising the priority above normal needs `CAP_SYS_NICE` or a suitable `RLIMIT_NICE`.
+/// Without them the thread stays at normal priority.
+pub fn set_current_thread_priority(priority: ffi::FlutterThreadPriority) {
+  let (policy, nice) = sched_params(priority);
+  let result = match sched_setattr(policy, nice) {
+    Err(e) if e.raw_os_error() == Some(libc::EPERM) && nice < 0 => {
+      if !WARNED_NO_PERMISSION.swap(true, Ordering::Relaxed) {
+        log::warn!("Not permitted to raise thread priorities. Using normal priority instead.");
+      }
+      sched_setattr(policy, 0)
+    }
+    result => result,
+  };
+  if let Err(e) = result {
+    log::debug!(
+      "failed to set the priority of thread {:?} to {}: {}",
+      std::thread::current().name(),
+      priority,
+      e
+    );
+  }
+}
+
+pub extern "C" fn thread_priority_setter(priority: ffi::FlutterThreadPriority) {
+  catch_panic(None, (), || {
+    set_current_thread_priority(priority);
+  })
+}
@@ -0,0 +1,192 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::ffi::c_void;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::thread::ThreadId;
+use std::time::Duration;
+
+use anyhow::Context;
+use anyhow::Result;
+use parking_lot::Condvar;
+use parking_lot::Mutex;
+use parking_lot::MutexGuard;
+
+use crate::callback::catch_panic;
+use crate::error::FFIFlutterEngineResultExt;
+use crate::ffi;
+use crate::task_runner::priority::set_current_thread_priority;
+
+/// Runs the engine's raster tasks on a dedicated thread, so that rasterization doesn't compete
+/// with platform work on the main event loop.
+///
+/// All GL work of the render context (backing stores, presenting) happens on this thread. The
+/// engine makes the context current through the `make_current` callback before using it.
+pub struct RenderTaskRunner {
+  shared: Arc<Shared>,
+  thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+  queue: Mutex<Queue>,
+  condvar: Condvar,
+  thread_id: Mutex<Option<ThreadId>>,
+}
+
+struct Queue {
+  /// tasks are held back until the engine is initialized
+  engine: Option<EnginePtr>,
+  tasks: BinaryHeap<Reverse<ScheduledTask>>,
+  /// tie breaker keeping tasks with the same target time in posting order
+  next_seq: u64,
+  shutdown: bool,
+}
+
+#[derive(Clone, Copy)]
+struct EnginePtr(ffi::FlutterEngine);
+unsafe impl Send for EnginePtr {}
+
+struct ScheduledTask {
+  target_time_nanos: u64,
+  seq: u64,
+  task: ffi::FlutterTask,
+}
+unsafe impl Send for ScheduledTask {}
+
+impl PartialEq for ScheduledTask {
+  fn eq(&self, other: &Self) -> bool {
+    (self.target_time_nanos, self.seq) == (other.target_time_nanos, other.seq)
+  }
+}
+
+impl Eq for ScheduledTask {}
+
+impl PartialOrd for ScheduledTask {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for ScheduledTask {
+  fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    (self.target_time_nanos, self.seq).cmp(&(other.target_time_nanos, other.seq))
+  }
+}
+
+impl RenderTaskRunner {
+  pub fn spawn() -> Result<Self> {
+    let shared = Arc::new(Shared {
+      queue: Mutex::new(Queue {
+        engine: None,
+        tasks: BinaryHeap::new(),
+        next_seq: 0,
+        shutdown: false,
+      }),
+      condvar: Condvar::new(),
+      thread_id: Mutex::new(None),
+    });
+    let thread = std::thread::Builder::new()
+      .name("raster".to_owned())
+      .spawn({
+        let shared = shared.clone();
+        move || run(&shared)
+      })
+      .context("failed to spawn the raster thread")?;
+    *shared.thread_id.lock() = Some(thread.thread().id());
+    Ok(Self {
+      shared,
+      thread: Some(thread),
+    })
+  }
+
+  /// Start running tasks with `engine`.
+  pub fn set_engine(&self, engine: ffi::FlutterEngine) {
+    self.shared.queue.lock().engine = Some(EnginePtr(engine));
+    self.shared.condvar.notify_one();
+  }
+
+  /// Must be kept alive as long as the engine may post tasks to it.
+  pub fn description(&self) -> ffi::FlutterTaskRunnerDescription {
+    ffi::FlutterTaskRunnerDescription {
+      struct_size: size_of::<ffi::FlutterTaskRunnerDescription>(),
+      user_data: Arc::as_ptr(&self.shared) as *mut c_void,
+      runs_task_on_current_thread_callback: Some(runs_task_on_current_thread_callback),
+      post_task_callback: Some(post_task_callback),
+      identifier: 2,
+      destruction_callback: None,
+    }
+  }
+}
+
+impl Drop for RenderTaskRunner {
+  fn drop(&mut self) {
+    self.shared.queue.lock().shutdown = true;
+    self.shared.condvar.notify_one();
+    if let Some(thread) = self.thread.take()
+      && thread.join().is_err()
+    {
+      log::error!("the raster thread panicked");
+    }
+  }
+}
+
+fn run(shared: &Shared) {
+  // the engine only sets priorities of the threads it creates itself
+  set_current_thread_priority(ffi::FlutterThreadPriority_kRaster);
+
+  let mut queue = shared.queue.lock();
+  loop {
+    if queue.shutdown {
+      break;
+    }
+    let Some(engine) = queue.engine else {
+      shared.condvar.wait(&mut queue);
+      continue;
+    };
+    let Some(Reverse(next)) = queue.tasks.peek() else {
+      shared.condvar.wait(&mut queue);
+      continue;
+    };
+    let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
+    if next.target_time_nanos > now {
+      let delay = Duration::from_nanos(next.target_time_nanos - now);
+      shared.condvar.wait_for(&mut queue, delay);
+      continue;
+    }
+    let Reverse(task) = queue.tasks.pop().unwrap();
+    MutexGuard::unlocked(&mut queue, || unsafe {
+      let ret = ffi::FlutterEngineRunTask(engine.0, &task.task).into_flutter_engine_result();
+      if let Err(e) = ret {
+        log::error!("failed to run the raster task posted by the engine: {}", e);
+      }
+    });
+  }
+}
+
+extern "C" fn runs_task_on_current_thread_callback(user_data: *mut c_void) -> bool {
+  catch_panic(None, false, || {
+    let shared = unsafe { &*(user_data as *const Shared) };
+    *shared.thread_id.lock() == Some(std::thread::current().id())
+  })
+}
+
+extern "C" fn post_task_callback(
+  task: ffi::FlutterTask,
+  target_time_nanos: u64,
+  user_data: *mut c_void,
+) {
+  catch_panic(None, (), || {
+    let shared = unsafe { &*(user_data as *const Shared) };
+    {
+      let mut queue = shared.queue.lock();
+      let seq = queue.next_seq;
+      queue.next_seq += 1;
+      queue.tasks.push(Reverse(ScheduledTask {
+        target_time_nanos,
+        seq,
+        task,
+      }));
+    }
+    shared.condvar.notify_one();
+  })
+}
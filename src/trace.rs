@@ -0,0 +1,196 @@
+use std::ffi::c_char;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use anyhow::Context;
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::ffi;
+
+/// Recording stops with a warning beyond this many events
+const MAX_EVENTS: usize = 1 << 20;
+
+/// `Some` while a [`Recording`] is alive
+static RECORDING: Mutex<Option<Vec<Event>>> = Mutex::new(None);
+/// spares the lock while not recording
+static RECORDING_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// A span of embedder work, recorded with `tracing` and in the engine's timeline, where DevTools
+/// shows it among the engine's own events. Ends when dropped. See `trace_span!`.
+pub struct Span {
+  /// nul-terminated
+  name: &'static str,
+  _entered: tracing::span::EnteredSpan,
+}
+
+impl Span {
+  /// `name` must be nul-terminated.
+  pub fn enter(span: tracing::Span, name: &'static str) -> Self {
+    debug_assert!(name.ends_with('\0'));
+    unsafe { ffi::FlutterEngineTraceEventDurationBegin(name.as_ptr() as *const c_char) };
+    record(|| Event::on_current_thread(name, Phase::Begin, now()));
+    Self {
+      name,
+      _entered: span.entered(),
+    }
+  }
+}
+
+impl Drop for Span {
+  fn drop(&mut self) {
+    record(|| Event::on_current_thread(self.name, Phase::End, now()));
+    unsafe { ffi::FlutterEngineTraceEventDurationEnd(self.name.as_ptr() as *const c_char) };
+  }
+}
+
+/// An event of the Chrome trace event format, which Perfetto and `chrome://tracing` open
+#[derive(Debug, Serialize)]
+struct Event {
+  name: &'static str,
+  ph: Phase,
+  /// microseconds of engine time
+  ts: f64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  dur: Option<f64>,
+  pid: u32,
+  tid: i32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+enum Phase {
+  #[serde(rename = "B")]
+  Begin,
+  #[serde(rename = "E")]
+  End,
+  #[serde(rename = "X")]
+  Complete,
+}
+
+/// Frame timings go to a track of their own
+const FRAMES_TID: i32 = 0;
+
+impl Event {
+  fn on_current_thread(name: &'static str, ph: Phase, ts: u64) -> Self {
+    Self {
+      name: name.trim_end_matches('\0'),
+      ph,
+      ts: micros(ts),
+      dur: None,
+      pid: std::process::id(),
+      tid: unsafe { libc::gettid() },
+    }
+  }
+
+  fn frame_phase(name: &'static str, start: u64, end: u64) -> Self {
+    Self {
+      name,
+      ph: Phase::Complete,
+      ts: micros(start),
+      dur: Some(micros(end.saturating_sub(start))),
+      pid: std::process::id(),
+      tid: FRAMES_TID,
+    }
+  }
+}
+
+fn now() -> u64 {
+  unsafe { ffi::FlutterEngineGetCurrentTime() }
+}
+
+fn micros(nanos: u64) -> f64 {
+  nanos as f64 / 1e3
+}
+
+fn record(event: impl FnOnce() -> Event) {
+  if !RECORDING_ACTIVE.load(Ordering::Relaxed) {
+    return;
+  }
+  let mut recording = RECORDING.lock();
+  let Some(events) = &mut *recording else {
+    return;
+  };
+  match events.len() {
+    len if len < MAX_EVENTS => events.push(event()),
+    MAX_EVENTS => {
+      log::warn!("recorded {} trace events, ignoring more", MAX_EVENTS);
+      // so that the warning is logged once
+      events.push(event());
+    }
+    _ => {}
+  }
+}
+
+/// The stages of a finished frame, in engine time. `build_start` and `presented_at` are `None`
+/// if unknown.
+pub fn record_frame(
+  build_start: Option<u64>,
+  raster_start: u64,
+  committed_at: u64,
+  presented_at: Option<u64>,
+) {
+  if !RECORDING_ACTIVE.load(Ordering::Relaxed) {
+    return;
+  }
+  if let Some(build_start) = build_start {
+    record(|| Event::frame_phase("build", build_start, raster_start));
+  }
+  record(|| Event::frame_phase("raster", raster_start, committed_at));
+  if let Some(presented_at) = presented_at {
+    record(|| Event::frame_phase("present", committed_at, presented_at));
+  }
+}
+
+/// Records spans and frame timings from creation until dropped, then writes them to a file in
+/// the Chrome trace event format. Only one at a time.
+pub struct Recording {
+  path: PathBuf,
+}
+
+impl Recording {
+  pub fn start(path: PathBuf) -> Result<Self> {
+    let mut recording = RECORDING.lock();
+    if recording.is_some() {
+      anyhow::bail!("already recording a trace");
+    }
+    *recording = Some(Vec::new());
+    RECORDING_ACTIVE.store(true, Ordering::Relaxed);
+    log::info!("recording a trace to {:?}", path);
+    Ok(Self { path })
+  }
+
+  fn write(&self, events: Vec<Event>) -> Result<()> {
+    let pid = std::process::id();
+    let frames_track = json!({
+      "name": "thread_name",
+      "ph": "M",
+      "pid": pid,
+      "tid": FRAMES_TID,
+      "args": { "name": "frames" },
+    });
+    let mut trace_events = vec![frames_track];
+    for event in events {
+      trace_events.push(serde_json::to_value(event)?);
+    }
+    let trace = json!({
+      "traceEvents": trace_events,
+      "displayTimeUnit": "ms",
+    });
+    std::fs::write(&self.path, serde_json::to_vec(&trace)?)
+      .with_context(|| format!("failed to write the trace to {:?}", self.path))
+  }
+}
+
+impl Drop for Recording {
+  fn drop(&mut self) {
+    RECORDING_ACTIVE.store(false, Ordering::Relaxed);
+    let events = RECORDING.lock().take().unwrap_or_default();
+    match self.write(events) {
+      Ok(()) => log::info!("wrote the trace to {:?}", self.path),
+      Err(e) => log::error!("{:#}", e),
+    }
+  }
+}
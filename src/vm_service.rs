@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use parking_lot::Mutex;
+use serde::Deserialize;
+
+/// What the engine prints once the VM service is up, followed by the URI
+const LISTENING_PREFIX: &str = "Dart VM service is listening on ";
+
+/// The Dart VM service, which DevTools and `flutter attach` connect to. Only debug and profile
+/// engines have it.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct VmServiceOptions {
+  pub disable: bool,
+  /// address to bind to. The engine binds to localhost if `None`.
+  pub host: Option<String>,
+  /// The engine picks a free port if `None`.
+  pub port: Option<u16>,
+  /// file to write the URI to once the service listens
+  pub uri_file: Option<PathBuf>,
+}
+
+/// set before the engine starts, read by the log message callback which has no state yet
+static URI_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// of the running engine
+static URI: Mutex<Option<String>> = Mutex::new(None);
+
+impl VmServiceOptions {
+  /// Engine switches, and remember where to write the URI to.
+  pub fn switches(&self) -> Vec<String> {
+    // of the engine started before
+    *URI.lock() = None;
+    if let Some(uri_file) = &self.uri_file {
+      let _ = URI_FILE.set(uri_file.clone());
+    }
+    if self.disable {
+      return vec!["--disable-vm-service".to_owned()];
+    }
+    let mut switches = Vec::new();
+    if let Some(host) = &self.host {
+      switches.push(format!("--vm-service-host={}", host));
+    }
+    if let Some(port) = self.port {
+      switches.push(format!("--vm-service-port={}", port));
+    }
+    switches
+  }
+}
+
+/// Pick the URI out of a message logged by the engine.
+pub fn on_log_message(message: &str) {
+  let Some((_, uri)) = message.split_once(LISTENING_PREFIX) else {
+    return;
+  };
+  let uri = uri.trim();
+  log::info!("Dart VM service: {}", uri);
+  *URI.lock() = Some(uri.to_owned());
+  if let Some(uri_file) = URI_FILE.get()
+    && let Err(e) = std::fs::write(uri_file, format!("{}\n", uri))
+  {
+    log::error!(
+      "failed to write the VM service URI to {:?}: {}",
+      uri_file,
+      e
+    );
+  }
+}
+
+/// Where the VM service of the running engine listens, once it does
+pub fn uri() -> Option<String> {
+  URI.lock().clone()
+}
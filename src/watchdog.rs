@@ -0,0 +1,181 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::EngineShared;
+use crate::FlutterEngine;
+use crate::FlutterEngineState;
+use crate::ffi;
+
+const TICK: Duration = Duration::from_secs(1);
+
+/// Reports the engine getting stuck: a vsync baton that isn't answered, or platform tasks piling
+/// up, for longer than `timeout`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "camelCase")]
+pub struct WatchdogOptions {
+  /// seconds a stall lasts before it's reported. 0 disables the watchdog.
+  pub timeout: u64,
+  /// platform tasks waiting to run that count as a stall
+  pub max_pending_tasks: usize,
+  /// Exit with an error on a stall, e.g. to be restarted, instead of only logging it
+  pub terminate: bool,
+}
+
+impl Default for WatchdogOptions {
+  fn default() -> Self {
+    Self {
+      timeout: 5,
+      max_pending_tasks: 1000,
+      terminate: false,
+    }
+  }
+}
+
+/// Progress of the engine, marked by the callbacks and read by the watchdog thread
+#[derive(Debug, Default)]
+pub struct Heartbeat {
+  /// engine time the outstanding vsync baton was handed out at. 0 if there's none, or it's
+  /// parked on purpose.
+  vsync_requested_at: AtomicU64,
+  /// engine time the last frame was committed at
+  last_commit: AtomicU64,
+  /// the last error `glGetError` reported on the raster thread
+  last_gl_error: AtomicU32,
+}
+
+impl Heartbeat {
+  pub fn vsync_requested(&self, now: u64) {
+    self.vsync_requested_at.store(now, Ordering::Relaxed);
+  }
+
+  /// Answered or parked
+  pub fn vsync_settled(&self) {
+    self.vsync_requested_at.store(0, Ordering::Relaxed);
+  }
+
+  /// On the raster thread with the render context current
+  pub fn frame_committed(&self, now: u64) {
+    self.last_commit.store(now, Ordering::Relaxed);
+    let error = unsafe { gl::GetError() };
+    if error != gl::NO_ERROR {
+      self.last_gl_error.store(error, Ordering::Relaxed);
+    }
+  }
+}
+
+/// The watchdog thread, stopped on drop
+pub struct Watchdog {
+  stop: Option<mpsc::Sender<()>>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+  /// `None` if disabled by the options
+  pub fn spawn(engine: &FlutterEngine, options: WatchdogOptions) -> Result<Option<Self>> {
+    if options.timeout == 0 {
+      return Ok(None);
+    }
+    let (stop, stopped) = mpsc::channel();
+    let shared = engine.shared.clone();
+    let thread = std::thread::Builder::new()
+      .name("watchdog".to_owned())
+      .spawn(move || run(&shared, &options, &stopped))
+      .context("failed to spawn the watchdog thread")?;
+    Ok(Some(Self {
+      stop: Some(stop),
+      thread: Some(thread),
+    }))
+  }
+}
+
+impl Drop for Watchdog {
+  fn drop(&mut self) {
+    drop(self.stop.take());
+    if let Some(thread) = self.thread.take()
+      && thread.join().is_err()
+    {
+      log::error!("the watchdog thread panicked");
+    }
+  }
+}
+
+fn run(shared: &Arc<EngineShared>, options: &WatchdogOptions, stopped: &mpsc::Receiver<()>) {
+  let timeout = Duration::from_secs(options.timeout);
+  let mut tasks_piling_since: Option<Instant> = None;
+  let mut reported = false;
+  while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(TICK) {
+    let Some(state) = shared.state.get() else {
+      continue;
+    };
+    let now = unsafe { ffi::FlutterEngineGetCurrentTime() };
+    let vsync_requested_at = state.heartbeat.vsync_requested_at.load(Ordering::Relaxed);
+    let vsync_stalled =
+      vsync_requested_at != 0 && now.saturating_sub(vsync_requested_at) > timeout.as_nanos() as u64;
+    let pending_tasks = state.task_runner_handle.pending();
+    if pending_tasks <= options.max_pending_tasks {
+      tasks_piling_since = None;
+    } else if tasks_piling_since.is_none() {
+      tasks_piling_since = Some(Instant::now());
+    }
+    let tasks_stalled = tasks_piling_since.is_some_and(|since| since.elapsed() > timeout);
+
+    if !vsync_stalled && !tasks_stalled {
+      reported = false;
+      continue;
+    }
+    if reported {
+      continue;
+    }
+    reported = true;
+    let what = match (vsync_stalled, tasks_stalled) {
+      (true, true) => "no vsync answered and platform tasks piling up",
+      (true, false) => "no vsync answered",
+      _ => "platform tasks piling up",
+    };
+    log::error!("the engine seems stuck: {} for {}s", what, options.timeout);
+    dump(state, now);
+    if options.terminate {
+      let error = anyhow::anyhow!("the engine got stuck: {}", what);
+      let _ = state.terminate.unbounded_send(Err(error));
+    }
+  }
+}
+
+fn dump(state: &FlutterEngineState, now: u64) {
+  let heartbeat = &state.heartbeat;
+  let ago = |time: u64| match time {
+    0 => "never".to_owned(),
+    time => format!("{:.1}s ago", now.saturating_sub(time) as f64 / 1e9),
+  };
+  log::error!(
+    "pending platform tasks: {}",
+    state.task_runner_handle.pending()
+  );
+  log::error!(
+    "vsync requested: {}",
+    ago(heartbeat.vsync_requested_at.load(Ordering::Relaxed))
+  );
+  log::error!(
+    "last frame committed: {}",
+    ago(heartbeat.last_commit.load(Ordering::Relaxed))
+  );
+  match serde_json::to_string(&state.frame_stats.summary()) {
+    Ok(summary) => log::error!("recent frames: {}", summary),
+    Err(e) => log::error!("recent frames: {}", e),
+  }
+  match heartbeat.last_gl_error.load(Ordering::Relaxed) {
+    gl::NO_ERROR => log::error!("no GL error seen"),
+    error => log::error!("last GL error: {:#06x}", error),
+  }
+}
@@ -1,8 +1,9 @@
-use std::cell::UnsafeCell;
+use std::cell::RefCell;
 use std::convert::Infallible;
 use std::future::poll_fn;
 use std::task::ready;
 
+use anyhow::Context;
 use anyhow::Result;
 use smithay_client_toolkit::compositor::CompositorHandler;
 use smithay_client_toolkit::compositor::CompositorState;
@@ -12,37 +13,117 @@ use smithay_client_toolkit::delegate_registry;
 use smithay_client_toolkit::delegate_seat;
 use smithay_client_toolkit::output::OutputHandler;
 use smithay_client_toolkit::output::OutputState;
+use smithay_client_toolkit::reexports::protocols::wp::content_type::v1::client::wp_content_type_manager_v1::WpContentTypeManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibit_manager_v1::ZwpKeyboardShortcutsInhibitManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_drm_syncobj::v1::client::wp_linux_drm_syncobj_manager_v1::WpLinuxDrmSyncobjManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation::WpPresentation;
+use smithay_client_toolkit::reexports::protocols::wp::tearing_control::v1::client::wp_tearing_control_manager_v1::WpTearingControlManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
+use smithay_client_toolkit::reexports::protocols_misc::zwp_input_method_v2::client::zwp_input_method_manager_v2::ZwpInputMethodManagerV2;
+use smithay_client_toolkit::reexports::protocols_wlr::gamma_control::v1::client::zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1;
 use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::ZwlrLayerShellV1;
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1;
 use smithay_client_toolkit::registry::ProvidesRegistryState;
 use smithay_client_toolkit::registry::RegistryState;
 use smithay_client_toolkit::registry_handlers;
 use smithay_client_toolkit::seat::SeatHandler;
 use smithay_client_toolkit::seat::SeatState;
+use smithay_client_toolkit::seat::keyboard::Modifiers;
+use wayland_client::protocol::wl_compositor::WlCompositor;
+use wayland_client::protocol::wl_keyboard::WlKeyboard;
+use wayland_client::protocol::wl_output::WlOutput;
 use wayland_client::protocol::wl_pointer::WlPointer;
 use wayland_client::protocol::wl_seat::WlSeat;
 use wayland_client::Connection;
 use wayland_client::EventQueue;
+use wayland_client::Proxy;
 use wayland_client::globals::registry_queue_init;
 
 use crate::FlutterEngine;
-
+use crate::error::ConnectionLost;
+use crate::error_in_callback;
+use crate::wayland::content_type::ContentTypeManager;
+use crate::wayland::dmabuf::LinuxDmabuf;
+use crate::wayland::explicit_sync::ExplicitSync;
+use crate::wayland::gamma_control::GammaControlManager;
+use crate::wayland::input_method::InputMethodManager;
+use crate::wayland::layer_shell::LayerShell;
+use crate::wayland::output_power::OutputPowerManager;
+use crate::wayland::presentation::FrameClock;
+use crate::wayland::shm::Shm;
+use crate::wayland::shortcuts_inhibit::ShortcutsInhibitManager;
+use crate::wayland::tearing_control::TearingControlManager;
+use crate::wayland::viewporter::Viewporter;
+
+pub mod content_type;
+pub mod dmabuf;
+pub mod explicit_sync;
+pub mod gamma_control;
+pub mod inject;
+pub mod input_method;
+mod keyboard;
 pub mod layer_shell;
+pub mod output_power;
 mod pointer;
+pub mod presentation;
+pub mod shm;
+pub mod shortcuts_inhibit;
+pub mod tearing_control;
+pub mod viewporter;
 
 pub struct WaylandClient<'a> {
   conn: &'a Connection,
-  queue: UnsafeCell<EventQueue<WaylandState>>,
-  state: UnsafeCell<WaylandState>,
+  queue: RefCell<EventQueue<WaylandState>>,
+  state: RefCell<WaylandState>,
 }
 
 impl<'a> WaylandClient<'a> {
   pub(super) fn new(conn: &'a Connection, engine: &'a FlutterEngine) -> Result<Self> {
-    let (globals, queue) = registry_queue_init::<WaylandState>(conn)?;
+    let (globals, mut queue) = registry_queue_init::<WaylandState>(conn)?;
     let qh = queue.handle();
     let output_state = OutputState::new(&globals, &qh);
     let compositor_state = CompositorState::bind(&globals, &qh)?;
     let seat_state = SeatState::new(&globals, &qh);
-    let layer_shell = globals.bind::<ZwlrLayerShellV1, _, _>(&qh, 1..=5, ())?;
+    let layer_shell = LayerShell::new(
+      globals.bind::<ZwlrLayerShellV1, _, _>(&qh, 1..=5, ())?,
+      qh.clone(),
+    );
+    let presentation = globals.bind::<WpPresentation, _, _>(&qh, 1..=2, ()).ok();
+    let frame_clock = FrameClock::new(presentation, qh.clone());
+    let explicit_sync = globals
+      .bind::<WpLinuxDrmSyncobjManagerV1, _, _>(&qh, 1..=1, ())
+      .ok()
+      .map(|manager| ExplicitSync::new(manager, qh.clone()));
+    let tearing_control_manager = globals
+      .bind::<WpTearingControlManagerV1, _, _>(&qh, 1..=1, ())
+      .ok()
+      .map(|manager| TearingControlManager::new(manager, qh.clone()));
+    let content_type_manager = globals
+      .bind::<WpContentTypeManagerV1, _, _>(&qh, 1..=1, ())
+      .ok()
+      .map(|manager| ContentTypeManager::new(manager, qh.clone()));
+    let viewporter = globals
+      .bind::<WpViewporter, _, _>(&qh, 1..=1, ())
+      .ok()
+      .map(|viewporter| Viewporter::new(viewporter, qh.clone()));
+    let linux_dmabuf = LinuxDmabuf::bind(&globals, &qh);
+    let shm = Shm::bind(&globals, &qh);
+    let input_method_manager = globals
+      .bind::<ZwpInputMethodManagerV2, _, _>(&qh, 1..=1, ())
+      .ok()
+      .map(|manager| InputMethodManager::new(manager, qh.clone()));
+    let shortcuts_inhibit_manager = globals
+      .bind::<ZwpKeyboardShortcutsInhibitManagerV1, _, _>(&qh, 1..=1, ())
+      .ok()
+      .map(|manager| ShortcutsInhibitManager::new(manager, qh.clone()));
+    let gamma_control_manager = globals
+      .bind::<ZwlrGammaControlManagerV1, _, _>(&qh, 1..=1, ())
+      .ok()
+      .map(|manager| GammaControlManager::new(manager, qh.clone()));
+    let output_power_manager = globals
+      .bind::<ZwlrOutputPowerManagerV1, _, _>(&qh, 1..=1, ())
+      .ok()
+      .map(|manager| OutputPowerManager::new(manager, qh.clone()));
 
     // `wayland-client` requires that the State struct should be 'static.
     //
@@ -51,34 +132,150 @@ impl<'a> WaylandClient<'a> {
     // `'a` outlives the future returned by `WaylandClient::run(&'a self)`.
     let static_engine_ref: &'static FlutterEngine = unsafe { std::mem::transmute(engine) };
 
-    let state = WaylandState {
+    let mut state = WaylandState {
       engine: static_engine_ref,
       registry_state: RegistryState::new(&globals),
       output_state,
       compositor_state,
       seat_state,
       layer_shell,
+      frame_clock,
+      explicit_sync,
+      tearing_control_manager,
+      content_type_manager,
+      viewporter,
+      linux_dmabuf,
+      shm,
+      input_method_manager,
+      shortcuts_inhibit_manager,
+      gamma_control_manager,
+      output_power_manager,
       pointer: None,
+      pointer_buttons: 0,
+      keyboard: None,
+      keyboard_modifiers: Modifiers::default(),
     };
+    // receive the names of outputs
+    queue.roundtrip(&mut state)?;
 
     Ok(Self {
       conn,
-      queue: UnsafeCell::new(queue),
-      state: UnsafeCell::new(state),
+      queue: RefCell::new(queue),
+      state: RefCell::new(state),
     })
   }
 
+  pub fn frame_clock(&self) -> FrameClock {
+    let state = self.state.borrow();
+    state.frame_clock.clone()
+  }
+
+  pub fn wl_compositor(&self) -> WlCompositor {
+    let state = self.state.borrow();
+    state.compositor_state.wl_compositor().clone()
+  }
+
+  pub fn layer_shell(&self) -> LayerShell {
+    let state = self.state.borrow();
+    state.layer_shell.clone()
+  }
+
+  /// `None` if the compositor doesn't support linux-drm-syncobj-v1
+  pub fn explicit_sync(&self) -> Option<ExplicitSync> {
+    let state = self.state.borrow();
+    state.explicit_sync.clone()
+  }
+
+  /// `None` if the compositor doesn't support tearing-control-v1
+  pub fn tearing_control_manager(&self) -> Option<TearingControlManager> {
+    let state = self.state.borrow();
+    state.tearing_control_manager.clone()
+  }
+
+  /// `None` if the compositor doesn't support content-type-v1
+  pub fn content_type_manager(&self) -> Option<ContentTypeManager> {
+    let state = self.state.borrow();
+    state.content_type_manager.clone()
+  }
+
+  /// `None` if the compositor doesn't support viewporter
+  pub fn viewporter(&self) -> Option<Viewporter> {
+    let state = self.state.borrow();
+    state.viewporter.clone()
+  }
+
+  /// `None` if the compositor doesn't support linux-dmabuf-v1 version 3
+  pub fn linux_dmabuf(&self) -> Option<LinuxDmabuf> {
+    let state = self.state.borrow();
+    state.linux_dmabuf.clone()
+  }
+
+  /// `None` if the compositor has no wl_shm, which it must
+  pub fn shm(&self) -> Option<Shm> {
+    let state = self.state.borrow();
+    state.shm.clone()
+  }
+
+  /// Outputs known so far
+  pub fn outputs(&self) -> Vec<Output> {
+    let state = self.state.borrow();
+    state
+      .output_state
+      .outputs()
+      .map(|wl_output| Output::new(&state.output_state, wl_output))
+      .collect()
+  }
+
+  /// `None` if the compositor doesn't support input-method-unstable-v2
+  pub fn input_method_manager(&self) -> Option<InputMethodManager> {
+    let state = self.state.borrow();
+    state.input_method_manager.clone()
+  }
+
+  /// `None` if the compositor doesn't support keyboard-shortcuts-inhibit-unstable-v1
+  pub fn shortcuts_inhibit_manager(&self) -> Option<ShortcutsInhibitManager> {
+    let state = self.state.borrow();
+    state.shortcuts_inhibit_manager.clone()
+  }
+
+  /// `None` if the compositor doesn't support wlr-gamma-control-unstable-v1
+  pub fn gamma_control_manager(&self) -> Option<GammaControlManager> {
+    let state = self.state.borrow();
+    state.gamma_control_manager.clone()
+  }
+
+  /// `None` if the compositor doesn't support wlr-output-power-management-unstable-v1
+  pub fn output_power_manager(&self) -> Option<OutputPowerManager> {
+    let state = self.state.borrow();
+    state.output_power_manager.clone()
+  }
+
+  /// The first seat, if there's any
+  pub fn seat(&self) -> Option<WlSeat> {
+    let state = self.state.borrow();
+    state.seat_state.seats().next()
+  }
+
+  /// Fails with [`ConnectionLost`] in the chain if the compositor goes away or kills the
+  /// connection.
   pub async fn run(&self) -> Result<Infallible> {
+    let Err(e) = self.dispatch().await;
+    match self.conn.protocol_error() {
+      Some(protocol_error) => Err(e.context(format!("protocol error: {}", protocol_error))),
+      None => Err(e),
+    }
+    .context(ConnectionLost)
+  }
+
+  async fn dispatch(&self) -> Result<Infallible> {
     loop {
-      // SAFETY: `Self: !Sync`, only one &mut per field inside brace,
-      // no reentrancy (Maybe I can call this again in event handlers
-      // in queue.dispatch_pending? I will never do that)
-      // and references are dropped before await point
+      // the borrows end before the await points. Event handlers must not reach back into the
+      // client, which would panic.
       {
-        let queue = unsafe { &mut *self.queue.get() };
-        let state = unsafe { &mut *self.state.get() };
+        let mut queue = self.queue.borrow_mut();
+        let mut state = self.state.borrow_mut();
         queue.flush()?;
-        queue.dispatch_pending(state)?;
+        queue.dispatch_pending(&mut *state)?;
       }
 
       let backend = self.conn.backend();
@@ -104,14 +301,91 @@ impl<'a> WaylandClient<'a> {
   }
 }
 
+/// A wl_output with its name, like `DP-1`, and description, like `Dell Inc. U2720Q (DP-1)`
+#[derive(Debug, Clone)]
+pub struct Output {
+  pub wl_output: WlOutput,
+  /// `None` if the compositor doesn't name outputs
+  pub name: Option<String>,
+  pub description: Option<String>,
+  /// the name of the wl_output global, which stays the same while the output is connected
+  pub display_id: u64,
+  /// of the current mode, in Hz. 0 if unknown.
+  pub refresh_rate: f64,
+  /// of the current mode, in pixels
+  pub size: (u32, u32),
+  pub scale: i32,
+}
+
+impl Output {
+  fn new(output_state: &OutputState, wl_output: WlOutput) -> Self {
+    let Some(info) = output_state.info(&wl_output) else {
+      // not described yet
+      return Self {
+        display_id: wl_output.id().protocol_id().into(),
+        wl_output,
+        name: None,
+        description: None,
+        refresh_rate: 0.0,
+        size: (0, 0),
+        scale: 1,
+      };
+    };
+    let (size, refresh_rate) = info
+      .modes
+      .iter()
+      .find(|mode| mode.current)
+      .map_or(((0, 0), 0.0), |mode| {
+        let (width, height) = mode.dimensions;
+        (
+          (width.max(0) as u32, height.max(0) as u32),
+          mode.refresh_rate as f64 / 1000.0,
+        )
+      });
+    Self {
+      wl_output,
+      name: info.name,
+      description: info.description,
+      display_id: info.id.into(),
+      refresh_rate,
+      size,
+      scale: info.scale_factor,
+    }
+  }
+
+  /// Case-insensitive
+  pub fn description_contains(&self, part: &str) -> bool {
+    self.description.as_ref().is_some_and(|description| {
+      description
+        .to_lowercase()
+        .contains(&part.to_lowercase())
+    })
+  }
+}
+
 struct WaylandState {
   engine: &'static FlutterEngine,
   registry_state: RegistryState,
   output_state: OutputState,
   compositor_state: CompositorState,
   seat_state: SeatState,
-  layer_shell: ZwlrLayerShellV1,
+  layer_shell: LayerShell,
+  frame_clock: FrameClock,
+  explicit_sync: Option<ExplicitSync>,
+  tearing_control_manager: Option<TearingControlManager>,
+  content_type_manager: Option<ContentTypeManager>,
+  viewporter: Option<Viewporter>,
+  linux_dmabuf: Option<LinuxDmabuf>,
+  shm: Option<Shm>,
+  input_method_manager: Option<InputMethodManager>,
+  shortcuts_inhibit_manager: Option<ShortcutsInhibitManager>,
+  gamma_control_manager: Option<GammaControlManager>,
+  output_power_manager: Option<OutputPowerManager>,
   pointer: Option<WlPointer>,
+  /// `FlutterPointerMouseButtons` held down on the pointer
+  pointer_buttons: i64,
+  keyboard: Option<WlKeyboard>,
+  keyboard_modifiers: Modifiers,
 }
 
 impl ProvidesRegistryState for WaylandState {
@@ -133,24 +407,53 @@ impl OutputHandler for WaylandState {
     &mut self,
     _conn: &Connection,
     _qh: &wayland_client::QueueHandle<Self>,
-    _output: wayland_client::protocol::wl_output::WlOutput,
+    output: wayland_client::protocol::wl_output::WlOutput,
   ) {
+    // outputs present at startup get their views in `Compositor::init`
+    let Some(state) = self.engine.try_state() else {
+      return;
+    };
+    let output = Output::new(&self.output_state, output);
+    state.output_powers.track(std::slice::from_ref(&output));
+    error_in_callback!(
+      state,
+      state.compositor.output_added(self.engine, &output),
+      return ()
+    );
   }
 
   fn update_output(
     &mut self,
     _conn: &Connection,
     _qh: &wayland_client::QueueHandle<Self>,
-    _output: wayland_client::protocol::wl_output::WlOutput,
+    output: wayland_client::protocol::wl_output::WlOutput,
   ) {
+    let Some(state) = self.engine.try_state() else {
+      return;
+    };
+    let output = Output::new(&self.output_state, output);
+    error_in_callback!(
+      state,
+      state.compositor.output_updated(self.engine, &output),
+      return ()
+    );
   }
 
   fn output_destroyed(
     &mut self,
     _conn: &Connection,
     _qh: &wayland_client::QueueHandle<Self>,
-    _output: wayland_client::protocol::wl_output::WlOutput,
+    output: wayland_client::protocol::wl_output::WlOutput,
   ) {
+    let Some(state) = self.engine.try_state() else {
+      return;
+    };
+    state.output_powers.output_removed(&output);
+    error_in_callback!(
+      state,
+      state.compositor.output_removed(self.engine, &output),
+      return ()
+    );
   }
 }
 
@@ -161,9 +464,14 @@ impl CompositorHandler for WaylandState {
     &mut self,
     _conn: &Connection,
     _qh: &wayland_client::QueueHandle<Self>,
-    _surface: &wayland_client::protocol::wl_surface::WlSurface,
-    _new_factor: i32,
+    surface: &wayland_client::protocol::wl_surface::WlSurface,
+    new_factor: i32,
   ) {
+    let state = self.engine.state();
+    let Some(view) = state.compositor.find_view_by_surface(surface) else {
+      return;
+    };
+    error_in_callback!(state, view.set_scale(self.engine, new_factor), return ());
   }
 
   fn transform_changed(
@@ -179,27 +487,63 @@ impl CompositorHandler for WaylandState {
     &mut self,
     _conn: &Connection,
     _qh: &wayland_client::QueueHandle<Self>,
-    _surface: &wayland_client::protocol::wl_surface::WlSurface,
+    surface: &wayland_client::protocol::wl_surface::WlSurface,
     _time: u32,
   ) {
+    let state = self.engine.state();
+    let Some(view) = state.compositor.find_view_by_surface(surface) else {
+      return;
+    };
+    view.frame_callback_done();
+
+    let parked_baton = state.parked_vsync_baton.lock().take();
+    if let Some(baton) = parked_baton {
+      log::debug!("{} is visible again, resume rendering", view.view_id);
+      error_in_callback!(state, self.engine.answer_vsync(baton), return ());
+      error_in_callback!(state, self.engine.schedule_frame(), return ());
+    }
   }
 
   fn surface_enter(
     &mut self,
     _conn: &Connection,
     _qh: &wayland_client::QueueHandle<Self>,
-    _surface: &wayland_client::protocol::wl_surface::WlSurface,
-    _output: &wayland_client::protocol::wl_output::WlOutput,
+    surface: &wayland_client::protocol::wl_surface::WlSurface,
+    output: &wayland_client::protocol::wl_output::WlOutput,
   ) {
+    let state = self.engine.state();
+    let Some(view) = state.compositor.find_view_by_surface(surface) else {
+      return;
+    };
+    let Some(display_id) = state.compositor.display_id(output) else {
+      return;
+    };
+    error_in_callback!(
+      state,
+      view.enter_display(self.engine, display_id),
+      return ()
+    );
   }
 
   fn surface_leave(
     &mut self,
     _conn: &Connection,
     _qh: &wayland_client::QueueHandle<Self>,
-    _surface: &wayland_client::protocol::wl_surface::WlSurface,
-    _output: &wayland_client::protocol::wl_output::WlOutput,
+    surface: &wayland_client::protocol::wl_surface::WlSurface,
+    output: &wayland_client::protocol::wl_output::WlOutput,
   ) {
+    let state = self.engine.state();
+    let Some(view) = state.compositor.find_view_by_surface(surface) else {
+      return;
+    };
+    let Some(display_id) = state.compositor.display_id(output) else {
+      return;
+    };
+    error_in_callback!(
+      state,
+      view.leave_display(self.engine, display_id),
+      return ()
+    );
   }
 }
 
@@ -239,6 +583,12 @@ impl SeatHandler for WaylandState {
         };
         self.pointer = Some(pointer);
       }
+      smithay_client_toolkit::seat::Capability::Keyboard => {
+        let Ok(keyboard) = self.seat_state.get_keyboard(qh, &seat, None) else {
+          return;
+        };
+        self.keyboard = Some(keyboard);
+      }
       _ => {}
     }
   }
@@ -256,6 +606,11 @@ impl SeatHandler for WaylandState {
           pointer.release();
         }
       }
+      smithay_client_toolkit::seat::Capability::Keyboard => {
+        if let Some(keyboard) = self.keyboard.take() {
+          keyboard.release();
+        }
+      }
       _ => {}
     }
   }
@@ -0,0 +1,68 @@
+use smithay_client_toolkit::reexports::protocols::wp::content_type::v1::client::wp_content_type_manager_v1;
+use smithay_client_toolkit::reexports::protocols::wp::content_type::v1::client::wp_content_type_manager_v1::WpContentTypeManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::content_type::v1::client::wp_content_type_v1;
+use smithay_client_toolkit::reexports::protocols::wp::content_type::v1::client::wp_content_type_v1::Type;
+use smithay_client_toolkit::reexports::protocols::wp::content_type::v1::client::wp_content_type_v1::WpContentTypeV1;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use super::WaylandState;
+
+/// content-type-v1 global
+#[derive(Clone)]
+pub struct ContentTypeManager {
+  manager: WpContentTypeManagerV1,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl ContentTypeManager {
+  pub(super) fn new(manager: WpContentTypeManagerV1, qh: QueueHandle<WaylandState>) -> Self {
+    Self { manager, qh }
+  }
+
+  pub fn get_surface_content_type(&self, surface: &WlSurface) -> SurfaceContentType {
+    SurfaceContentType(self.manager.get_surface_content_type(surface, &self.qh, ()))
+  }
+}
+
+/// Content type hint of one surface
+pub struct SurfaceContentType(WpContentTypeV1);
+
+impl SurfaceContentType {
+  /// Takes effect with the next commit.
+  pub fn set(&self, content_type: Type) {
+    self.0.set_content_type(content_type);
+  }
+}
+
+impl Drop for SurfaceContentType {
+  fn drop(&mut self) {
+    self.0.destroy();
+  }
+}
+
+impl Dispatch<WpContentTypeManagerV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WpContentTypeManagerV1,
+    _event: wp_content_type_manager_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<WpContentTypeV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WpContentTypeV1,
+    _event: wp_content_type_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
@@ -0,0 +1,223 @@
+use std::os::fd::AsFd;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use parking_lot::Mutex;
+use anyhow::Result;
+use smithay_client_toolkit::reexports::protocols::wp::linux_dmabuf::zv1::client::zwp_linux_buffer_params_v1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_dmabuf::zv1::client::zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_dmabuf::zv1::client::zwp_linux_dmabuf_v1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_dmabuf::zv1::client::zwp_linux_dmabuf_feedback_v1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_dmabuf::zv1::client::zwp_linux_dmabuf_feedback_v1::ZwpLinuxDmabufFeedbackV1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_dmabuf::zv1::client::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::delegate_noop;
+use wayland_client::globals::GlobalList;
+use wayland_client::globals::GlobalListContents;
+use wayland_client::globals::registry_queue_init;
+use wayland_client::protocol::wl_buffer;
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_registry;
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use super::WaylandState;
+use crate::opengl::gbm::Dmabuf;
+
+/// Format/modifier pairs advertised by the compositor
+type Formats = Arc<Mutex<Vec<(u32, u64)>>>;
+
+/// linux-dmabuf-v1 global, bound at version 3 where the supported modifiers come as plain
+/// events
+#[derive(Clone)]
+pub struct LinuxDmabuf {
+  manager: ZwpLinuxDmabufV1,
+  qh: QueueHandle<WaylandState>,
+  formats: Formats,
+}
+
+impl LinuxDmabuf {
+  pub(super) fn bind(globals: &GlobalList, qh: &QueueHandle<WaylandState>) -> Option<Self> {
+    let formats = Formats::default();
+    let manager = globals
+      .bind::<ZwpLinuxDmabufV1, _, _>(qh, 3..=3, formats.clone())
+      .ok()?;
+    Some(Self {
+      manager,
+      qh: qh.clone(),
+      formats,
+    })
+  }
+
+  /// Empty until the compositor has sent them after binding.
+  pub fn modifiers(&self, format: u32) -> Vec<u64> {
+    self
+      .formats
+      .lock()
+      .iter()
+      .filter(|(f, _)| *f == format)
+      .map(|(_, modifier)| *modifier)
+      .collect()
+  }
+
+  /// Wrap `dmabuf`, which holds a GL rendering, in a `wl_buffer`.
+  pub fn create_buffer(&self, dmabuf: &Dmabuf) -> DmabufBuffer {
+    let params = self.manager.create_params(&self.qh, ());
+    for (i, plane) in dmabuf.planes.iter().enumerate() {
+      params.add(
+        plane.fd.as_fd(),
+        i as u32,
+        plane.offset,
+        plane.stride,
+        (dmabuf.modifier >> 32) as u32,
+        dmabuf.modifier as u32,
+      );
+    }
+    let released = Arc::new(AtomicBool::new(true));
+    let buffer = params.create_immed(
+      dmabuf.width as i32,
+      dmabuf.height as i32,
+      dmabuf.format,
+      // GL renders bottom-up
+      zwp_linux_buffer_params_v1::Flags::YInvert,
+      &self.qh,
+      released.clone(),
+    );
+    params.destroy();
+    DmabufBuffer { buffer, released }
+  }
+}
+
+/// A `wl_buffer` tracking whether the compositor still reads from it
+#[derive(Debug)]
+pub struct DmabufBuffer {
+  buffer: WlBuffer,
+  released: Arc<AtomicBool>,
+}
+
+impl DmabufBuffer {
+  /// `false` from [`DmabufBuffer::attach`] until the compositor releases the buffer
+  pub fn is_released(&self) -> bool {
+    self.released.load(Ordering::Acquire)
+  }
+
+  /// Attach the buffer to `surface`. Takes effect with the next commit.
+  pub fn attach(&self, surface: &WlSurface) {
+    self.released.store(false, Ordering::Release);
+    surface.attach(Some(&self.buffer), 0, 0);
+  }
+}
+
+impl Drop for DmabufBuffer {
+  fn drop(&mut self) {
+    self.buffer.destroy();
+  }
+}
+
+impl Dispatch<ZwpLinuxDmabufV1, Formats> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &ZwpLinuxDmabufV1,
+    event: zwp_linux_dmabuf_v1::Event,
+    formats: &Formats,
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    // formats without modifiers are repeated by modifier events since version 3
+    if let zwp_linux_dmabuf_v1::Event::Modifier {
+      format,
+      modifier_hi,
+      modifier_lo,
+    } = event
+    {
+      let modifier = ((modifier_hi as u64) << 32) | modifier_lo as u64;
+      formats.lock().push((format, modifier));
+    }
+  }
+}
+
+impl Dispatch<ZwpLinuxBufferParamsV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &ZwpLinuxBufferParamsV1,
+    event: zwp_linux_buffer_params_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    if let zwp_linux_buffer_params_v1::Event::Failed = event {
+      log::error!("The compositor failed to import a dmabuf");
+    }
+  }
+}
+
+impl Dispatch<WlBuffer, Arc<AtomicBool>> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WlBuffer,
+    event: wl_buffer::Event,
+    released: &Arc<AtomicBool>,
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    if let wl_buffer::Event::Release = event {
+      released.store(true, Ordering::Release);
+    }
+  }
+}
+
+/// The device (`dev_t`) the compositor composites on, from the default dmabuf feedback of
+/// linux-dmabuf-v1 version 4.
+///
+/// Uses its own event queue, so it can be asked before anything else is set up.
+pub fn query_main_device(conn: &Connection) -> Result<Option<u64>> {
+  let (globals, mut queue) = registry_queue_init::<MainDeviceQuery>(conn)?;
+  let qh = queue.handle();
+  let Ok(manager) = globals.bind::<ZwpLinuxDmabufV1, _, _>(&qh, 4..=5, ()) else {
+    return Ok(None);
+  };
+  let feedback = manager.get_default_feedback(&qh, ());
+  let mut query = MainDeviceQuery::default();
+  queue.roundtrip(&mut query)?;
+  feedback.destroy();
+  manager.destroy();
+  Ok(query.main_device)
+}
+
+#[derive(Default)]
+struct MainDeviceQuery {
+  main_device: Option<u64>,
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for MainDeviceQuery {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WlRegistry,
+    _event: wl_registry::Event,
+    _data: &GlobalListContents,
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+delegate_noop!(MainDeviceQuery: ignore ZwpLinuxDmabufV1);
+
+impl Dispatch<ZwpLinuxDmabufFeedbackV1, ()> for MainDeviceQuery {
+  fn event(
+    state: &mut Self,
+    _proxy: &ZwpLinuxDmabufFeedbackV1,
+    event: zwp_linux_dmabuf_feedback_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    if let zwp_linux_dmabuf_feedback_v1::Event::MainDevice { device } = event {
+      // a `dev_t` in native byte order
+      state.main_device = device.try_into().ok().map(u64::from_ne_bytes);
+    }
+  }
+}
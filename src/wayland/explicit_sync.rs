@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::os::fd::AsFd;
+use std::os::fd::OwnedFd;
+use std::sync::Arc;
+
+use anyhow::Result;
+use smithay_client_toolkit::reexports::protocols::wp::linux_drm_syncobj::v1::client::wp_linux_drm_syncobj_manager_v1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_drm_syncobj::v1::client::wp_linux_drm_syncobj_manager_v1::WpLinuxDrmSyncobjManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_drm_syncobj::v1::client::wp_linux_drm_syncobj_surface_v1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_drm_syncobj::v1::client::wp_linux_drm_syncobj_surface_v1::WpLinuxDrmSyncobjSurfaceV1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_drm_syncobj::v1::client::wp_linux_drm_syncobj_timeline_v1;
+use smithay_client_toolkit::reexports::protocols::wp::linux_drm_syncobj::v1::client::wp_linux_drm_syncobj_timeline_v1::WpLinuxDrmSyncobjTimelineV1;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use super::WaylandState;
+use crate::opengl::drm_syncobj::DrmDevice;
+use crate::opengl::drm_syncobj::DrmTimeline;
+
+/// linux-drm-syncobj-v1 global
+#[derive(Clone)]
+pub struct ExplicitSync {
+  manager: WpLinuxDrmSyncobjManagerV1,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl ExplicitSync {
+  pub(super) fn new(manager: WpLinuxDrmSyncobjManagerV1, qh: QueueHandle<WaylandState>) -> Self {
+    Self { manager, qh }
+  }
+
+  /// Take over buffer synchronization of `surface`.
+  ///
+  /// Every commit with a buffer must carry acquire and release points from then on, see
+  /// [`SurfaceSync`].
+  pub fn surface_sync(&self, surface: &WlSurface, device: &Arc<DrmDevice>) -> Result<SurfaceSync> {
+    let timeline = DrmTimeline::new(device)?;
+    let timeline_proxy = self
+      .manager
+      .import_timeline(timeline.export()?.as_fd(), &self.qh, ());
+    let syncobj_surface = self.manager.get_surface(surface, &self.qh, ());
+    Ok(SurfaceSync {
+      syncobj_surface,
+      timeline_proxy,
+      timeline,
+      last_point: 0,
+      pending_releases: VecDeque::new(),
+    })
+  }
+}
+
+/// Acquire/release points of one surface, all on a single timeline.
+///
+/// Even points are acquire points (rendering done), odd points are release points (the
+/// compositor is done reading the buffer).
+pub struct SurfaceSync {
+  syncobj_surface: WpLinuxDrmSyncobjSurfaceV1,
+  timeline_proxy: WpLinuxDrmSyncobjTimelineV1,
+  timeline: DrmTimeline,
+  last_point: u64,
+  /// release points of commits the compositor may still read from
+  pending_releases: VecDeque<u64>,
+}
+
+impl SurfaceSync {
+  /// Wait until the buffer about to be drawn into is released by the compositor.
+  ///
+  /// Only the buffer of the latest commit may still be in use, like with double buffering.
+  pub fn wait_for_release(&mut self) -> Result<()> {
+    while self.pending_releases.len() > 1 {
+      let point = self.pending_releases.pop_front().unwrap();
+      self.timeline.wait(point)?;
+    }
+    Ok(())
+  }
+
+  /// Attach acquire and release points to the next commit. The buffer becomes readable once
+  /// `rendering_done` signals.
+  pub fn set_points(&mut self, rendering_done: &OwnedFd) -> Result<()> {
+    let acquire = self.last_point + 1;
+    let release = self.last_point + 2;
+    self.timeline.import_sync_file(acquire, rendering_done)?;
+    self.syncobj_surface.set_acquire_point(
+      &self.timeline_proxy,
+      (acquire >> 32) as u32,
+      acquire as u32,
+    );
+    self.syncobj_surface.set_release_point(
+      &self.timeline_proxy,
+      (release >> 32) as u32,
+      release as u32,
+    );
+    self.last_point = release;
+    self.pending_releases.push_back(release);
+    Ok(())
+  }
+}
+
+impl Drop for SurfaceSync {
+  fn drop(&mut self) {
+    self.syncobj_surface.destroy();
+    self.timeline_proxy.destroy();
+  }
+}
+
+impl Dispatch<WpLinuxDrmSyncobjManagerV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WpLinuxDrmSyncobjManagerV1,
+    _event: wp_linux_drm_syncobj_manager_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<WpLinuxDrmSyncobjSurfaceV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WpLinuxDrmSyncobjSurfaceV1,
+    _event: wp_linux_drm_syncobj_surface_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<WpLinuxDrmSyncobjTimelineV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WpLinuxDrmSyncobjTimelineV1,
+    _event: wp_linux_drm_syncobj_timeline_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use smithay_client_toolkit::reexports::protocols_wlr::gamma_control::v1::client::zwlr_gamma_control_manager_v1;
+use smithay_client_toolkit::reexports::protocols_wlr::gamma_control::v1::client::zwlr_gamma_control_manager_v1::ZwlrGammaControlManagerV1;
+use smithay_client_toolkit::reexports::protocols_wlr::gamma_control::v1::client::zwlr_gamma_control_v1;
+use smithay_client_toolkit::reexports::protocols_wlr::gamma_control::v1::client::zwlr_gamma_control_v1::ZwlrGammaControlV1;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::protocol::wl_output::WlOutput;
+
+use super::WaylandState;
+use crate::gamma::ColorSettings;
+
+/// wlr-gamma-control-unstable-v1 global
+#[derive(Clone)]
+pub struct GammaControlManager {
+  manager: ZwlrGammaControlManagerV1,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl GammaControlManager {
+  pub(super) fn new(manager: ZwlrGammaControlManagerV1, qh: QueueHandle<WaylandState>) -> Self {
+    Self { manager, qh }
+  }
+
+  /// Take the gamma of `output`. At most one client has it at a time.
+  pub fn get_gamma_control(&self, output: &WlOutput) -> GammaControl {
+    let state = Arc::new(Mutex::new(GammaState::default()));
+    GammaControl {
+      control: self
+        .manager
+        .get_gamma_control(output, &self.qh, state.clone()),
+      state,
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct GammaState {
+  /// entries per ramp, `None` until the compositor told
+  size: Option<usize>,
+  /// set before the size was known
+  pending: Option<ColorSettings>,
+  failed: bool,
+}
+
+/// Gamma of one output, restored by the compositor when dropped
+pub struct GammaControl {
+  control: ZwlrGammaControlV1,
+  state: Arc<Mutex<GammaState>>,
+}
+
+impl GammaControl {
+  /// Set the gamma ramps, once the compositor told their size if it didn't yet.
+  pub fn set(&self, settings: ColorSettings) -> Result<()> {
+    let mut state = self.state.lock();
+    match state.size {
+      Some(size) => set_gamma(&self.control, &settings.ramps(size)),
+      None => {
+        state.pending = Some(settings);
+        Ok(())
+      }
+    }
+  }
+
+  /// The output doesn't support gamma tables, or another client took them.
+  pub fn failed(&self) -> bool {
+    self.state.lock().failed
+  }
+}
+
+impl Drop for GammaControl {
+  fn drop(&mut self) {
+    self.control.destroy();
+  }
+}
+
+/// `ramps` are the red, green and blue ramps one after another.
+fn set_gamma(control: &ZwlrGammaControlV1, ramps: &[u16]) -> Result<()> {
+  let fd = unsafe { libc::memfd_create(c"wayflutter-gamma".as_ptr(), libc::MFD_CLOEXEC) };
+  if fd < 0 {
+    return Err(std::io::Error::last_os_error().into());
+  }
+  let mut file = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+  let bytes = ramps
+    .iter()
+    .flat_map(|entry| entry.to_ne_bytes())
+    .collect::<Vec<_>>();
+  file.write_all(&bytes)?;
+  // the compositor reads from the offset of the fd
+  file.seek(SeekFrom::Start(0))?;
+  control.set_gamma(file.as_fd());
+  Ok(())
+}
+
+impl Dispatch<ZwlrGammaControlManagerV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &ZwlrGammaControlManagerV1,
+    _event: zwlr_gamma_control_manager_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<ZwlrGammaControlV1, Arc<Mutex<GammaState>>> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    proxy: &ZwlrGammaControlV1,
+    event: zwlr_gamma_control_v1::Event,
+    data: &Arc<Mutex<GammaState>>,
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    let mut state = data.lock();
+    match event {
+      zwlr_gamma_control_v1::Event::GammaSize { size } => {
+        let size = size as usize;
+        state.size = Some(size);
+        if let Some(settings) = state.pending.take()
+          && let Err(e) = set_gamma(proxy, &settings.ramps(size))
+        {
+          log::warn!("Failed to set the gamma: {:#}", e);
+        }
+      }
+      zwlr_gamma_control_v1::Event::Failed => {
+        log::warn!("Gamma control failed: the output doesn't support it, or another client has it");
+        state.failed = true;
+      }
+      _ => {}
+    }
+  }
+}
@@ -0,0 +1,238 @@
+//! Input from the control socket, for scripted tests of the app. It becomes the same engine
+//! events as input from the compositor, from a pointer and keyboard of its own.
+
+use anyhow::Context;
+use anyhow::Result;
+use parking_lot::Mutex;
+use smithay_client_toolkit::seat::keyboard::KeyEvent;
+use smithay_client_toolkit::seat::keyboard::Keysym;
+use smithay_client_toolkit::seat::keyboard::Modifiers;
+use xkbcommon::xkb;
+
+use crate::FlutterEngine;
+use crate::compositor::ViewId;
+use crate::ffi;
+use crate::ipc::PointerButton;
+
+/// Apart from the pointer of the seat, so that the two don't disturb each other
+const DEVICE: i32 = 1;
+
+/// The view the injected pointer is over
+#[derive(Default)]
+pub struct InjectedPointer {
+  view: Mutex<Option<ViewId>>,
+}
+
+/// What the injected pointer does at a point of a view
+#[derive(Debug, Clone, Copy)]
+pub enum PointerAction {
+  Move,
+  Click(PointerButton),
+  /// by logical pixels
+  Scroll {
+    dx: f64,
+    dy: f64,
+  },
+}
+
+/// Move the injected pointer to `(x, y)` in logical pixels of `view_id`, then act there.
+pub fn pointer(
+  engine: &FlutterEngine,
+  view_id: ViewId,
+  (x, y): (f64, f64),
+  action: PointerAction,
+) -> Result<()> {
+  let state = engine.state();
+  let view = state
+    .compositor
+    .get_view(view_id)
+    .with_context(|| format!("{} not found", view_id))?;
+  let scale = view
+    .geometry
+    .lock()
+    .current
+    .map_or(1.0, |current| current.pixel_ratio());
+  let timestamp = unsafe { ffi::FlutterEngineGetCurrentTime() } as usize / 1000;
+  let base = ffi::FlutterPointerEvent {
+    struct_size: size_of::<ffi::FlutterPointerEvent>(),
+    phase: ffi::FlutterPointerPhase_kHover,
+    timestamp,
+    x: x * scale,
+    y: y * scale,
+    device: DEVICE,
+    signal_kind: ffi::FlutterPointerSignalKind_kFlutterPointerSignalKindNone,
+    scroll_delta_x: 0.0,
+    scroll_delta_y: 0.0,
+    device_kind: ffi::FlutterPointerDeviceKind_kFlutterPointerDeviceKindMouse,
+    buttons: 0,
+    pan_x: 0.0,
+    pan_y: 0.0,
+    scale: 1.0,
+    rotation: 0.0,
+    view_id: view_id.raw(),
+  };
+
+  let mut events = Vec::new();
+  let mut current = state.injected_pointer.view.lock();
+  if *current != Some(view_id) {
+    if let Some(previous) = *current
+      && state.compositor.get_view(previous).is_some()
+    {
+      events.push(ffi::FlutterPointerEvent {
+        phase: ffi::FlutterPointerPhase_kRemove,
+        view_id: previous.raw(),
+        ..base
+      });
+    }
+    events.push(ffi::FlutterPointerEvent {
+      phase: ffi::FlutterPointerPhase_kAdd,
+      ..base
+    });
+    *current = Some(view_id);
+  }
+  events.push(base);
+  match action {
+    PointerAction::Move => {}
+    PointerAction::Click(button) => {
+      let buttons = flutter_button(button);
+      events.push(ffi::FlutterPointerEvent {
+        phase: ffi::FlutterPointerPhase_kDown,
+        buttons,
+        ..base
+      });
+      events.push(ffi::FlutterPointerEvent {
+        phase: ffi::FlutterPointerPhase_kUp,
+        ..base
+      });
+    }
+    PointerAction::Scroll { dx, dy } => events.push(ffi::FlutterPointerEvent {
+      signal_kind: ffi::FlutterPointerSignalKind_kFlutterPointerSignalKindScroll,
+      scroll_delta_x: dx * scale,
+      scroll_delta_y: dy * scale,
+      ..base
+    }),
+  }
+  engine.send_pointer_events(&events)
+}
+
+fn flutter_button(button: PointerButton) -> i64 {
+  let button = match button {
+    PointerButton::Left => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMousePrimary,
+    PointerButton::Right => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMouseSecondary,
+    PointerButton::Middle => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMouseMiddle,
+    PointerButton::Back => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMouseBack,
+    PointerButton::Forward => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMouseForward,
+  };
+  button as i64
+}
+
+/// Focus `view_id`, then press and release each key, given by its keysym name with modifiers
+/// like `ctrl+shift+Tab`.
+pub fn keys(engine: &FlutterEngine, view_id: ViewId, keys: &[String]) -> Result<()> {
+  let keymap = Keymap::new()?;
+  let keys = keys
+    .iter()
+    .map(|key| keymap.parse(key))
+    .collect::<Result<Vec<_>>>()?;
+  press(engine, view_id, &keys)
+}
+
+/// Focus `view_id`, then type `text` key by key.
+pub fn text(engine: &FlutterEngine, view_id: ViewId, text: &str) -> Result<()> {
+  let keymap = Keymap::new()?;
+  let keys = text
+    .chars()
+    .map(|c| {
+      keymap
+        .key(Keysym::from_char(c), Modifiers::default())
+        .with_context(|| format!("no key types {:?}", c))
+    })
+    .collect::<Result<Vec<_>>>()?;
+  press(engine, view_id, &keys)
+}
+
+fn press(engine: &FlutterEngine, view_id: ViewId, keys: &[Key]) -> Result<()> {
+  super::keyboard::move_focus(engine, Some(view_id))?;
+  for key in keys {
+    let event = KeyEvent {
+      time: 0,
+      raw_code: key.code,
+      keysym: key.keysym,
+      utf8: key.keysym.key_char().map(String::from),
+    };
+    super::keyboard::send_key_event(engine, "keydown", &event, key.modifiers)?;
+    let event = KeyEvent {
+      utf8: None,
+      ..event
+    };
+    super::keyboard::send_key_event(engine, "keyup", &event, key.modifiers)?;
+  }
+  Ok(())
+}
+
+struct Key {
+  /// evdev code, like [`KeyEvent::raw_code`]
+  code: u32,
+  keysym: Keysym,
+  modifiers: Modifiers,
+}
+
+/// The layout of `XKB_DEFAULT_LAYOUT` and friends, for the keys that type keysyms. The keymap
+/// of the seat stays with the toolkit.
+struct Keymap(xkb::Keymap);
+
+impl Keymap {
+  fn new() -> Result<Self> {
+    let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+    let keymap = xkb::Keymap::new_from_names(&context, "", "", "", "", None, 0)
+      .context("failed to compile the default keymap")?;
+    Ok(Self(keymap))
+  }
+
+  /// `ctrl+a`, `shift+Tab` or `Return`
+  fn parse(&self, key: &str) -> Result<Key> {
+    let (modifier_names, name) = key.rsplit_once('+').unwrap_or(("", key));
+    let mut modifiers = Modifiers::default();
+    for modifier in modifier_names.split('+').filter(|name| !name.is_empty()) {
+      match modifier.to_lowercase().as_str() {
+        "ctrl" | "control" => modifiers.ctrl = true,
+        "shift" => modifiers.shift = true,
+        "alt" => modifiers.alt = true,
+        "super" | "logo" => modifiers.logo = true,
+        _ => anyhow::bail!("unknown modifier {:?} in {:?}", modifier, key),
+      }
+    }
+    let mut keysym = xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS);
+    if keysym == Keysym::NoSymbol {
+      keysym = xkb::keysym_from_name(name, xkb::KEYSYM_CASE_INSENSITIVE);
+    }
+    if keysym == Keysym::NoSymbol {
+      anyhow::bail!("unknown key {:?}", name);
+    }
+    self
+      .key(keysym, modifiers)
+      .with_context(|| format!("no key for {:?} in the default keymap", name))
+  }
+
+  /// The key of `keysym` in the first layout, with shift if it's on the second level
+  fn key(&self, keysym: Keysym, mut modifiers: Modifiers) -> Option<Key> {
+    let keymap = &self.0;
+    for level in 0..2 {
+      for code in keymap.min_keycode().raw()..=keymap.max_keycode().raw() {
+        if keymap
+          .key_get_syms_by_level(code.into(), 0, level)
+          .contains(&keysym)
+        {
+          modifiers.shift |= level == 1;
+          return Some(Key {
+            // xkb keycodes are evdev codes offset by 8
+            code: code - 8,
+            keysym,
+            modifiers,
+          });
+        }
+      }
+    }
+    None
+  }
+}
@@ -0,0 +1,262 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde::Serialize;
+use smithay_client_toolkit::compositor::CompositorState;
+use smithay_client_toolkit::compositor::Surface;
+use smithay_client_toolkit::globals::ProvidesBoundGlobal;
+use smithay_client_toolkit::reexports::protocols_misc::zwp_input_method_v2::client::zwp_input_method_manager_v2;
+use smithay_client_toolkit::reexports::protocols_misc::zwp_input_method_v2::client::zwp_input_method_manager_v2::ZwpInputMethodManagerV2;
+use smithay_client_toolkit::reexports::protocols_misc::zwp_input_method_v2::client::zwp_input_method_v2;
+use smithay_client_toolkit::reexports::protocols_misc::zwp_input_method_v2::client::zwp_input_method_v2::ZwpInputMethodV2;
+use smithay_client_toolkit::reexports::protocols_misc::zwp_input_method_v2::client::zwp_input_popup_surface_v2;
+use smithay_client_toolkit::reexports::protocols_misc::zwp_input_method_v2::client::zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2;
+use smithay_client_toolkit::reexports::protocols::wp::text_input::zv3::client::zwp_text_input_v3::ChangeCause;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::WEnum;
+use wayland_client::protocol::wl_compositor::WlCompositor;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use super::WaylandState;
+use crate::compositor;
+use crate::error_in_callback;
+
+/// input-method-unstable-v2 global
+#[derive(Clone)]
+pub struct InputMethodManager {
+  manager: ZwpInputMethodManagerV2,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl InputMethodManager {
+  pub(super) fn new(manager: ZwpInputMethodManagerV2, qh: QueueHandle<WaylandState>) -> Self {
+    Self { manager, qh }
+  }
+
+  /// Become the input method of `seat`. Only one client per seat can be, so this is done only
+  /// when a view needs it.
+  pub fn get_input_method(&self, seat: &WlSeat) -> InputMethod {
+    let data = Arc::new(Mutex::new(InputMethodData::default()));
+    InputMethod {
+      input_method: self.manager.get_input_method(seat, &self.qh, data.clone()),
+      qh: self.qh.clone(),
+      data,
+    }
+  }
+}
+
+/// The focused text input, as of the last `done`
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextInputState {
+  /// whether a text input is focused and takes our input
+  pub active: bool,
+  pub surrounding_text: Option<SurroundingText>,
+  /// whether the last change of the text was ours rather than the app's
+  pub changed_by_input_method: bool,
+  /// `zwp_text_input_v3.content_hint` flags
+  pub content_hint: u32,
+  /// a `zwp_text_input_v3.content_purpose`
+  pub content_purpose: u32,
+}
+
+/// The text around the cursor, with byte offsets into it
+#[derive(Debug, Clone, Serialize)]
+pub struct SurroundingText {
+  pub text: String,
+  pub cursor: u32,
+  /// the other end of the selection, `cursor` if nothing is selected
+  pub anchor: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct InputMethodData {
+  /// applied on `done`
+  pending: TextInputState,
+  /// the number of `done` events, which commits refer to
+  serial: u32,
+  unavailable: bool,
+}
+
+pub struct InputMethod {
+  input_method: ZwpInputMethodV2,
+  qh: QueueHandle<WaylandState>,
+  data: Arc<Mutex<InputMethodData>>,
+}
+
+impl InputMethod {
+  /// Replace the preedit text, which is shown with the cursor between the byte offsets
+  /// `cursor_begin` and `cursor_end`, or hidden if they are -1. Takes effect on [`Self::commit`].
+  pub fn set_preedit_string(&self, text: String, cursor_begin: i32, cursor_end: i32) {
+    self
+      .input_method
+      .set_preedit_string(text, cursor_begin, cursor_end);
+  }
+
+  /// Insert `text` at the cursor, replacing the selection. Takes effect on [`Self::commit`].
+  pub fn commit_string(&self, text: String) {
+    self.input_method.commit_string(text);
+  }
+
+  /// Delete bytes around the cursor, or the selection. Takes effect on [`Self::commit`].
+  pub fn delete_surrounding_text(&self, before_length: u32, after_length: u32) {
+    self
+      .input_method
+      .delete_surrounding_text(before_length, after_length);
+  }
+
+  /// Apply the pending changes to the text input, all at once.
+  pub fn commit(&self) -> Result<()> {
+    let data = self.data.lock();
+    if data.unavailable {
+      anyhow::bail!("another input method is running on the seat");
+    }
+    self.input_method.commit(data.serial);
+    Ok(())
+  }
+
+  /// A surface shown by the compositor next to the focused text input while the input method
+  /// is active
+  pub fn create_popup_surface(
+    &self,
+    compositor: &impl ProvidesBoundGlobal<WlCompositor, { CompositorState::API_VERSION_MAX }>,
+  ) -> Result<InputPopupSurface> {
+    let surface = Surface::new(compositor, &self.qh)?;
+    let popup = self
+      .input_method
+      .get_input_popup_surface(surface.wl_surface(), &self.qh, ());
+    Ok(InputPopupSurface { surface, popup })
+  }
+}
+
+impl Drop for InputMethod {
+  fn drop(&mut self) {
+    self.input_method.destroy();
+  }
+}
+
+pub struct InputPopupSurface {
+  surface: Surface,
+  popup: ZwpInputPopupSurfaceV2,
+}
+
+impl InputPopupSurface {
+  pub fn wl_surface(&self) -> &WlSurface {
+    self.surface.wl_surface()
+  }
+}
+
+impl Drop for InputPopupSurface {
+  fn drop(&mut self) {
+    // before `surface` destroys the wl_surface
+    self.popup.destroy();
+  }
+}
+
+impl Dispatch<ZwpInputMethodManagerV2, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &ZwpInputMethodManagerV2,
+    _event: zwp_input_method_manager_v2::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<ZwpInputMethodV2, Arc<Mutex<InputMethodData>>> for WaylandState {
+  fn event(
+    state: &mut Self,
+    _proxy: &ZwpInputMethodV2,
+    event: zwp_input_method_v2::Event,
+    data: &Arc<Mutex<InputMethodData>>,
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    let mut data = data.lock();
+    match event {
+      zwp_input_method_v2::Event::Activate => {
+        log::debug!("Input method activated");
+        // a new text input, nothing carries over
+        data.pending = TextInputState {
+          active: true,
+          ..Default::default()
+        };
+      }
+      zwp_input_method_v2::Event::Deactivate => {
+        log::debug!("Input method deactivated");
+        data.pending = TextInputState::default();
+      }
+      zwp_input_method_v2::Event::SurroundingText {
+        text,
+        cursor,
+        anchor,
+      } => {
+        data.pending.surrounding_text = Some(SurroundingText {
+          text,
+          cursor,
+          anchor,
+        });
+      }
+      zwp_input_method_v2::Event::TextChangeCause { cause } => {
+        data.pending.changed_by_input_method =
+          matches!(cause, WEnum::Value(ChangeCause::InputMethod));
+      }
+      zwp_input_method_v2::Event::ContentType { hint, purpose } => {
+        data.pending.content_hint = hint.into();
+        data.pending.content_purpose = purpose.into();
+      }
+      zwp_input_method_v2::Event::Done => {
+        data.serial = data.serial.wrapping_add(1);
+        let text_input = data.pending.clone();
+        // unlocked, as Dart may commit right away
+        drop(data);
+        let Some(engine_state) = state.engine.try_state() else {
+          return;
+        };
+        error_in_callback!(
+          engine_state,
+          compositor::input_method::done(state.engine, text_input),
+          return ()
+        );
+      }
+      zwp_input_method_v2::Event::Unavailable => {
+        log::warn!("Input popups won't be shown: another input method is running on the seat");
+        data.unavailable = true;
+      }
+      _ => {}
+    }
+  }
+}
+
+impl Dispatch<ZwpInputPopupSurfaceV2, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &ZwpInputPopupSurfaceV2,
+    event: zwp_input_popup_surface_v2::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    if let zwp_input_popup_surface_v2::Event::TextInputRectangle {
+      x,
+      y,
+      width,
+      height,
+    } = event
+    {
+      log::debug!(
+        "Input popup placed next to the text input at ({}, {}) {}x{}",
+        x,
+        y,
+        width,
+        height
+      );
+    }
+  }
+}
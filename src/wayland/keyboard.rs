@@ -0,0 +1,193 @@
+use anyhow::Result;
+use serde_json::json;
+use smithay_client_toolkit::delegate_keyboard;
+use smithay_client_toolkit::seat::keyboard::KeyEvent;
+use smithay_client_toolkit::seat::keyboard::KeyboardHandler;
+use smithay_client_toolkit::seat::keyboard::Keysym;
+use smithay_client_toolkit::seat::keyboard::Modifiers;
+use smithay_client_toolkit::seat::keyboard::RawModifiers;
+use wayland_client::Connection;
+use wayland_client::QueueHandle;
+use wayland_client::protocol::wl_keyboard::WlKeyboard;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use crate::FlutterEngine;
+use crate::compositor::ViewId;
+use crate::compositor::popup;
+use crate::error_in_callback;
+
+/// Key events in the format of the GTK embedder, from which the framework derives both
+/// `RawKeyEvent`s and `KeyEvent`s. They go to the focused view.
+const KEY_EVENT_CHANNEL: &str = "flutter/keyevent";
+
+// GdkModifierType
+const GDK_SHIFT_MASK: u32 = 1 << 0;
+const GDK_LOCK_MASK: u32 = 1 << 1;
+const GDK_CONTROL_MASK: u32 = 1 << 2;
+const GDK_MOD1_MASK: u32 = 1 << 3;
+const GDK_MOD2_MASK: u32 = 1 << 4;
+const GDK_SUPER_MASK: u32 = 1 << 26;
+
+impl KeyboardHandler for super::WaylandState {
+  fn enter(
+    &mut self,
+    _conn: &Connection,
+    _qh: &QueueHandle<Self>,
+    _keyboard: &WlKeyboard,
+    surface: &WlSurface,
+    _serial: u32,
+    _raw: &[u32],
+    _keysyms: &[Keysym],
+  ) {
+    let state = self.engine.state();
+    let view_id = state
+      .compositor
+      .find_view_by_surface(surface)
+      .map(|view| view.view_id);
+    self.keyboard_result(move_focus(self.engine, view_id));
+  }
+
+  fn leave(
+    &mut self,
+    _conn: &Connection,
+    _qh: &QueueHandle<Self>,
+    _keyboard: &WlKeyboard,
+    _surface: &WlSurface,
+    _serial: u32,
+  ) {
+    self.keyboard_result(move_focus(self.engine, None));
+  }
+
+  fn press_key(
+    &mut self,
+    _conn: &Connection,
+    _qh: &QueueHandle<Self>,
+    _keyboard: &WlKeyboard,
+    _serial: u32,
+    event: KeyEvent,
+  ) {
+    self.keyboard_result(send_key_event(
+      self.engine,
+      "keydown",
+      &event,
+      self.keyboard_modifiers,
+    ));
+  }
+
+  fn repeat_key(
+    &mut self,
+    _conn: &Connection,
+    _qh: &QueueHandle<Self>,
+    _keyboard: &WlKeyboard,
+    _serial: u32,
+    event: KeyEvent,
+  ) {
+    self.keyboard_result(send_key_event(
+      self.engine,
+      "keydown",
+      &event,
+      self.keyboard_modifiers,
+    ));
+  }
+
+  fn release_key(
+    &mut self,
+    _conn: &Connection,
+    _qh: &QueueHandle<Self>,
+    _keyboard: &WlKeyboard,
+    _serial: u32,
+    event: KeyEvent,
+  ) {
+    self.keyboard_result(send_key_event(
+      self.engine,
+      "keyup",
+      &event,
+      self.keyboard_modifiers,
+    ));
+  }
+
+  fn update_modifiers(
+    &mut self,
+    _conn: &Connection,
+    _qh: &QueueHandle<Self>,
+    _keyboard: &WlKeyboard,
+    _serial: u32,
+    modifiers: Modifiers,
+    _raw_modifiers: RawModifiers,
+    _layout: u32,
+  ) {
+    self.keyboard_modifiers = modifiers;
+  }
+}
+
+delegate_keyboard!(super::WaylandState);
+
+impl super::WaylandState {
+  fn keyboard_result(&self, result: Result<()>) {
+    let state = self.engine.state();
+    error_in_callback!(state, result, return ());
+  }
+}
+
+/// Dismisses a popup losing the focus.
+pub(super) fn move_focus(engine: &FlutterEngine, view_id: Option<ViewId>) -> Result<()> {
+  let state = engine.state();
+  let previous = state.compositor.set_keyboard_focus(engine, view_id)?;
+  if let Some(previous) = previous
+    && view_id != Some(previous)
+    && popup::is_popup(engine, previous)
+  {
+    popup::dismiss(engine, previous, "focusLost")?;
+  }
+  Ok(())
+}
+
+/// Escape dismisses a focused popup instead of reaching the app.
+pub(super) fn send_key_event(
+  engine: &FlutterEngine,
+  kind: &str,
+  event: &KeyEvent,
+  modifiers: Modifiers,
+) -> Result<()> {
+  let state = engine.state();
+  let Some(focus) = state.compositor.keyboard_focus() else {
+    // e.g. the focused view was removed
+    return Ok(());
+  };
+  if event.keysym == Keysym::Escape && popup::is_popup(engine, focus) {
+    if kind == "keydown" {
+      popup::dismiss(engine, focus, "escape")?;
+    }
+    return Ok(());
+  }
+  let unicode = event
+    .utf8
+    .as_deref()
+    .and_then(|text| text.chars().next())
+    .map_or(0, u32::from);
+  let message = json!({
+    "type": kind,
+    "keymap": "linux",
+    "toolkit": "gtk",
+    // xkb keycodes are evdev codes offset by 8, like GDK's hardware keycodes
+    "scanCode": event.raw_code + 8,
+    "keyCode": event.keysym.raw(),
+    "modifiers": gdk_modifiers(modifiers),
+    "unicodeScalarValues": unicode,
+  });
+  engine.send_platform_message(KEY_EVENT_CHANNEL, message.to_string().as_bytes())
+}
+
+fn gdk_modifiers(modifiers: Modifiers) -> u32 {
+  [
+    (modifiers.shift, GDK_SHIFT_MASK),
+    (modifiers.caps_lock, GDK_LOCK_MASK),
+    (modifiers.ctrl, GDK_CONTROL_MASK),
+    (modifiers.alt, GDK_MOD1_MASK),
+    (modifiers.num_lock, GDK_MOD2_MASK),
+    (modifiers.logo, GDK_SUPER_MASK),
+  ]
+  .into_iter()
+  .filter(|(pressed, _)| *pressed)
+  .fold(0, |mask, (_, bit)| mask | bit)
+}
@@ -1,5 +1,6 @@
 use anyhow::Result;
 use bon::Builder;
+use smithay_client_toolkit::compositor::CompositorState;
 use smithay_client_toolkit::compositor::Surface;
 use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::Layer;
 use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::KeyboardInteractivity;
@@ -7,14 +8,18 @@ use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::z
 use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::Anchor;
 use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1;
 use smithay_client_toolkit::reexports::protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::ZwlrLayerShellV1;
+use smithay_client_toolkit::globals::ProvidesBoundGlobal;
+use wayland_client::protocol::wl_compositor::WlCompositor;
 use wayland_client::protocol::wl_output::WlOutput;
 use wayland_client::protocol::wl_surface::WlSurface;
 use wayland_client::Connection;
 use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
 
+use super::WaylandState;
 use crate::FlutterEngine;
 
-type LayerSurfaceEventListener<T> = for<'a> fn(&'a FlutterEngine, zwlr_layer_surface_v1::Event, &T);
+pub type LayerSurfaceEventListener<T> = for<'a> fn(&'a FlutterEngine, zwlr_layer_surface_v1::Event, &T);
 
 #[derive(Builder)]
 pub struct CreateLayerSurfaceProp<T> {
@@ -62,28 +67,39 @@ impl LayerSurface {
   }
 }
 
-pub trait WaylandClientLayerSurfaceExt {
-  fn create_layer_surface<T: Send + Sync + 'static>(
-    &self,
-    prop: CreateLayerSurfaceProp<T>,
-  ) -> Result<LayerSurface>;
+impl Drop for LayerSurface {
+  fn drop(&mut self) {
+    // before `surface` destroys the wl_surface
+    self.wlr_layer_surface.destroy();
+  }
 }
 
-impl WaylandClientLayerSurfaceExt for super::WaylandClient<'_> {
-  fn create_layer_surface<T: Send + Sync + 'static>(
+/// zwlr_layer_shell_v1 global, usable wherever surfaces are created
+#[derive(Clone)]
+pub struct LayerShell {
+  layer_shell: ZwlrLayerShellV1,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl LayerShell {
+  pub(super) fn new(layer_shell: ZwlrLayerShellV1, qh: QueueHandle<WaylandState>) -> Self {
+    Self { layer_shell, qh }
+  }
+
+  pub fn create_layer_surface<T: Send + Sync + 'static>(
     &self,
+    compositor: &impl ProvidesBoundGlobal<WlCompositor, { CompositorState::API_VERSION_MAX }>,
     prop: CreateLayerSurfaceProp<T>,
   ) -> Result<LayerSurface> {
     let layer_surface = {
-      let state = unsafe { &mut *self.state.get() };
-      let qh = unsafe { (&*self.queue.get()).handle() };
-      let surface = Surface::new(&state.compositor_state, &qh)?;
-      let wlr_layer_surface = state.layer_shell.get_layer_surface(
+      let qh = &self.qh;
+      let surface = Surface::new(compositor, qh)?;
+      let wlr_layer_surface = self.layer_shell.get_layer_surface(
         surface.wl_surface(),
         prop.output.as_ref(),
         prop.layer,
         prop.namespace.unwrap_or_default(),
-        &qh,
+        qh,
         (prop.event_listener.unwrap_or(|_, _, _| {}), prop.user_data),
       );
 
@@ -125,7 +141,7 @@ impl WaylandClientLayerSurfaceExt for super::WaylandClient<'_> {
   }
 }
 
-impl Dispatch<ZwlrLayerShellV1, ()> for super::WaylandState {
+impl Dispatch<ZwlrLayerShellV1, ()> for WaylandState {
   fn event(
     _state: &mut Self,
     _proxy: &ZwlrLayerShellV1,
@@ -138,7 +154,7 @@ impl Dispatch<ZwlrLayerShellV1, ()> for super::WaylandState {
   }
 }
 
-impl<T> Dispatch<ZwlrLayerSurfaceV1, (LayerSurfaceEventListener<T>, T)> for super::WaylandState {
+impl<T> Dispatch<ZwlrLayerSurfaceV1, (LayerSurfaceEventListener<T>, T)> for WaylandState {
   fn event(
     state: &mut Self,
     _proxy: &ZwlrLayerSurfaceV1,
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::zwlr_output_power_manager_v1;
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::zwlr_output_power_manager_v1::ZwlrOutputPowerManagerV1;
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::zwlr_output_power_v1;
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::zwlr_output_power_v1::Mode;
+use smithay_client_toolkit::reexports::protocols_wlr::output_power_management::v1::client::zwlr_output_power_v1::ZwlrOutputPowerV1;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::WEnum;
+use wayland_client::protocol::wl_output::WlOutput;
+
+use super::WaylandState;
+
+/// wlr-output-power-management-unstable-v1 global
+#[derive(Clone)]
+pub struct OutputPowerManager {
+  manager: ZwlrOutputPowerManagerV1,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl OutputPowerManager {
+  pub(super) fn new(manager: ZwlrOutputPowerManagerV1, qh: QueueHandle<WaylandState>) -> Self {
+    Self { manager, qh }
+  }
+
+  pub fn get_output_power(&self, output: &WlOutput) -> OutputPower {
+    let state = Arc::new(Mutex::new(OutputPowerState::default()));
+    OutputPower {
+      power: self
+        .manager
+        .get_output_power(output, &self.qh, state.clone()),
+      state,
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct OutputPowerState {
+  /// `None` until the compositor told
+  on: Option<bool>,
+  failed: bool,
+}
+
+/// Power mode of one output
+pub struct OutputPower {
+  power: ZwlrOutputPowerV1,
+  state: Arc<Mutex<OutputPowerState>>,
+}
+
+impl OutputPower {
+  /// Turn the output on or off. Takes effect immediately.
+  pub fn set_on(&self, on: bool) {
+    self.power.set_mode(if on { Mode::On } else { Mode::Off });
+  }
+
+  /// `None` until the compositor told
+  pub fn is_on(&self) -> Option<bool> {
+    self.state.lock().on
+  }
+
+  /// The output doesn't support power management, another client has it, or it's gone.
+  pub fn failed(&self) -> bool {
+    self.state.lock().failed
+  }
+}
+
+impl Drop for OutputPower {
+  fn drop(&mut self) {
+    self.power.destroy();
+  }
+}
+
+impl Dispatch<ZwlrOutputPowerManagerV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &ZwlrOutputPowerManagerV1,
+    _event: zwlr_output_power_manager_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<ZwlrOutputPowerV1, Arc<Mutex<OutputPowerState>>> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &ZwlrOutputPowerV1,
+    event: zwlr_output_power_v1::Event,
+    data: &Arc<Mutex<OutputPowerState>>,
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    let mut state = data.lock();
+    match event {
+      zwlr_output_power_v1::Event::Mode { mode } => {
+        state.on = match mode {
+          WEnum::Value(Mode::On) => Some(true),
+          WEnum::Value(Mode::Off) => Some(false),
+          _ => None,
+        };
+      }
+      zwlr_output_power_v1::Event::Failed => state.failed = true,
+      _ => {}
+    }
+  }
+}
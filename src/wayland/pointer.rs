@@ -1,10 +1,22 @@
 use smithay_client_toolkit::delegate_pointer;
+use smithay_client_toolkit::seat::pointer::BTN_BACK;
+use smithay_client_toolkit::seat::pointer::BTN_EXTRA;
+use smithay_client_toolkit::seat::pointer::BTN_FORWARD;
+use smithay_client_toolkit::seat::pointer::BTN_LEFT;
+use smithay_client_toolkit::seat::pointer::BTN_MIDDLE;
+use smithay_client_toolkit::seat::pointer::BTN_RIGHT;
+use smithay_client_toolkit::seat::pointer::BTN_SIDE;
 use smithay_client_toolkit::seat::pointer::PointerEvent;
+use smithay_client_toolkit::seat::pointer::PointerEventKind;
 use smithay_client_toolkit::seat::pointer::PointerHandler;
 use wayland_client::Connection;
 use wayland_client::QueueHandle;
 use wayland_client::protocol::wl_pointer::WlPointer;
 
+use crate::compositor::auto_hide;
+use crate::error_in_callback;
+use crate::ffi;
+
 impl PointerHandler for super::WaylandState {
   fn pointer_frame(
     &mut self,
@@ -13,10 +25,145 @@ impl PointerHandler for super::WaylandState {
     _pointer: &WlPointer,
     events: &[PointerEvent],
   ) {
+    let state = self.engine.state();
+    let timestamp = unsafe { ffi::FlutterEngineGetCurrentTime() } as usize / 1000;
+    let mut flutter_events = Vec::with_capacity(events.len());
     for event in events {
-      log::info!("Pointer event: {:#?}", event);
+      let Some(view) = state.compositor.find_view_by_surface(&event.surface) else {
+        continue;
+      };
+      // Flutter wants physical pixels
+      let scale = view
+        .geometry
+        .lock()
+        .current
+        .map_or(1.0, |current| current.pixel_ratio());
+      let base = ffi::FlutterPointerEvent {
+        struct_size: size_of::<ffi::FlutterPointerEvent>(),
+        phase: ffi::FlutterPointerPhase_kHover,
+        timestamp,
+        x: event.position.0 * scale,
+        y: event.position.1 * scale,
+        device: 0,
+        signal_kind: ffi::FlutterPointerSignalKind_kFlutterPointerSignalKindNone,
+        scroll_delta_x: 0.0,
+        scroll_delta_y: 0.0,
+        device_kind: ffi::FlutterPointerDeviceKind_kFlutterPointerDeviceKindMouse,
+        buttons: self.pointer_buttons,
+        pan_x: 0.0,
+        pan_y: 0.0,
+        scale: 1.0,
+        rotation: 0.0,
+        view_id: view.view_id.raw(),
+      };
+      let moved = if self.pointer_buttons == 0 {
+        ffi::FlutterPointerPhase_kHover
+      } else {
+        ffi::FlutterPointerPhase_kMove
+      };
+
+      match &event.kind {
+        PointerEventKind::Enter { .. } => {
+          if let Err(e) = auto_hide::set_hovered(self.engine, &view, true) {
+            log::error!("failed to reveal {}: {:#}", view.view_id, e);
+          }
+          flutter_events.push(ffi::FlutterPointerEvent {
+            phase: ffi::FlutterPointerPhase_kAdd,
+            ..base
+          });
+        }
+        PointerEventKind::Leave { .. } => {
+          if let Err(e) = auto_hide::set_hovered(self.engine, &view, false) {
+            log::error!("failed to hide {}: {:#}", view.view_id, e);
+          }
+          if self.pointer_buttons != 0 {
+            flutter_events.push(ffi::FlutterPointerEvent {
+              phase: ffi::FlutterPointerPhase_kCancel,
+              ..base
+            });
+            self.pointer_buttons = 0;
+          }
+          flutter_events.push(ffi::FlutterPointerEvent {
+            phase: ffi::FlutterPointerPhase_kRemove,
+            buttons: 0,
+            ..base
+          });
+        }
+        PointerEventKind::Motion { .. } => flutter_events.push(ffi::FlutterPointerEvent {
+          phase: moved,
+          ..base
+        }),
+        PointerEventKind::Press { button, .. } => {
+          let Some(button) = flutter_button(*button) else {
+            continue;
+          };
+          let phase = if self.pointer_buttons == 0 {
+            ffi::FlutterPointerPhase_kDown
+          } else {
+            ffi::FlutterPointerPhase_kMove
+          };
+          self.pointer_buttons |= button;
+          flutter_events.push(ffi::FlutterPointerEvent {
+            phase,
+            buttons: self.pointer_buttons,
+            ..base
+          });
+        }
+        PointerEventKind::Release { button, .. } => {
+          let Some(button) = flutter_button(*button) else {
+            continue;
+          };
+          if self.pointer_buttons & button == 0 {
+            // pressed before the pointer entered
+            continue;
+          }
+          self.pointer_buttons &= !button;
+          let phase = if self.pointer_buttons == 0 {
+            ffi::FlutterPointerPhase_kUp
+          } else {
+            ffi::FlutterPointerPhase_kMove
+          };
+          flutter_events.push(ffi::FlutterPointerEvent {
+            phase,
+            buttons: self.pointer_buttons,
+            ..base
+          });
+        }
+        PointerEventKind::Axis {
+          horizontal,
+          vertical,
+          ..
+        } => flutter_events.push(ffi::FlutterPointerEvent {
+          phase: moved,
+          signal_kind: ffi::FlutterPointerSignalKind_kFlutterPointerSignalKindScroll,
+          scroll_delta_x: horizontal.absolute * scale,
+          scroll_delta_y: vertical.absolute * scale,
+          ..base
+        }),
+      }
+    }
+
+    if !flutter_events.is_empty() {
+      error_in_callback!(
+        state,
+        self.engine.send_pointer_events(&flutter_events),
+        return ()
+      );
     }
   }
 }
 
+/// The `FlutterPointerMouseButtons` bit of a linux input event code
+fn flutter_button(button: u32) -> Option<i64> {
+  let button = match button {
+    BTN_LEFT => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMousePrimary,
+    BTN_RIGHT => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMouseSecondary,
+    BTN_MIDDLE => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMouseMiddle,
+    BTN_SIDE | BTN_BACK => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMouseBack,
+    BTN_EXTRA | BTN_FORWARD => ffi::FlutterPointerMouseButtons_kFlutterPointerButtonMouseForward,
+    _ => return None,
+  };
+  Some(button as i64)
+}
+
 delegate_pointer!(super::WaylandState);
@@ -0,0 +1,190 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation::WpPresentation;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation_feedback;
+use smithay_client_toolkit::reexports::protocols::wp::presentation_time::client::wp_presentation_feedback::WpPresentationFeedback;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use super::WaylandState;
+
+/// Refresh interval assumed until the compositor reports a real one
+const DEFAULT_REFRESH_NANOS: u64 = 1_000_000_000 / 60;
+
+/// Vsync timing derived from wp_presentation feedback.
+///
+/// All timestamps are nanoseconds of CLOCK_MONOTONIC, the clock `FlutterEngineGetCurrentTime`
+/// uses.
+#[derive(Clone)]
+pub struct FrameClock {
+  state: Arc<Mutex<FrameClockState>>,
+  presentation: Option<WpPresentation>,
+  qh: QueueHandle<WaylandState>,
+}
+
+struct FrameClockState {
+  /// clock of the timestamps the compositor sends
+  clock_id: libc::clockid_t,
+  last_presentation: Option<u64>,
+  refresh: u64,
+  /// `frame_start` of the last frame the engine was told to build
+  last_frame_start: Option<u64>,
+}
+
+impl FrameClock {
+  pub(super) fn new(presentation: Option<WpPresentation>, qh: QueueHandle<WaylandState>) -> Self {
+    if presentation.is_none() {
+      log::warn!("wp_presentation is not supported. Frame timing falls back to 60Hz estimates.");
+    }
+    Self {
+      state: Arc::new(Mutex::new(FrameClockState {
+        clock_id: libc::CLOCK_MONOTONIC,
+        last_presentation: None,
+        refresh: DEFAULT_REFRESH_NANOS,
+        last_frame_start: None,
+      })),
+      presentation,
+      qh,
+    }
+  }
+
+  /// Request presentation feedback for the next commit of `surface`, which carries `frame`
+  /// of [`crate::frame_stats::FrameStats`].
+  ///
+  /// Returns false if there won't be any feedback.
+  pub fn request_feedback(&self, surface: &WlSurface, frame: u64) -> bool {
+    let Some(presentation) = &self.presentation else {
+      return false;
+    };
+    presentation.feedback(
+      surface,
+      &self.qh,
+      FeedbackData {
+        state: self.state.clone(),
+        committed_at: clock_nanos(libc::CLOCK_MONOTONIC),
+        frame,
+      },
+    );
+    true
+  }
+
+  /// Request a wl_surface frame callback for the next commit of `surface`, which ends up in
+  /// `CompositorHandler::frame`.
+  pub fn request_frame_callback(&self, surface: &WlSurface) {
+    surface.frame(&self.qh, surface.clone());
+  }
+
+  /// Returns `(frame_start, frame_target)` for a frame requested at `now`: the next vsync
+  /// after `now` and the one after it.
+  pub fn next_frame(&self, now: u64) -> (u64, u64) {
+    let state = self.state.lock();
+    let refresh = state.refresh;
+    let frame_start = match state.last_presentation {
+      Some(last) if last < now => last + (now - last).div_ceil(refresh) * refresh,
+      Some(last) => last,
+      None => now,
+    };
+    (frame_start, frame_start + refresh)
+  }
+
+  /// The engine was told to build a frame starting at `frame_start`.
+  pub fn frame_started(&self, frame_start: u64) {
+    self.state.lock().last_frame_start = Some(frame_start);
+  }
+
+  /// When to answer a vsync baton requested at `now` so that frames start at least `interval`
+  /// nanoseconds apart. Half a refresh early, as the frame starts at the vsync after.
+  pub fn limit(&self, now: u64, interval: u64) -> u64 {
+    let state = self.state.lock();
+    match state.last_frame_start {
+      Some(last) => now.max((last + interval).saturating_sub(state.refresh / 2)),
+      None => now,
+    }
+  }
+}
+
+pub(super) struct FeedbackData {
+  state: Arc<Mutex<FrameClockState>>,
+  committed_at: u64,
+  frame: u64,
+}
+
+fn clock_nanos(clock_id: libc::clockid_t) -> u64 {
+  let mut ts = libc::timespec {
+    tv_sec: 0,
+    tv_nsec: 0,
+  };
+  unsafe { libc::clock_gettime(clock_id, &mut ts) };
+  ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+impl Dispatch<WpPresentation, ()> for WaylandState {
+  fn event(
+    state: &mut Self,
+    _proxy: &WpPresentation,
+    event: wp_presentation::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    if let wp_presentation::Event::ClockId { clk_id } = event {
+      log::debug!("presentation clock id: {}", clk_id);
+      state.frame_clock.state.lock().clock_id = clk_id as libc::clockid_t;
+    }
+  }
+}
+
+impl Dispatch<WpPresentationFeedback, FeedbackData> for WaylandState {
+  fn event(
+    wayland_state: &mut Self,
+    _proxy: &WpPresentationFeedback,
+    event: wp_presentation_feedback::Event,
+    data: &FeedbackData,
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    match event {
+      wp_presentation_feedback::Event::Presented {
+        tv_sec_hi,
+        tv_sec_lo,
+        tv_nsec,
+        refresh,
+        ..
+      } => {
+        let mut state = data.state.lock();
+        let tv_sec = ((tv_sec_hi as u64) << 32) | tv_sec_lo as u64;
+        let mut presented = tv_sec * 1_000_000_000 + tv_nsec as u64;
+        if state.clock_id != libc::CLOCK_MONOTONIC {
+          // translate into CLOCK_MONOTONIC by the current offset between the two clocks
+          let offset =
+            clock_nanos(state.clock_id) as i64 - clock_nanos(libc::CLOCK_MONOTONIC) as i64;
+          presented = (presented as i64 - offset) as u64;
+        }
+        if refresh != 0 {
+          state.refresh = refresh as u64;
+        }
+        state.last_presentation = Some(presented);
+        log::trace!(
+          "frame presented at {} ({} ns after commit), refresh: {} ns",
+          presented,
+          presented.saturating_sub(data.committed_at),
+          refresh
+        );
+        drop(state);
+        let engine = wayland_state.engine;
+        let frame_stats = &engine.state().frame_stats;
+        frame_stats.finish(engine, data.frame, Some(presented));
+      }
+      wp_presentation_feedback::Event::Discarded => {
+        log::trace!("frame committed at {} discarded", data.committed_at);
+        let frame_stats = &wayland_state.engine.state().frame_stats;
+        frame_stats.discarded(data.frame);
+      }
+      _ => {}
+    }
+  }
+}
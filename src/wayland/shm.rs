@@ -0,0 +1,159 @@
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::FromRawFd;
+use std::os::fd::OwnedFd;
+
+use anyhow::Result;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::globals::GlobalList;
+use wayland_client::protocol::wl_buffer;
+use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_shm;
+use wayland_client::protocol::wl_shm::WlShm;
+use wayland_client::protocol::wl_shm_pool;
+use wayland_client::protocol::wl_shm_pool::WlShmPool;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use super::WaylandState;
+
+/// wl_shm global
+#[derive(Clone)]
+pub struct Shm {
+  shm: WlShm,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl Shm {
+  pub(super) fn bind(globals: &GlobalList, qh: &QueueHandle<WaylandState>) -> Option<Self> {
+    let shm = globals.bind::<WlShm, _, _>(qh, 1..=1, ()).ok()?;
+    Some(Self {
+      shm,
+      qh: qh.clone(),
+    })
+  }
+
+  /// A premultiplied ARGB8888 buffer of its own memfd, i.e. B, G, R, A bytes, rows top to
+  /// bottom.
+  pub fn create_buffer(&self, width: u32, height: u32) -> Result<ShmBuffer> {
+    let stride = width as usize * 4;
+    let len = stride * height as usize;
+    let fd = unsafe { libc::memfd_create(c"wayflutter-shm".as_ptr(), libc::MFD_CLOEXEC) };
+    if fd < 0 {
+      return Err(std::io::Error::last_os_error().into());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } < 0 {
+      return Err(std::io::Error::last_os_error().into());
+    }
+    let data = unsafe {
+      libc::mmap(
+        std::ptr::null_mut(),
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        fd.as_raw_fd(),
+        0,
+      )
+    };
+    if data == libc::MAP_FAILED {
+      return Err(std::io::Error::last_os_error().into());
+    }
+
+    let pool = self.shm.create_pool(fd.as_fd(), len as i32, &self.qh, ());
+    let buffer = pool.create_buffer(
+      0,
+      width as i32,
+      height as i32,
+      stride as i32,
+      wl_shm::Format::Argb8888,
+      &self.qh,
+      (),
+    );
+    // the buffer keeps the memory of the pool
+    pool.destroy();
+    Ok(ShmBuffer {
+      buffer,
+      data: data as *mut u8,
+      len,
+      stride,
+    })
+  }
+}
+
+/// A `wl_buffer` with its memory mapped.
+///
+/// It's never reused once attached, so the compositor can keep reading it after it's gone.
+#[derive(Debug)]
+pub struct ShmBuffer {
+  buffer: WlBuffer,
+  data: *mut u8,
+  len: usize,
+  pub stride: usize,
+}
+
+/// The mapping is plain memory, written by the raster thread only
+unsafe impl Send for ShmBuffer {}
+unsafe impl Sync for ShmBuffer {}
+
+impl ShmBuffer {
+  pub fn data(&self) -> *mut u8 {
+    self.data
+  }
+
+  pub fn bytes(&self) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(self.data, self.len) }
+  }
+
+  /// Attach the buffer to `surface`. Takes effect with the next commit.
+  pub fn attach(&self, surface: &WlSurface) {
+    surface.attach(Some(&self.buffer), 0, 0);
+  }
+}
+
+impl Drop for ShmBuffer {
+  fn drop(&mut self) {
+    // the compositor keeps the contents of a destroyed buffer that's still attached
+    self.buffer.destroy();
+    unsafe { libc::munmap(self.data as _, self.len) };
+  }
+}
+
+impl Dispatch<WlShm, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WlShm,
+    _event: wl_shm::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    // ARGB8888 is always supported
+  }
+}
+
+impl Dispatch<WlShmPool, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WlShmPool,
+    _event: wl_shm_pool::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<WlBuffer, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WlBuffer,
+    _event: wl_buffer::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    // released buffers are of no interest, as they aren't reused
+  }
+}
@@ -0,0 +1,76 @@
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibit_manager_v1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibit_manager_v1::ZwpKeyboardShortcutsInhibitManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibitor_v1;
+use smithay_client_toolkit::reexports::protocols::wp::keyboard_shortcuts_inhibit::zv1::client::zwp_keyboard_shortcuts_inhibitor_v1::ZwpKeyboardShortcutsInhibitorV1;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use super::WaylandState;
+
+/// keyboard-shortcuts-inhibit-unstable-v1 global
+#[derive(Clone)]
+pub struct ShortcutsInhibitManager {
+  manager: ZwpKeyboardShortcutsInhibitManagerV1,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl ShortcutsInhibitManager {
+  pub(super) fn new(
+    manager: ZwpKeyboardShortcutsInhibitManagerV1,
+    qh: QueueHandle<WaylandState>,
+  ) -> Self {
+    Self { manager, qh }
+  }
+
+  /// Ask for the keys of `seat` the compositor would take as shortcuts while `surface` has
+  /// the keyboard focus. At most one per surface and seat.
+  pub fn inhibit(&self, surface: &WlSurface, seat: &WlSeat) -> ShortcutsInhibitor {
+    ShortcutsInhibitor(self.manager.inhibit_shortcuts(surface, seat, &self.qh, ()))
+  }
+}
+
+/// Restores the compositor's shortcuts when dropped
+pub struct ShortcutsInhibitor(ZwpKeyboardShortcutsInhibitorV1);
+
+impl Drop for ShortcutsInhibitor {
+  fn drop(&mut self) {
+    self.0.destroy();
+  }
+}
+
+impl Dispatch<ZwpKeyboardShortcutsInhibitManagerV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &ZwpKeyboardShortcutsInhibitManagerV1,
+    _event: zwp_keyboard_shortcuts_inhibit_manager_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<ZwpKeyboardShortcutsInhibitorV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &ZwpKeyboardShortcutsInhibitorV1,
+    event: zwp_keyboard_shortcuts_inhibitor_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+    // the compositor may deny or revoke it, e.g. on a user's request
+    match event {
+      zwp_keyboard_shortcuts_inhibitor_v1::Event::Active => {
+        log::debug!("compositor shortcuts inhibited");
+      }
+      zwp_keyboard_shortcuts_inhibitor_v1::Event::Inactive => {
+        log::debug!("compositor shortcuts restored");
+      }
+      _ => {}
+    }
+  }
+}
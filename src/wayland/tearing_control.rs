@@ -0,0 +1,73 @@
+use smithay_client_toolkit::reexports::protocols::wp::tearing_control::v1::client::wp_tearing_control_manager_v1;
+use smithay_client_toolkit::reexports::protocols::wp::tearing_control::v1::client::wp_tearing_control_manager_v1::WpTearingControlManagerV1;
+use smithay_client_toolkit::reexports::protocols::wp::tearing_control::v1::client::wp_tearing_control_v1;
+use smithay_client_toolkit::reexports::protocols::wp::tearing_control::v1::client::wp_tearing_control_v1::PresentationHint;
+use smithay_client_toolkit::reexports::protocols::wp::tearing_control::v1::client::wp_tearing_control_v1::WpTearingControlV1;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use super::WaylandState;
+
+/// tearing-control-v1 global
+#[derive(Clone)]
+pub struct TearingControlManager {
+  manager: WpTearingControlManagerV1,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl TearingControlManager {
+  pub(super) fn new(manager: WpTearingControlManagerV1, qh: QueueHandle<WaylandState>) -> Self {
+    Self { manager, qh }
+  }
+
+  pub fn get_tearing_control(&self, surface: &WlSurface) -> TearingControl {
+    TearingControl(self.manager.get_tearing_control(surface, &self.qh, ()))
+  }
+}
+
+/// Presentation hint of one surface
+pub struct TearingControl(WpTearingControlV1);
+
+impl TearingControl {
+  /// Ask for async presentation, which may tear. Takes effect with the next commit.
+  pub fn set_async(&self, allow_tearing: bool) {
+    let hint = if allow_tearing {
+      PresentationHint::Async
+    } else {
+      PresentationHint::Vsync
+    };
+    self.0.set_presentation_hint(hint);
+  }
+}
+
+impl Drop for TearingControl {
+  fn drop(&mut self) {
+    self.0.destroy();
+  }
+}
+
+impl Dispatch<WpTearingControlManagerV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WpTearingControlManagerV1,
+    _event: wp_tearing_control_manager_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<WpTearingControlV1, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WpTearingControlV1,
+    _event: wp_tearing_control_v1::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
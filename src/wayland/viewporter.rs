@@ -0,0 +1,71 @@
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewport::WpViewport;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter;
+use smithay_client_toolkit::reexports::protocols::wp::viewporter::client::wp_viewporter::WpViewporter;
+use wayland_client::Connection;
+use wayland_client::Dispatch;
+use wayland_client::QueueHandle;
+use wayland_client::protocol::wl_surface::WlSurface;
+
+use super::WaylandState;
+
+/// viewporter global
+#[derive(Clone)]
+pub struct Viewporter {
+  viewporter: WpViewporter,
+  qh: QueueHandle<WaylandState>,
+}
+
+impl Viewporter {
+  pub(super) fn new(viewporter: WpViewporter, qh: QueueHandle<WaylandState>) -> Self {
+    Self { viewporter, qh }
+  }
+
+  pub fn get_viewport(&self, surface: &WlSurface) -> Viewport {
+    Viewport(self.viewporter.get_viewport(surface, &self.qh, ()))
+  }
+}
+
+/// Scaling of one surface
+pub struct Viewport(WpViewport);
+
+impl Viewport {
+  /// Stretch the whole buffer to `(width, height)` in surface coordinates, or size the surface
+  /// after the buffer and its scale again with `None`. Takes effect with the next commit.
+  pub fn set_destination(&self, size: Option<(u32, u32)>) {
+    match size {
+      Some((width, height)) => self.0.set_destination(width as i32, height as i32),
+      None => self.0.set_destination(-1, -1),
+    }
+  }
+}
+
+impl Drop for Viewport {
+  fn drop(&mut self) {
+    self.0.destroy();
+  }
+}
+
+impl Dispatch<WpViewporter, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WpViewporter,
+    _event: wp_viewporter::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}
+
+impl Dispatch<WpViewport, ()> for WaylandState {
+  fn event(
+    _state: &mut Self,
+    _proxy: &WpViewport,
+    _event: wp_viewport::Event,
+    _data: &(),
+    _conn: &Connection,
+    _qhandle: &QueueHandle<Self>,
+  ) {
+  }
+}